@@ -1,11 +1,27 @@
 use dotenv::dotenv;
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use hex::FromHex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::time::Duration;
 
+use crate::node::logger::{LogFilter, LogLevel};
+use crate::node::peer::outbox::OverflowPolicy;
+use crate::types::transaction::PublicKeyHash;
+
 pub(crate) fn retrieve_verifying_key(node_id: usize) -> VerifyingKey {
+    if let Some(genesis) = retrieve_genesis() {
+        let validator = genesis
+            .validators
+            .get(node_id)
+            .unwrap_or_else(|| panic!("Validator {} not in genesis file", node_id));
+        return validator.public_key;
+    }
+
     dotenv().ok();
     let env_key = format!("PUBLIC_KEY_{}", node_id);
     let pk_hex = env::var(&env_key).expect(&format!("{} not set", &env_key));
@@ -31,6 +47,10 @@ pub(crate) fn retrieve_signing_key_checked(node_id: usize) -> SigningKey {
 }
 
 pub fn retrieve_num_validators() -> usize {
+    if let Some(genesis) = retrieve_genesis() {
+        return genesis.validators.len();
+    }
+
     dotenv().ok();
 
     env::var("NUM_VALIDATORS")
@@ -39,21 +59,34 @@ pub fn retrieve_num_validators() -> usize {
         .expect("NUM_VALIDATORS must be a number")
 }
 
-pub fn retrieve_validator_set() -> HashSet<VerifyingKey> {
-    dotenv().ok();
+/// Most validators that can be faulty (f) among `num_validators` = 3f + 1
+pub fn max_faulty_among(num_validators: usize) -> usize {
+    (num_validators - 1) / 3
+}
 
-    let num_validators = self::retrieve_num_validators();
+/// Number of signatures (2f + 1) needed for a quorum among `num_validators`
+pub fn quorum_threshold_among(num_validators: usize) -> usize {
+    2 * max_faulty_among(num_validators) + 1
+}
 
-    let mut validator_set = HashSet::new();
-    for i in 0..num_validators {
-        let env_key = format!("PUBLIC_KEY_{}", i);
-        let pk_hex = env::var(&env_key).expect(&format!("{} not set", &env_key));
+pub fn max_faulty() -> usize {
+    max_faulty_among(retrieve_num_validators())
+}
 
-        let pk_bytes = <[u8; 32]>::from_hex(&pk_hex).expect("Invalid hex");
-        let pk = VerifyingKey::from_bytes(&pk_bytes).expect("Invalid public key bytes");
-        validator_set.insert(pk);
-    }
-    validator_set
+/// Quorum size for QCs and TCs, derived from the configured number of validators
+pub fn quorum_threshold() -> usize {
+    quorum_threshold_among(retrieve_num_validators())
+}
+
+/// Validator keys indexed by node id
+pub(crate) fn retrieve_validator_keys() -> Vec<VerifyingKey> {
+    (0..retrieve_num_validators())
+        .map(retrieve_verifying_key)
+        .collect()
+}
+
+pub fn retrieve_validator_set() -> HashSet<VerifyingKey> {
+    retrieve_validator_keys().into_iter().collect()
 }
 
 pub fn retrieve_faucet_keys() -> (VerifyingKey, SigningKey) {
@@ -73,6 +106,233 @@ pub fn retrieve_faucet_keys() -> (VerifyingKey, SigningKey) {
     (pk, sk)
 }
 
+// GENESIS
+
+/// A validator listed in the genesis file
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisValidator {
+    pub id: usize,
+    pub public_key: VerifyingKey,
+    /// Address other nodes dial to reach this validator
+    pub address: String,
+}
+
+/// Validator set, sorted by id, and ledger parameters read from the JSON file at `GENESIS_FILE`.
+/// When present it replaces `NUM_VALIDATORS`, `PUBLIC_KEY_<i>` and `PEER_ADDR_<i>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Genesis {
+    pub validators: Vec<GenesisValidator>,
+    pub ledger: LedgerParams,
+}
+
+/// Rules every validator executes blocks by, from the `ledger` object of the genesis file.
+/// They are kept in the ledger state and committed to its state root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerParams {
+    /// Most orders an account may have resting at once
+    pub max_open_orders: usize,
+    /// Percentage of a price level's orders that may be cancelled before it is compacted
+    pub level_prune_threshold: u32,
+    /// Views a recipient has to wait between faucet drips, 0 disables the cooldown
+    pub faucet_cooldown: u64,
+    /// Account allowed to send admin transactions such as market status changes
+    pub admin: PublicKeyHash,
+    /// Cut of what a taker receives on each fill, in basis points, 0 disables fees
+    pub taker_fee_bps: u64,
+    /// Account taker fees are paid into
+    pub fee_collector: PublicKeyHash,
+}
+
+impl Default for LedgerParams {
+    fn default() -> Self {
+        Self {
+            max_open_orders: DEFAULT_MAX_OPEN_ORDERS,
+            level_prune_threshold: DEFAULT_LEVEL_PRUNE_THRESHOLD,
+            faucet_cooldown: DEFAULT_FAUCET_COOLDOWN,
+            admin: retrieve_faucet_keys().0.to_bytes(),
+            taker_fee_bps: DEFAULT_TAKER_FEE_BPS,
+            fee_collector: retrieve_faucet_keys().0.to_bytes(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawGenesis {
+    validators: Vec<RawGenesisValidator>,
+    #[serde(default)]
+    ledger: RawLedgerParams,
+}
+
+#[derive(Deserialize)]
+struct RawGenesisValidator {
+    id: usize,
+    public_key: String,
+    address: String,
+}
+
+/// Fields left out of the genesis file take their default
+#[derive(Deserialize, Default)]
+struct RawLedgerParams {
+    max_open_orders: Option<usize>,
+    level_prune_threshold: Option<u32>,
+    faucet_cooldown: Option<u64>,
+    admin: Option<String>,
+    taker_fee_bps: Option<u64>,
+    fee_collector: Option<String>,
+}
+
+impl RawLedgerParams {
+    fn into_params(self) -> Result<LedgerParams, GenesisError> {
+        let defaults = LedgerParams::default();
+        let level_prune_threshold = self
+            .level_prune_threshold
+            .unwrap_or(defaults.level_prune_threshold);
+        if level_prune_threshold > 100 {
+            return Err(GenesisError::InvalidLedgerParam("level_prune_threshold"));
+        }
+        let taker_fee_bps = self.taker_fee_bps.unwrap_or(defaults.taker_fee_bps);
+        if taker_fee_bps > MAX_TAKER_FEE_BPS {
+            return Err(GenesisError::InvalidLedgerParam("taker_fee_bps"));
+        }
+
+        Ok(LedgerParams {
+            max_open_orders: self.max_open_orders.unwrap_or(defaults.max_open_orders),
+            level_prune_threshold,
+            faucet_cooldown: self.faucet_cooldown.unwrap_or(defaults.faucet_cooldown),
+            admin: parse_ledger_key(self.admin, "admin")?.unwrap_or(defaults.admin),
+            taker_fee_bps,
+            fee_collector: parse_ledger_key(self.fee_collector, "fee_collector")?
+                .unwrap_or(defaults.fee_collector),
+        })
+    }
+}
+
+/// Public key given as hex in the genesis file, `name` is reported if it is invalid
+fn parse_ledger_key(
+    pk_hex: Option<String>,
+    name: &'static str,
+) -> Result<Option<PublicKeyHash>, GenesisError> {
+    let Some(pk_hex) = pk_hex else {
+        return Ok(None);
+    };
+    <[u8; 32]>::from_hex(&pk_hex)
+        .ok()
+        .filter(|bytes| VerifyingKey::from_bytes(bytes).is_ok())
+        .map(Some)
+        .ok_or(GenesisError::InvalidLedgerParam(name))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    Read(String),
+    Parse(String),
+    NoValidators,
+    DuplicateId(usize),
+    /// Ids must run from 0 to the number of validators - 1
+    MissingId(usize),
+    InvalidPublicKey(usize),
+    DuplicatePublicKey(usize),
+    /// Ledger parameter out of its range
+    InvalidLedgerParam(&'static str),
+}
+
+impl Genesis {
+    pub fn load(path: &Path) -> Result<Self, GenesisError> {
+        let json = fs::read_to_string(path).map_err(|e| GenesisError::Read(e.to_string()))?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, GenesisError> {
+        let raw: RawGenesis =
+            serde_json::from_str(json).map_err(|e| GenesisError::Parse(e.to_string()))?;
+        if raw.validators.is_empty() {
+            return Err(GenesisError::NoValidators);
+        }
+
+        let mut seen_ids = HashSet::new();
+        let mut seen_keys = HashSet::new();
+        let mut validators = Vec::with_capacity(raw.validators.len());
+        for validator in raw.validators {
+            if !seen_ids.insert(validator.id) {
+                return Err(GenesisError::DuplicateId(validator.id));
+            }
+
+            let public_key = <[u8; 32]>::from_hex(&validator.public_key)
+                .ok()
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                .ok_or(GenesisError::InvalidPublicKey(validator.id))?;
+            if !seen_keys.insert(public_key) {
+                return Err(GenesisError::DuplicatePublicKey(validator.id));
+            }
+
+            validators.push(GenesisValidator {
+                id: validator.id,
+                public_key,
+                address: validator.address,
+            });
+        }
+
+        // node ids double as indices for leader rotation and key lookup
+        validators.sort_by_key(|validator| validator.id);
+        if let Some(missing) = (0..validators.len()).find(|&i| validators[i].id != i) {
+            return Err(GenesisError::MissingId(missing));
+        }
+
+        let ledger = raw.ledger.into_params()?;
+        Ok(Self { validators, ledger })
+    }
+
+    /// Address node `node_id` binds to: `listen_ip` on the port of its genesis address
+    pub fn listen_addr(&self, node_id: usize, listen_ip: &str) -> Option<String> {
+        let address = &self.validators.get(node_id)?.address;
+        let (_, port) = address.rsplit_once(':')?;
+        Some(format!("{}:{}", listen_ip, port))
+    }
+}
+
+pub fn retrieve_genesis_path() -> Option<PathBuf> {
+    dotenv().ok();
+
+    env::var("GENESIS_FILE").ok().map(PathBuf::from)
+}
+
+/// The genesis validator set if `GENESIS_FILE` is set, loaded on first use
+pub fn retrieve_genesis() -> Option<&'static Genesis> {
+    static GENESIS: OnceLock<Option<Genesis>> = OnceLock::new();
+
+    GENESIS
+        .get_or_init(|| {
+            let path = retrieve_genesis_path()?;
+            let genesis = Genesis::load(&path)
+                .unwrap_or_else(|e| panic!("Invalid genesis file {}: {:?}", path.display(), e));
+            Some(genesis)
+        })
+        .as_ref()
+}
+
+/// Ledger parameters of the genesis file, the defaults if `GENESIS_FILE` is unset
+pub fn retrieve_ledger_params() -> LedgerParams {
+    retrieve_genesis().map_or_else(LedgerParams::default, |genesis| genesis.ledger.clone())
+}
+
+// LOGGING
+
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Parsed from `LOG_LEVEL`, e.g. `info,state=warn`. `REPLICA_DEBUG=true` also enables
+/// replica debug logs
+pub fn retrieve_log_filter() -> LogFilter {
+    dotenv().ok();
+
+    let spec = env::var("LOG_LEVEL").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string());
+    let mut filter = LogFilter::parse(&spec).expect("LOG_LEVEL must be a valid log filter");
+
+    if env::var("REPLICA_DEBUG").is_ok_and(|v| v == "true") {
+        filter.set_module("hotstuff::replica", LogLevel::Debug);
+    }
+    filter
+}
+
 // PACEMAKER
 
 pub fn retrieve_tick_duration() -> Duration {
@@ -94,3 +354,631 @@ pub fn retrieve_multiplicative_factor() -> f32 {
         .parse::<f32>()
         .expect("multiplicative factorTICK_DURATION must be a number")
 }
+
+pub const DEFAULT_MAX_TIMEOUT_MS: u64 = 10_000;
+
+pub fn retrieve_max_timeout() -> Duration {
+    dotenv().ok();
+
+    let duration_ms = match env::var("MAX_TIMEOUT") {
+        Ok(max_timeout) => max_timeout
+            .parse::<u64>()
+            .expect("MAX_TIMEOUT must be a number"),
+        Err(_) => DEFAULT_MAX_TIMEOUT_MS,
+    };
+
+    Duration::from_millis(duration_ms)
+}
+
+// SPOT
+
+pub const DEFAULT_MAX_OPEN_ORDERS: usize = 100;
+
+pub const DEFAULT_MATCHING_THREADS: usize = 1;
+
+/// Worker threads used to execute the independent parts of a block. Does not affect the
+/// resulting state, so validators may run with different values
+pub fn retrieve_matching_threads() -> usize {
+    dotenv().ok();
+
+    match env::var("MATCHING_THREADS") {
+        Ok(matching_threads) => matching_threads
+            .parse::<usize>()
+            .ok()
+            .filter(|threads| *threads > 0)
+            .expect("MATCHING_THREADS must be a positive number"),
+        Err(_) => DEFAULT_MATCHING_THREADS,
+    }
+}
+
+pub const DEFAULT_LEVEL_PRUNE_THRESHOLD: u32 = 50;
+
+// FAUCET
+
+pub const DEFAULT_FAUCET_COOLDOWN: u64 = 0;
+
+// FEES
+
+pub const DEFAULT_TAKER_FEE_BPS: u64 = 0;
+pub const MAX_TAKER_FEE_BPS: u64 = 10_000;
+
+// MEMPOOL
+
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 10_000;
+
+pub fn retrieve_mempool_capacity() -> usize {
+    dotenv().ok();
+
+    match env::var("MEMPOOL_CAPACITY") {
+        Ok(capacity) => capacity
+            .parse::<usize>()
+            .expect("MEMPOOL_CAPACITY must be a number"),
+        Err(_) => DEFAULT_MEMPOOL_CAPACITY,
+    }
+}
+
+// BLOCKS
+
+pub const DEFAULT_MAX_BLOCK_TRANSACTIONS: usize = 1_000;
+pub const DEFAULT_MAX_BLOCK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Most transactions a proposal may carry before replicas refuse to vote for it
+pub fn retrieve_max_block_transactions() -> usize {
+    dotenv().ok();
+
+    match env::var("MAX_BLOCK_TRANSACTIONS") {
+        Ok(max_transactions) => max_transactions
+            .parse::<usize>()
+            .expect("MAX_BLOCK_TRANSACTIONS must be a number"),
+        Err(_) => DEFAULT_MAX_BLOCK_TRANSACTIONS,
+    }
+}
+
+/// Largest serialized proposal, in bytes, replicas will vote for
+pub fn retrieve_max_block_size() -> u64 {
+    dotenv().ok();
+
+    match env::var("MAX_BLOCK_SIZE") {
+        Ok(max_size) => max_size
+            .parse::<u64>()
+            .expect("MAX_BLOCK_SIZE must be a number"),
+        Err(_) => DEFAULT_MAX_BLOCK_SIZE,
+    }
+}
+
+pub const DEFAULT_BATCH_WINDOW_MS: u64 = 0;
+pub const DEFAULT_BATCH_SIZE: usize = 16;
+
+/// How long a leader accumulates mempool transactions before proposing, 0 proposes
+/// as soon as the leader has a quorum to extend
+pub fn retrieve_batch_window() -> Duration {
+    dotenv().ok();
+
+    let duration_ms = match env::var("BATCH_WINDOW") {
+        Ok(window) => window
+            .parse::<u64>()
+            .expect("BATCH_WINDOW must be a number"),
+        Err(_) => DEFAULT_BATCH_WINDOW_MS,
+    };
+
+    Duration::from_millis(duration_ms)
+}
+
+/// Transactions a leader proposes per block, proposing early once this many are pending
+pub fn retrieve_batch_size() -> usize {
+    dotenv().ok();
+
+    match env::var("BATCH_SIZE") {
+        Ok(batch_size) => batch_size
+            .parse::<usize>()
+            .expect("BATCH_SIZE must be a number"),
+        Err(_) => DEFAULT_BATCH_SIZE,
+    }
+}
+
+// STORAGE
+
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+pub fn retrieve_data_dir() -> PathBuf {
+    dotenv().ok();
+
+    match env::var("DATA_DIR") {
+        Ok(data_dir) => PathBuf::from(data_dir),
+        Err(_) => PathBuf::from(DEFAULT_DATA_DIR),
+    }
+}
+
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100;
+
+pub fn retrieve_checkpoint_interval() -> u64 {
+    dotenv().ok();
+
+    match env::var("CHECKPOINT_INTERVAL") {
+        Ok(interval) => interval
+            .parse::<u64>()
+            .expect("CHECKPOINT_INTERVAL must be a number"),
+        Err(_) => DEFAULT_CHECKPOINT_INTERVAL,
+    }
+}
+
+// METRICS
+
+/// Node `i` serves metrics on `LISTEN_IP:METRICS_BASE_PORT + i`. Metrics are disabled if unset
+pub fn retrieve_metrics_base_port() -> Option<u16> {
+    dotenv().ok();
+
+    env::var("METRICS_BASE_PORT").ok().map(|port| {
+        port.parse::<u16>()
+            .expect("METRICS_BASE_PORT must be a port number")
+    })
+}
+
+// NETWORK
+
+pub const DEFAULT_LISTEN_IP: &str = "127.0.0.1";
+pub const DEFAULT_BASE_PORT: u16 = 6400;
+pub const DEFAULT_CLIENT_PORT: u16 = 8000;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest message frame, in bytes, a node will read from or write to a connection
+pub fn retrieve_max_frame_size() -> usize {
+    dotenv().ok();
+
+    match env::var("MAX_FRAME_SIZE") {
+        Ok(max_frame_size) => max_frame_size
+            .parse::<usize>()
+            .expect("MAX_FRAME_SIZE must be a number"),
+        Err(_) => DEFAULT_MAX_FRAME_SIZE,
+    }
+}
+
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 500;
+pub const DEFAULT_HEARTBEAT_MISS_THRESHOLD: u32 = 3;
+
+pub fn retrieve_heartbeat_interval() -> Duration {
+    dotenv().ok();
+
+    let interval_ms = match env::var("HEARTBEAT_INTERVAL") {
+        Ok(interval) => interval
+            .parse::<u64>()
+            .expect("HEARTBEAT_INTERVAL must be a number"),
+        Err(_) => DEFAULT_HEARTBEAT_INTERVAL_MS,
+    };
+
+    Duration::from_millis(interval_ms)
+}
+
+/// Number of heartbeat intervals a peer may stay silent before it is considered down
+pub fn retrieve_heartbeat_miss_threshold() -> u32 {
+    dotenv().ok();
+
+    match env::var("HEARTBEAT_MISS_THRESHOLD") {
+        Ok(threshold) => threshold
+            .parse::<u32>()
+            .expect("HEARTBEAT_MISS_THRESHOLD must be a number"),
+        Err(_) => DEFAULT_HEARTBEAT_MISS_THRESHOLD,
+    }
+}
+
+pub const DEFAULT_PEER_OUTBOX_CAPACITY: usize = 1024;
+pub const DEFAULT_PEER_OUTBOX_POLICY: &str = "drop";
+
+/// Most messages queued for a single peer before `PEER_OUTBOX_POLICY` applies
+pub fn retrieve_peer_outbox_capacity() -> usize {
+    dotenv().ok();
+
+    match env::var("PEER_OUTBOX_CAPACITY") {
+        Ok(capacity) => capacity
+            .parse::<usize>()
+            .expect("PEER_OUTBOX_CAPACITY must be a number"),
+        Err(_) => DEFAULT_PEER_OUTBOX_CAPACITY,
+    }
+}
+
+/// `drop` sheds the stalest messages queued for a slow peer, `block` stalls consensus
+/// until the peer catches up
+pub(crate) fn retrieve_peer_outbox_policy() -> OverflowPolicy {
+    dotenv().ok();
+
+    let policy =
+        env::var("PEER_OUTBOX_POLICY").unwrap_or_else(|_| DEFAULT_PEER_OUTBOX_POLICY.to_string());
+    OverflowPolicy::parse(&policy).expect("PEER_OUTBOX_POLICY must be drop or block")
+}
+
+pub const DEFAULT_MAX_CLIENT_CONNECTIONS: usize = 256;
+pub const DEFAULT_CLIENT_REQUESTS_PER_SECOND: u32 = 50;
+
+/// Most client connections a node serves at once; further connections are refused
+pub fn retrieve_max_client_connections() -> usize {
+    dotenv().ok();
+
+    match env::var("MAX_CLIENT_CONNECTIONS") {
+        Ok(max_connections) => max_connections
+            .parse::<usize>()
+            .expect("MAX_CLIENT_CONNECTIONS must be a number"),
+        Err(_) => DEFAULT_MAX_CLIENT_CONNECTIONS,
+    }
+}
+
+/// Sustained request rate a single client connection may make, also its burst size
+pub fn retrieve_client_requests_per_second() -> u32 {
+    dotenv().ok();
+
+    match env::var("CLIENT_REQUESTS_PER_SECOND") {
+        Ok(rate) => rate
+            .parse::<u32>()
+            .expect("CLIENT_REQUESTS_PER_SECOND must be a number"),
+        Err(_) => DEFAULT_CLIENT_REQUESTS_PER_SECOND,
+    }
+}
+
+/// Addresses a node binds to and dials. Resolved from env vars, then CLI flags
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub listen_ip: String,
+    pub base_port: u16,
+    pub client_port: u16,
+    /// Per-node consensus address overrides (`PEER_ADDR_<i>`) for multi-host deployments
+    pub peer_addrs: Vec<Option<String>>,
+}
+
+impl NetworkConfig {
+    pub fn from_env(num_nodes: usize) -> Self {
+        dotenv().ok();
+        Self::from_lookup(num_nodes, |key| env::var(key).ok())
+    }
+
+    fn from_lookup(num_nodes: usize, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let listen_ip = lookup("LISTEN_IP").unwrap_or_else(|| DEFAULT_LISTEN_IP.to_owned());
+
+        let base_port = match lookup("BASE_PORT") {
+            Some(port) => port
+                .parse::<u16>()
+                .expect("BASE_PORT must be a port number"),
+            None => DEFAULT_BASE_PORT,
+        };
+
+        let client_port = match lookup("CLIENT_PORT") {
+            Some(port) => port
+                .parse::<u16>()
+                .expect("CLIENT_PORT must be a port number"),
+            None => DEFAULT_CLIENT_PORT,
+        };
+
+        let peer_addrs = (0..num_nodes)
+            .map(|i| lookup(&format!("PEER_ADDR_{}", i)))
+            .collect();
+
+        Self {
+            listen_ip,
+            base_port,
+            client_port,
+            peer_addrs,
+        }
+    }
+
+    /// Applies `--ip`, `--base-port` and `--client-port` flags, which take precedence over env vars.
+    /// Returns the remaining arguments
+    pub fn apply_args(&mut self, args: &[String]) -> Vec<String> {
+        let mut rest = vec![];
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_owned())),
+                None => (arg.as_str(), None),
+            };
+
+            if !matches!(flag, "--ip" | "--base-port" | "--client-port") {
+                rest.push(arg.clone());
+                continue;
+            }
+
+            let value = inline_value
+                .or_else(|| iter.next().cloned())
+                .unwrap_or_else(|| panic!("{} requires a value", flag));
+
+            match flag {
+                "--ip" => self.listen_ip = value,
+                "--base-port" => {
+                    self.base_port = value.parse().expect("--base-port must be a port number")
+                }
+                _ => self.client_port = value.parse().expect("--client-port must be a port number"),
+            }
+        }
+
+        rest
+    }
+
+    /// Address node `node_id` listens on for consensus traffic
+    pub fn consensus_listen_addr(&self, node_id: usize) -> String {
+        format!("{}:{}", self.listen_ip, self.consensus_port(node_id))
+    }
+
+    /// Address other nodes dial to reach node `node_id`
+    pub fn peer_addr(&self, node_id: usize) -> String {
+        match self.peer_addrs.get(node_id) {
+            Some(Some(addr)) => addr.clone(),
+            _ => format!("{}:{}", self.listen_ip, self.consensus_port(node_id)),
+        }
+    }
+
+    pub fn client_addr(&self) -> String {
+        format!("{}:{}", self.listen_ip, self.client_port)
+    }
+
+    fn consensus_port(&self, node_id: usize) -> u16 {
+        let offset = u16::try_from(node_id).expect("Node id out of port range");
+        self.base_port
+            .checked_add(offset)
+            .expect("BASE_PORT + node id overflows the port range")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_genesis {
+        use super::*;
+
+        fn validator_json(id: usize, seed: u8, address: &str) -> String {
+            let public_key = SigningKey::from_bytes(&[seed; 32]).verifying_key();
+            format!(
+                r#"{{"id": {}, "public_key": "{}", "address": "{}"}}"#,
+                id,
+                hex::encode(public_key.as_bytes()),
+                address
+            )
+        }
+
+        fn genesis_json(validators: &[String]) -> String {
+            format!(r#"{{"validators": [{}]}}"#, validators.join(","))
+        }
+
+        #[test]
+        fn test_parses_validators_sorted_by_id() {
+            let json = genesis_json(&[
+                validator_json(1, 2, "10.0.0.2:6401"),
+                validator_json(0, 1, "10.0.0.1:6400"),
+                validator_json(2, 3, "10.0.0.3:7000"),
+            ]);
+
+            let genesis = Genesis::from_json(&json).unwrap();
+
+            let ids: Vec<usize> = genesis.validators.iter().map(|v| v.id).collect();
+            assert_eq!(ids, vec![0, 1, 2]);
+            assert_eq!(
+                genesis.validators[1].public_key,
+                SigningKey::from_bytes(&[2; 32]).verifying_key()
+            );
+            assert_eq!(genesis.validators[2].address, "10.0.0.3:7000");
+            assert_eq!(
+                genesis.listen_addr(2, "0.0.0.0"),
+                Some("0.0.0.0:7000".to_string())
+            );
+            assert_eq!(genesis.listen_addr(3, "0.0.0.0"), None);
+        }
+
+        #[test]
+        fn test_ledger_params_default_when_left_out() {
+            let validators = [validator_json(0, 1, "10.0.0.1:6400")];
+            let genesis = Genesis::from_json(&genesis_json(&validators)).unwrap();
+            assert_eq!(genesis.ledger, LedgerParams::default());
+
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"max_open_orders": 5}}}}"#,
+                validators[0]
+            );
+            let genesis = Genesis::from_json(&json).unwrap();
+            assert_eq!(genesis.ledger.max_open_orders, 5);
+            assert_eq!(
+                genesis.ledger.level_prune_threshold,
+                DEFAULT_LEVEL_PRUNE_THRESHOLD
+            );
+
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"level_prune_threshold": 101}}}}"#,
+                validators[0]
+            );
+            assert_eq!(
+                Genesis::from_json(&json),
+                Err(GenesisError::InvalidLedgerParam("level_prune_threshold"))
+            );
+
+            let admin = SigningKey::from_bytes(&[7; 32]).verifying_key();
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"admin": "{}"}}}}"#,
+                validators[0],
+                hex::encode(admin.as_bytes())
+            );
+            let genesis = Genesis::from_json(&json).unwrap();
+            assert_eq!(genesis.ledger.admin, admin.to_bytes());
+
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"admin": "zz"}}}}"#,
+                validators[0]
+            );
+            assert_eq!(
+                Genesis::from_json(&json),
+                Err(GenesisError::InvalidLedgerParam("admin"))
+            );
+
+            let fee_collector = SigningKey::from_bytes(&[9; 32]).verifying_key();
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"taker_fee_bps": 10, "fee_collector": "{}"}}}}"#,
+                validators[0],
+                hex::encode(fee_collector.as_bytes())
+            );
+            let genesis = Genesis::from_json(&json).unwrap();
+            assert_eq!(genesis.ledger.taker_fee_bps, 10);
+            assert_eq!(genesis.ledger.fee_collector, fee_collector.to_bytes());
+
+            let json = format!(
+                r#"{{"validators": [{}], "ledger": {{"taker_fee_bps": {}}}}}"#,
+                validators[0],
+                MAX_TAKER_FEE_BPS + 1
+            );
+            assert_eq!(
+                Genesis::from_json(&json),
+                Err(GenesisError::InvalidLedgerParam("taker_fee_bps"))
+            );
+        }
+
+        #[test]
+        fn test_rejects_duplicate_ids() {
+            let json = genesis_json(&[
+                validator_json(0, 1, "10.0.0.1:6400"),
+                validator_json(0, 2, "10.0.0.2:6401"),
+            ]);
+
+            assert_eq!(Genesis::from_json(&json), Err(GenesisError::DuplicateId(0)));
+        }
+
+        #[test]
+        fn test_rejects_invalid_validator_sets() {
+            assert_eq!(
+                Genesis::from_json(&genesis_json(&[])),
+                Err(GenesisError::NoValidators)
+            );
+
+            let gap = genesis_json(&[
+                validator_json(0, 1, "10.0.0.1:6400"),
+                validator_json(2, 2, "10.0.0.2:6401"),
+            ]);
+            assert_eq!(Genesis::from_json(&gap), Err(GenesisError::MissingId(1)));
+
+            let same_key = genesis_json(&[
+                validator_json(0, 1, "10.0.0.1:6400"),
+                validator_json(1, 1, "10.0.0.2:6401"),
+            ]);
+            assert_eq!(
+                Genesis::from_json(&same_key),
+                Err(GenesisError::DuplicatePublicKey(1))
+            );
+
+            let bad_key =
+                r#"{"validators": [{"id": 0, "public_key": "zz", "address": "10.0.0.1:6400"}]}"#;
+            assert_eq!(
+                Genesis::from_json(bad_key),
+                Err(GenesisError::InvalidPublicKey(0))
+            );
+
+            assert!(matches!(
+                Genesis::from_json("{"),
+                Err(GenesisError::Parse(_))
+            ));
+        }
+    }
+
+    mod test_quorum {
+        use super::*;
+
+        #[test]
+        fn test_single_validator() {
+            assert_eq!(max_faulty_among(1), 0);
+            assert_eq!(quorum_threshold_among(1), 1);
+        }
+
+        #[test]
+        fn test_four_validators() {
+            assert_eq!(max_faulty_among(4), 1);
+            assert_eq!(quorum_threshold_among(4), 3);
+        }
+
+        #[test]
+        fn test_seven_validators() {
+            assert_eq!(max_faulty_among(7), 2);
+            assert_eq!(quorum_threshold_among(7), 5);
+        }
+
+        #[test]
+        fn test_quorum_follows_configured_validators() {
+            let num_validators = retrieve_num_validators();
+            assert_eq!(max_faulty(), max_faulty_among(num_validators));
+            assert_eq!(quorum_threshold(), 2 * max_faulty() + 1);
+        }
+    }
+
+    mod test_network_config {
+        use std::collections::HashMap;
+
+        use super::*;
+
+        fn from_vars(num_nodes: usize, vars: &[(&str, &str)]) -> NetworkConfig {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            NetworkConfig::from_lookup(num_nodes, |key| vars.get(key).cloned())
+        }
+
+        fn args(args: &[&str]) -> Vec<String> {
+            args.iter().map(|s| s.to_string()).collect()
+        }
+
+        #[test]
+        fn test_defaults() {
+            let config = from_vars(4, &[]);
+
+            assert_eq!(config.listen_ip, DEFAULT_LISTEN_IP);
+            assert_eq!(config.base_port, DEFAULT_BASE_PORT);
+            assert_eq!(config.client_port, DEFAULT_CLIENT_PORT);
+            assert_eq!(config.consensus_listen_addr(2), "127.0.0.1:6402");
+            assert_eq!(config.peer_addr(3), "127.0.0.1:6403");
+            assert_eq!(config.client_addr(), "127.0.0.1:8000");
+        }
+
+        #[test]
+        fn test_env_overrides() {
+            let config = from_vars(
+                4,
+                &[
+                    ("LISTEN_IP", "0.0.0.0"),
+                    ("BASE_PORT", "7000"),
+                    ("CLIENT_PORT", "9000"),
+                    ("PEER_ADDR_1", "10.0.0.2:7001"),
+                ],
+            );
+
+            assert_eq!(config.consensus_listen_addr(1), "0.0.0.0:7001");
+            assert_eq!(config.peer_addr(0), "0.0.0.0:7000");
+            assert_eq!(config.peer_addr(1), "10.0.0.2:7001");
+            assert_eq!(config.client_addr(), "0.0.0.0:9000");
+        }
+
+        #[test]
+        fn test_cli_flags_override_env() {
+            let mut config = from_vars(4, &[("LISTEN_IP", "0.0.0.0"), ("BASE_PORT", "7000")]);
+
+            let rest = config.apply_args(&args(&[
+                "node",
+                "--ip",
+                "192.168.1.5",
+                "1",
+                "--base-port=7100",
+                "--client-port",
+                "9100",
+            ]));
+
+            assert_eq!(rest, args(&["node", "1"]));
+            assert_eq!(config.consensus_listen_addr(1), "192.168.1.5:7101");
+            assert_eq!(config.client_addr(), "192.168.1.5:9100");
+        }
+
+        #[test]
+        #[should_panic(expected = "BASE_PORT must be a port number")]
+        fn test_invalid_port_panics() {
+            from_vars(4, &[("BASE_PORT", "70000")]);
+        }
+
+        #[test]
+        #[should_panic(expected = "--ip requires a value")]
+        fn test_flag_without_value_panics() {
+            let mut config = from_vars(4, &[]);
+            config.apply_args(&args(&["node", "--ip"]));
+        }
+    }
+}
@@ -3,8 +3,10 @@ use ed25519_dalek::{SigningKey, VerifyingKey};
 use hex::FromHex;
 use rand::rngs::OsRng;
 use std::{
+    collections::BTreeMap,
     io::{self, Write},
     sync::Arc,
+    time::Duration,
 };
 use tokio::{net::tcp::OwnedWriteHalf, sync::Mutex};
 
@@ -15,11 +17,12 @@ use crate::{
         asset::{Asset, AssetId},
         order::{LimitOrder, OrderDirection, OrderId, OrderType},
         spot_clearinghouse::{AccountBalance, MarketId},
-        spot_market::{LevelInfo, MarketInfo},
+        spot_market::{LevelInfo, MarketInfo, OrderBookDepth, format_price, parse_price},
+        state::{Nonce, OrderQueryResult},
     },
     types::transaction::{
-        CancelOrderTransaction, OrderTransaction, PublicKeyString, TransactionStatus,
-        TransferTransaction, UnsignedTransaction,
+        CancelOrderTransaction, OrderTransaction, PublicKeyHash, PublicKeyString, Sha256Hash,
+        TransactionStatus, TransferTransaction, UnsignedTransaction,
     },
 };
 
@@ -44,6 +47,7 @@ impl ClientAccount {
 }
 
 const ANSI_ESC: &str = "\x1B[2J\x1B[1;1H";
+const DEFAULT_ORDERBOOK_DEPTH: usize = 10;
 
 fn prompt_confirmation() -> std::io::Result<bool> {
     print!("Confirm order? (y/n): ");
@@ -104,29 +108,6 @@ fn format_last_executed_price(
     }
 }
 
-fn parse_price_to_multiple(price: f64, tick_size: u32, tick_decimals: u8) -> Option<u64> {
-    let factor = 10u64.pow(tick_decimals as u32) as f64;
-    let raw_units = (price * factor).round() as u64;
-    let tick_size = tick_size as u64;
-    if raw_units % tick_size != 0 {
-        None // not an exact multiple
-    } else {
-        Some(raw_units / tick_size)
-    }
-}
-
-fn format_price(price_multiple: u64, tick_size: u32, tick_decimals: u8) -> String {
-    let raw_price_units = price_multiple * tick_size as u64;
-    let factor = 10u64.pow(tick_decimals as u32);
-    let whole = raw_price_units / factor;
-    let fractional = raw_price_units % factor;
-    format!(
-        "{}.{}",
-        whole,
-        format!("{:0>width$}", fractional, width = tick_decimals as usize)
-    )
-}
-
 fn format_level(
     level: &Option<LevelInfo>,
     tick_size: u32,
@@ -142,6 +123,60 @@ fn format_level(
     }
 }
 
+/// Pairs each level with the cumulative volume from the best price up to and including it
+fn with_cumulative_volume(levels: &[LevelInfo]) -> Vec<(&LevelInfo, u64)> {
+    let mut cumulative = 0;
+    levels
+        .iter()
+        .map(|level| {
+            cumulative += level.volume;
+            (level, cumulative)
+        })
+        .collect()
+}
+
+/// Prints asks above the spread (best ask lowest) and bids below it (best bid highest)
+fn display_order_book(
+    order_book: &OrderBookDepth,
+    market_info: &MarketInfo,
+    base_asset_info: &Asset,
+) {
+    let tick_size = market_info.tick;
+    let tick_decimals = market_info.tick_decimals;
+    let lot_size = base_asset_info.lot_size;
+    let decimals = base_asset_info.decimals;
+
+    let format_row = |level: &LevelInfo, cumulative: u64| {
+        format!(
+            "{:>15} {:>15} {:>15}",
+            format_price(level.price, tick_size, tick_decimals),
+            lots_to_amount(level.volume, lot_size, decimals),
+            lots_to_amount(cumulative, lot_size, decimals),
+        )
+    };
+
+    println!("Order Book: {}", market_info.market_name.blue());
+    println!("{:>15} {:>15} {:>15}", "Price", "Size", "Total");
+
+    for (level, cumulative) in with_cumulative_volume(&order_book.asks).into_iter().rev() {
+        println!("{}", format_row(level, cumulative).red());
+    }
+
+    let spread = match (order_book.bids.first(), order_book.asks.first()) {
+        (Some(bid), Some(ask)) => format_price(
+            ask.price.saturating_sub(bid.price),
+            tick_size,
+            tick_decimals,
+        ),
+        _ => "None".to_string(),
+    };
+    println!("{:>15} {:>15}", "Spread", spread);
+
+    for (level, cumulative) in with_cumulative_volume(&order_book.bids) {
+        println!("{}", format_row(level, cumulative).green());
+    }
+}
+
 fn display_spot_balances(asset_infos: &Vec<Asset>, spot_balance: AccountBalance) {
     for token_balance in &spot_balance.asset_balances {
         let Some(asset) = asset_infos.get(token_balance.asset_id as usize) else {
@@ -157,7 +192,7 @@ fn display_spot_balances(asset_infos: &Vec<Asset>, spot_balance: AccountBalance)
 
         println!(
             "Asset: {}, Total: {}, Available: {}",
-            asset.asset_name.blue(),
+            asset.display_symbol().blue(),
             total_balance.blue(),
             available_balance.blue(),
         );
@@ -237,13 +272,16 @@ fn display_user_available_balance(
     let quote_amount = format_asset_balance(quote_available, quote_asset_info.decimals).blue();
     println!(
         "Available: {} {} | {} {}",
-        base_amount, base_asset_info.asset_name, quote_amount, quote_asset_info.asset_name
+        base_amount,
+        base_asset_info.display_symbol(),
+        quote_amount,
+        quote_asset_info.display_symbol()
     );
     println!();
 }
 
 fn display_open_orders(
-    open_orders: &Vec<LimitOrder>,
+    open_orders: &BTreeMap<OrderId, LimitOrder>,
     tick_size: u32,
     tick_decimals: u8,
     base_asset_info: &Asset,
@@ -254,7 +292,7 @@ fn display_open_orders(
         "OrderID", "Side", "Price", "Size", "Filled", "Self Filled"
     );
 
-    for order in open_orders {
+    for order in open_orders.values() {
         let side = match order.common.direction {
             OrderDirection::Buy => "Buy".green(),
             OrderDirection::Sell => "Sell".red(),
@@ -278,6 +316,51 @@ fn display_open_orders(
     }
 }
 
+/// Lists open orders across all markets with their remaining size
+fn display_account_orders(
+    open_orders: &BTreeMap<OrderId, LimitOrder>,
+    markets: &[MarketInfo],
+    asset_infos: &[Asset],
+) {
+    println!("Open Orders:");
+    println!(
+        "{:<10} {:<12} {:<6} {:<15} {:<12}",
+        "OrderID", "Market", "Side", "Price", "Remaining"
+    );
+
+    for order in open_orders.values() {
+        let side = match order.common.direction {
+            OrderDirection::Buy => "Buy".green(),
+            OrderDirection::Sell => "Sell".red(),
+        };
+
+        let market = markets
+            .iter()
+            .find(|market| market.market_id == order.common.market_id);
+        let base_asset = market.and_then(|market| asset_infos.get(market.base_asset_id as usize));
+        let (Some(market), Some(base_asset)) = (market, base_asset) else {
+            println!("Could not find market info for order: {}", order.common.id);
+            continue;
+        };
+
+        let price = format_price(order.price_multiple, market.tick, market.tick_decimals);
+        let remaining = lots_to_amount(
+            order.get_order_remaining(),
+            base_asset.lot_size,
+            base_asset.decimals,
+        );
+
+        println!(
+            "{:<10} {:<12} {:<6} {:<15} {:<12}",
+            order.common.id, market.market_name, side, price, remaining,
+        );
+    }
+
+    if open_orders.is_empty() {
+        println!("No open orders.");
+    }
+}
+
 fn handle_help() {
     println!("{}", "Commands:".green());
     println!(
@@ -292,6 +375,13 @@ fn handle_help() {
     );
     println!("{}   {}", "  drip".blue(), "Request balance from faucet");
     println!("{}", "  transfer <to> <amount>".blue());
+    println!("{}   List your open orders", "  orders".blue());
+    println!("{}   Cancel an open order", "  cancel <order_id>".blue());
+    println!(
+        "{}   Shows the order book ladder, {} levels per side by default",
+        "  orderbook <market_id> [depth]".blue(),
+        DEFAULT_ORDERBOOK_DEPTH
+    );
     println!("{}", "  quit, q".blue());
 }
 
@@ -337,6 +427,186 @@ async fn handle_drip(
     Ok(())
 }
 
+/// Parses `orderbook <market_id> [depth]`, defaulting the depth when omitted
+fn parse_orderbook_command(trimmed: &str) -> Option<(MarketId, usize)> {
+    let parts: Vec<&str> = trimmed.split_whitespace().collect();
+    let (market_id, depth) = match parts.as_slice() {
+        ["orderbook", market_id] => (market_id.parse().ok()?, DEFAULT_ORDERBOOK_DEPTH),
+        ["orderbook", market_id, depth] => (market_id.parse().ok()?, depth.parse().ok()?),
+        _ => return None,
+    };
+
+    if depth == 0 {
+        return None;
+    }
+    Some((market_id, depth))
+}
+
+async fn handle_orderbook(
+    trimmed: &str,
+    client_connection: &ClientConnection,
+    asset_infos: &[Asset],
+) -> std::io::Result<()> {
+    let Some((market_id, depth)) = parse_orderbook_command(trimmed) else {
+        println!("Usage: orderbook <market_id> [depth]");
+        return Ok(());
+    };
+
+    let market_info = message_protocol::send_market_info_query(
+        market_id,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+
+    let order_book = message_protocol::send_order_book_query(
+        market_id,
+        depth,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+
+    let (Some(market_info), Some(order_book)) = (market_info, order_book) else {
+        println!("No market with ID {}", market_id);
+        return Ok(());
+    };
+
+    let Some(base_asset_info) = asset_infos.get(market_info.base_asset_id as usize) else {
+        println!(
+            "Error fetching Asset data for {}",
+            market_info.base_asset_id
+        );
+        return Ok(());
+    };
+
+    display_order_book(&order_book, &market_info, base_asset_info);
+    Ok(())
+}
+
+/// Parses `cancel <order_id>`
+fn parse_cancel_command(trimmed: &str) -> Option<OrderId> {
+    match trimmed.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["cancel", order_id] => order_id.parse().ok(),
+        _ => None,
+    }
+}
+
+fn cancel_order_payload(
+    from: PublicKeyHash,
+    market_id: MarketId,
+    order_id: OrderId,
+    nonce: Nonce,
+) -> UnsignedTransaction {
+    UnsignedTransaction::CancelOrder(CancelOrderTransaction {
+        from,
+        market_id,
+        order_id,
+        status: TransactionStatus::Pending,
+        nonce,
+        valid_until: None,
+    })
+}
+
+/// Explains why an order that isn't open can't be cancelled
+fn cancel_rejection_reason(order_id: OrderId, order: Option<&OrderQueryResult>) -> String {
+    match order {
+        Some(result) => format!(
+            "Cannot cancel order {}: order is {:?}",
+            order_id,
+            result.order.get_status()
+        ),
+        None => format!("Cannot cancel order {}: order not found", order_id),
+    }
+}
+
+async fn handle_orders(
+    client: &Option<ClientAccount>,
+    client_connection: &ClientConnection,
+    asset_infos: &[Asset],
+) -> std::io::Result<()> {
+    let Some(client) = client else {
+        println!("Please create or load an account.");
+        return Ok(());
+    };
+
+    let account_info_with_balances = message_protocol::send_account_query(
+        client.pk_str.to_bytes(),
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+
+    let markets = message_protocol::send_markets_query(
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+
+    display_account_orders(
+        &account_info_with_balances.account_info.open_orders,
+        &markets,
+        asset_infos,
+    );
+    Ok(())
+}
+
+async fn handle_cancel(
+    trimmed: &str,
+    client: &mut Option<ClientAccount>,
+    client_connection: &ClientConnection,
+) -> std::io::Result<()> {
+    let Some(client) = client else {
+        println!("Please create or load an account.");
+        return Ok(());
+    };
+
+    let Some(order_id) = parse_cancel_command(trimmed) else {
+        println!("Usage: cancel <order_id>");
+        return Ok(());
+    };
+
+    let account_info = message_protocol::send_account_query(
+        client.pk_str.to_bytes(),
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?
+    .account_info;
+
+    let open_order = account_info.open_orders.get(&order_id);
+
+    let Some(open_order) = open_order else {
+        let order = message_protocol::send_order_query(
+            client.pk_str.to_bytes(),
+            order_id,
+            client_connection.reader.clone(),
+            client_connection.writer.clone(),
+        )
+        .await?;
+        println!("{}", cancel_rejection_reason(order_id, order.as_ref()));
+        return Ok(());
+    };
+
+    let txn = cancel_order_payload(
+        client.pk_str.to_bytes(),
+        open_order.common.market_id,
+        order_id,
+        account_info.expected_nonce,
+    );
+    let tx = txn.sign(&mut client.sk);
+
+    println!("Submitting transaction... ");
+    let tx_hash = message_protocol::submit_transaction(
+        tx,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+    println!("Transaction submitted: {}", hex::encode(tx_hash));
+    Ok(())
+}
+
 fn handle_load(trimmed: &str) -> ClientAccount {
     let sk_hex = trimmed["load ".len()..].trim();
     let sk_bytes = <[u8; 32]>::from_hex(sk_hex).expect("Invalid hex");
@@ -412,11 +682,18 @@ async fn handle_transfer(
         asset_id,
         nonce: account_info.expected_nonce,
         status: TransactionStatus::Pending,
+        valid_until: None,
     });
 
     let tx = txn.sign(&mut client.sk);
 
-    message_protocol::send_transaction(client_connection.writer.clone(), tx).await?;
+    let tx_hash = message_protocol::submit_transaction(
+        tx,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+    println!("Transaction submitted: {}", hex::encode(tx_hash));
     return Ok(());
 }
 
@@ -541,13 +818,20 @@ async fn handle_limit_order(
         order_type,
         status: TransactionStatus::Pending,
         nonce: account_info.expected_nonce,
+        valid_until: None,
     });
 
     let tx = txn.sign(&mut client.sk);
 
     println!("Submitting transaction... ");
-    message_protocol::send_transaction(client_connection.writer.clone(), tx).await?;
-    println!("Transaction submitted");
+    let tx_hash = message_protocol::submit_transaction(
+        tx,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+    println!("Transaction submitted: {}", hex::encode(tx_hash));
+    report_order_id(tx_hash, client_connection).await?;
     prompt_ack()?;
     Ok(())
 }
@@ -603,17 +887,49 @@ async fn handle_market_order(
         order_type,
         status: TransactionStatus::Pending,
         nonce: account_info.expected_nonce,
+        valid_until: None,
     });
 
     let tx = txn.sign(&mut client.sk);
 
     println!("Submitting transaction... ");
-    message_protocol::send_transaction(client_connection.writer.clone(), tx).await?;
-    println!("Transaction submitted");
+    let tx_hash = message_protocol::submit_transaction(
+        tx,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+    println!("Transaction submitted: {}", hex::encode(tx_hash));
+    report_order_id(tx_hash, client_connection).await?;
     prompt_ack()?;
     Ok(())
 }
 
+const ORDER_RECEIPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for a submitted order to execute and prints the id to cancel it by
+async fn report_order_id(
+    tx_hash: Sha256Hash,
+    client_connection: &ClientConnection,
+) -> std::io::Result<()> {
+    let receipt = message_protocol::await_receipt(
+        tx_hash,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+        ORDER_RECEIPT_TIMEOUT,
+    )
+    .await?;
+
+    match receipt {
+        Some(receipt) => match receipt.order_id {
+            Some(order_id) => println!("Order {}: {:?}", order_id, receipt.status),
+            None => println!("Order was not placed: {:?}", receipt.status),
+        },
+        None => println!("Order has not executed yet, it may have been rejected"),
+    }
+    Ok(())
+}
+
 async fn handle_cancel_order(
     client: &mut ClientAccount,
     client_connection: &ClientConnection,
@@ -629,19 +945,23 @@ async fn handle_cancel_order(
 
     let account_info = account_info_with_balances.account_info;
 
-    let txn = UnsignedTransaction::CancelOrder(CancelOrderTransaction {
-        from: client.pk_str.to_bytes(),
+    let txn = cancel_order_payload(
+        client.pk_str.to_bytes(),
         market_id,
         order_id,
-        status: TransactionStatus::Pending,
-        nonce: account_info.expected_nonce,
-    });
+        account_info.expected_nonce,
+    );
 
     let tx = txn.sign(&mut client.sk);
 
     println!("Submitting transaction... ");
-    message_protocol::send_transaction(client_connection.writer.clone(), tx).await?;
-    println!("Transaction submitted");
+    let tx_hash = message_protocol::submit_transaction(
+        tx,
+        client_connection.reader.clone(),
+        client_connection.writer.clone(),
+    )
+    .await?;
+    println!("Transaction submitted: {}", hex::encode(tx_hash));
     prompt_ack()?;
     Ok(())
 }
@@ -738,7 +1058,6 @@ async fn handle_market(
 
             ["limit", "buy", price, base_amount] => {
                 let raw_amount = base_amount.parse::<f64>().unwrap_or(0.0);
-                let raw_price = price.parse::<f64>().unwrap_or(0.0);
 
                 let lot_size = base_asset_info.lot_size;
                 let decimals = base_asset_info.decimals;
@@ -754,7 +1073,7 @@ async fn handle_market(
                     continue;
                 }
 
-                let price = parse_price_to_multiple(raw_price, *tick_size, *tick_decimals);
+                let price = parse_price(price, *tick_size, *tick_decimals);
 
                 let Some(price) = price else {
                     println!("Invalid base amount");
@@ -768,7 +1087,9 @@ async fn handle_market(
 
                 println!(
                     "Limit Buy {} {} at price: {}",
-                    raw_amount, base_name, raw_price
+                    raw_amount,
+                    base_name,
+                    format_price(price, *tick_size, *tick_decimals)
                 );
 
                 if prompt_confirmation()? {
@@ -788,7 +1109,6 @@ async fn handle_market(
 
             ["limit", "sell", price, base_amount] => {
                 let raw_amount = base_amount.parse::<f64>().unwrap_or(0.0);
-                let raw_price = price.parse::<f64>().unwrap_or(0.0);
 
                 let lot_size = base_asset_info.lot_size;
                 let decimals = base_asset_info.decimals;
@@ -804,7 +1124,7 @@ async fn handle_market(
                     continue;
                 }
 
-                let price = parse_price_to_multiple(raw_price, *tick_size, *tick_decimals);
+                let price = parse_price(price, *tick_size, *tick_decimals);
 
                 let Some(price) = price else {
                     println!("Invalid base amount");
@@ -818,7 +1138,9 @@ async fn handle_market(
 
                 println!(
                     "Limit Sell {} {} at price: {}",
-                    raw_amount, base_name, raw_price
+                    raw_amount,
+                    base_name,
+                    format_price(price, *tick_size, *tick_decimals)
                 );
 
                 if prompt_confirmation()? {
@@ -996,8 +1318,135 @@ pub async fn run_console(addr: &str) -> std::io::Result<()> {
                 handle_transfer(trimmed, &mut client_account, &connection).await?
             }
             "markets" => handle_markets(&mut client_account, &connection, &asset_infos).await?,
+            "orders" => handle_orders(&client_account, &connection, &asset_infos).await?,
+            _ if trimmed.starts_with("cancel") => {
+                handle_cancel(trimmed, &mut client_account, &connection).await?
+            }
+            _ if trimmed.starts_with("orderbook") => {
+                handle_orderbook(trimmed, &connection, &asset_infos).await?
+            }
             "quit" | "q" => return Ok(()),
             _ => println!("Unknown command. Type `help` for options."),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_orderbook_command {
+        use super::*;
+
+        #[test]
+        fn test_depth_defaults_when_omitted() {
+            assert_eq!(
+                parse_orderbook_command("orderbook 1"),
+                Some((1, DEFAULT_ORDERBOOK_DEPTH))
+            );
+        }
+
+        #[test]
+        fn test_explicit_depth() {
+            assert_eq!(parse_orderbook_command("orderbook 0 25"), Some((0, 25)));
+            assert_eq!(
+                parse_orderbook_command("  orderbook   2   3 "),
+                Some((2, 3))
+            );
+        }
+
+        #[test]
+        fn test_invalid_arguments_are_rejected() {
+            assert_eq!(parse_orderbook_command("orderbook"), None);
+            assert_eq!(parse_orderbook_command("orderbook abc"), None);
+            assert_eq!(parse_orderbook_command("orderbook 1 -5"), None);
+            assert_eq!(parse_orderbook_command("orderbook 1 0"), None);
+            assert_eq!(parse_orderbook_command("orderbook 1 2 3"), None);
+        }
+
+        #[test]
+        fn test_cumulative_volume_accumulates_from_best_price() {
+            let levels = vec![
+                LevelInfo {
+                    price: 100,
+                    volume: 5,
+                },
+                LevelInfo {
+                    price: 99,
+                    volume: 3,
+                },
+                LevelInfo {
+                    price: 98,
+                    volume: 7,
+                },
+            ];
+
+            let cumulative: Vec<u64> = with_cumulative_volume(&levels)
+                .into_iter()
+                .map(|(_, total)| total)
+                .collect();
+            assert_eq!(cumulative, vec![5, 8, 15]);
+        }
+    }
+
+    mod test_cancel_command {
+        use super::*;
+        use crate::state::order::{CommonOrderFields, Order, OrderStatus};
+
+        #[test]
+        fn test_parse_cancel_command() {
+            assert_eq!(parse_cancel_command("cancel 42"), Some(42));
+            assert_eq!(parse_cancel_command("  cancel   7 "), Some(7));
+            assert_eq!(parse_cancel_command("cancel"), None);
+            assert_eq!(parse_cancel_command("cancel abc"), None);
+            assert_eq!(parse_cancel_command("cancel 1 2"), None);
+        }
+
+        #[test]
+        fn test_cancel_order_payload() {
+            let from = [3u8; 32];
+            let UnsignedTransaction::CancelOrder(cancel) = cancel_order_payload(from, 1, 42, 5)
+            else {
+                panic!("Expected cancel order transaction");
+            };
+
+            assert_eq!(cancel.from, from);
+            assert_eq!(cancel.market_id, 1);
+            assert_eq!(cancel.order_id, 42);
+            assert_eq!(cancel.nonce, 5);
+            assert_eq!(cancel.status, TransactionStatus::Pending);
+            assert_eq!(cancel.valid_until, None);
+        }
+
+        #[test]
+        fn test_cancel_rejection_reason() {
+            assert_eq!(
+                cancel_rejection_reason(9, None),
+                "Cannot cancel order 9: order not found"
+            );
+
+            let filled = OrderQueryResult {
+                order: Order::Limit(LimitOrder {
+                    common: CommonOrderFields {
+                        id: 9,
+                        market_id: 0,
+                        status: OrderStatus::Filled,
+                        account: [3u8; 32],
+                        direction: OrderDirection::Buy,
+                    },
+                    price_multiple: 100,
+                    base_lots: 10,
+                    filled_base_lots: 10,
+                    self_filled: 0,
+                    priority: 9,
+                    all_or_none: false,
+                }),
+                remaining_base_lots: 0,
+            };
+            assert_eq!(
+                cancel_rejection_reason(9, Some(&filled)),
+                "Cannot cancel order 9: order is Filled"
+            );
+        }
+    }
+}
@@ -1,10 +1,14 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock, RwLockReadGuard},
+    sync::{Arc, OnceLock, RwLock},
     vec,
 };
 
-use crate::types::transaction::{Sha256Hash, SignedTransaction};
+use crate::{
+    config,
+    state::state::LedgerState,
+    types::transaction::{PublicKeyHash, Sha256Hash, SignedTransaction},
+};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -22,6 +26,10 @@ pub enum Block {
         view_number: ViewNumber,
         justify: QuorumCertificate,
         merkle_root: Sha256Hash,
+        // initial validator keys, indexed by node id
+        validators: Vec<PublicKeyHash>,
+        // state root of the initial ledger
+        state_root: Sha256Hash,
     },
     Normal {
         parent_id: BlockHash,
@@ -29,6 +37,9 @@ pub enum Block {
         view_number: ViewNumber,
         justify: QuorumCertificate,
         merkle_root: Sha256Hash,
+        // ledger state root of the proposer after executing `executed_block_hash`
+        state_root: Sha256Hash,
+        executed_block_hash: BlockHash,
         // proposer: PublicKeyString,
         // block_hash: TODO
     },
@@ -38,56 +49,71 @@ pub enum Block {
 struct HashableBlock {
     parent_id: BlockHash,
     merkle_root: Sha256Hash,
+    state_root: Sha256Hash,
+    executed_block_hash: BlockHash,
     view_number: ViewNumber,
 }
 
+#[derive(Serialize)]
+struct HashableGenesis<'a> {
+    validators: &'a [PublicKeyHash],
+    state_root: Sha256Hash,
+}
+
 impl Block {
     pub fn create_leaf(
         parent: &Block,
         transactions: Vec<SignedTransaction>,
         view_number: ViewNumber,
         justify: QuorumCertificate,
+        state_root: Sha256Hash,
+        executed_block_hash: BlockHash,
     ) -> Self {
         return Self::Normal {
             parent_id: parent.hash(),
             view_number,
             justify,
             merkle_root: Self::hash_transactions(&transactions),
+            state_root,
+            executed_block_hash,
             transactions: transactions,
         };
     }
 
+    /// Whether the locked block, certified in `locked_view`, is an ancestor of this block.
+    /// Views increase along a branch, so the walk up `parent_id`s stops at the locked view
     pub fn extends_from(
         &self,
         locked_block_hash: BlockHash,
+        locked_view: ViewNumber,
         block_store: &HashMap<BlockHash, Arc<RwLock<Block>>>,
     ) -> bool {
-        let mut current = self;
-        let mut parent: RwLockReadGuard<'_, Block>;
-
-        // check 3 parents up
-        for _ in 0..3 {
-            match current {
-                Block::Genesis { .. } => {
-                    return false;
-                }
-                Block::Normal { parent_id, .. } => {
-                    if *parent_id == locked_block_hash {
-                        return true;
-                    }
-                    let temp = match block_store.get(parent_id) {
-                        Some(node) => node.read().unwrap(),
-                        None => {
-                            return false;
-                        } // missing parent, unsafe
-                    };
-
-                    parent = temp;
-                    current = &parent;
-                }
+        let Block::Normal { parent_id, .. } = self else {
+            return false;
+        };
+        let mut parent_id = *parent_id;
+
+        loop {
+            if parent_id == locked_block_hash {
+                return true;
+            }
+            let Some(parent) = block_store.get(&parent_id) else {
+                // missing parent, unsafe
+                return false;
+            };
+
+            let parent = parent.read().unwrap();
+            if parent.view_number() <= locked_view {
+                // passed the locked view on another branch
+                return false;
+            }
+            match &*parent {
+                Block::Genesis { .. } => return false,
+                Block::Normal {
+                    parent_id: next, ..
+                } => parent_id = *next,
             }
         }
-        false
     }
 
     pub fn compute_merkle_root(mut hashes: Vec<[u8; 32]>) -> Sha256Hash {
@@ -137,16 +163,24 @@ impl Block {
 
     pub fn hash(&self) -> BlockHash {
         match self {
-            Self::Genesis { .. } => Sha256::digest(b"GENESIS").into(),
+            Self::Genesis {
+                validators,
+                state_root,
+                ..
+            } => Self::hash_genesis(validators, *state_root),
             Self::Normal {
                 parent_id,
                 view_number,
                 merkle_root,
+                state_root,
+                executed_block_hash,
                 ..
             } => {
                 let hashable = HashableBlock {
                     parent_id: *parent_id,
                     merkle_root: *merkle_root,
+                    state_root: *state_root,
+                    executed_block_hash: *executed_block_hash,
                     view_number: *view_number,
                 };
 
@@ -161,16 +195,59 @@ impl Block {
         transactions
     }
 
+    /// A no-op block, proposed to keep the chain and its QCs moving while the mempool is empty
+    pub fn is_empty(&self) -> bool {
+        self.transactions().is_empty()
+    }
+
     pub fn transactions_mut(&mut self) -> &mut Vec<SignedTransaction> {
         let (Block::Genesis { transactions, .. } | Block::Normal { transactions, .. }) = self;
         transactions
     }
 
+    pub fn view_number(&self) -> ViewNumber {
+        let (Block::Genesis { view_number, .. } | Block::Normal { view_number, .. }) = self;
+        *view_number
+    }
+
+    /// Hash of the block this one extends, genesis has none
+    pub fn parent_id(&self) -> Option<BlockHash> {
+        match self {
+            Block::Genesis { .. } => None,
+            Block::Normal { parent_id, .. } => Some(*parent_id),
+        }
+    }
+
     pub fn merkle_root(&self) -> Sha256Hash {
         let (Block::Genesis { merkle_root, .. } | Block::Normal { merkle_root, .. }) = self;
         *merkle_root
     }
 
+    fn hash_genesis(validators: &[PublicKeyHash], state_root: Sha256Hash) -> BlockHash {
+        let hashable = HashableGenesis {
+            validators,
+            state_root,
+        };
+        let encoded = bincode::serialize(&hashable).unwrap();
+        Sha256::digest(&encoded).into()
+    }
+
+    fn genesis_validators() -> Vec<PublicKeyHash> {
+        config::retrieve_validator_keys()
+            .iter()
+            .map(|key| key.to_bytes())
+            .collect()
+    }
+
+    /// Hash of the genesis block for the configured validators and initial ledger
+    pub fn genesis_hash() -> BlockHash {
+        static GENESIS_HASH: OnceLock<BlockHash> = OnceLock::new();
+        *GENESIS_HASH.get_or_init(|| {
+            Self::hash_genesis(&Self::genesis_validators(), LedgerState::new().state_root())
+        })
+    }
+
+    /// The genesis block every chain extends, justified by the genesis QC
     pub fn create_genesis_block() -> (Block, QuorumCertificate) {
         let qc = crypto::QuorumCertificate::create_genesis_qc();
 
@@ -179,11 +256,59 @@ impl Block {
             justify: qc.clone(),
             transactions: vec![],
             merkle_root: Sha256Hash::default(),
+            validators: Self::genesis_validators(),
+            state_root: LedgerState::new().state_root(),
         };
         return (genesis, qc);
     }
 }
 
+/// Why a block was refused for being too big
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedBlock {
+    TooManyTransactions(usize),
+    TooLarge(u64),
+}
+
+/// Upper bounds on the blocks a replica will vote for, so a leader cannot stall validators
+/// with a gigantic proposal
+#[derive(Debug, Clone, Copy)]
+pub struct BlockLimits {
+    pub max_transactions: usize,
+    pub max_size: u64,
+}
+
+impl BlockLimits {
+    pub fn new(max_transactions: usize, max_size: u64) -> Self {
+        Self {
+            max_transactions,
+            max_size,
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(
+            config::retrieve_max_block_transactions(),
+            config::retrieve_max_block_size(),
+        )
+    }
+
+    /// Checks the transaction count first, so oversized blocks are usually refused without
+    /// serializing them
+    pub fn check(&self, block: &Block) -> Result<(), OversizedBlock> {
+        let transactions = block.transactions().len();
+        if transactions > self.max_transactions {
+            return Err(OversizedBlock::TooManyTransactions(transactions));
+        }
+
+        let size = bincode::serialized_size(block).unwrap_or(u64::MAX);
+        if size > self.max_size {
+            return Err(OversizedBlock::TooLarge(size));
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -273,4 +398,113 @@ mod tests {
 
         assert_ne!(root1, root2);
     }
+
+    #[test]
+    fn test_genesis_carries_validators_and_initial_state_root() {
+        let (genesis, _) = Block::create_genesis_block();
+        let Block::Genesis {
+            validators,
+            state_root,
+            ..
+        } = &genesis
+        else {
+            panic!("Expected genesis block");
+        };
+
+        assert_eq!(validators.len(), config::retrieve_num_validators());
+        assert_eq!(validators[0], config::retrieve_verifying_key(0).to_bytes());
+        assert_eq!(*state_root, LedgerState::new().state_root());
+        assert_eq!(genesis.hash(), Block::genesis_hash());
+    }
+
+    #[test]
+    fn test_first_block_is_justified_by_genesis() {
+        let (genesis, genesis_qc) = Block::create_genesis_block();
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let block = Block::create_leaf(
+            &genesis,
+            vec![],
+            1,
+            genesis_qc.clone(),
+            LedgerState::new().state_root(),
+            genesis.hash(),
+        );
+        let Block::Normal {
+            parent_id, justify, ..
+        } = &block
+        else {
+            panic!("Expected normal block");
+        };
+
+        assert_eq!(*parent_id, genesis.hash());
+        assert_eq!(justify.block_hash, genesis.hash());
+        assert!(justify.is_genesis());
+        assert!(justify.verify(&validator_set, quorum));
+
+        // an unsigned view 0 QC only stands in for a quorum when it certifies genesis
+        let mut forged = genesis_qc;
+        forged.block_hash = block.hash();
+        assert!(!forged.verify(&validator_set, quorum));
+    }
+
+    mod test_extends_from {
+        use super::*;
+
+        fn store(blocks: &[&Block]) -> HashMap<BlockHash, Arc<RwLock<Block>>> {
+            blocks
+                .iter()
+                .map(|block| (block.hash(), Arc::new(RwLock::new((*block).clone()))))
+                .collect()
+        }
+
+        fn child(parent: &Block, view: ViewNumber) -> Block {
+            Block::create_leaf(
+                parent,
+                vec![],
+                view,
+                QuorumCertificate::mock(view - 1),
+                [0; 32],
+                [0; 32],
+            )
+        }
+
+        #[test]
+        fn test_extends_lock_several_blocks_back() {
+            let (genesis, _) = Block::create_genesis_block();
+            let mut chain = vec![child(&genesis, 1)];
+            for view in 2..=6 {
+                chain.push(child(chain.last().unwrap(), view));
+            }
+            let block_store = store(&chain.iter().collect::<Vec<_>>());
+
+            assert!(chain[5].extends_from(chain[0].hash(), 1, &block_store));
+            assert!(chain[5].extends_from(genesis.hash(), 0, &block_store));
+            assert!(!chain[0].extends_from(chain[5].hash(), 6, &block_store));
+        }
+
+        #[test]
+        fn test_fork_does_not_extend_lock() {
+            let (genesis, _) = Block::create_genesis_block();
+            let first = child(&genesis, 1);
+            let locked = child(&first, 2);
+            let fork_root = child(&first, 3);
+            let fork_tip = child(&fork_root, 4);
+            let block_store = store(&[&first, &locked, &fork_root]);
+
+            assert!(!fork_tip.extends_from(locked.hash(), 2, &block_store));
+            assert!(fork_tip.extends_from(first.hash(), 1, &block_store));
+        }
+
+        #[test]
+        fn test_missing_ancestor_does_not_extend_lock() {
+            let (genesis, _) = Block::create_genesis_block();
+            let first = child(&genesis, 1);
+            let second = child(&first, 2);
+            let third = child(&second, 3);
+
+            assert!(!third.extends_from(first.hash(), 1, &store(&[&first])));
+        }
+    }
 }
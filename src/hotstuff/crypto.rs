@@ -5,9 +5,13 @@ use ed25519_dalek::VerifyingKey;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::types::transaction::Sha256Hash;
+use crate::{log_warn, types::transaction::Sha256Hash};
 
-use super::{block::BlockHash, hexstring, replica::ViewNumber};
+use super::{
+    block::{Block, BlockHash},
+    hexstring, message,
+    replica::ViewNumber,
+};
 
 pub type PartialSigHash = Sha256Hash;
 pub type QuorumCertificateHash = Sha256Hash;
@@ -72,15 +76,10 @@ pub struct QuorumCertificate {
 }
 
 impl QuorumCertificate {
-    const GENESIS_BLOCK_HASH: [u8; 32] = [
-        144, 17, 49, 216, 56, 177, 122, 172, 15, 120, 133, 184, 30, 3, 203, 220, 159, 81, 87, 160,
-        3, 67, 211, 10, 178, 32, 131, 104, 94, 209, 65, 106,
-    ];
-
     pub(crate) fn create_genesis_qc() -> QuorumCertificate {
         QuorumCertificate {
             view_number: 0,
-            block_hash: Self::GENESIS_BLOCK_HASH,
+            block_hash: Block::genesis_hash(),
             message_hash: [0u8; 32],
             partial_sigs: vec![],
         }
@@ -106,14 +105,28 @@ impl QuorumCertificate {
         }
     }
 
+    /// The unsigned QC certifying the genesis block, accepted without a quorum
+    pub fn is_genesis(&self) -> bool {
+        self.view_number == 0
+            && self.partial_sigs.is_empty()
+            && self.block_hash == Block::genesis_hash()
+    }
+
     pub fn verify(&self, validator_set: &HashSet<VerifyingKey>, quorum_size: usize) -> bool {
-        let mut unique_signers = HashSet::new();
+        self.is_genesis() || self.valid_signers(validator_set).len() >= quorum_size
+    }
 
-        let mut valid_sig_count = 0;
+    /// Known validators with a valid signature over the vote for the certified block
+    pub fn valid_signers<'a>(
+        &'a self,
+        validator_set: &HashSet<VerifyingKey>,
+    ) -> HashSet<&'a VerifyingKey> {
+        let mut unique_signers = HashSet::new();
+        let mut valid_signers = HashSet::new();
 
-        if self.view_number == 0 && self.block_hash == Self::GENESIS_BLOCK_HASH {
-            // Genesis QC
-            return true;
+        // signatures must be over a vote for the certified block
+        if self.message_hash != message::vote_hash(self.view_number, self.block_hash) {
+            return valid_signers;
         }
 
         for sig in &self.partial_sigs {
@@ -129,16 +142,18 @@ impl QuorumCertificate {
 
             // verify that signatures are valid
             if pk.verify_strict(&self.message_hash, &sig.signature).is_ok() {
-                valid_sig_count += 1;
+                valid_signers.insert(pk);
             } else {
-                println!(
-                    "Not valid leh {:?} {:?}. pk: {:?}",
-                    &self.message_hash, &sig.signature, pk,
+                log_warn!(
+                    "Invalid signature in quorum certificate {:?} {:?}. pk: {:?}",
+                    &self.message_hash,
+                    &sig.signature,
+                    pk,
                 )
             }
         }
 
-        valid_sig_count >= quorum_size
+        valid_signers
     }
 
     pub fn hash(&self) -> QuorumCertificateHash {
@@ -156,6 +171,67 @@ impl QuorumCertificate {
     }
 }
 
+/// Proof that a quorum of validators timed out in `view_number`, allowing the view to
+/// advance without a QC. Carries the highest QC reported by the timed out validators
+/// so the next leader can extend it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeoutCertificate {
+    pub(crate) view_number: ViewNumber,
+    pub(crate) high_qc: QuorumCertificate,
+    pub(crate) partial_sigs: Vec<PartialSig>,
+}
+
+impl TimeoutCertificate {
+    pub fn from_signatures(
+        view_number: ViewNumber,
+        high_qc: QuorumCertificate,
+        partial_sigs: Vec<&PartialSig>,
+    ) -> Self {
+        assert!(
+            !partial_sigs.is_empty(),
+            "from_signatures requires at least one partial signature"
+        );
+        let partial_sigs: Vec<PartialSig> = partial_sigs.into_iter().cloned().collect();
+
+        TimeoutCertificate {
+            view_number,
+            high_qc,
+            partial_sigs,
+        }
+    }
+
+    pub fn verify(&self, validator_set: &HashSet<VerifyingKey>, quorum_size: usize) -> bool {
+        if self.high_qc.view_number > self.view_number {
+            return false;
+        }
+
+        if !self.high_qc.verify(validator_set, quorum_size) {
+            return false;
+        }
+
+        let message_hash = message::timeout_hash(self.view_number);
+        let mut unique_signers = HashSet::new();
+        let mut valid_sig_count = 0;
+
+        for sig in &self.partial_sigs {
+            let pk = &sig.signer_id;
+            if !validator_set.contains(pk) {
+                continue;
+            }
+
+            if !unique_signers.insert(pk) {
+                continue;
+            }
+
+            if pk.verify_strict(&message_hash, &sig.signature).is_ok() {
+                valid_sig_count += 1;
+            }
+        }
+
+        valid_sig_count >= quorum_size
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -164,11 +240,36 @@ mod tests {
     use ed25519_dalek::SigningKey;
 
     use crate::{
-        hotstuff::{crypto::PartialSig, replica::ViewNumber},
+        config,
+        hotstuff::{crypto::PartialSig, message::vote_hash, replica::ViewNumber},
         types::transaction::Sha256Hash,
     };
 
-    use super::QuorumCertificate;
+    use super::{Block, QuorumCertificate, TimeoutCertificate};
+
+    /// QC for `block_hash` signed by the configured validators with the given ids
+    fn create_validator_qc(
+        view_number: ViewNumber,
+        block_hash: [u8; 32],
+        signer_ids: &[usize],
+    ) -> QuorumCertificate {
+        let message_hash = vote_hash(view_number, block_hash);
+        let partial_sigs = signer_ids
+            .iter()
+            .map(|&id| {
+                let mut sk = config::retrieve_signing_key(id);
+                let sig = sk.sign(&message_hash);
+                PartialSig::new(sk.verifying_key(), sig)
+            })
+            .collect();
+
+        QuorumCertificate {
+            view_number,
+            block_hash,
+            message_hash,
+            partial_sigs,
+        }
+    }
 
     impl QuorumCertificate {
         pub fn mock(view_number: ViewNumber) -> Self {
@@ -185,13 +286,7 @@ mod tests {
     fn test_create_genesis_qc_defaults() {
         let qc = QuorumCertificate::create_genesis_qc();
         assert_eq!(qc.view_number, 0);
-        assert_eq!(
-            qc.block_hash,
-            [
-                144, 17, 49, 216, 56, 177, 122, 172, 15, 120, 133, 184, 30, 3, 203, 220, 159, 81,
-                87, 160, 3, 67, 211, 10, 178, 32, 131, 104, 94, 209, 65, 106,
-            ]
-        );
+        assert_eq!(qc.block_hash, Block::create_genesis_block().0.hash());
         assert_eq!(qc.message_hash, [0u8; 32]);
         assert!(qc.partial_sigs.is_empty());
     }
@@ -200,7 +295,7 @@ mod tests {
     fn test_verify_qc_with_valid_sigs() {
         let mut sk1 = SigningKey::generate(&mut rand::rngs::OsRng);
         let pk1 = sk1.verifying_key();
-        let message_hash = Sha256Hash::from([2u8; 32]);
+        let message_hash = vote_hash(5, [3u8; 32]);
         let sig1 = sk1.sign(&message_hash);
 
         let mut sk2 = SigningKey::generate(&mut rand::rngs::OsRng);
@@ -267,6 +362,90 @@ mod tests {
         assert!(!qc.verify(&validator_set, 2));
     }
 
+    #[test]
+    fn test_verify_validator_qc_with_quorum() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let qc = create_validator_qc(4, [7u8; 32], &[0, 1, 2]);
+        assert!(qc.verify(&validator_set, quorum));
+    }
+
+    #[test]
+    fn test_verify_rejects_under_quorum_qc() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let qc = create_validator_qc(4, [7u8; 32], &[0, 1]);
+        assert!(!qc.verify(&validator_set, quorum));
+    }
+
+    #[test]
+    fn test_verify_rejects_duplicate_signer_qc() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        // signer 1 appears twice, only 2 distinct validators
+        let qc = create_validator_qc(4, [7u8; 32], &[0, 1, 1]);
+        assert!(!qc.verify(&validator_set, quorum));
+    }
+
+    #[test]
+    fn test_verify_rejects_qc_for_different_block() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let mut qc = create_validator_qc(4, [7u8; 32], &[0, 1, 2]);
+        qc.block_hash = [8u8; 32];
+        assert!(!qc.verify(&validator_set, quorum));
+    }
+
+    fn create_validator_tc(view_number: ViewNumber, signer_ids: &[usize]) -> TimeoutCertificate {
+        let message_hash = crate::hotstuff::message::timeout_hash(view_number);
+        let signatures: Vec<PartialSig> = signer_ids
+            .iter()
+            .map(|&id| {
+                let mut sk = config::retrieve_signing_key(id);
+                let sig = sk.sign(&message_hash);
+                PartialSig::new(sk.verifying_key(), sig)
+            })
+            .collect();
+
+        TimeoutCertificate::from_signatures(
+            view_number,
+            QuorumCertificate::create_genesis_qc(),
+            signatures.iter().collect(),
+        )
+    }
+
+    #[test]
+    fn test_verify_tc_with_quorum() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let tc = create_validator_tc(3, &[0, 2, 3]);
+        assert!(tc.verify(&validator_set, quorum));
+    }
+
+    #[test]
+    fn test_verify_rejects_under_quorum_tc() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let tc = create_validator_tc(3, &[0, 2, 2]);
+        assert!(!tc.verify(&validator_set, quorum));
+    }
+
+    #[test]
+    fn test_verify_rejects_tc_signed_for_other_view() {
+        let validator_set = config::retrieve_validator_set();
+        let quorum = config::quorum_threshold();
+
+        let mut tc = create_validator_tc(3, &[0, 2, 3]);
+        tc.view_number = 4;
+        assert!(!tc.verify(&validator_set, quorum));
+    }
+
     #[test]
     fn test_signature_serialization_round_trip() {
         let mut signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
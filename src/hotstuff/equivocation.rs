@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::types::transaction::PublicKeyHash;
+
+use super::{message::HotStuffMessage, replica::ViewNumber};
+
+/// Proof that a validator signed votes for two different blocks in the same view.
+#[derive(Debug, Clone)]
+pub struct EquivocationEvidence {
+    pub view: ViewNumber,
+    pub signer: PublicKeyHash,
+    pub first_vote: HotStuffMessage,
+    pub second_vote: HotStuffMessage,
+}
+
+/// Remembers the first vote seen from each validator per view and records evidence
+/// whenever a validator is seen voting for a different block in the same view.
+pub struct EquivocationDetector {
+    votes: BTreeMap<ViewNumber, HashMap<PublicKeyHash, HotStuffMessage>>,
+    pub evidence: Vec<EquivocationEvidence>,
+}
+
+impl EquivocationDetector {
+    pub fn new() -> Self {
+        Self {
+            votes: BTreeMap::new(),
+            evidence: vec![],
+        }
+    }
+
+    /// Records a vote, returning the evidence if it conflicts with an earlier vote.
+    /// Votes with invalid signatures are ignored so evidence cannot be forged.
+    pub fn observe(&mut self, msg: &HotStuffMessage) -> Option<&EquivocationEvidence> {
+        let HotStuffMessage::Vote {
+            node,
+            partial_sig,
+            view,
+            ..
+        } = msg
+        else {
+            return None;
+        };
+
+        if partial_sig
+            .signer_id
+            .verify_strict(&msg.hash(), &partial_sig.signature)
+            .is_err()
+        {
+            return None;
+        }
+
+        let signer = partial_sig.signer_id.to_bytes();
+        let view_votes = self.votes.entry(*view).or_default();
+
+        let Some(first_vote) = view_votes.get(&signer) else {
+            view_votes.insert(signer, msg.clone());
+            return None;
+        };
+
+        let HotStuffMessage::Vote {
+            node: first_node, ..
+        } = first_vote
+        else {
+            return None;
+        };
+
+        if first_node.hash() == node.hash() {
+            return None;
+        }
+
+        self.evidence.push(EquivocationEvidence {
+            view: *view,
+            signer,
+            first_vote: first_vote.clone(),
+            second_vote: msg.clone(),
+        });
+        self.evidence.last()
+    }
+
+    pub fn prune_before_view(&mut self, view: ViewNumber) {
+        self.votes = self.votes.split_off(&view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hotstuff::block::Block, test_utils::test_helpers::get_alice_sk};
+
+    fn create_block(view: ViewNumber, seed: u8) -> Block {
+        let (genesis_block, genesis_qc) = Block::create_genesis_block();
+        Block::create_leaf(
+            &genesis_block,
+            vec![],
+            view,
+            genesis_qc,
+            [seed; 32],
+            genesis_block.hash(),
+        )
+    }
+
+    #[test]
+    fn test_conflicting_votes_are_captured() {
+        let mut detector = EquivocationDetector::new();
+        let mut sk = get_alice_sk();
+
+        let vote_1 = HotStuffMessage::create_vote(create_block(1, 0), 1, 0, 1, &mut sk);
+        let vote_2 = HotStuffMessage::create_vote(create_block(1, 1), 1, 0, 1, &mut sk);
+
+        assert!(detector.observe(&vote_1).is_none());
+        let evidence = detector
+            .observe(&vote_2)
+            .expect("Expected equivocation evidence");
+
+        assert_eq!(evidence.view, 1);
+        assert_eq!(evidence.signer, sk.verifying_key().to_bytes());
+        assert_eq!(evidence.first_vote, vote_1);
+        assert_eq!(evidence.second_vote, vote_2);
+        assert_eq!(detector.evidence.len(), 1);
+    }
+
+    #[test]
+    fn test_repeated_vote_is_not_equivocation() {
+        let mut detector = EquivocationDetector::new();
+        let mut sk = get_alice_sk();
+
+        let vote = HotStuffMessage::create_vote(create_block(1, 0), 1, 0, 1, &mut sk);
+
+        assert!(detector.observe(&vote).is_none());
+        assert!(detector.observe(&vote).is_none());
+        assert!(detector.evidence.is_empty());
+    }
+
+    #[test]
+    fn test_votes_in_different_views_are_not_equivocation() {
+        let mut detector = EquivocationDetector::new();
+        let mut sk = get_alice_sk();
+
+        let vote_1 = HotStuffMessage::create_vote(create_block(1, 0), 1, 0, 1, &mut sk);
+        let vote_2 = HotStuffMessage::create_vote(create_block(2, 1), 2, 0, 2, &mut sk);
+
+        assert!(detector.observe(&vote_1).is_none());
+        assert!(detector.observe(&vote_2).is_none());
+
+        detector.prune_before_view(2);
+        assert!(detector.observe(&vote_1).is_none());
+    }
+}
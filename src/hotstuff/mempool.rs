@@ -1,6 +1,10 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+};
 
 use crate::{
+    network::RecentlySeen,
     state::state::Nonce,
     types::transaction::{PublicKeyHash, SignedTransaction, UnsignedTransaction},
 };
@@ -16,6 +20,11 @@ pub type AccountQueue = BTreeMap<Nonce, SignedTransaction>;
 
 const PRIORITY_LEVELS: u8 = 3;
 
+// Number of transaction signatures remembered for deduplication
+pub const MAX_SEEN_SIGNATURES: usize = 100_000;
+
+type SignatureBytes = [u8; 64];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum Priority {
     Liquidation = 0,
@@ -25,6 +34,43 @@ enum Priority {
 
 type PriorityIndex = (PublicKeyHash, Nonce);
 
+impl Priority {
+    fn of(txn: &SignedTransaction) -> Self {
+        match &txn.tx {
+            UnsignedTransaction::Transfer(_)
+            | UnsignedTransaction::Order(_)
+            | UnsignedTransaction::BatchOrder(_)
+            | UnsignedTransaction::SetMarketStatus(_)
+            | UnsignedTransaction::TransferAll(_) => Priority::Other,
+            UnsignedTransaction::CancelOrder(_)
+            | UnsignedTransaction::CancelOrderAtPrice(_)
+            | UnsignedTransaction::ReduceOrder(_) => Priority::Cancel,
+        }
+    }
+}
+
+/// Reason a transaction was not admitted to the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    InvalidSignature,
+    StaleNonce,
+    Duplicate,
+    Expired,
+    Full,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::InvalidSignature => write!(f, "Invalid signature"),
+            MempoolError::StaleNonce => write!(f, "Stale nonce"),
+            MempoolError::Duplicate => write!(f, "Duplicate transaction"),
+            MempoolError::Expired => write!(f, "Expired"),
+            MempoolError::Full => write!(f, "Mempool full"),
+        }
+    }
+}
+
 /// PriorityMempool organizes transactions for fast block proposal selection.
 ///
 /// # Design Goals
@@ -51,56 +97,159 @@ pub struct PriorityMempool {
     /// - Liquidations and cancels are processed first.
     /// - Transfers are processed after urgent actions are handled.
     priority_buckets: [VecDeque<PriorityIndex>; PRIORITY_LEVELS as usize],
+    /// Signatures of admitted transactions, the oldest are forgotten once capacity is reached.
+    seen_signatures: RecentlySeen<SignatureBytes>,
+    /// Maximum number of queued transactions, ready or future
+    capacity: usize,
+    /// Queued transactions per priority in arrival order, used to pick eviction candidates.
+    /// Entries for transactions that have since left the mempool are skipped lazily.
+    arrival_order: [VecDeque<PriorityIndex>; PRIORITY_LEVELS as usize],
+    /// Earliest view a block including new transactions can have
+    next_view: u64,
+}
+
+fn is_queued(account_queues: &HashMap<PublicKeyHash, AccountQueue>, index: &PriorityIndex) -> bool {
+    let (pk, nonce) = index;
+    account_queues
+        .get(pk)
+        .is_some_and(|account_queue| account_queue.contains_key(nonce))
 }
 
 impl PriorityMempool {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         PriorityMempool {
             length: 0,
             ready_transactions_length: 0,
             account_queues: HashMap::new(),
             priority_buckets: Default::default(),
+            seen_signatures: RecentlySeen::new(MAX_SEEN_SIGNATURES),
+            capacity,
+            arrival_order: Default::default(),
+            next_view: 0,
         }
     }
 
-    /// We allow duplicate nonce to be added to the ready bucket, but we enforce uniqueness on execution
-    pub fn insert(&mut self, txn: SignedTransaction, expected_nonce: Nonce) {
+    /// Picks the transaction to drop to make room for one of `incoming` priority.
+    /// Only transactions that are no more urgent than the incoming one are considered,
+    /// oldest first within the least urgent priority. Within the chosen account the highest
+    /// eligible nonce goes first, so no other queued nonce of the account is stranded.
+    ///
+    /// This is where fee-based ordering will plug in once transactions carry fees.
+    fn select_eviction_candidate(&mut self, incoming: Priority) -> Option<PriorityIndex> {
+        let priorities = [Priority::Other, Priority::Cancel, Priority::Liquidation];
+
+        for priority in priorities.into_iter().filter(|p| *p >= incoming) {
+            while let Some(index) = self.arrival_order[priority as usize].pop_front() {
+                let (pk, nonce) = index;
+                let Some(account_queue) = self.account_queues.get(&pk) else {
+                    continue;
+                };
+                if !account_queue.contains_key(&nonce) {
+                    continue;
+                }
+
+                let highest = account_queue
+                    .iter()
+                    .rev()
+                    .find(|(_, transaction)| Priority::of(transaction) >= incoming)
+                    .map_or(nonce, |(highest, _)| *highest);
+                if highest != nonce {
+                    // still queued, so it stays a candidate for later evictions
+                    self.arrival_order[priority as usize].push_front(index);
+                }
+                return Some((pk, highest));
+            }
+        }
+        None
+    }
+
+    fn evict(&mut self, (pk, nonce): PriorityIndex) -> Option<SignedTransaction> {
+        let transaction = self.account_queues.get_mut(&pk)?.remove(&nonce)?;
+        self.length -= 1;
+        for bucket in self.priority_buckets.iter_mut() {
+            let ready_before = bucket.len();
+            bucket.retain(|index| *index != (pk, nonce));
+            self.ready_transactions_length -= ready_before - bucket.len();
+        }
+        // allow the evicted transaction to be resubmitted later
+        self.seen_signatures
+            .remove(&transaction.signature.as_signature().to_bytes());
+        Some(transaction)
+    }
+
+    /// We allow duplicate nonce to be added to the ready bucket, but we enforce uniqueness on execution.
+    /// Transactions that are not signed by their sender, or were already seen by the mempool, are dropped.
+    /// When the mempool is full, an older transaction of equal or lower priority is evicted
+    /// and returned so the caller can report it.
+    pub fn insert(
+        &mut self,
+        txn: SignedTransaction,
+        expected_nonce: Nonce,
+    ) -> Result<Option<SignedTransaction>, MempoolError> {
         let transaction_nonce = txn.get_nonce();
         let transaction_from = txn.get_from_account();
+        let priority = Priority::of(&txn);
+
+        if !txn.verify_sender() {
+            return Err(MempoolError::InvalidSignature);
+        }
 
         if transaction_nonce < expected_nonce {
-            return;
+            return Err(MempoolError::StaleNonce);
         }
-        let account = self.account_queues.entry(transaction_from).or_default();
 
-        if transaction_nonce == expected_nonce {
-            match &txn.tx {
-                UnsignedTransaction::Transfer(_) | UnsignedTransaction::Order(_) => {
-                    self.priority_buckets[Priority::Other as usize]
-                        .push_back((transaction_from, expected_nonce));
-                }
-                UnsignedTransaction::CancelOrder(_) => {
-                    self.priority_buckets[Priority::Cancel as usize]
-                        .push_back((transaction_from, expected_nonce));
-                }
-            }
+        let signature = txn.signature.as_signature().to_bytes();
+        if self.seen_signatures.contains(&signature) {
+            return Err(MempoolError::Duplicate);
+        }
+
+        if txn.is_expired(self.next_view) {
+            return Err(MempoolError::Expired);
+        }
+
+        let mut evicted = None;
+        if self.length >= self.capacity {
+            let Some(candidate) = self.select_eviction_candidate(priority) else {
+                return Err(MempoolError::Full);
+            };
+            evicted = self.evict(candidate);
+        }
+
+        self.seen_signatures.insert(signature);
 
+        if transaction_nonce == expected_nonce {
+            self.priority_buckets[priority as usize].push_back((transaction_from, expected_nonce));
             self.ready_transactions_length += 1;
         }
 
+        let arrival_order = &mut self.arrival_order[priority as usize];
+        arrival_order.push_back((transaction_from, transaction_nonce));
+        if arrival_order.len() > 2 * self.capacity {
+            arrival_order.retain(|index| is_queued(&self.account_queues, index));
+        }
+
         self.length += 1;
-        account.insert(transaction_nonce, txn);
+        self.account_queues
+            .entry(transaction_from)
+            .or_default()
+            .insert(transaction_nonce, txn);
+        Ok(evicted)
     }
 
     pub fn _pop_next(&mut self) -> Option<SignedTransaction> {
         let priorities = [Priority::Liquidation, Priority::Cancel, Priority::Other];
 
         for priority in priorities {
-            if let Some((pk, nonce)) = self.priority_buckets[priority as usize].pop_front() {
-                if let Some(account_queue) = self.account_queues.get_mut(&pk) {
+            while let Some((pk, nonce)) = self.priority_buckets[priority as usize].pop_front() {
+                self.ready_transactions_length -= 1;
+                // skip transactions that expired while waiting
+                if let Some(transaction) = self
+                    .account_queues
+                    .get_mut(&pk)
+                    .and_then(|account_queue| account_queue.remove(&nonce))
+                {
                     self.length -= 1;
-                    self.ready_transactions_length -= 1;
-                    return account_queue.remove(&nonce);
+                    return Some(transaction);
                 }
             }
         }
@@ -133,19 +282,29 @@ impl PriorityMempool {
                 }
             }
         }
+        self.ready_transactions_length -= keys.len();
+
         for (pk, nonce) in keys {
-            if let Some(account_queue) = self.account_queues.get_mut(&pk) {
-                if let Some(transaction) = account_queue.remove(&nonce) {
-                    self.length -= 1;
-                    self.ready_transactions_length -= 1;
-                    result.push(transaction);
-                }
+            let Some(account_queue) = self.account_queues.get_mut(&pk) else {
+                continue;
+            };
+
+            // follow a ready transaction with the account's consecutive future nonces,
+            // so they execute in order within the same block
+            let mut next_nonce = nonce;
+            while result.len() < n {
+                let Some(transaction) = account_queue.remove(&next_nonce) else {
+                    break;
+                };
+                self.length -= 1;
+                result.push(transaction);
+                next_nonce += 1;
             }
         }
         return result;
     }
 
-    pub fn _len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.length
     }
 
@@ -153,6 +312,26 @@ impl PriorityMempool {
         self.ready_transactions_length
     }
 
+    /// Drops transactions that can no longer execute in a block of `next_view` or later,
+    /// returning them so the caller can report them
+    pub fn remove_expired(&mut self, next_view: u64) -> Vec<SignedTransaction> {
+        self.next_view = next_view;
+
+        let mut expired = vec![];
+        for account_queue in self.account_queues.values_mut() {
+            account_queue.retain(|_, transaction| {
+                if transaction.is_expired(next_view) {
+                    expired.push(transaction.clone());
+                    return false;
+                }
+                true
+            });
+        }
+
+        self.length -= expired.len();
+        expired
+    }
+
     pub fn update_after_execution(&mut self, accounts_nonces: Vec<Option<(PublicKeyHash, Nonce)>>) {
         for (pk, next_expected_nonce) in accounts_nonces.into_iter().flatten() {
             let Some(account) = self.account_queues.get_mut(&pk) else {
@@ -172,30 +351,47 @@ impl PriorityMempool {
 mod tests {
     use super::*;
     use crate::state::state::Nonce;
-    use crate::test_utils::test_helpers::get_alice_sk;
-    use crate::types::transaction::{SignedTransaction, TransactionStatus, TransferTransaction};
-
-    fn mock_tx(pk: PublicKeyHash, nonce: Nonce) -> SignedTransaction {
-        let mut alice_sk = get_alice_sk();
+    use crate::types::transaction::{
+        CancelOrderTransaction, SignedTransaction, TransactionStatus, TransferTransaction,
+    };
+    use ed25519_dalek::SigningKey;
+
+    /// `seed` picks the signing key, so each seed is a different account
+    fn mock_tx(seed: [u8; 32], nonce: Nonce) -> SignedTransaction {
+        let mut sk = SigningKey::from_bytes(&seed);
         let tx = UnsignedTransaction::Transfer(TransferTransaction {
-            from: pk,
+            from: sk.verifying_key().to_bytes(),
             to: [2u8; 32],
             amount: 10,
             asset_id: 0,
             nonce,
             status: TransactionStatus::Pending,
+            valid_until: None,
         });
-        tx.sign(&mut alice_sk)
+        tx.sign(&mut sk)
+    }
+
+    fn mock_cancel_tx(seed: [u8; 32], nonce: Nonce) -> SignedTransaction {
+        let mut sk = SigningKey::from_bytes(&seed);
+        let tx = UnsignedTransaction::CancelOrder(CancelOrderTransaction {
+            from: sk.verifying_key().to_bytes(),
+            market_id: 0,
+            order_id: 0,
+            status: TransactionStatus::Pending,
+            nonce,
+            valid_until: None,
+        });
+        tx.sign(&mut sk)
     }
 
     #[test]
-    fn test_len() {
+    fn testlen() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx = mock_tx(pk, 0);
-        mempool.insert(tx, 0);
-        assert_eq!(mempool._len(), 1);
+        mempool.insert(tx, 0).unwrap();
+        assert_eq!(mempool.len(), 1);
         assert_eq!(mempool.ready_transactions_length(), 1);
         mempool._pop_next();
         assert_eq!(mempool.ready_transactions_length(), 0);
@@ -204,74 +400,74 @@ mod tests {
     #[test]
     fn test_pop_next_returns_ready_tx() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx = mock_tx(pk, 0);
-        mempool.insert(tx.clone(), 0);
+        mempool.insert(tx.clone(), 0).unwrap();
         assert_eq!(mempool.ready_transactions_length(), 1);
         let popped = mempool._pop_next().unwrap();
         assert_eq!(popped.hash, tx.hash);
-        assert_eq!(mempool._len(), 0);
+        assert_eq!(mempool.len(), 0);
     }
 
     #[test]
     fn test_pop_next_returns_ready_tx_in_insertion_order() {
         let pk_1 = [1u8; 32];
         let pk_2 = [2u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx_1 = mock_tx(pk_1, 0);
         let tx_2 = mock_tx(pk_2, 0);
-        mempool.insert(tx_1.clone(), 0);
-        mempool.insert(tx_2.clone(), 0);
+        mempool.insert(tx_1.clone(), 0).unwrap();
+        mempool.insert(tx_2.clone(), 0).unwrap();
         assert_eq!(mempool.ready_transactions_length(), 2);
         let popped = mempool._pop_next().unwrap();
         assert_eq!(popped.hash, tx_1.hash);
-        assert_eq!(mempool._len(), 1);
+        assert_eq!(mempool.len(), 1);
 
         let popped = mempool._pop_next().unwrap();
         assert_eq!(popped.hash, tx_2.hash);
-        assert_eq!(mempool._len(), 0);
+        assert_eq!(mempool.len(), 0);
     }
 
     #[test]
     fn test_pop_next_does_not_return_future_tx() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx = mock_tx(pk, 1);
-        mempool.insert(tx.clone(), 0);
+        mempool.insert(tx.clone(), 0).unwrap();
         assert_eq!(mempool.ready_transactions_length(), 0);
         let popped = mempool._pop_next();
         assert!(popped.is_none());
-        assert_eq!(mempool._len(), 1);
+        assert_eq!(mempool.len(), 1);
         assert_eq!(mempool.ready_transactions_length(), 0);
     }
 
     #[test]
     fn test_insert_ignores_old_nonce() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx = mock_tx(pk, 0);
-        mempool.insert(tx.clone(), 1); // old nonce
-        assert_eq!(mempool._len(), 0);
+        assert_eq!(mempool.insert(tx.clone(), 1), Err(MempoolError::StaleNonce)); // old nonce
+        assert_eq!(mempool.len(), 0);
     }
 
     #[test]
     fn test_update_after_execution_pushes_ready_nonce() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         // Insert two txs: nonce 0 (ready), 1 (future)
         let tx0 = mock_tx(pk, 0);
         let tx1 = mock_tx(pk, 1);
-        mempool.insert(tx0, 0);
-        mempool.insert(tx1.clone(), 0);
+        mempool.insert(tx0, 0).unwrap();
+        mempool.insert(tx1.clone(), 0).unwrap();
 
         let _ = mempool._pop_next().unwrap();
         // Execute nonce 0
-        let next_nonce = Some((pk, 1));
+        let next_nonce = Some((tx1.get_from_account(), 1));
         mempool.update_after_execution(vec![next_nonce]);
 
         // Should now pop tx1
@@ -280,21 +476,264 @@ mod tests {
         assert_eq!(tx.hash, tx1.hash);
     }
 
+    #[test]
+    fn test_insert_rejects_duplicate_transaction() {
+        let pk = [1u8; 32];
+        let mut mempool = PriorityMempool::new(16);
+
+        let tx = mock_tx(pk, 0);
+        assert_eq!(mempool.insert(tx.clone(), 0), Ok(None));
+        assert_eq!(mempool.insert(tx.clone(), 0), Err(MempoolError::Duplicate));
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(mempool.ready_transactions_length(), 1);
+
+        // still rejected once it has left the mempool for a proposal
+        assert_eq!(mempool.pop_next_n(2).len(), 1);
+        assert_eq!(mempool.insert(tx, 0), Err(MempoolError::Duplicate));
+        assert_eq!(mempool.len(), 0);
+    }
+
+    #[test]
+    fn test_seen_signatures_are_bounded() {
+        let pk = [1u8; 32];
+        let mut mempool = PriorityMempool::new(16);
+        mempool.seen_signatures = RecentlySeen::new(2);
+
+        let tx0 = mock_tx(pk, 0);
+        let tx2 = mock_tx(pk, 2);
+        assert!(mempool.insert(tx0.clone(), 0).is_ok());
+        assert!(mempool.insert(mock_tx(pk, 1), 0).is_ok());
+        assert!(mempool.insert(tx2.clone(), 0).is_ok());
+        assert!(
+            mempool
+                .seen_signatures
+                .contains(&tx2.signature.as_signature().to_bytes())
+        );
+
+        // the oldest signature has been forgotten
+        assert!(
+            !mempool
+                .seen_signatures
+                .contains(&tx0.signature.as_signature().to_bytes())
+        );
+    }
+
+    #[test]
+    fn test_insert_rejects_invalid_signature() {
+        let mut mempool = PriorityMempool::new(16);
+
+        let valid = mock_tx([1u8; 32], 0);
+        let mut tampered_signature = mock_tx([1u8; 32], 1);
+        tampered_signature.signature = mock_tx([2u8; 32], 1).signature;
+        assert_eq!(
+            mempool.insert(tampered_signature, 0),
+            Err(MempoolError::InvalidSignature)
+        );
+
+        // altering the contents after signing is caught as well
+        let mut tampered_contents = valid.clone();
+        if let UnsignedTransaction::Transfer(transfer) = &mut tampered_contents.tx {
+            transfer.amount = 1_000;
+        }
+        assert_eq!(
+            mempool.insert(tampered_contents, 0),
+            Err(MempoolError::InvalidSignature)
+        );
+        assert_eq!(mempool.len(), 0);
+
+        assert_eq!(mempool.insert(valid, 0), Ok(None));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_expired_transactions_are_dropped() {
+        let mut mempool = PriorityMempool::new(16);
+
+        let mut sk = SigningKey::from_bytes(&[1u8; 32]);
+        let expiring = |nonce: Nonce, valid_until: u64, sk: &mut SigningKey| {
+            UnsignedTransaction::Transfer(TransferTransaction {
+                from: sk.verifying_key().to_bytes(),
+                to: [2u8; 32],
+                amount: 10,
+                asset_id: 0,
+                nonce,
+                status: TransactionStatus::Pending,
+                valid_until: Some(valid_until),
+            })
+            .sign(sk)
+        };
+
+        let tx0 = expiring(0, 5, &mut sk);
+        let tx1 = expiring(1, 6, &mut sk);
+        mempool.insert(tx0.clone(), 0).unwrap();
+        mempool.insert(tx1.clone(), 0).unwrap();
+
+        // a block in view 5 can still include tx0
+        assert!(mempool.remove_expired(5).is_empty());
+
+        let expired = mempool.remove_expired(6);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].hash, tx0.hash);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.pop_next_n(2).is_empty());
+
+        assert_eq!(
+            mempool.insert(expiring(2, 5, &mut sk), 0),
+            Err(MempoolError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_evicting_ready_transaction_updates_ready_count() {
+        let mut mempool = PriorityMempool::new(1);
+
+        mempool.insert(mock_tx([1u8; 32], 0), 0).unwrap();
+        let tx2 = mock_tx([2u8; 32], 0);
+        assert!(mempool.insert(tx2.clone(), 0).unwrap().is_some());
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(mempool.ready_transactions_length(), 1);
+
+        let popped = mempool.pop_next_n(1);
+        assert_eq!(popped.len(), 1);
+        assert_eq!(popped[0].hash, tx2.hash);
+        assert_eq!(mempool.ready_transactions_length(), 0);
+    }
+
+    #[test]
+    fn test_eviction_prefers_future_nonces_of_an_account() {
+        let mut mempool = PriorityMempool::new(3);
+
+        let ready = mock_tx([1u8; 32], 0);
+        let future_1 = mock_tx([1u8; 32], 1);
+        let future_2 = mock_tx([1u8; 32], 2);
+        mempool.insert(ready.clone(), 0).unwrap();
+        mempool.insert(future_1.clone(), 0).unwrap();
+        mempool.insert(future_2.clone(), 0).unwrap();
+
+        let evicted = mempool.insert(mock_tx([2u8; 32], 0), 0).unwrap();
+        assert_eq!(evicted.map(|tx| tx.hash), Some(future_2.hash));
+        let evicted = mempool.insert(mock_tx([3u8; 32], 0), 0).unwrap();
+        assert_eq!(evicted.map(|tx| tx.hash), Some(future_1.hash));
+        assert_eq!(mempool.ready_transactions_length(), 3);
+
+        // the oldest ready transaction goes once its account has nothing later queued
+        let evicted = mempool.insert(mock_tx([4u8; 32], 0), 0).unwrap();
+        assert_eq!(evicted.map(|tx| tx.hash), Some(ready.hash));
+        assert_eq!(mempool.len(), 3);
+        assert_eq!(mempool.ready_transactions_length(), 3);
+    }
+
+    #[test]
+    fn test_insert_evicts_oldest_when_full() {
+        let mut mempool = PriorityMempool::new(2);
+
+        let tx1 = mock_tx([1u8; 32], 0);
+        let tx2 = mock_tx([2u8; 32], 0);
+        let tx3 = mock_tx([3u8; 32], 0);
+        mempool.insert(tx1.clone(), 0).unwrap();
+        mempool.insert(tx2.clone(), 0).unwrap();
+
+        let evicted = mempool.insert(tx3.clone(), 0).unwrap();
+        assert_eq!(evicted.map(|tx| tx.hash), Some(tx1.hash));
+        assert_eq!(mempool.len(), 2);
+
+        let popped = mempool.pop_next_n(3);
+        assert_eq!(popped.len(), 2);
+        assert_eq!(popped[0].hash, tx2.hash);
+        assert_eq!(popped[1].hash, tx3.hash);
+        assert_eq!(mempool.ready_transactions_length(), 0);
+
+        // the evicted transaction can be resubmitted once there is room
+        assert_eq!(mempool.insert(tx1, 0), Ok(None));
+    }
+
+    #[test]
+    fn test_resubmitted_eviction_is_remembered_as_newest() {
+        let mut mempool = PriorityMempool::new(2);
+        mempool.seen_signatures = RecentlySeen::new(3);
+
+        let tx1 = mock_tx([1u8; 32], 0);
+        mempool.insert(tx1.clone(), 0).unwrap();
+        mempool.insert(mock_tx([2u8; 32], 0), 0).unwrap();
+        let evicted = mempool.insert(mock_tx([3u8; 32], 0), 0).unwrap();
+        assert_eq!(evicted.map(|tx| tx.hash), Some(tx1.hash));
+        assert_eq!(mempool.pop_next_n(3).len(), 2);
+
+        // the eviction left no stale entry that would forget the resubmission early
+        assert_eq!(mempool.insert(tx1.clone(), 0), Ok(None));
+        assert_eq!(mempool.insert(tx1, 0), Err(MempoolError::Duplicate));
+    }
+
+    #[test]
+    fn test_insert_rejects_when_full_of_more_urgent_transactions() {
+        let mut mempool = PriorityMempool::new(1);
+
+        mempool.insert(mock_cancel_tx([1u8; 32], 0), 0).unwrap();
+        assert_eq!(
+            mempool.insert(mock_tx([2u8; 32], 0), 0),
+            Err(MempoolError::Full)
+        );
+        assert_eq!(mempool.len(), 1);
+
+        // a cancel may still evict an older cancel
+        let evicted = mempool.insert(mock_cancel_tx([3u8; 32], 0), 0).unwrap();
+        assert!(evicted.is_some());
+        assert_eq!(mempool.len(), 1);
+    }
+
     #[test]
     fn test_pop_next_n_with_fewer_ready_than_n() {
         let pk = [1u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx0 = mock_tx(pk, 0);
-        let tx1 = mock_tx(pk, 1);
+        let tx2 = mock_tx(pk, 2);
 
-        mempool.insert(tx0.clone(), 0);
-        mempool.insert(tx1.clone(), 0); // tx1 is not ready yet
+        mempool.insert(tx0.clone(), 0).unwrap();
+        mempool.insert(tx2.clone(), 0).unwrap(); // tx2 is not ready yet, nonce 1 is missing
 
         let popped = mempool.pop_next_n(2);
         assert_eq!(popped.len(), 1);
         assert_eq!(popped[0].hash, tx0.hash);
-        assert_eq!(mempool._len(), 1);
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_pop_next_n_orders_account_transactions_by_nonce() {
+        let pk = [1u8; 32];
+        let mut mempool = PriorityMempool::new(16);
+
+        for nonce in [2, 0, 1] {
+            mempool.insert(mock_tx(pk, nonce), 0).unwrap();
+        }
+        assert_eq!(mempool.ready_transactions_length(), 1);
+
+        let nonces: Vec<Nonce> = mempool
+            .pop_next_n(3)
+            .iter()
+            .map(|tx| tx.get_nonce())
+            .collect();
+        assert_eq!(nonces, vec![0, 1, 2]);
+        assert_eq!(mempool.len(), 0);
+        assert_eq!(mempool.ready_transactions_length(), 0);
+    }
+
+    #[test]
+    fn test_pop_next_n_stops_at_nonce_gap() {
+        let pk = [1u8; 32];
+        let mut mempool = PriorityMempool::new(16);
+
+        for nonce in [0, 1, 3] {
+            mempool.insert(mock_tx(pk, nonce), 0).unwrap();
+        }
+
+        let nonces: Vec<Nonce> = mempool
+            .pop_next_n(3)
+            .iter()
+            .map(|tx| tx.get_nonce())
+            .collect();
+        assert_eq!(nonces, vec![0, 1]);
+        assert_eq!(mempool.len(), 1);
     }
 
     #[test]
@@ -302,39 +741,39 @@ mod tests {
         let pk1 = [1u8; 32];
         let pk2 = [2u8; 32];
         let pk3 = [3u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx1 = mock_tx(pk1, 0);
         let tx2 = mock_tx(pk2, 0);
         let tx3 = mock_tx(pk3, 0);
 
-        mempool.insert(tx1.clone(), 0);
-        mempool.insert(tx2.clone(), 0);
-        mempool.insert(tx3.clone(), 0);
+        mempool.insert(tx1.clone(), 0).unwrap();
+        mempool.insert(tx2.clone(), 0).unwrap();
+        mempool.insert(tx3.clone(), 0).unwrap();
 
         let popped = mempool.pop_next_n(3);
         assert_eq!(popped.len(), 3);
         assert!(popped.iter().any(|tx| tx.hash == tx1.hash));
         assert!(popped.iter().any(|tx| tx.hash == tx2.hash));
         assert!(popped.iter().any(|tx| tx.hash == tx3.hash));
-        assert_eq!(mempool._len(), 0);
+        assert_eq!(mempool.len(), 0);
     }
 
     #[test]
     fn test_pop_next_n_updates_state() {
         let pk1 = [1u8; 32];
         let pk2 = [2u8; 32];
-        let mut mempool = PriorityMempool::new();
+        let mut mempool = PriorityMempool::new(16);
 
         let tx1 = mock_tx(pk1, 0);
         let tx2 = mock_tx(pk2, 0);
 
-        mempool.insert(tx1.clone(), 0);
-        mempool.insert(tx2.clone(), 0);
+        mempool.insert(tx1.clone(), 0).unwrap();
+        mempool.insert(tx2.clone(), 0).unwrap();
 
         let _ = mempool.pop_next_n(2);
 
-        assert_eq!(mempool._len(), 0);
+        assert_eq!(mempool.len(), 0);
         assert_eq!(mempool.ready_transactions_length(), 0);
     }
 }
@@ -17,17 +17,35 @@ pub struct UnsignedVote<'a> {
 
 impl<'a> UnsignedVote<'a> {
     pub fn hash(&self) -> Sha256Hash {
-        let hashable = HashableMessage {
-            view_number: self.view,
-            block_hash: self.node.hash(),
-            quorum_hash: QuorumCertificateHash::default(),
-        };
-
-        let encoded = bincode::serialize(&hashable).unwrap();
-        Sha256::digest(&encoded).into()
+        vote_hash(self.view, self.node.hash())
     }
 }
 
+/// Message hash signed by validators voting for `block_hash` in `view`
+pub fn vote_hash(view: ViewNumber, block_hash: BlockHash) -> Sha256Hash {
+    let hashable = HashableMessage {
+        view_number: view,
+        block_hash,
+        quorum_hash: QuorumCertificateHash::default(),
+    };
+
+    let encoded = bincode::serialize(&hashable).unwrap();
+    Sha256::digest(&encoded).into()
+}
+
+#[derive(Serialize)]
+struct HashableTimeout {
+    view_number: ViewNumber,
+}
+
+/// Message hash signed by validators that timed out waiting for progress in `view`
+pub fn timeout_hash(view: ViewNumber) -> Sha256Hash {
+    let hashable = HashableTimeout { view_number: view };
+
+    let encoded = bincode::serialize(&hashable).unwrap();
+    Sha256::digest(&encoded).into()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum HotStuffMessage {
     Proposal {
@@ -49,6 +67,13 @@ pub enum HotStuffMessage {
         sender: PeerId,
         sender_view: ViewNumber,
     },
+    Timeout {
+        high_qc: QuorumCertificate,
+        partial_sig: PartialSig,
+        view: ViewNumber,
+        sender: PeerId,
+        sender_view: ViewNumber,
+    },
 }
 
 // Hashable message should only contain these two f
@@ -114,6 +139,29 @@ impl HotStuffMessage {
         }
     }
 
+    pub fn create_timeout(
+        high_qc: QuorumCertificate,
+        view: ViewNumber,
+        sender: PeerId,
+        sender_view: ViewNumber,
+        signing_key: &mut SigningKey,
+    ) -> Self {
+        let signature = signing_key.sign(&timeout_hash(view));
+
+        let partial_sig = PartialSig {
+            signer_id: signing_key.verifying_key(),
+            signature,
+        };
+
+        HotStuffMessage::Timeout {
+            high_qc,
+            partial_sig,
+            view,
+            sender,
+            sender_view,
+        }
+    }
+
     pub fn hash(&self) -> Sha256Hash {
         let hashable = match self {
             HotStuffMessage::Proposal { node, view, .. } => HashableMessage {
@@ -131,6 +179,7 @@ impl HotStuffMessage {
                 block_hash: BlockHash::default(),
                 quorum_hash: justify.hash(),
             },
+            HotStuffMessage::Timeout { view, .. } => return timeout_hash(*view),
         };
 
         let encoded = bincode::serialize(&hashable).unwrap();
@@ -140,7 +189,8 @@ impl HotStuffMessage {
     pub fn get_view_number(&self) -> ViewNumber {
         let (HotStuffMessage::Proposal { view, .. }
         | HotStuffMessage::Vote { view, .. }
-        | HotStuffMessage::NewView { view, .. }) = self;
+        | HotStuffMessage::NewView { view, .. }
+        | HotStuffMessage::Timeout { view, .. }) = self;
 
         *view
     }
@@ -148,14 +198,16 @@ impl HotStuffMessage {
     pub fn get_sender(&self) -> PeerId {
         let (HotStuffMessage::Proposal { sender, .. }
         | HotStuffMessage::Vote { sender, .. }
-        | HotStuffMessage::NewView { sender, .. }) = self;
+        | HotStuffMessage::NewView { sender, .. }
+        | HotStuffMessage::Timeout { sender, .. }) = self;
         *sender
     }
 
     pub fn get_sender_view(&self) -> ViewNumber {
         let (HotStuffMessage::Proposal { sender_view, .. }
         | HotStuffMessage::Vote { sender_view, .. }
-        | HotStuffMessage::NewView { sender_view, .. }) = self;
+        | HotStuffMessage::NewView { sender_view, .. }
+        | HotStuffMessage::Timeout { sender_view, .. }) = self;
         *sender_view
     }
 }
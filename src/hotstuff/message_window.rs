@@ -50,6 +50,8 @@ impl MessageWindow {
         self.lowest_view = view;
     }
 
+    /// Stores `msg` under its view. Returns false for messages of pruned views and for
+    /// messages already stored, which network retries can deliver more than once
     pub fn push(&mut self, msg: HotStuffMessage) -> bool {
         if msg.get_view_number() < self.lowest_view {
             return false;
@@ -59,6 +61,9 @@ impl MessageWindow {
         let opt_vector: Option<&mut Vec<HotStuffMessage>> = self.messages.get_mut(index as usize);
         match opt_vector {
             Some(vector) => {
+                if vector.contains(&msg) {
+                    return false;
+                }
                 vector.push(msg);
             }
             None => {
@@ -196,4 +201,13 @@ mod tests {
         assert!(window.messages[1].contains(&msg1));
         assert!(window.messages[1].contains(&msg2));
     }
+
+    #[test]
+    fn insert_duplicate_message_returns_false() {
+        let mut window = MessageWindow::new(0);
+        let msg = dummy_message(1, 1);
+        assert!(window.push(msg.clone()));
+        assert!(!window.push(msg));
+        assert_eq!(window.messages[1].len(), 1);
+    }
 }
@@ -4,8 +4,10 @@ pub mod hexstring;
 pub mod message;
 pub mod pacemaker;
 pub mod replica;
+pub mod safety;
 pub mod utils;
 
+mod equivocation;
 mod mempool;
 mod message_window;
 mod replica_sender;
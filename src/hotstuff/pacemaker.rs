@@ -5,24 +5,31 @@ use std::{
 
 use crate::{config, pacemaker_log};
 
-use super::{crypto::QuorumCertificate, replica::ViewNumber};
+use super::{
+    crypto::{QuorumCertificate, TimeoutCertificate},
+    replica::ViewNumber,
+};
 
 pub struct Pacemaker {
     pub curr_view: ViewNumber,
     pub last_commited_view: ViewNumber,
     pub timeout: Duration,
     pub base_timeout: Duration,
+    pub max_timeout: Duration,
     pub timeout_multiplicative_factor: f32,
+    pub last_qc_view: ViewNumber,
     pub last_view_change: Instant,
-    replica_ids: Vec<usize>,
+    pub batch_window: Duration,
+    pub batch_size: usize,
+    /// When the leader of the current view became ready to propose
+    batch_started: Option<Instant>,
+    num_validators: usize,
     // pub highest_qc: QuorumCertificate,
     // pub leaf: Block,
 }
 
 impl Pacemaker {
     pub(crate) fn new() -> Self {
-        let replica_len = config::retrieve_num_validators();
-        let replica_ids = (0..replica_len).collect();
         Self {
             curr_view: 0,
             last_commited_view: 0,
@@ -30,8 +37,13 @@ impl Pacemaker {
             timeout_multiplicative_factor: config::retrieve_multiplicative_factor(),
 
             base_timeout: config::retrieve_tick_duration(),
+            max_timeout: config::retrieve_max_timeout(),
+            last_qc_view: 0,
             last_view_change: Instant::now(),
-            replica_ids,
+            batch_window: config::retrieve_batch_window(),
+            batch_size: config::retrieve_batch_size(),
+            batch_started: None,
+            num_validators: config::retrieve_num_validators(),
         }
     }
 
@@ -39,20 +51,35 @@ impl Pacemaker {
         self.last_view_change.elapsed() > self.get_current_timeout()
     }
 
+    /// Number of views since the last view that made progress (formed a QC or committed)
+    pub(crate) fn consecutive_timeouts(&self) -> u64 {
+        let last_progress_view = self.last_commited_view.max(self.last_qc_view);
+        self.curr_view.saturating_sub(last_progress_view)
+    }
+
+    /// Timeout grows by the multiplicative factor for every view without progress,
+    /// capped at `max_timeout`
     pub(crate) fn get_current_timeout(&self) -> Duration {
-        let failed_views = self.curr_view - self.last_commited_view;
+        let failed_views = self.consecutive_timeouts();
 
-        self.base_timeout
+        let timeout = self.base_timeout
             * self
                 .timeout_multiplicative_factor
                 .powf(failed_views as f32)
-                .round() as u32
+                .round() as u32;
+
+        timeout.min(self.max_timeout)
     }
 
     pub(crate) fn set_last_committed_view(&mut self, qc: Arc<QuorumCertificate>) {
         self.last_commited_view = qc.view_number;
     }
 
+    /// Resets the backoff once a QC has been formed for a view
+    pub(crate) fn record_qc(&mut self, qc: &QuorumCertificate) {
+        self.last_qc_view = self.last_qc_view.max(qc.view_number);
+    }
+
     pub(crate) fn advance_view(&mut self) {
         pacemaker_log!(
             "Timeout occured - advancing view from {:?} to {:?}",
@@ -61,6 +88,7 @@ impl Pacemaker {
         );
         self.curr_view += 1;
         self.last_view_change = Instant::now();
+        self.batch_started = None;
     }
 
     pub(crate) fn fast_forward_view(&mut self, incoming_view: ViewNumber) -> bool {
@@ -74,15 +102,34 @@ impl Pacemaker {
         );
         self.curr_view = incoming_view;
         self.last_view_change = Instant::now();
+        self.batch_started = None;
+        true
+    }
+
+    /// Moves past the view certified by the timeout certificate
+    pub(crate) fn advance_view_with_tc(&mut self, tc: &TimeoutCertificate) -> bool {
+        let next_view = tc.view_number + 1;
+        if next_view <= self.curr_view {
+            return false;
+        }
+        pacemaker_log!(
+            "Timeout certificate formed - advancing view from {:?} to {:?}",
+            self.curr_view,
+            next_view
+        );
+        self.curr_view = next_view;
+        self.last_view_change = Instant::now();
+        self.batch_started = None;
         true
     }
 
     pub(crate) fn current_leader(&self) -> usize {
-        self.replica_ids[(self.curr_view as usize) % self.replica_ids.len()]
+        self.leader_for_view(self.curr_view)
     }
 
-    pub(crate) fn get_leader_for_view(&self, incoming_view: ViewNumber) -> usize {
-        self.replica_ids[(incoming_view as usize) % self.replica_ids.len()]
+    /// Node id of the leader of `view`, rotating round robin through the validators
+    pub(crate) fn leader_for_view(&self, view: ViewNumber) -> usize {
+        (view % self.num_validators as ViewNumber) as usize
     }
 
     pub(crate) fn reset_timer(&mut self) {
@@ -93,6 +140,28 @@ impl Pacemaker {
         let end_time = self.last_view_change + self.get_current_timeout();
         end_time.saturating_duration_since(Instant::now())
     }
+
+    /// Opens the batching window on first call, then whether the leader should propose:
+    /// once `batch_size` transactions are pending or the window has elapsed
+    pub(crate) fn batch_ready(&mut self, pending_transactions: usize) -> bool {
+        let started = *self.batch_started.get_or_insert_with(Instant::now);
+        pending_transactions >= self.batch_size || started.elapsed() >= self.batch_window
+    }
+
+    /// Whether the leader is waiting on an open batching window
+    pub(crate) fn batch_pending(&self) -> bool {
+        self.batch_started.is_some()
+    }
+
+    /// Time left in the open batching window, if any
+    pub(crate) fn batch_time_remaining(&self) -> Option<Duration> {
+        self.batch_started
+            .map(|started| (started + self.batch_window).saturating_duration_since(Instant::now()))
+    }
+
+    pub(crate) fn close_batch(&mut self) {
+        self.batch_started = None;
+    }
 }
 
 #[cfg(test)]
@@ -105,10 +174,7 @@ mod tests {
     fn test_new_pacemaker_starts_at_view_zero() {
         let pacemaker = Pacemaker::new();
         assert_eq!(pacemaker.curr_view, 0);
-        assert_eq!(
-            pacemaker.replica_ids.len(),
-            config::retrieve_num_validators()
-        );
+        assert_eq!(pacemaker.num_validators, config::retrieve_num_validators());
     }
 
     #[test]
@@ -176,18 +242,97 @@ mod tests {
         assert_eq!(pacemaker.get_current_timeout(), expected);
     }
 
+    #[test]
+    fn test_timeout_backoff_grows_and_resets_on_qc() {
+        let mut pacemaker = Pacemaker::new();
+        pacemaker.base_timeout = Duration::from_millis(10);
+        pacemaker.max_timeout = Duration::from_millis(1_000);
+        pacemaker.timeout_multiplicative_factor = 2.0;
+
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(10));
+
+        pacemaker.advance_view();
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(20));
+        pacemaker.advance_view();
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(40));
+        pacemaker.advance_view();
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(80));
+        assert_eq!(pacemaker.consecutive_timeouts(), 3);
+
+        let qc = QuorumCertificate {
+            view_number: 3,
+            block_hash: [0; 32],
+            message_hash: [0; 32],
+            partial_sigs: vec![],
+        };
+        pacemaker.record_qc(&qc);
+        assert_eq!(pacemaker.consecutive_timeouts(), 0);
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_timeout_backoff_is_capped() {
+        let mut pacemaker = Pacemaker::new();
+        pacemaker.base_timeout = Duration::from_millis(10);
+        pacemaker.max_timeout = Duration::from_millis(50);
+        pacemaker.timeout_multiplicative_factor = 2.0;
+
+        pacemaker.curr_view = 40;
+        assert_eq!(pacemaker.get_current_timeout(), Duration::from_millis(50));
+    }
+
     #[test]
     fn test_current_leader_rotates_among_replicas() {
         let mut pacemaker = Pacemaker::new();
-        let total_replicas = pacemaker.replica_ids.len();
+        let total_replicas = pacemaker.num_validators;
 
         for i in 0..total_replicas * 2 {
             pacemaker.curr_view = i as u64;
-            let expected_leader = pacemaker.replica_ids[i % total_replicas];
-            assert_eq!(pacemaker.current_leader(), expected_leader);
+            assert_eq!(pacemaker.current_leader(), i % total_replicas);
         }
     }
 
+    #[test]
+    fn test_leader_for_view_cycles_through_validators() {
+        let mut pacemaker = Pacemaker::new();
+        pacemaker.num_validators = 4;
+
+        let leaders: Vec<usize> = (0..9).map(|view| pacemaker.leader_for_view(view)).collect();
+        assert_eq!(leaders, vec![0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(pacemaker.leader_for_view(4 * 1_000 + 3), 3);
+    }
+
+    #[test]
+    fn test_batch_ready_after_window_or_batch_size() {
+        let mut pacemaker = Pacemaker::new();
+        pacemaker.batch_window = Duration::from_millis(20);
+        pacemaker.batch_size = 4;
+
+        assert!(!pacemaker.batch_pending());
+        assert!(!pacemaker.batch_ready(1));
+        assert!(pacemaker.batch_pending());
+        assert!(pacemaker.batch_ready(4));
+
+        sleep(Duration::from_millis(25));
+        assert_eq!(pacemaker.batch_time_remaining(), Some(Duration::ZERO));
+        assert!(pacemaker.batch_ready(0));
+
+        pacemaker.close_batch();
+        assert!(!pacemaker.batch_pending());
+        assert!(!pacemaker.batch_ready(0));
+    }
+
+    #[test]
+    fn test_view_change_closes_batch() {
+        let mut pacemaker = Pacemaker::new();
+        pacemaker.batch_window = Duration::from_millis(1_000);
+
+        assert!(!pacemaker.batch_ready(0));
+        pacemaker.advance_view();
+        assert!(!pacemaker.batch_pending());
+        assert_eq!(pacemaker.batch_time_remaining(), None);
+    }
+
     #[test]
     fn test_time_remaining_decreases() {
         let pacemaker = Pacemaker::new();
@@ -1,39 +1,54 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use tokio::{
     pin,
-    sync::mpsc::{self},
+    sync::{
+        broadcast,
+        mpsc::{self},
+    },
     time::sleep,
 };
 
 use crate::{
     config,
     hotstuff::utils,
-    node::client::handler::QueryRequest,
+    message_protocol::{NodeHealth, SyncMessage},
+    node::{
+        block_store::BlockStore,
+        checkpoint::{CheckpointRef, CheckpointStore},
+        client::handler::{ClientQuery, ClientResponse, QueryRequest},
+        metrics::Metrics,
+        state::PeerId,
+    },
     replica_debug, replica_log,
-    state::state::LedgerState,
+    state::{receipt::TransactionReceipt, state::LedgerState},
     types::{
         message::{ReplicaInBound, ReplicaOutbound},
-        transaction::{PublicKeyHash, Sha256Hash, SignedTransaction},
+        transaction::{
+            PublicKeyHash, RejectReason, Sha256Hash, SignedTransaction, TransactionStatus,
+        },
     },
 };
 
 use super::{
-    block::{Block, BlockHash},
-    crypto::{PartialSig, QuorumCertificate},
-    mempool::PriorityMempool,
+    block::{Block, BlockHash, BlockLimits},
+    crypto::{PartialSig, QuorumCertificate, TimeoutCertificate},
+    equivocation::{EquivocationDetector, EquivocationEvidence},
+    mempool::{MempoolError, PriorityMempool},
     message::HotStuffMessage,
     message_window::MessageWindow,
     pacemaker::Pacemaker,
     replica_sender::ReplicaSender,
+    safety::{SafetyState, SafetyStore},
 };
 
 pub type ViewNumber = u64;
-const BLOCK_TRANSACTION_LENGTH: usize = 16;
+const MAX_SYNC_BLOCKS: usize = 64;
 
 struct ViewProgress {
     pub leader_has_proposed: bool,
@@ -50,27 +65,51 @@ impl ViewProgress {
 pub struct HotStuffReplica {
     pub node_id: usize,
     pub validator_set: HashSet<VerifyingKey>,
+    quorum_threshold: usize,
+    validator_ids: HashMap<VerifyingKey, usize>,
+    // QCs each validator's signature appeared in, indexed by node id
+    qc_participation: Vec<u64>,
+    // view of the last QC counted towards participation
+    participation_view: ViewNumber,
     signing_key: SigningKey,
 
     // Arc is needed as we are sending our replica across threads
     generic_qc: Arc<QuorumCertificate>,
     locked_qc: Arc<QuorumCertificate>,
+    highest_tc: Option<TimeoutCertificate>,
+    last_voted_view: ViewNumber,
+    safety_store: Option<SafetyStore>,
 
     current_proposal: Option<Arc<RwLock<Block>>>,
+    block_limits: BlockLimits,
     mempool: PriorityMempool,
     pending_transactions: HashMap<Sha256Hash, SignedTransaction>,
     committed_transactions: HashMap<Sha256Hash, SignedTransaction>,
 
     pub messages: MessageWindow,
     pub pacemaker: Pacemaker,
+    equivocation_detector: EquivocationDetector,
 
     pub rep_node_channel: ReplicaSender,
 
     // State
     blockstore: HashMap<BlockHash, Arc<RwLock<Block>>>,
     ledger_state: LedgerState,
+    // state root of the ledger right after each executed block
+    executed_state_roots: HashMap<BlockHash, Sha256Hash>,
+    last_executed_block: BlockHash,
+    // hashes of blocks executed since the last checkpoint, in commit order
+    committed_blocks: Vec<BlockHash>,
+    block_store: Option<BlockStore>,
+    // number of committed blocks covered by the last checkpoint
+    checkpoint_height: u64,
+    checkpoint_interval: u64,
+    checkpoint_store: Option<CheckpointStore>,
+    // peer to fetch missing blocks from, and the block we are missing
+    pending_sync_request: Option<(PeerId, BlockHash)>,
 
     view_progress: ViewProgress,
+    metrics: Arc<Metrics>,
 }
 
 impl HotStuffReplica {
@@ -88,35 +127,59 @@ impl HotStuffReplica {
             Arc::new(RwLock::new(genesis_block.clone())),
         );
 
+        let ledger_state = LedgerState::new();
+        let genesis_hash = genesis_block.hash();
+        let executed_state_roots = HashMap::from([(genesis_hash, ledger_state.state_root())]);
+
         let genesis_qc = Arc::new(genesis_qc);
+        let validator_keys = config::retrieve_validator_keys();
         HotStuffReplica {
             node_id,
             validator_set: config::retrieve_validator_set(),
+            quorum_threshold: config::quorum_threshold(),
+            validator_ids: validator_keys
+                .iter()
+                .enumerate()
+                .map(|(node_id, key)| (*key, node_id))
+                .collect(),
+            qc_participation: vec![0; validator_keys.len()],
+            participation_view: 0,
             signing_key,
 
             generic_qc: genesis_qc.clone(),
             locked_qc: genesis_qc,
+            highest_tc: None,
+            last_voted_view: 0,
+            safety_store: None,
 
             current_proposal: None,
+            block_limits: BlockLimits::from_config(),
             blockstore,
-            mempool: PriorityMempool::new(),
+            mempool: PriorityMempool::new(config::retrieve_mempool_capacity()),
             pending_transactions: HashMap::new(),
             committed_transactions: HashMap::new(),
 
             messages: MessageWindow::new(0),
 
             pacemaker: Pacemaker::new(),
-            rep_node_channel: ReplicaSender {
-                replica_tx,
-                node_tx,
-            },
-
-            ledger_state: LedgerState::new(),
+            equivocation_detector: EquivocationDetector::new(),
+            rep_node_channel: ReplicaSender::new(replica_tx, node_tx),
+
+            ledger_state,
+            executed_state_roots,
+            last_executed_block: genesis_hash,
+            committed_blocks: vec![],
+            block_store: None,
+            checkpoint_height: 0,
+            checkpoint_interval: config::retrieve_checkpoint_interval(),
+            checkpoint_store: None,
+            pending_sync_request: None,
 
             view_progress: ViewProgress {
                 leader_has_proposed: false,
                 replica_has_voted: false,
             },
+            metrics: Arc::new(Metrics::new()),
         }
     }
 
@@ -130,13 +193,198 @@ impl HotStuffReplica {
         )
     }
 
+    /// Resumes from the latest checkpoint in `store`, if any, and writes all future
+    /// checkpoints to it. Must be called before `restore_blocks`.
+    pub fn restore_checkpoint(&mut self, store: CheckpointStore) -> std::io::Result<()> {
+        if let Some(checkpoint) = store.load()? {
+            replica_log!(
+                self.node_id,
+                "Restored checkpoint at height {:?}",
+                checkpoint.height
+            );
+
+            let block_hash = checkpoint.block.hash();
+            self.ledger_state = checkpoint.ledger_state;
+            self.executed_state_roots
+                .insert(block_hash, self.ledger_state.state_root());
+            self.last_executed_block = block_hash;
+            self.checkpoint_height = checkpoint.height;
+            self.blockstore
+                .insert(block_hash, Arc::new(RwLock::new(checkpoint.block)));
+        }
+        self.checkpoint_store = Some(store);
+        Ok(())
+    }
+
+    /// Rebuilds the ledger by replaying committed blocks from `store`, and appends all
+    /// future commits to it. Must be called before the replica joins consensus.
+    pub fn restore_blocks(&mut self, mut store: BlockStore) -> std::io::Result<()> {
+        let mut blocks = store.load_blocks()?;
+
+        // blocks up to the checkpoint are left behind if we crashed before clearing the log
+        if let Some(checkpoint_index) = blocks
+            .iter()
+            .position(|block| block.hash() == self.last_executed_block)
+        {
+            blocks.drain(..=checkpoint_index);
+        }
+
+        if !blocks.is_empty() {
+            replica_log!(
+                self.node_id,
+                "Replaying {:?} committed blocks",
+                blocks.len()
+            );
+        }
+
+        for mut block in blocks {
+            self.ledger_state.apply_block(&mut block);
+            self.add_block_transactions_to_committed(&block);
+
+            let block_hash = block.hash();
+            self.executed_state_roots
+                .insert(block_hash, self.ledger_state.state_root());
+            self.last_executed_block = block_hash;
+            self.committed_blocks.push(block_hash);
+            self.blockstore
+                .insert(block_hash, Arc::new(RwLock::new(block)));
+        }
+        self.metrics
+            .record_committed_block(self.committed_height(), &self.ledger_state);
+
+        self.block_store = Some(store);
+        Ok(())
+    }
+
+    /// Restores the persisted voting state, if any, and persists all future updates to `store`
+    pub fn restore_safety_state(&mut self, store: SafetyStore) -> std::io::Result<()> {
+        if let Some(state) = store.load()? {
+            replica_log!(
+                self.node_id,
+                "Restored safety state, last voted view: {:?}",
+                state.last_voted_view
+            );
+            self.last_voted_view = state.last_voted_view;
+            self.pacemaker.fast_forward_view(state.last_voted_view);
+            if state.locked_qc.view_number > self.generic_qc.view_number {
+                self.generic_qc = Arc::new(state.locked_qc.clone());
+            }
+            self.locked_qc = Arc::new(state.locked_qc);
+        }
+        self.safety_store = Some(store);
+        Ok(())
+    }
+
+    fn persist_safety_state(&self) -> std::io::Result<()> {
+        let Some(store) = &self.safety_store else {
+            return Ok(());
+        };
+
+        store.save(&SafetyState {
+            last_voted_view: self.last_voted_view,
+            locked_qc: (*self.locked_qc).clone(),
+        })
+    }
+
+    /// Records a vote in `view`, persisting it before the vote is sent.
+    /// Returns false if the replica has already voted in this view or later.
+    fn record_vote(&mut self, view: ViewNumber) -> bool {
+        if view <= self.last_voted_view {
+            return false;
+        }
+
+        let previous_voted_view = self.last_voted_view;
+        self.last_voted_view = view;
+
+        if let Err(e) = self.persist_safety_state() {
+            replica_log!(self.node_id, "Failed to persist safety state: {:?}", e);
+            self.last_voted_view = previous_voted_view;
+            return false;
+        }
+        true
+    }
+
+    pub fn timeout_message(&mut self) -> HotStuffMessage {
+        HotStuffMessage::create_timeout(
+            (*self.generic_qc).clone(),
+            self.pacemaker.curr_view,
+            self.node_id,
+            self.pacemaker.curr_view,
+            &mut self.signing_key,
+        )
+    }
+
+    pub fn get_highest_tc(&self) -> Option<&TimeoutCertificate> {
+        self.highest_tc.as_ref()
+    }
+
+    pub fn get_equivocation_evidence(&self) -> &Vec<EquivocationEvidence> {
+        &self.equivocation_detector.evidence
+    }
+
+    /// Records the vote for equivocation detection, logging any evidence found
+    fn observe_vote(&mut self, msg: &HotStuffMessage) {
+        if let Some(evidence) = self.equivocation_detector.observe(msg) {
+            replica_log!(
+                self.node_id,
+                "Equivocation detected: validator {} voted for conflicting blocks in view {:?}",
+                hex::encode(evidence.signer),
+                evidence.view
+            );
+        }
+        self.equivocation_detector
+            .prune_before_view(self.generic_qc.view_number);
+    }
+
+    /// Stores an incoming message. Returns false if it brings nothing new: a duplicate,
+    /// a message for a pruned view, or a vote for a view that already has a QC
+    fn record_message(&mut self, msg: &HotStuffMessage) -> bool {
+        self.observe_vote(msg);
+
+        if let HotStuffMessage::Vote { view, .. } = msg
+            && *view <= self.generic_qc.view_number
+        {
+            return false;
+        }
+        self.messages.push(msg.clone())
+    }
+
     pub fn matching_message(message: HotStuffMessage, view_number: ViewNumber) -> bool {
         view_number == message.get_view_number()
     }
 
     fn quorum_threshold(&self) -> usize {
-        let n = self.validator_set.len();
-        2 * ((n - 1) / 3) + 1
+        self.quorum_threshold
+    }
+
+    /// Verifies `qc` against the validator set. The signers of the first valid QC seen for
+    /// each view are credited in the participation counts
+    fn verify_qc(&mut self, qc: &QuorumCertificate) -> bool {
+        if qc.is_genesis() {
+            return true;
+        }
+
+        let signers = qc.valid_signers(&self.validator_set);
+        if signers.len() < self.quorum_threshold() {
+            return false;
+        }
+
+        if qc.view_number > self.participation_view {
+            self.participation_view = qc.view_number;
+            for signer in signers {
+                if let Some(node_id) = self.validator_ids.get(signer) {
+                    self.qc_participation[*node_id] += 1;
+                }
+            }
+            self.metrics
+                .set_validator_qc_signatures(&self.qc_participation);
+        }
+        true
+    }
+
+    /// Number of QCs each validator's signature appeared in, indexed by node id
+    pub fn qc_participation(&self) -> &[u64] {
+        &self.qc_participation
     }
 
     fn validate_block_transactions(node: &Block) -> bool {
@@ -145,6 +393,23 @@ impl HotStuffReplica {
             .all(|transaction| transaction.verify_sender())
     }
 
+    /// Checks the proposer's claimed state root against our own execution of the same block.
+    /// Proposals built on a block we have not executed cannot be checked and are rejected.
+    fn validate_state_root(&self, node: &Block) -> bool {
+        let Block::Normal {
+            state_root,
+            executed_block_hash,
+            ..
+        } = node
+        else {
+            return true;
+        };
+
+        self.executed_state_roots
+            .get(executed_block_hash)
+            .is_some_and(|executed_state_root| executed_state_root == state_root)
+    }
+
     /// Try to build QC(view) once any block‐hash has n‑f signatures.
     /// Returns None if no such QC exists yet.
     pub fn try_create_qc_for_view(&self, view: ViewNumber) -> Option<QuorumCertificate> {
@@ -208,18 +473,116 @@ impl HotStuffReplica {
         None
     }
 
+    /// Try to build TC(view) once n-f validators have timed out in the view.
+    /// The TC carries the highest valid QC among the timeout messages.
+    pub fn try_create_tc_for_view(&self, view: ViewNumber) -> Option<TimeoutCertificate> {
+        let msgs = self.messages.get_messages_for_view(view);
+        let quorum = self.quorum_threshold();
+
+        // cheap check before verifying any signatures
+        if !utils::has_quorum_timeouts_for_view(msgs, view, quorum) {
+            return None;
+        }
+
+        let mut seen: HashSet<PublicKeyHash> = HashSet::new();
+        let mut sigs: Vec<&PartialSig> = vec![];
+        let mut high_qcs: Vec<&QuorumCertificate> = vec![];
+
+        for message in msgs? {
+            let HotStuffMessage::Timeout {
+                high_qc: timeout_qc,
+                partial_sig,
+                view: timeout_view,
+                ..
+            } = message
+            else {
+                continue;
+            };
+
+            if *timeout_view != view || !self.validator_set.contains(&partial_sig.signer_id) {
+                continue;
+            }
+
+            if partial_sig
+                .signer_id
+                .verify_strict(&message.hash(), &partial_sig.signature)
+                .is_err()
+            {
+                continue;
+            }
+
+            if !seen.insert(*partial_sig.signer_id.as_bytes()) {
+                continue;
+            }
+            sigs.push(partial_sig);
+
+            if timeout_qc.view_number <= view {
+                high_qcs.push(timeout_qc);
+            }
+        }
+
+        if sigs.len() < quorum {
+            return None;
+        }
+
+        // only verify QCs until the highest valid one is found
+        high_qcs.sort_by_key(|qc| std::cmp::Reverse(qc.view_number));
+        let high_qc = high_qcs
+            .into_iter()
+            .find(|qc| qc.verify(&self.validator_set, quorum))?
+            .clone();
+
+        Some(TimeoutCertificate::from_signatures(view, high_qc, sigs))
+    }
+
+    fn update_generic_qc(&mut self, qc: &QuorumCertificate) {
+        if qc.view_number > self.generic_qc.view_number {
+            self.generic_qc = Arc::new(qc.clone());
+            self.messages.prune_before_view(self.generic_qc.view_number);
+        }
+    }
+
+    /// Advances past `view` if a quorum of validators have timed out in it.
+    /// Returns true if the view was advanced.
+    fn advance_view_with_tc(&mut self, view: ViewNumber) -> bool {
+        if view < self.pacemaker.curr_view {
+            return false;
+        }
+
+        let Some(tc) = self.try_create_tc_for_view(view) else {
+            return false;
+        };
+
+        if !self.pacemaker.advance_view_with_tc(&tc) {
+            return false;
+        }
+
+        self.update_generic_qc(&tc.high_qc);
+        self.view_progress.reset();
+        self.highest_tc = Some(tc);
+        self.metrics.record_view_change();
+        true
+    }
+
     pub fn safe_node(&self, block: &Block, qc: &QuorumCertificate) -> bool {
         let locked_qc = &self.locked_qc;
         let locked_block_hash = locked_qc.block_hash;
-        let extends = block.extends_from(locked_block_hash, &self.blockstore);
+        let extends =
+            block.extends_from(locked_block_hash, locked_qc.view_number, &self.blockstore);
         let newer_qc = qc.view_number > locked_qc.view_number;
 
         extends || newer_qc
     }
 
-    /// Selects a transaction from the mempool
+    /// Selects up to a batch of transactions from the mempool
     fn select_transactions(&mut self) -> Vec<SignedTransaction> {
-        self.mempool.pop_next_n(BLOCK_TRANSACTION_LENGTH)
+        let transactions = self.mempool.pop_next_n(
+            self.pacemaker
+                .batch_size
+                .min(self.block_limits.max_transactions),
+        );
+        self.metrics.set_mempool_size(self.mempool.len());
+        transactions
     }
 
     fn leader_create_message(&mut self, new_block: Block) -> HotStuffMessage {
@@ -295,6 +658,15 @@ impl HotStuffReplica {
             curr_view
         );
 
+        if curr_view == 0 {
+            // genesis view, there is no previous view to build on
+            return None;
+        }
+
+        if self.node_id != self.pacemaker.current_leader() {
+            return None;
+        }
+
         self.pacemaker.reset_timer();
 
         let is_new_view = utils::has_quorum_for_new_view(
@@ -303,7 +675,10 @@ impl HotStuffReplica {
             self.quorum_threshold(),
         );
 
-        if is_new_view {
+        // previous leader failed, extend the highest QC reported by the timed out replicas
+        let timeout_certificate = self.try_create_tc_for_view(curr_view - 1);
+
+        if is_new_view || timeout_certificate.is_some() {
             replica_log!(
                 self.node_id,
                 "Leader handle new view (Prepare), view num: {:?}",
@@ -324,6 +699,10 @@ impl HotStuffReplica {
                 };
             }
 
+            if let Some(tc) = &timeout_certificate {
+                self.update_generic_qc(&tc.high_qc);
+            }
+
             let parent = {
                 let Some(parent) = self.blockstore.get(&self.generic_qc.block_hash) else {
                     // cant find QC's block
@@ -337,6 +716,12 @@ impl HotStuffReplica {
                 parent.clone()
             };
 
+            // hold the proposal open until the batching window fills or runs out
+            if !self.pacemaker.batch_ready(self.mempool.len()) {
+                return None;
+            }
+            self.pacemaker.close_batch();
+
             let selected_transactions = &self.select_transactions();
 
             let curr_view = self.pacemaker.curr_view;
@@ -345,6 +730,8 @@ impl HotStuffReplica {
                 selected_transactions.clone(),
                 curr_view,
                 (*self.generic_qc).clone(),
+                self.ledger_state.state_root(),
+                self.last_executed_block,
             );
 
             let sending_block = new_block.clone();
@@ -404,26 +791,37 @@ impl HotStuffReplica {
         sender: usize,
     ) -> Option<HotStuffMessage> {
         let curr_view = self.pacemaker.curr_view;
-        if sender != self.pacemaker.get_leader_for_view(curr_view) {
-            // Ignore messages not from leader of the current view
-            // replica_debug!(
-            //     self.node_id,
-            //     self.pacemaker.curr_view,
-            //     "msg sender: {:?} is not the leader. Leader is: {:?}",
-            //     msg.sender,
-            //     self.pacemaker.get_leader_for_view(curr_view)
-            // );
+        let leader = self.pacemaker.leader_for_view(curr_view);
+        // only the scheduled leader of the current view may propose, and only for that view
+        if sender != leader || node.view_number() != curr_view {
+            replica_debug!(
+                self.node_id,
+                curr_view,
+                "Ignoring proposal for view {:?} from node {:?}, leader is node {:?}",
+                node.view_number(),
+                sender,
+                leader
+            );
+            return None;
+        }
+
+        // refuse oversized proposals before storing them
+        if let Err(oversized) = self.block_limits.check(&node) {
+            replica_log!(self.node_id, "Rejecting oversized block: {:?}", oversized);
             return None;
         }
+
         // b*
         let b_star = Arc::new(RwLock::new(node));
         self.blockstore
             .insert(b_star.read().unwrap().hash(), b_star.clone());
 
         // b″ := b*.justify.node
-        let (Some(b_double_prime), Some(b_star_justify)) =
-            self.get_justifed_block_and_qc(b_star.clone())
-        else {
+        let (Some(_), Some(b_star_justify)) = self.get_justifed_block_and_qc(b_star.clone()) else {
+            if let Block::Normal { justify, .. } = &*b_star.read().unwrap() {
+                // we are missing blocks the rest of the network has certified
+                self.pending_sync_request = Some((sender, justify.block_hash));
+            }
             // replica_debug!(
             //     self.node_id,
             //     self.pacemaker.curr_view,
@@ -441,17 +839,29 @@ impl HotStuffReplica {
             self.safe_node(&b_star.read().unwrap(), &b_star_justify)
         };
 
-        let is_valid_sig = { b_star_justify.verify(&self.validator_set, self.quorum_threshold()) };
+        let is_valid_sig = self.verify_qc(&b_star_justify);
 
         if is_safe && is_valid_sig {
-            let block = b_star.read().unwrap();
+            {
+                let block = b_star.read().unwrap();
+
+                if !Self::validate_block_transactions(&block) {
+                    return None;
+                }
+
+                let block_merkle_root = block.hash_block_transaction();
+                if block_merkle_root != block.merkle_root() {
+                    return None;
+                }
+            }
 
-            if !Self::validate_block_transactions(&block) {
+            self.execute_proposal_base(&b_star);
+            if !self.validate_state_root(&b_star.read().unwrap()) {
+                replica_log!(self.node_id, "Rejecting block with unverifiable state root");
                 return None;
             }
 
-            let block_merkle_root = block.hash_block_transaction();
-            if block_merkle_root != block.merkle_root() {
+            if !self.record_vote(curr_view) {
                 return None;
             }
             outbound_msg = Some(self.vote_message(&b_star.read().unwrap()));
@@ -466,138 +876,445 @@ impl HotStuffReplica {
             return outbound_msg;
         }
 
-        if !self.is_parent(&b_star.read().unwrap(), &b_double_prime.read().unwrap()) {
+        // b* ← b″ ← b′ ← b, as far as each QC certifies the direct parent
+        let chain = self.direct_chain(&b_star);
+
+        // one-chain on b″
+        if chain.is_empty() {
             return outbound_msg;
         }
         self.generic_qc = b_star_justify.clone();
+        self.pacemaker.record_qc(&self.generic_qc);
 
         self.messages.prune_before_view(self.generic_qc.view_number);
 
-        // replica_debug!(
-        //     self.node_id,
-        //     self.pacemaker.curr_view,
-        //     "Start commit phase on b*'s grandparent",
-        // );
-
-        // b′ := b″.justify.node
-        let (Some(b_prime), Some(b_double_prime_justify)) =
-            self.get_justifed_block_and_qc(b_double_prime.clone())
-        else {
-            // replica_debug!(
-            //     self.node_id,
-            //     self.pacemaker.curr_view,
-            //     "Missing b′ justified by b″"
-            // );
+        // two-chain on b′: lock on the QC b″ carries
+        if chain.len() < 2 {
             return outbound_msg;
+        }
+        let b_double_prime_justify = match &*chain[0].read().unwrap() {
+            Block::Normal { justify, .. } => justify.clone(),
+            Block::Genesis { .. } => return outbound_msg,
         };
-
-        let b_double_prime_justify = Arc::new(b_double_prime_justify.clone());
-
-        if !self.is_parent(&b_double_prime.read().unwrap(), &b_prime.read().unwrap()) {
-            return outbound_msg;
+        self.locked_qc = Arc::new(b_double_prime_justify);
+        if let Err(e) = self.persist_safety_state() {
+            replica_log!(self.node_id, "Failed to persist safety state: {:?}", e);
         }
-
-        self.locked_qc = b_double_prime_justify.clone();
         self.pacemaker
             .set_last_committed_view(self.locked_qc.clone());
 
-        // b := b′.justify.node
-        let commited_block = {
-            let Some(b) = self.get_justified_block(&b_prime.read().unwrap()) else {
-                // replica_debug!(
-                //     self.node_id,
-                //     self.pacemaker.curr_view,
-                //     "Missing b justified by b′"
-                // );
-                return outbound_msg;
-            };
-
-            if !self.is_parent(&b_prime.read().unwrap(), &b.read().unwrap()) {
-                return outbound_msg;
-            }
-            b
+        // three-chain on b: commit it along with its uncommitted ancestors
+        let Some(committed_block) = chain.get(2).cloned() else {
+            return outbound_msg;
         };
 
         replica_log!(self.node_id, "Commit success on view: {:?}", curr_view);
-        // replica_debug!(
-        //     self.node_id,
-        //     self.pacemaker.curr_view,
-        //     "Applying transaction, {:?}",
-        //     &commited_block.transactions()
-        // );
-        let account_nonces = self
-            .ledger_state
-            .apply_block(&mut commited_block.write().unwrap());
-        self.remove_block_transactions_from_pending(&commited_block.read().unwrap());
-        self.add_block_transactions_to_committed(&commited_block.read().unwrap());
-        self.mempool.update_after_execution(account_nonces);
+        self.commit_chain(committed_block);
 
         return outbound_msg;
     }
 
-    pub fn replica_handle_vote(&mut self) -> Option<HotStuffMessage> {
-        let curr_view = self.pacemaker.curr_view;
-        if self.node_id != self.pacemaker.get_leader_for_view(curr_view + 1) {
-            return None;
+    /// Walks up from `block` through its justify QCs while each certifies the direct parent.
+    /// Returns up to b″, b′ and b, nearest first; b is committed once all three are found
+    fn direct_chain(&self, block: &Arc<RwLock<Block>>) -> Vec<Arc<RwLock<Block>>> {
+        let mut chain: Vec<Arc<RwLock<Block>>> = vec![];
+        let mut child = block.clone();
+
+        while chain.len() < 3 {
+            let Some(parent) = self.get_justified_block(&child.read().unwrap()) else {
+                break;
+            };
+            if !self.is_parent(&child.read().unwrap(), &parent.read().unwrap()) {
+                break;
+            }
+            chain.push(parent.clone());
+            child = parent;
         }
+        chain
+    }
 
-        if !self.view_progress.replica_has_voted {
-            // Node must vote before attempting to lead the next view, else it's own vote would be missing from the quorum
-            return None;
+    /// A replica that caught up through sync may not have executed the block a proposal
+    /// builds on yet. The three-chain the proposal carries already commits it, so run that first
+    fn execute_proposal_base(&mut self, b_star: &Arc<RwLock<Block>>) {
+        let has_base = match &*b_star.read().unwrap() {
+            Block::Normal {
+                executed_block_hash,
+                ..
+            } => self.executed_state_roots.contains_key(executed_block_hash),
+            Block::Genesis { .. } => true,
+        };
+        if has_base {
+            return;
         }
+        if let Some(committed_block) = self.direct_chain(b_star).get(2).cloned() {
+            self.commit_chain(committed_block);
+        }
+    }
 
-        if !utils::has_quorum_votes_for_view(
-            self.messages.get_messages_for_view(curr_view),
-            curr_view,
-            self.quorum_threshold(),
-        ) {
-            return None;
+    /// Executes `block` along with any ancestors that have not been executed yet, oldest first
+    fn commit_chain(&mut self, block: Arc<RwLock<Block>>) {
+        let mut uncommitted = vec![];
+        let mut next = Some(block);
+
+        while let Some(block) = next {
+            let (block_hash, parent_id) = {
+                let block = block.read().unwrap();
+                match &*block {
+                    Block::Normal { parent_id, .. } => (block.hash(), *parent_id),
+                    Block::Genesis { .. } => break,
+                }
+            };
+
+            if self.executed_state_roots.contains_key(&block_hash) {
+                break;
+            }
+            uncommitted.push(block);
+            next = self.blockstore.get(&parent_id).cloned();
         }
 
-        replica_debug!(
-            self.node_id,
-            self.pacemaker.curr_view,
-            "Optimistically advancing to new view",
-        );
-        // Advance view early without waiting for pacemaker timeout
-        self.pacemaker.advance_view();
+        for block in uncommitted.into_iter().rev() {
+            self.execute_block(&block);
+        }
+    }
 
-        return Some(self.create_new_view());
+    fn execute_block(&mut self, block: &Arc<RwLock<Block>>) {
+        // execute a copy so the stored block keeps its transactions as proposed,
+        // letting it be re-validated when served to lagging replicas
+        let mut executed_block = block.read().unwrap().clone();
+        let outcomes = self.ledger_state.apply_block(&mut executed_block);
+        self.remove_block_transactions_from_pending(&executed_block);
+        self.add_block_transactions_to_committed(&executed_block);
+        for outcome in outcomes
+            .iter()
+            .filter(|outcome| outcome.next_nonce.is_none())
+        {
+            replica_debug!(
+                self.node_id,
+                self.pacemaker.curr_view,
+                "Transaction {} not executed: {:?}",
+                hex::encode(outcome.tx_hash),
+                outcome.status
+            );
+        }
+        self.mempool
+            .update_after_execution(outcomes.iter().map(|outcome| outcome.next_nonce).collect());
+        for expired in self
+            .mempool
+            .remove_expired(self.ledger_state.current_view() + 1)
+        {
+            self.reject_transaction(expired.hash, RejectReason::Expired);
+        }
+
+        if let Some(block_store) = &mut self.block_store
+            && let Err(e) = block_store.append(&block.read().unwrap())
+        {
+            replica_log!(self.node_id, "Failed to persist committed block: {:?}", e);
+        }
+
+        let block_hash = block.read().unwrap().hash();
+        self.executed_state_roots
+            .insert(block_hash, self.ledger_state.state_root());
+        self.last_executed_block = block_hash;
+        self.committed_blocks.push(block_hash);
+        self.metrics
+            .record_committed_block(self.committed_height(), &self.ledger_state);
+        self.metrics.set_mempool_size(self.mempool.len());
+
+        if self.committed_blocks.len() as u64 >= self.checkpoint_interval {
+            self.checkpoint();
+        }
     }
 
-    pub fn replica_handle_message(&mut self, msg: HotStuffMessage) -> Option<HotStuffMessage> {
-        // replica_log!(
-        //     self.node_id,
-        //     "Replica handle message with at view: {:?}",
-        //     self.pacemaker.curr_view
-        // );
+    /// Snapshots the ledger at the last executed block, then drops every block older
+    /// than it from memory and from the block log
+    fn checkpoint(&mut self) {
+        let Some(block) = self.blockstore.get(&self.last_executed_block).cloned() else {
+            return;
+        };
+        let block = block.read().unwrap();
+        let height = self.committed_height();
+
+        if let Some(checkpoint_store) = &self.checkpoint_store {
+            let checkpoint = CheckpointRef {
+                height,
+                block: &block,
+                ledger_state: &self.ledger_state,
+            };
 
-        return match msg {
-            HotStuffMessage::NewView { .. } => {
-                // replica shouldn't handle new view
-                return None;
-            }
-            HotStuffMessage::Proposal { node, sender, .. } => {
-                self.replica_handle_proposal(node, sender)
-            }
-            HotStuffMessage::Vote { .. } => {
-                // Messages that fall in this block are votes sent to the *next* leader
-                // We can try to optimistically advance the view, otherwise we ignore the messages
-                return self.replica_handle_vote();
+            match checkpoint_store.save(&checkpoint) {
+                Ok(()) => {
+                    if let Some(block_store) = &mut self.block_store
+                        && let Err(e) = block_store.clear()
+                    {
+                        replica_log!(self.node_id, "Failed to clear block log: {:?}", e);
+                    }
+                }
+                Err(e) => {
+                    replica_log!(self.node_id, "Failed to save checkpoint: {:?}", e);
+                    return;
+                }
             }
-        };
+        }
+
+        let checkpoint_view = block.view_number();
+        drop(block);
+
+        self.checkpoint_height = height;
+        self.committed_blocks.clear();
+        self.blockstore
+            .retain(|_, block| block.read().unwrap().view_number() >= checkpoint_view);
+        self.executed_state_roots
+            .retain(|block_hash, _| self.blockstore.contains_key(block_hash));
     }
 
-    fn sync_view(&mut self, msg: &HotStuffMessage) -> bool {
-        let incoming_view = msg.get_view_number();
-        self.pacemaker.fast_forward_view(incoming_view)
+    /// Shared with the metrics endpoint, which reads it while the replica runs
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
     }
 
-    async fn handle_message(&mut self, msg: HotStuffMessage) -> Result<(), std::io::Error> {
-        if self.sync_view(&msg) {
-            // replica_debug!(
-            //     self.node_id,
-            //     self.pacemaker.curr_view,
+    pub fn committed_height(&self) -> u64 {
+        self.checkpoint_height + self.committed_blocks.len() as u64
+    }
+
+    /// Checks a block received through sync: the justify QC must be signed by a quorum,
+    /// the block must be within our limits, and the transactions must match the merkle root
+    fn validate_synced_block(&mut self, block: &Block) -> bool {
+        let Block::Normal { justify, .. } = block else {
+            return false;
+        };
+
+        self.verify_qc(justify)
+            && self.block_limits.check(block).is_ok()
+            && block.hash_block_transaction() == block.merkle_root()
+            && Self::validate_block_transactions(block)
+    }
+
+    /// Whether `successor` carries a quorum-signed QC for the block hashed `block_hash`.
+    /// A block's own justify only certifies its parent, so this is what certifies the block
+    fn certifies(&mut self, successor: &Block, block_hash: BlockHash) -> bool {
+        let Block::Normal { justify, .. } = successor else {
+            return false;
+        };
+        justify.block_hash == block_hash && self.verify_qc(justify)
+    }
+
+    /// Serves committed blocks from `from_height`, along with the uncommitted chain up to
+    /// `target_block` so the requester can resume voting.
+    /// Blocks before our last checkpoint have been pruned and cannot be served.
+    pub fn create_sync_response(&self, from_height: u64, target_block: BlockHash) -> SyncMessage {
+        let committed_blocks: Vec<Block> = match from_height.checked_sub(self.checkpoint_height) {
+            Some(offset) => self
+                .committed_blocks
+                .iter()
+                .skip(offset as usize)
+                .take(MAX_SYNC_BLOCKS)
+                .filter_map(|hash| self.blockstore.get(hash))
+                .map(|block| block.read().unwrap().clone())
+                .collect(),
+            None => vec![],
+        };
+
+        let mut pending_blocks = vec![];
+        let mut next = self.blockstore.get(&target_block).cloned();
+        while let Some(block) = next {
+            let block = block.read().unwrap().clone();
+            let Block::Normal { parent_id, .. } = block else {
+                break;
+            };
+
+            if self.executed_state_roots.contains_key(&block.hash())
+                || pending_blocks.len() >= MAX_SYNC_BLOCKS
+            {
+                break;
+            }
+            pending_blocks.push(block);
+            next = self.blockstore.get(&parent_id).cloned();
+        }
+        pending_blocks.reverse();
+
+        SyncMessage::BlockResponse {
+            from_height,
+            committed_blocks,
+            pending_blocks,
+        }
+    }
+
+    /// Applies validated committed blocks from a sync response in order and stores the
+    /// pending blocks. The blocks must form one chain from our last executed block, and each
+    /// committed block must be certified by the justify of the block after it.
+    /// Returns the number of blocks executed.
+    pub fn apply_sync_response(
+        &mut self,
+        from_height: u64,
+        mut committed_blocks: Vec<Block>,
+        mut pending_blocks: Vec<Block>,
+    ) -> usize {
+        if from_height != self.committed_height() {
+            // stale response, our height has changed since the request
+            return 0;
+        }
+
+        // nothing follows the newest committed block to certify it, so it is only stored
+        if pending_blocks.is_empty()
+            && let Some(newest) = committed_blocks.pop()
+        {
+            pending_blocks.push(newest);
+        }
+
+        let mut executed = 0;
+        let mut expected_parent = self.last_executed_block;
+        for (index, block) in committed_blocks.iter().enumerate() {
+            let block_hash = block.hash();
+            if self.executed_state_roots.contains_key(&block_hash) {
+                expected_parent = block_hash;
+                continue;
+            }
+
+            let Some(successor) = committed_blocks.get(index + 1).or(pending_blocks.first()) else {
+                break;
+            };
+            if block.parent_id() != Some(expected_parent)
+                || !self.validate_synced_block(block)
+                || !self.validate_state_root(block)
+                || !self.certifies(successor, block_hash)
+            {
+                replica_log!(self.node_id, "Rejecting invalid block from sync");
+                return executed;
+            }
+
+            let block = Arc::new(RwLock::new(block.clone()));
+            self.blockstore.insert(block_hash, block.clone());
+            self.execute_block(&block);
+            expected_parent = block_hash;
+            executed += 1;
+        }
+
+        for block in pending_blocks {
+            let block_hash = block.hash();
+            if self.executed_state_roots.contains_key(&block_hash) {
+                expected_parent = block_hash;
+                continue;
+            }
+            if block.parent_id() != Some(expected_parent) || !self.validate_synced_block(&block) {
+                break;
+            }
+            self.blockstore
+                .insert(block_hash, Arc::new(RwLock::new(block)));
+            expected_parent = block_hash;
+        }
+
+        if executed > 0 {
+            replica_log!(
+                self.node_id,
+                "Synced {:?} blocks, committed height: {:?}",
+                executed,
+                self.committed_height()
+            );
+        }
+        executed
+    }
+
+    async fn send_pending_sync_request(&mut self) -> Result<(), std::io::Error> {
+        let Some((peer_id, target_block)) = self.pending_sync_request.take() else {
+            return Ok(());
+        };
+
+        let request = SyncMessage::BlockRequest {
+            from_height: self.committed_height(),
+            target_block,
+            sender: self.node_id,
+        };
+        self.rep_node_channel.send_sync(peer_id, request).await
+    }
+
+    async fn handle_sync_message(&mut self, msg: SyncMessage) -> Result<(), std::io::Error> {
+        match msg {
+            SyncMessage::BlockRequest {
+                from_height,
+                target_block,
+                sender,
+            } => {
+                let response = self.create_sync_response(from_height, target_block);
+                self.rep_node_channel.send_sync(sender, response).await
+            }
+            SyncMessage::BlockResponse {
+                from_height,
+                committed_blocks,
+                pending_blocks,
+            } => {
+                self.apply_sync_response(from_height, committed_blocks, pending_blocks);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn replica_handle_vote(&mut self) -> Option<HotStuffMessage> {
+        let curr_view = self.pacemaker.curr_view;
+        if self.node_id != self.pacemaker.leader_for_view(curr_view + 1) {
+            return None;
+        }
+
+        if !self.view_progress.replica_has_voted {
+            // Node must vote before attempting to lead the next view, else it's own vote would be missing from the quorum
+            return None;
+        }
+
+        if !utils::has_quorum_votes_for_view(
+            self.messages.get_messages_for_view(curr_view),
+            curr_view,
+            self.quorum_threshold(),
+        ) {
+            return None;
+        }
+
+        replica_debug!(
+            self.node_id,
+            self.pacemaker.curr_view,
+            "Optimistically advancing to new view",
+        );
+        // Advance view early without waiting for pacemaker timeout
+        self.pacemaker.advance_view();
+
+        return Some(self.create_new_view());
+    }
+
+    pub fn replica_handle_message(&mut self, msg: HotStuffMessage) -> Option<HotStuffMessage> {
+        // replica_log!(
+        //     self.node_id,
+        //     "Replica handle message with at view: {:?}",
+        //     self.pacemaker.curr_view
+        // );
+
+        return match msg {
+            HotStuffMessage::NewView { .. } => {
+                // replica shouldn't handle new view
+                return None;
+            }
+            HotStuffMessage::Proposal { node, sender, .. } => {
+                self.replica_handle_proposal(node, sender)
+            }
+            HotStuffMessage::Vote { .. } => {
+                // Messages that fall in this block are votes sent to the *next* leader
+                // We can try to optimistically advance the view, otherwise we ignore the messages
+                return self.replica_handle_vote();
+            }
+            HotStuffMessage::Timeout { .. } => {
+                // timeouts are aggregated into a TC in handle_message
+                return None;
+            }
+        };
+    }
+
+    fn sync_view(&mut self, msg: &HotStuffMessage) -> bool {
+        let incoming_view = msg.get_view_number();
+        self.pacemaker.fast_forward_view(incoming_view)
+    }
+
+    async fn handle_message(&mut self, msg: HotStuffMessage) -> Result<(), std::io::Error> {
+        if self.sync_view(&msg) {
+            // replica_debug!(
+            //     self.node_id,
+            //     self.pacemaker.curr_view,
             //     "Recieved higher view message from node: {:?}",
             //     msg.sender
             // );
@@ -621,7 +1338,19 @@ impl HotStuffReplica {
             // );
         }
 
-        self.messages.push(msg.clone());
+        if !self.record_message(&msg) {
+            return Ok(());
+        }
+
+        if let HotStuffMessage::Timeout { view, .. } = msg
+            && self.advance_view_with_tc(view)
+        {
+            replica_log!(
+                self.node_id,
+                "Advanced to view {:?} with timeout certificate",
+                self.pacemaker.curr_view
+            );
+        }
 
         let leader = self.pacemaker.current_leader();
         let is_leader = self.node_id == leader;
@@ -631,43 +1360,7 @@ impl HotStuffReplica {
         // Every node executes replica logic
         // Leader executes leader logic and sends message to itself to handle as replica
         if is_leader && !self.view_progress.leader_has_proposed {
-            let leader_outbound_msg_opt = self.leader_handle_message();
-            let Some(leader_outbound_msg) = leader_outbound_msg_opt else {
-                return Ok(());
-            };
-
-            self.rep_node_channel
-                .broadcast(leader_outbound_msg.clone())
-                .await?;
-            self.view_progress.leader_has_proposed = true;
-
-            // handle leader's msg as replica
-            // Dont send to same channel to prevent data race
-            self.messages.push(leader_outbound_msg.clone());
-            let replica_outbound_msg_opt = self.replica_handle_message(leader_outbound_msg.clone());
-
-            let Some(replica_outbound_msg) = replica_outbound_msg_opt else {
-                return Ok(());
-            };
-            let next_leader = self
-                .pacemaker
-                .get_leader_for_view(self.pacemaker.curr_view + 1);
-
-            // replica_debug!(
-            //     self.node_id,
-            //     self.pacemaker.curr_view,
-            //     "Sending to next leader: {:?} with msg view: {:?}",
-            //     next_leader,
-            //     replica_outbound_msg.view_number
-            // );
-
-            self.rep_node_channel
-                .send_to_node(next_leader, replica_outbound_msg)
-                .await?;
-
-            self.view_progress.replica_has_voted = true;
-
-            return Ok(());
+            return self.leader_propose().await;
         }
 
         // Handle as replica only
@@ -677,7 +1370,7 @@ impl HotStuffReplica {
             return Ok(());
         };
 
-        let next_leader = self.pacemaker.get_leader_for_view(curr_view + 1);
+        let next_leader = self.pacemaker.leader_for_view(curr_view + 1);
 
         self.view_progress.replica_has_voted = true;
 
@@ -705,6 +1398,59 @@ impl HotStuffReplica {
         Ok(())
     }
 
+    /// Broadcasts the leader's proposal, if it is ready, and votes for it as a replica
+    async fn leader_propose(&mut self) -> Result<(), std::io::Error> {
+        let leader_outbound_msg_opt = self.leader_handle_message();
+        let Some(leader_outbound_msg) = leader_outbound_msg_opt else {
+            return Ok(());
+        };
+
+        self.rep_node_channel
+            .broadcast(leader_outbound_msg.clone())
+            .await?;
+        self.view_progress.leader_has_proposed = true;
+
+        // handle leader's msg as replica
+        // Dont send to same channel to prevent data race
+        self.messages.push(leader_outbound_msg.clone());
+        let replica_outbound_msg_opt = self.replica_handle_message(leader_outbound_msg.clone());
+
+        let Some(replica_outbound_msg) = replica_outbound_msg_opt else {
+            return Ok(());
+        };
+        let next_leader = self.pacemaker.leader_for_view(self.pacemaker.curr_view + 1);
+
+        // replica_debug!(
+        //     self.node_id,
+        //     self.pacemaker.curr_view,
+        //     "Sending to next leader: {:?} with msg view: {:?}",
+        //     next_leader,
+        //     replica_outbound_msg.view_number
+        // );
+
+        self.rep_node_channel
+            .send_to_node(next_leader, replica_outbound_msg)
+            .await?;
+
+        self.view_progress.replica_has_voted = true;
+
+        Ok(())
+    }
+
+    /// Proposes the block held back by the batching window once it is ready
+    async fn propose_pending_batch(&mut self) -> Result<(), std::io::Error> {
+        if !self.pacemaker.batch_pending() || self.view_progress.leader_has_proposed {
+            return Ok(());
+        }
+        self.leader_propose().await?;
+
+        // window ran out without a proposal, stop waking up for it
+        if self.pacemaker.batch_time_remaining() == Some(Duration::ZERO) {
+            self.pacemaker.close_batch();
+        }
+        Ok(())
+    }
+
     fn create_new_view(&mut self) -> HotStuffMessage {
         HotStuffMessage::create_new_view(
             (*self.generic_qc).clone(),
@@ -714,16 +1460,73 @@ impl HotStuffReplica {
         )
     }
 
-    fn handle_query(&self, query_request: QueryRequest) {
-        let client_response = self.ledger_state.handle_query(query_request.query);
+    fn handle_query(&mut self, query_request: QueryRequest) {
+        let client_response = match query_request.query {
+            ClientQuery::HealthQuery => ClientResponse::HealthQueryResponse(self.health()),
+            ClientQuery::SubscribeAccount(public_key) => {
+                ClientResponse::AccountSubscription(self.ledger_state.subscribe_account(public_key))
+            }
+            ClientQuery::ReceiptQuery(tx_hash) => {
+                ClientResponse::ReceiptQueryResponse(self.transaction_receipt(&tx_hash))
+            }
+            query => self.ledger_state.handle_query(query),
+        };
         let _ = query_request.response_channel.send(client_response);
     }
 
+    /// Receipt of a transaction, pending while it sits in a block we voted for and
+    /// committed once the three-chain rule executes that block. Committed transactions
+    /// without a receipt of their own, such as transfers, report their final status
+    fn transaction_receipt(&self, tx_hash: &Sha256Hash) -> Option<TransactionReceipt> {
+        if let Some(receipt) = self.ledger_state.get_receipt(tx_hash) {
+            return Some(receipt);
+        }
+        if let Some(transaction) = self.committed_transactions.get(tx_hash) {
+            return Some(TransactionReceipt::new(*tx_hash, transaction.get_status()));
+        }
+        self.pending_transactions
+            .contains_key(tx_hash)
+            .then(|| TransactionReceipt::pending(*tx_hash))
+    }
+
+    /// Consensus progress, without the peer count which only the node knows
+    fn health(&self) -> NodeHealth {
+        NodeHealth {
+            committed_height: self.committed_height(),
+            view: self.pacemaker.curr_view,
+            mempool_size: self.mempool.len(),
+            peer_count: 0,
+        }
+    }
+
     fn handle_transaction(&mut self, txn: SignedTransaction) {
         let account_info = self
             .ledger_state
             .get_account_info_or_default(&txn.get_from_account());
-        self.mempool.insert(txn, account_info.expected_nonce);
+        let tx_hash = txn.hash;
+
+        // leave a receipt for transactions dropped by the mempool so the submitter can find out
+        match self.mempool.insert(txn, account_info.expected_nonce) {
+            Ok(None) => {}
+            Ok(Some(evicted)) => {
+                self.reject_transaction(evicted.hash, RejectReason::EvictedFromMempool)
+            }
+            Err(MempoolError::Full) => self.reject_transaction(tx_hash, RejectReason::MempoolFull),
+            Err(MempoolError::Expired) => self.reject_transaction(tx_hash, RejectReason::Expired),
+            // stale and duplicate transactions may already have a receipt from execution,
+            // and a forged signature must not overwrite the receipt of the genuine transaction
+            Err(
+                MempoolError::InvalidSignature | MempoolError::StaleNonce | MempoolError::Duplicate,
+            ) => {}
+        }
+        self.metrics.set_mempool_size(self.mempool.len());
+    }
+
+    fn reject_transaction(&mut self, tx_hash: Sha256Hash, reason: RejectReason) {
+        self.ledger_state.receipts.insert(TransactionReceipt::new(
+            tx_hash,
+            TransactionStatus::Rejected(reason),
+        ));
     }
 
     async fn send_new_view_to_leader(&mut self) -> Result<(), std::io::Error> {
@@ -741,39 +1544,1385 @@ impl HotStuffReplica {
         }
     }
 
+    /// Broadcasts a signed timeout for the current view so that replicas yet to time out
+    /// can advance once a TC forms, instead of waiting on their own timers
+    async fn broadcast_timeout(&mut self) -> Result<(), std::io::Error> {
+        let timeout_msg = self.timeout_message();
+        // our own timeout counts towards the TC
+        self.messages.push(timeout_msg.clone());
+        self.rep_node_channel.broadcast(timeout_msg).await
+    }
+
     async fn advance_view(&mut self) -> Result<(), std::io::Error> {
+        self.broadcast_timeout().await?;
         self.pacemaker.advance_view();
+        self.metrics.record_view_change();
         self.view_progress.reset();
         self.send_new_view_to_leader().await?;
         Ok(())
     }
 
+    async fn handle_inbound(&mut self, msg: ReplicaInBound) -> Result<(), std::io::Error> {
+        match msg {
+            ReplicaInBound::HotStuff(msg) => {
+                self.handle_message(msg).await?;
+                self.send_pending_sync_request().await
+            }
+            ReplicaInBound::Sync(msg) => self.handle_sync_message(msg).await,
+            ReplicaInBound::Transaction(tx) => {
+                self.handle_transaction(tx);
+                self.propose_pending_batch().await
+            }
+            ReplicaInBound::Query(query) => {
+                self.handle_query(query);
+                Ok(())
+            }
+        }
+    }
+
+    /// Forces everything the replica has persisted so far to disk
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.persist_safety_state()?;
+        if let Some(block_store) = &mut self.block_store {
+            block_store.flush()?;
+        }
+        Ok(())
+    }
+
     pub async fn run_replica(
         &mut self,
         mut to_replica_rx: mpsc::Receiver<ReplicaInBound>,
+        mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<(), std::io::Error> {
         replica_log!(self.node_id, "Running replica...");
         loop {
-            // Refresh pacemaker timer dynamically each loop
+            // Refresh pacemaker timer dynamically each loop, waking early for an open batch
             let time_remaining = self.pacemaker.time_remaining();
+            let time_remaining = match self.pacemaker.batch_time_remaining() {
+                Some(batch_remaining) => time_remaining.min(batch_remaining),
+                None => time_remaining,
+            };
             let pacemaker_timer = sleep(time_remaining);
             pin!(pacemaker_timer);
 
             tokio::select! {
-                Some(msg) = to_replica_rx.recv() => {
-                    match msg {
-                        ReplicaInBound::HotStuff(msg) => self.handle_message(msg).await?,
-                        ReplicaInBound::Transaction(tx) => self.handle_transaction(tx),
-                        ReplicaInBound::Query(query) => self.handle_query(query),
-                    }
-                },
+                Some(msg) = to_replica_rx.recv() => self.handle_inbound(msg).await?,
 
                 _ = &mut pacemaker_timer => {
                     if self.pacemaker.should_advance_view() {
                         self.advance_view().await?;
+                    } else {
+                        self.propose_pending_batch().await?;
+                    }
+                }
+
+                _ = shutdown_rx.recv() => {
+                    // finish handling messages that arrived before the shutdown
+                    while let Ok(msg) = to_replica_rx.try_recv() {
+                        self.handle_inbound(msg).await?;
                     }
+                    self.flush()?;
+
+                    replica_log!(
+                        self.node_id,
+                        "Shut down at view {:?}, committed height: {:?}",
+                        self.pacemaker.curr_view,
+                        self.committed_height()
+                    );
+                    return Ok(());
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_replica(node_id: usize) -> HotStuffReplica {
+        let (replica_tx, _) = mpsc::channel(16);
+        let (node_tx, _) = mpsc::channel(16);
+        HotStuffReplica::new(node_id, replica_tx, node_tx)
+    }
+
+    fn create_first_proposal(state_root: Sha256Hash) -> Block {
+        let (genesis_block, genesis_qc) = Block::create_genesis_block();
+        Block::create_leaf(
+            &genesis_block,
+            vec![],
+            1,
+            genesis_qc,
+            state_root,
+            genesis_block.hash(),
+        )
+    }
+
+    mod test_state_root_validation {
+        use super::*;
+
+        #[test]
+        fn test_votes_for_block_with_matching_state_root() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            let vote = replica.replica_handle_proposal(block, leader);
+
+            assert!(matches!(vote, Some(HotStuffMessage::Vote { .. })));
+        }
+
+        #[test]
+        fn test_rejects_block_with_tampered_state_root() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let mut tampered_root = replica.ledger_state.state_root();
+            tampered_root[0] ^= 1;
+
+            let block = create_first_proposal(tampered_root);
+            let vote = replica.replica_handle_proposal(block, leader);
+
+            assert!(vote.is_none());
+        }
+
+        #[test]
+        fn test_rejects_block_built_on_unexecuted_block() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let (genesis_block, genesis_qc) = Block::create_genesis_block();
+            let block = Block::create_leaf(
+                &genesis_block,
+                vec![],
+                1,
+                genesis_qc,
+                replica.ledger_state.state_root(),
+                [7; 32],
+            );
+            let vote = replica.replica_handle_proposal(block, leader);
+
+            assert!(vote.is_none());
+        }
+    }
+
+    mod test_block_limits {
+        use super::*;
+        use crate::types::transaction::{TransferTransaction, UnsignedTransaction};
+
+        fn proposal_with_transfers(replica: &HotStuffReplica, count: u64) -> Block {
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let transactions = (0..count)
+                .map(|nonce| {
+                    UnsignedTransaction::Transfer(TransferTransaction {
+                        from: faucet_sk.verifying_key().to_bytes(),
+                        to: [7; 32],
+                        amount: 1_000,
+                        asset_id: 0,
+                        nonce,
+                        status: TransactionStatus::Pending,
+                        valid_until: None,
+                    })
+                    .sign(&mut faucet_sk)
+                })
+                .collect();
+
+            let (genesis_block, genesis_qc) = Block::create_genesis_block();
+            Block::create_leaf(
+                &genesis_block,
+                transactions,
+                1,
+                genesis_qc,
+                replica.ledger_state.state_root(),
+                genesis_block.hash(),
+            )
+        }
+
+        fn propose_with_limits(
+            block_limits: BlockLimits,
+            transfers: u64,
+        ) -> Option<HotStuffMessage> {
+            let mut replica = create_replica(0);
+            replica.block_limits = block_limits;
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let block = proposal_with_transfers(&replica, transfers);
+            let block_hash = block.hash();
+            let vote = replica.replica_handle_proposal(block, leader);
+            assert_eq!(vote.is_some(), replica.blockstore.contains_key(&block_hash));
+            vote
+        }
+
+        #[test]
+        fn test_votes_for_block_at_transaction_limit() {
+            let vote = propose_with_limits(BlockLimits::new(3, u64::MAX), 3);
+            assert!(matches!(vote, Some(HotStuffMessage::Vote { .. })));
+        }
+
+        #[test]
+        fn test_rejects_block_over_transaction_limit() {
+            let vote = propose_with_limits(BlockLimits::new(3, u64::MAX), 4);
+            assert!(vote.is_none());
+        }
+
+        #[test]
+        fn test_votes_for_block_at_size_limit() {
+            let block = proposal_with_transfers(&create_replica(0), 3);
+            let size = bincode::serialized_size(&block).unwrap();
+
+            let vote = propose_with_limits(BlockLimits::new(usize::MAX, size), 3);
+            assert!(matches!(vote, Some(HotStuffMessage::Vote { .. })));
+        }
+
+        #[test]
+        fn test_rejects_block_over_size_limit() {
+            let block = proposal_with_transfers(&create_replica(0), 3);
+            let size = bincode::serialized_size(&block).unwrap();
+
+            let vote = propose_with_limits(BlockLimits::new(usize::MAX, size - 1), 3);
+            assert!(vote.is_none());
+        }
+    }
+
+    mod test_timeout_certificate {
+        use super::*;
+
+        /// Replicas at view 1 whose leader never proposed, along with their timeouts
+        pub(super) fn create_timed_out_replicas() -> (Vec<HotStuffReplica>, Vec<HotStuffMessage>) {
+            let mut replicas: Vec<HotStuffReplica> = (0..config::retrieve_num_validators())
+                .map(create_replica)
+                .collect();
+            let silent_leader = replicas[0].pacemaker.leader_for_view(1);
+
+            let timeouts = replicas
+                .iter_mut()
+                .filter(|replica| replica.node_id != silent_leader)
+                .map(|replica| {
+                    replica.pacemaker.curr_view = 1;
+                    replica.timeout_message()
+                })
+                .collect();
+
+            (replicas, timeouts)
+        }
+
+        #[test]
+        fn test_replicas_advance_via_tc_when_leader_is_silent() {
+            let (mut replicas, timeouts) = create_timed_out_replicas();
+            let quorum = replicas[0].quorum_threshold();
+            let next_leader = replicas[0].pacemaker.leader_for_view(2);
+            let replica = &mut replicas[next_leader];
+
+            for (i, timeout) in timeouts.iter().enumerate() {
+                replica.messages.push(timeout.clone());
+                let advanced = replica.advance_view_with_tc(1);
+                assert_eq!(advanced, i + 1 == quorum);
+            }
+
+            assert_eq!(replica.pacemaker.curr_view, 2);
+            let tc = replica
+                .get_highest_tc()
+                .cloned()
+                .expect("Expected timeout certificate");
+            assert_eq!(tc.view_number, 1);
+            assert!(tc.verify(&replica.validator_set, quorum));
+
+            let Some(HotStuffMessage::Proposal { node, view, .. }) =
+                replica.leader_handle_message()
+            else {
+                panic!("Expected new leader to propose");
+            };
+            assert_eq!(view, 2);
+            let Block::Normal { justify, .. } = node else {
+                panic!("Expected normal block");
+            };
+            assert_eq!(justify, tc.high_qc);
+        }
+
+        #[test]
+        fn test_only_leader_of_view_proposes() {
+            let (mut replicas, timeouts) = create_timed_out_replicas();
+            let next_leader = replicas[0].pacemaker.leader_for_view(2);
+
+            for replica in replicas.iter_mut() {
+                for timeout in timeouts.iter() {
+                    replica.messages.push(timeout.clone());
+                }
+                replica.advance_view_with_tc(1);
+                assert_eq!(replica.pacemaker.curr_view, 2);
+
+                let proposal = replica.leader_handle_message();
+                if replica.node_id == next_leader {
+                    assert!(matches!(proposal, Some(HotStuffMessage::Proposal { .. })));
+                } else {
+                    assert!(proposal.is_none());
+                }
+            }
+        }
+
+        #[test]
+        fn test_replica_does_not_advance_without_quorum_of_timeouts() {
+            let (mut replicas, timeouts) = create_timed_out_replicas();
+            let quorum = replicas[0].quorum_threshold();
+            let replica = &mut replicas[0];
+
+            for timeout in timeouts.iter().take(quorum - 1) {
+                replica.messages.push(timeout.clone());
+            }
+
+            assert!(!replica.advance_view_with_tc(1));
+            assert_eq!(replica.pacemaker.curr_view, 1);
+            assert!(replica.get_highest_tc().is_none());
+        }
+    }
+
+    mod test_vote_collection {
+        use super::*;
+
+        fn vote(block: &Block, node_id: usize) -> HotStuffMessage {
+            HotStuffMessage::create_vote(
+                block.clone(),
+                1,
+                node_id,
+                1,
+                &mut config::retrieve_signing_key(node_id),
+            )
+        }
+
+        fn has_quorum_votes(replica: &HotStuffReplica) -> bool {
+            utils::has_quorum_votes_for_view(
+                replica.messages.get_messages_for_view(1),
+                1,
+                replica.quorum_threshold(),
+            )
+        }
+
+        #[test]
+        fn test_duplicate_votes_count_once() {
+            let mut replica = create_replica(0);
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            let quorum = replica.quorum_threshold();
+
+            for node_id in 0..quorum - 1 {
+                let vote = vote(&block, node_id);
+                assert!(replica.record_message(&vote));
+                assert!(!replica.record_message(&vote));
+
+                // redelivered with different routing fields, still the same validator's vote
+                let HotStuffMessage::Vote {
+                    node,
+                    partial_sig,
+                    view,
+                    sender,
+                    ..
+                } = vote
+                else {
+                    unreachable!();
+                };
+                let resent = HotStuffMessage::Vote {
+                    node,
+                    partial_sig,
+                    view,
+                    sender,
+                    sender_view: 2,
+                };
+                assert!(replica.record_message(&resent));
+            }
+            assert!(!has_quorum_votes(&replica));
+            assert!(replica.try_create_qc_for_view(1).is_none());
+
+            assert!(replica.record_message(&vote(&block, quorum - 1)));
+            assert!(has_quorum_votes(&replica));
+            let qc = replica.try_create_qc_for_view(1).expect("Expected QC");
+            assert_eq!(qc.partial_sigs.len(), quorum);
+        }
+
+        #[test]
+        fn test_late_votes_are_ignored() {
+            let mut replica = create_replica(0);
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            let quorum = replica.quorum_threshold();
+
+            for node_id in 0..quorum {
+                assert!(replica.record_message(&vote(&block, node_id)));
+            }
+            let qc = replica.try_create_qc_for_view(1).expect("Expected QC");
+            replica.generic_qc = Arc::new(qc.clone());
+            let stored = replica.messages.get_messages_for_view(1).unwrap().len();
+
+            for node_id in quorum..config::retrieve_num_validators() {
+                assert!(!replica.record_message(&vote(&block, node_id)));
+            }
+            assert_eq!(
+                replica.messages.get_messages_for_view(1).unwrap().len(),
+                stored
+            );
+            assert_eq!(replica.try_create_qc_for_view(1), Some(qc));
+        }
+    }
+
+    mod test_leader_schedule {
+        use super::*;
+
+        #[test]
+        fn test_drops_proposal_from_non_leader() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+            let impostor = (leader + 1) % config::retrieve_num_validators();
+
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            assert!(
+                replica
+                    .replica_handle_proposal(block.clone(), impostor)
+                    .is_none()
+            );
+            assert!(!replica.blockstore.contains_key(&block.hash()));
+
+            let vote = replica.replica_handle_proposal(block, leader);
+            assert!(matches!(vote, Some(HotStuffMessage::Vote { .. })));
+        }
+
+        #[test]
+        fn test_drops_leader_proposal_for_another_view() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 2;
+            let leader = replica.pacemaker.leader_for_view(2);
+
+            // built for view 1, replayed by the leader of view 2
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            assert!(replica.replica_handle_proposal(block, leader).is_none());
+        }
+    }
+
+    mod test_safety_state {
+        use super::*;
+
+        #[test]
+        fn test_restarted_replica_does_not_vote_twice() {
+            let path = std::env::temp_dir().join(format!(
+                "superliquid_replica_restart_{}.json",
+                std::process::id()
+            ));
+
+            let mut replica = create_replica(0);
+            replica
+                .restore_safety_state(SafetyStore::new(&path))
+                .unwrap();
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            let vote = replica.replica_handle_proposal(block.clone(), leader);
+            assert!(matches!(vote, Some(HotStuffMessage::Vote { .. })));
+
+            // simulate restart
+            let mut restarted = create_replica(0);
+            restarted
+                .restore_safety_state(SafetyStore::new(&path))
+                .unwrap();
+            assert_eq!(restarted.last_voted_view, 1);
+            assert_eq!(restarted.pacemaker.curr_view, 1);
+
+            let vote = restarted.replica_handle_proposal(block, leader);
+            assert!(vote.is_none());
+
+            std::fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn test_replica_votes_once_per_view() {
+            let mut replica = create_replica(0);
+            replica.pacemaker.curr_view = 1;
+            let leader = replica.pacemaker.leader_for_view(1);
+
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            assert!(
+                replica
+                    .replica_handle_proposal(block.clone(), leader)
+                    .is_some()
+            );
+            assert!(replica.replica_handle_proposal(block, leader).is_none());
+        }
+    }
+
+    mod test_shutdown {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_shutdown_flushes_queued_messages() {
+            let path = std::env::temp_dir().join(format!(
+                "superliquid_replica_shutdown_{}.json",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+
+            let leader = create_replica(0).pacemaker.leader_for_view(1);
+            // neither this view's leader nor the next, so the vote goes out to the node
+            let node_id = (leader + 2) % config::retrieve_num_validators();
+
+            let (replica_tx, replica_rx) = mpsc::channel(16);
+            let (node_tx, mut node_rx) = mpsc::channel(16);
+            let mut replica = HotStuffReplica::new(node_id, replica_tx.clone(), node_tx);
+            replica
+                .restore_safety_state(SafetyStore::new(&path))
+                .unwrap();
+            replica.pacemaker.curr_view = 1;
+
+            // a proposal is still queued when the shutdown is triggered
+            let block = create_first_proposal(replica.ledger_state.state_root());
+            let proposal = HotStuffMessage::create_proposal(block, 1, leader, 1);
+            replica_tx
+                .send(ReplicaInBound::HotStuff(proposal))
+                .await
+                .unwrap();
+
+            let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+            shutdown_tx.send(()).unwrap();
+            replica.run_replica(replica_rx, shutdown_rx).await.unwrap();
+
+            assert!(matches!(
+                node_rx.try_recv(),
+                Ok(ReplicaOutbound::SendTo(_, HotStuffMessage::Vote { .. }))
+            ));
+            let safety_state = SafetyStore::new(&path).load().unwrap().unwrap();
+            assert_eq!(safety_state.last_voted_view, 1);
+
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    mod test_participation {
+        use super::*;
+        use crate::hotstuff::message::vote_hash;
+        use ed25519::signature::SignerMut;
+
+        /// QC for a block of `view` signed by the validators with the given ids
+        fn create_qc(view: ViewNumber, signers: &[usize]) -> QuorumCertificate {
+            let block_hash = [view as u8; 32];
+            let message_hash = vote_hash(view, block_hash);
+            let signatures: Vec<PartialSig> = signers
+                .iter()
+                .map(|id| {
+                    let mut sk = config::retrieve_signing_key(*id);
+                    PartialSig::new(sk.verifying_key(), sk.sign(&message_hash))
+                })
+                .collect();
+
+            QuorumCertificate::from_signatures(
+                view,
+                block_hash,
+                message_hash,
+                signatures.iter().collect(),
+            )
+        }
+
+        #[test]
+        fn test_absent_validator_has_lower_participation() {
+            let mut replica = create_replica(0);
+            let num_validators = config::retrieve_num_validators();
+            let silent = num_validators - 1;
+            let quorum = replica.quorum_threshold();
+            let signers: Vec<usize> = (0..num_validators)
+                .filter(|id| *id != silent)
+                .take(quorum)
+                .collect();
+
+            assert!(replica.verify_qc(&create_qc(1, &(0..num_validators).collect::<Vec<_>>())));
+            for view in 2..=4 {
+                assert!(replica.verify_qc(&create_qc(view, &signers)));
+            }
+
+            let participation = replica.qc_participation();
+            assert_eq!(participation[signers[0]], 4);
+            assert_eq!(participation[silent], 1);
+            assert_eq!(
+                replica.metrics().validator_qc_signatures(),
+                participation.to_vec()
+            );
+        }
+
+        #[test]
+        fn test_qc_for_counted_view_is_not_counted_again() {
+            let mut replica = create_replica(0);
+            let signers: Vec<usize> = (0..replica.quorum_threshold()).collect();
+
+            let qc = create_qc(1, &signers);
+            assert!(replica.verify_qc(&qc));
+            assert!(replica.verify_qc(&qc));
+
+            assert_eq!(replica.qc_participation()[0], 1);
+        }
+
+        #[test]
+        fn test_qc_without_quorum_is_not_counted() {
+            let mut replica = create_replica(0);
+            let signers: Vec<usize> = (0..replica.quorum_threshold() - 1).collect();
+
+            assert!(!replica.verify_qc(&create_qc(1, &signers)));
+            assert!(replica.qc_participation().iter().all(|count| *count == 0));
+        }
+    }
+
+    mod test_state_sync {
+        use super::*;
+        use crate::{
+            hotstuff::message::vote_hash,
+            types::transaction::{TransactionStatus, TransferTransaction, UnsignedTransaction},
+        };
+        use ed25519::signature::SignerMut;
+
+        /// QC for `block` signed by every validator
+        pub(super) fn certify(block: &Block, view: ViewNumber) -> QuorumCertificate {
+            let message_hash = vote_hash(view, block.hash());
+            let signatures: Vec<PartialSig> = (0..config::retrieve_num_validators())
+                .map(|id| {
+                    let mut sk = config::retrieve_signing_key(id);
+                    PartialSig::new(sk.verifying_key(), sk.sign(&message_hash))
+                })
+                .collect();
+
+            QuorumCertificate::from_signatures(
+                view,
+                block.hash(),
+                message_hash,
+                signatures.iter().collect(),
+            )
+        }
+
+        /// Chain of certified blocks for views 1..=length, the first funds an account
+        pub(super) fn build_chain(length: ViewNumber) -> Vec<Block> {
+            let (genesis_block, genesis_qc) = Block::create_genesis_block();
+            let mut ledger_state = LedgerState::new();
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+
+            // a proposal at view v builds on the block at view v - 4, the last one
+            // executed once the proposal for view v - 1 committed it
+            let mut executed = vec![(genesis_block.hash(), ledger_state.state_root())];
+            let mut blocks: Vec<Block> = vec![];
+            let mut parent = genesis_block.clone();
+            let mut justify = genesis_qc;
+
+            for view in 1..=length {
+                let transactions = if view == 1 {
+                    let unsigned = UnsignedTransaction::Transfer(TransferTransaction {
+                        from: faucet_sk.verifying_key().to_bytes(),
+                        to: [7; 32],
+                        amount: 1_000,
+                        asset_id: 0,
+                        nonce: 0,
+                        status: TransactionStatus::Pending,
+                        valid_until: None,
+                    });
+                    vec![unsigned.sign(&mut faucet_sk)]
+                } else {
+                    vec![]
+                };
+
+                let (executed_block_hash, state_root) = executed[view.saturating_sub(4) as usize];
+                let block = Block::create_leaf(
+                    &parent,
+                    transactions,
+                    view,
+                    justify,
+                    state_root,
+                    executed_block_hash,
+                );
+                ledger_state.apply_block(&mut block.clone());
+                executed.push((block.hash(), ledger_state.state_root()));
+                justify = certify(&block, view);
+                parent = block.clone();
+                blocks.push(block);
+            }
+
+            blocks
+        }
+
+        pub(super) fn propose(
+            replica: &mut HotStuffReplica,
+            block: &Block,
+            view: ViewNumber,
+        ) -> Option<HotStuffMessage> {
+            replica.pacemaker.curr_view = view;
+            let leader = replica.pacemaker.leader_for_view(view);
+            replica.replica_handle_proposal(block.clone(), leader)
+        }
+
+        #[test]
+        fn test_lagging_replica_catches_up_via_sync() {
+            let chain = build_chain(8);
+
+            let mut up_to_date = create_replica(0);
+            for (i, block) in chain[..6].iter().enumerate() {
+                assert!(propose(&mut up_to_date, block, i as ViewNumber + 1).is_some());
+            }
+            assert_eq!(up_to_date.committed_height(), 3);
+
+            // late replica sees the view 7 proposal without the blocks it builds on
+            let mut late = create_replica(1);
+            assert!(propose(&mut late, &chain[6], 7).is_none());
+            let (peer_id, target_block) = late
+                .pending_sync_request
+                .take()
+                .expect("Expected sync request");
+            assert_eq!(peer_id, late.pacemaker.leader_for_view(7));
+            assert_eq!(target_block, chain[5].hash());
+
+            let SyncMessage::BlockResponse {
+                from_height,
+                committed_blocks,
+                pending_blocks,
+            } = up_to_date.create_sync_response(late.committed_height(), target_block)
+            else {
+                panic!("Expected block response");
+            };
+            assert_eq!(pending_blocks.len(), 3);
+
+            let executed = late.apply_sync_response(from_height, committed_blocks, pending_blocks);
+            assert_eq!(executed, 3);
+            assert_eq!(late.committed_height(), 3);
+            assert_eq!(
+                late.ledger_state.state_root(),
+                up_to_date.ledger_state.state_root()
+            );
+
+            // both replicas continue on the same chain
+            assert!(propose(&mut up_to_date, &chain[6], 7).is_some());
+            assert!(propose(&mut up_to_date, &chain[7], 8).is_some());
+            assert!(propose(&mut late, &chain[7], 8).is_some());
+
+            assert_eq!(late.committed_height(), 5);
+            assert_eq!(late.committed_height(), up_to_date.committed_height());
+            assert_eq!(
+                late.ledger_state.state_root(),
+                up_to_date.ledger_state.state_root()
+            );
+        }
+
+        #[test]
+        fn test_sync_rejects_blocks_without_valid_qc() {
+            let chain = build_chain(6);
+
+            let mut up_to_date = create_replica(0);
+            for (i, block) in chain.iter().enumerate() {
+                propose(&mut up_to_date, block, i as ViewNumber + 1);
+            }
+
+            let SyncMessage::BlockResponse {
+                committed_blocks, ..
+            } = up_to_date.create_sync_response(0, chain[5].hash())
+            else {
+                panic!("Expected block response");
+            };
+
+            // tamper with the QC certifying the second block
+            let mut committed_blocks = committed_blocks;
+            if let Block::Normal { justify, .. } = &mut committed_blocks[2] {
+                justify.partial_sigs.truncate(1);
+            }
+
+            let mut late = create_replica(1);
+            assert_eq!(late.apply_sync_response(0, committed_blocks, vec![]), 1);
+            assert_eq!(late.committed_height(), 1);
+        }
+
+        #[test]
+        fn test_sync_rejects_forged_block_reusing_real_qc() {
+            let chain = build_chain(6);
+
+            let mut up_to_date = create_replica(0);
+            for (i, block) in chain.iter().enumerate() {
+                propose(&mut up_to_date, block, i as ViewNumber + 1);
+            }
+            let SyncMessage::BlockResponse {
+                committed_blocks,
+                pending_blocks,
+                ..
+            } = up_to_date.create_sync_response(0, chain[5].hash())
+            else {
+                panic!("Expected block response");
+            };
+
+            // same parent, justify and state root as the second block, other transactions
+            let Block::Normal {
+                justify,
+                state_root,
+                executed_block_hash,
+                ..
+            } = committed_blocks[1].clone()
+            else {
+                panic!("Expected normal block");
+            };
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let theft = UnsignedTransaction::Transfer(TransferTransaction {
+                from: faucet_sk.verifying_key().to_bytes(),
+                to: [8; 32],
+                amount: 1_000,
+                asset_id: 0,
+                nonce: 1,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            })
+            .sign(&mut faucet_sk);
+            let forged = Block::create_leaf(
+                &committed_blocks[0],
+                vec![theft],
+                2,
+                justify,
+                state_root,
+                executed_block_hash,
+            );
+
+            // the next block's justify certifies the real block, not the forged one
+            let mut with_forged_middle = committed_blocks.clone();
+            with_forged_middle[1] = forged.clone();
+            let mut late = create_replica(1);
+            assert_eq!(
+                late.apply_sync_response(0, with_forged_middle, pending_blocks.clone()),
+                1
+            );
+            assert_eq!(late.committed_height(), 1);
+
+            // as the newest block nothing certifies it, so it is never executed
+            let mut late = create_replica(1);
+            assert_eq!(
+                late.apply_sync_response(
+                    0,
+                    vec![committed_blocks[0].clone(), forged.clone()],
+                    vec![]
+                ),
+                1
+            );
+            assert_eq!(late.committed_height(), 1);
+
+            // nor can it skip the block it builds on
+            let mut late = create_replica(1);
+            assert_eq!(late.apply_sync_response(0, vec![forged], pending_blocks), 0);
+            assert_eq!(late.committed_height(), 0);
+        }
+    }
+
+    mod test_locking_rule {
+        use super::{
+            test_state_sync::{build_chain, certify, propose},
+            *,
+        };
+
+        /// Block of `view` extending `parent`, justified by a QC for `parent`
+        fn fork(parent: &Block, parent_view: ViewNumber, view: ViewNumber) -> Block {
+            let (genesis_block, _) = Block::create_genesis_block();
+            Block::create_leaf(
+                parent,
+                vec![],
+                view,
+                certify(parent, parent_view),
+                LedgerState::new().state_root(),
+                genesis_block.hash(),
+            )
+        }
+
+        #[test]
+        fn test_refuses_branch_conflicting_with_lock() {
+            let chain = build_chain(4);
+            let mut replica = create_replica(0);
+            for (i, block) in chain.iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+            }
+            assert_eq!(replica.locked_qc.block_hash, chain[1].hash());
+
+            // extends the view 1 block, which conflicts with the lock on the view 2 block
+            let conflicting = fork(&chain[0], 1, 5);
+            assert!(propose(&mut replica, &conflicting, 5).is_none());
+
+            let extending = fork(&chain[3], 4, 5);
+            assert!(propose(&mut replica, &extending, 5).is_some());
+        }
+
+        #[test]
+        fn test_conflicting_branch_is_safe_with_higher_qc() {
+            let chain = build_chain(2);
+            let mut replica = create_replica(0);
+            replica.locked_qc = Arc::new(certify(&chain[1], 2));
+
+            let other_branch = fork(&chain[0], 1, 3);
+            let proposal = fork(&other_branch, 3, 4);
+            for block in [&chain[0], &chain[1], &other_branch] {
+                replica
+                    .blockstore
+                    .insert(block.hash(), Arc::new(RwLock::new(block.clone())));
+            }
+
+            assert!(!proposal.extends_from(chain[1].hash(), 2, &replica.blockstore));
+            assert!(replica.safe_node(&proposal, &certify(&other_branch, 3)));
+            assert!(!replica.safe_node(&proposal, &certify(&chain[1], 2)));
+        }
+    }
+
+    mod test_commit_rule {
+        use super::{
+            test_state_sync::{build_chain, certify, propose},
+            *,
+        };
+
+        fn is_executed(replica: &HotStuffReplica, block: &Block) -> bool {
+            replica.executed_state_roots.contains_key(&block.hash())
+        }
+
+        #[test]
+        fn test_three_chain_commits_prefix_and_leaves_tail() {
+            let chain = build_chain(6);
+            let mut replica = create_replica(0);
+
+            for (i, block) in chain.iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+                // the block of view v commits with the proposal of view v + 3
+                assert_eq!(replica.committed_height(), i.saturating_sub(2) as u64);
+            }
+
+            for block in &chain[..3] {
+                assert!(is_executed(&replica, block));
+            }
+            for block in &chain[3..] {
+                assert!(!is_executed(&replica, block));
+            }
+        }
+
+        #[test]
+        fn test_qc_skipping_parent_breaks_the_chain() {
+            let chain = build_chain(4);
+            let mut replica = create_replica(0);
+            for (i, block) in chain.iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+            }
+            assert_eq!(replica.committed_height(), 1);
+
+            // extends the view 4 block but carries the QC for view 3
+            let (genesis_block, _) = Block::create_genesis_block();
+            let extend = |parent: &Block, view: ViewNumber, justify: QuorumCertificate| {
+                Block::create_leaf(
+                    parent,
+                    vec![],
+                    view,
+                    justify,
+                    LedgerState::new().state_root(),
+                    genesis_block.hash(),
+                )
+            };
+            let gapped = extend(&chain[3], 5, certify(&chain[2], 3));
+            let mut tail = vec![gapped];
+            for view in 6..=8 {
+                let parent = tail.last().unwrap();
+                let next = extend(parent, view, certify(parent, view - 1));
+                tail.push(next);
+            }
+
+            for (i, block) in tail.iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 5).is_some());
+                if i < 3 {
+                    assert_eq!(replica.committed_height(), 1);
+                }
+            }
+
+            // the three-chain on the gapped block commits it along with its ancestors
+            assert_eq!(replica.committed_height(), 5);
+            assert!(is_executed(&replica, &chain[3]));
+            assert!(is_executed(&replica, &tail[0]));
+            assert!(!is_executed(&replica, &tail[1]));
+        }
+    }
+
+    mod test_empty_blocks {
+        use super::{
+            test_state_sync::{build_chain, propose},
+            *,
+        };
+
+        #[test]
+        fn test_empty_blocks_commit_without_touching_balances() {
+            let chain = build_chain(8);
+            let mut replica = create_replica(0);
+            let funded: PublicKeyHash = [7; 32];
+
+            // the view 1 block funds an account, commits once view 4 is proposed
+            for (i, block) in chain[..4].iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+            }
+            assert_eq!(replica.committed_height(), 1);
+            let state_root = replica.ledger_state.state_root();
+            let balances = replica.ledger_state.get_balance_summary(&funded);
+
+            for (i, block) in chain.iter().enumerate().skip(4) {
+                assert!(block.is_empty());
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+            }
+
+            assert_eq!(replica.pacemaker.curr_view, 8);
+            assert_eq!(replica.committed_height(), 5);
+            assert_eq!(replica.ledger_state.current_view(), 5);
+            assert_eq!(replica.ledger_state.state_root(), state_root);
+            assert_eq!(replica.ledger_state.get_balance_summary(&funded), balances);
+        }
+    }
+
+    mod test_finality {
+        use super::{
+            test_state_sync::{build_chain, propose},
+            *,
+        };
+        use crate::state::receipt::Finality;
+
+        #[test]
+        fn test_receipt_flips_from_pending_to_committed() {
+            let chain = build_chain(4);
+            let tx_hash = chain[0].transactions()[0].hash;
+            let mut replica = create_replica(0);
+            assert!(replica.transaction_receipt(&tx_hash).is_none());
+
+            for (i, block) in chain[..3].iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+                let receipt = replica.transaction_receipt(&tx_hash).unwrap();
+                assert_eq!(receipt.finality, Finality::Pending);
+                assert_eq!(receipt.status, TransactionStatus::Pending);
+            }
+
+            // the view 4 proposal completes the three-chain on the view 1 block
+            assert!(propose(&mut replica, &chain[3], 4).is_some());
+            let receipt = replica.transaction_receipt(&tx_hash).unwrap();
+            assert_eq!(receipt.finality, Finality::Committed);
+            assert_eq!(receipt.status, TransactionStatus::Executed);
+        }
+    }
+
+    mod test_checkpoint {
+        use super::{
+            test_state_sync::{build_chain, propose},
+            *,
+        };
+        use std::path::PathBuf;
+
+        fn temp_path(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!(
+                "superliquid_checkpoint_{}_{}.bin",
+                name,
+                std::process::id()
+            ))
+        }
+
+        fn create_restored_replica(
+            checkpoint_path: &PathBuf,
+            blocks_path: &PathBuf,
+        ) -> HotStuffReplica {
+            let mut replica = create_replica(0);
+            replica.checkpoint_interval = 2;
+            replica
+                .restore_checkpoint(CheckpointStore::new(checkpoint_path))
+                .unwrap();
+            replica
+                .restore_blocks(BlockStore::open(blocks_path).unwrap())
+                .unwrap();
+            replica
+        }
+
+        #[test]
+        fn test_restart_from_checkpoint_after_pruning() {
+            let checkpoint_path = temp_path("state");
+            let blocks_path = temp_path("blocks");
+            let _ = std::fs::remove_file(&checkpoint_path);
+            let _ = std::fs::remove_file(&blocks_path);
+
+            let chain = build_chain(8);
+            let mut replica = create_restored_replica(&checkpoint_path, &blocks_path);
+            for (i, block) in chain.iter().enumerate() {
+                assert!(propose(&mut replica, block, i as ViewNumber + 1).is_some());
+            }
+
+            // checkpoints at heights 2 and 4, the fifth block is left in the log
+            assert_eq!(replica.committed_height(), 5);
+            assert_eq!(replica.checkpoint_height, 4);
+            assert_eq!(replica.committed_blocks, vec![chain[4].hash()]);
+            for block in &chain[..3] {
+                assert!(!replica.blockstore.contains_key(&block.hash()));
+                assert!(!replica.executed_state_roots.contains_key(&block.hash()));
+            }
+            assert!(replica.blockstore.contains_key(&chain[3].hash()));
+
+            // pruned blocks can no longer be served to lagging replicas
+            let SyncMessage::BlockResponse {
+                committed_blocks, ..
+            } = replica.create_sync_response(0, chain[7].hash())
+            else {
+                panic!("Expected block response");
+            };
+            assert!(committed_blocks.is_empty());
+
+            let restarted = create_restored_replica(&checkpoint_path, &blocks_path);
+            assert_eq!(restarted.committed_height(), 5);
+            assert_eq!(restarted.last_executed_block, chain[4].hash());
+            assert_eq!(
+                restarted.ledger_state.state_root(),
+                replica.ledger_state.state_root()
+            );
+
+            let balances = restarted
+                .ledger_state
+                .get_account_info_with_balances_or_default(&[7; 32])
+                .spot_balances;
+            assert_eq!(balances.find_asset_id(0).unwrap().total_balance, 1_000);
+
+            std::fs::remove_file(checkpoint_path).unwrap();
+            std::fs::remove_file(blocks_path).unwrap();
+        }
+    }
+
+    mod test_metrics {
+        use super::*;
+        use crate::{
+            state::order::{OrderDirection, OrderType},
+            test_utils::test_helpers::{get_alice_sk, get_bob_sk},
+            types::transaction::{OrderTransaction, TransferTransaction, UnsignedTransaction},
+        };
+
+        pub(super) fn faucet_txn(
+            to: &SigningKey,
+            asset_id: u32,
+            amount: u128,
+            nonce: u64,
+        ) -> SignedTransaction {
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            UnsignedTransaction::Transfer(TransferTransaction {
+                from: faucet_sk.verifying_key().to_bytes(),
+                to: to.verifying_key().to_bytes(),
+                amount,
+                asset_id,
+                nonce,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            })
+            .sign(&mut faucet_sk)
+        }
+
+        fn order_txn(
+            sk: &mut SigningKey,
+            direction: OrderDirection,
+            order_type: OrderType,
+            nonce: u64,
+        ) -> SignedTransaction {
+            UnsignedTransaction::Order(OrderTransaction {
+                from: sk.verifying_key().to_bytes(),
+                market_id: 0,
+                direction,
+                order_type,
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: None,
+            })
+            .sign(sk)
+        }
+
+        pub(super) fn execute(replica: &mut HotStuffReplica, transactions: Vec<SignedTransaction>) {
+            let (genesis_block, genesis_qc) = Block::create_genesis_block();
+            let parent = replica.blockstore[&replica.last_executed_block]
+                .read()
+                .unwrap()
+                .clone();
+            let block = Block::create_leaf(
+                &parent,
+                transactions,
+                parent.view_number() + 1,
+                genesis_qc,
+                replica.ledger_state.state_root(),
+                genesis_block.hash(),
+            );
+            let block = Arc::new(RwLock::new(block));
+            replica
+                .blockstore
+                .insert(block.read().unwrap().hash(), block.clone());
+            replica.execute_block(&block);
+        }
+
+        #[test]
+        fn test_matched_orders_counter_increments_after_block_with_orders() {
+            let mut replica = create_replica(0);
+            let metrics = replica.metrics();
+            let mut alice_sk = get_alice_sk();
+            let mut bob_sk = get_bob_sk();
+
+            execute(
+                &mut replica,
+                vec![
+                    faucet_txn(&alice_sk, 0, 1_000_000_000, 0),
+                    faucet_txn(&bob_sk, 1, 1_000_000_000_000, 1),
+                    order_txn(
+                        &mut alice_sk,
+                        OrderDirection::Sell,
+                        OrderType::Limit(1_000, 10),
+                        0,
+                    ),
+                    order_txn(
+                        &mut alice_sk,
+                        OrderDirection::Sell,
+                        OrderType::Limit(1_100, 10),
+                        1,
+                    ),
+                ],
+            );
+            assert_eq!(metrics.blocks_committed(), 1);
+            assert_eq!(metrics.orders_matched(), 0);
+
+            execute(
+                &mut replica,
+                vec![order_txn(
+                    &mut bob_sk,
+                    OrderDirection::Buy,
+                    OrderType::Market(100_000),
+                    0,
+                )],
+            );
+            assert_eq!(metrics.blocks_committed(), 2);
+            assert_eq!(metrics.orders_matched(), 2);
+            assert!(
+                metrics
+                    .render()
+                    .contains("superliquid_orders_matched_total 2\n")
+            );
+        }
+    }
+
+    mod test_health_query {
+        use super::{
+            test_metrics::{execute, faucet_txn},
+            *,
+        };
+        use crate::test_utils::test_helpers::{get_alice_sk, get_bob_sk};
+
+        fn query_health(replica: &mut HotStuffReplica) -> NodeHealth {
+            let (response_tx, mut response_rx) = tokio::sync::oneshot::channel();
+            replica.handle_query(QueryRequest {
+                query: ClientQuery::HealthQuery,
+                response_channel: response_tx,
+            });
+            match response_rx.try_recv() {
+                Ok(ClientResponse::HealthQueryResponse(health)) => health,
+                other => panic!("Expected HealthQueryResponse, got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_health_reflects_executed_block_and_mempool() {
+            let mut replica = create_replica(0);
+            let alice_sk = get_alice_sk();
+            let bob_sk = get_bob_sk();
+
+            execute(&mut replica, vec![faucet_txn(&alice_sk, 0, 1_000, 0)]);
+            replica.pacemaker.curr_view = 3;
+            replica.handle_transaction(faucet_txn(&bob_sk, 0, 1_000, 1));
+
+            assert_eq!(
+                query_health(&mut replica),
+                NodeHealth {
+                    committed_height: 1,
+                    view: 3,
+                    mempool_size: 1,
+                    peer_count: 0,
+                }
+            );
+        }
+    }
+
+    mod test_batching {
+        use super::{
+            test_metrics::faucet_txn, test_timeout_certificate::create_timed_out_replicas, *,
+        };
+        use crate::test_utils::test_helpers::get_alice_sk;
+
+        /// Leader of view 2, ready to propose off the timeout certificate for view 1
+        fn create_ready_leader(batch_window: Duration, batch_size: usize) -> HotStuffReplica {
+            let (replicas, timeouts) = create_timed_out_replicas();
+            let next_leader = replicas[0].pacemaker.leader_for_view(2);
+            let mut leader = replicas.into_iter().nth(next_leader).unwrap();
+
+            for timeout in timeouts {
+                leader.messages.push(timeout);
+            }
+            leader.advance_view_with_tc(1);
+            leader.pacemaker.batch_window = batch_window;
+            leader.pacemaker.batch_size = batch_size;
+            leader
+        }
+
+        fn proposed_transactions(proposal: Option<HotStuffMessage>) -> usize {
+            let Some(HotStuffMessage::Proposal { node, .. }) = proposal else {
+                panic!("Expected leader to propose");
+            };
+            node.transactions().len()
+        }
+
+        #[test]
+        fn test_transactions_within_window_are_batched_into_one_block() {
+            let mut leader = create_ready_leader(Duration::from_secs(60), 3);
+            let alice_sk = get_alice_sk();
+
+            assert!(leader.leader_handle_message().is_none());
+            for nonce in 0..2 {
+                leader.handle_transaction(faucet_txn(&alice_sk, 0, 1_000, nonce));
+                assert!(leader.leader_handle_message().is_none());
+            }
+
+            leader.handle_transaction(faucet_txn(&alice_sk, 0, 1_000, 2));
+            assert_eq!(proposed_transactions(leader.leader_handle_message()), 3);
+            assert!(!leader.pacemaker.batch_pending());
+        }
+
+        #[test]
+        fn test_leader_proposes_partial_batch_once_window_elapses() {
+            let mut leader = create_ready_leader(Duration::from_millis(20), 3);
+
+            assert!(leader.leader_handle_message().is_none());
+            leader.handle_transaction(faucet_txn(&get_alice_sk(), 0, 1_000, 0));
+            std::thread::sleep(Duration::from_millis(25));
+
+            assert_eq!(proposed_transactions(leader.leader_handle_message()), 1);
+        }
+
+        #[test]
+        fn test_block_holds_at_most_one_batch() {
+            let mut leader = create_ready_leader(Duration::from_secs(60), 2);
+            let alice_sk = get_alice_sk();
+
+            for nonce in 0..3 {
+                leader.handle_transaction(faucet_txn(&alice_sk, 0, 1_000, nonce));
+            }
+
+            assert_eq!(proposed_transactions(leader.leader_handle_message()), 2);
+            assert_eq!(leader.mempool.len(), 1);
+        }
+    }
+
+    mod test_equivocation {
+        use super::*;
+
+        #[test]
+        fn test_conflicting_votes_from_validator_are_recorded() {
+            let mut replica = create_replica(0);
+            let mut signing_key = config::retrieve_signing_key(1);
+
+            let block_1 = create_first_proposal([0; 32]);
+            let block_2 = create_first_proposal([1; 32]);
+            let vote_1 = HotStuffMessage::create_vote(block_1, 1, 1, 1, &mut signing_key);
+            let vote_2 = HotStuffMessage::create_vote(block_2, 1, 1, 1, &mut signing_key);
+
+            replica.observe_vote(&vote_1);
+            assert!(replica.get_equivocation_evidence().is_empty());
+
+            replica.observe_vote(&vote_2);
+            let evidence = replica.get_equivocation_evidence();
+            assert_eq!(evidence.len(), 1);
+            assert_eq!(evidence[0].first_vote, vote_1);
+            assert_eq!(evidence[0].second_vote, vote_2);
+        }
+    }
+}
@@ -1,15 +1,45 @@
+use sha2::{Digest, Sha256};
 use tokio::sync::mpsc;
 
-use crate::types::message::{ReplicaInBound, ReplicaOutbound, mpsc_error};
+use crate::{
+    message_protocol::SyncMessage,
+    network::RecentlySeen,
+    node::state::PeerId,
+    types::{
+        message::{ReplicaInBound, ReplicaOutbound, mpsc_error},
+        transaction::Sha256Hash,
+    },
+};
 
 use super::message::HotStuffMessage;
 
+/// Number of broadcast message hashes remembered to suppress re-broadcasts
+pub(super) const MAX_RECENT_BROADCASTS: usize = 4096;
+
 pub struct ReplicaSender {
     pub replica_tx: mpsc::Sender<ReplicaInBound>,
     pub node_tx: mpsc::Sender<ReplicaOutbound>,
+    recent_broadcasts: RecentlySeen,
+}
+
+/// Hash of the full message, unlike `HotStuffMessage::hash` which only covers the signed fields
+fn content_hash(msg: &HotStuffMessage) -> Sha256Hash {
+    let encoded = bincode::serialize(msg).unwrap();
+    Sha256::digest(&encoded).into()
 }
 
 impl ReplicaSender {
+    pub fn new(
+        replica_tx: mpsc::Sender<ReplicaInBound>,
+        node_tx: mpsc::Sender<ReplicaOutbound>,
+    ) -> Self {
+        Self {
+            replica_tx,
+            node_tx,
+            recent_broadcasts: RecentlySeen::new(MAX_RECENT_BROADCASTS),
+        }
+    }
+
     pub(super) async fn send_to_self(&self, msg: HotStuffMessage) -> Result<(), std::io::Error> {
         self.replica_tx
             .send(ReplicaInBound::HotStuff(msg))
@@ -17,7 +47,12 @@ impl ReplicaSender {
             .map_err(|e| mpsc_error("Send to replica failed", e))
     }
 
-    pub(super) async fn broadcast(&self, msg: HotStuffMessage) -> Result<(), std::io::Error> {
+    /// Broadcasts `msg` to all peers, unless the same message was already broadcast recently
+    pub(super) async fn broadcast(&mut self, msg: HotStuffMessage) -> Result<(), std::io::Error> {
+        if !self.recent_broadcasts.insert(content_hash(&msg)) {
+            return Ok(());
+        }
+
         self.node_tx
             .send(ReplicaOutbound::Broadcast(msg))
             .await
@@ -34,4 +69,43 @@ impl ReplicaSender {
             .await
             .map_err(|e| mpsc_error("failed to send to node", e))
     }
+
+    pub(super) async fn send_sync(
+        &self,
+        peer_id: PeerId,
+        msg: SyncMessage,
+    ) -> Result<(), std::io::Error> {
+        self.node_tx
+            .send(ReplicaOutbound::Sync(peer_id, msg))
+            .await
+            .map_err(|e| mpsc_error("failed to send to node", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotstuff::crypto::QuorumCertificate;
+
+    #[tokio::test]
+    async fn test_repeated_broadcast_is_forwarded_once() {
+        let (replica_tx, _replica_rx) = mpsc::channel(8);
+        let (node_tx, mut node_rx) = mpsc::channel(8);
+        let mut sender = ReplicaSender::new(replica_tx, node_tx);
+
+        let msg = HotStuffMessage::create_new_view(QuorumCertificate::create_genesis_qc(), 1, 0, 1);
+        let other =
+            HotStuffMessage::create_new_view(QuorumCertificate::create_genesis_qc(), 2, 0, 2);
+
+        sender.broadcast(msg.clone()).await.unwrap();
+        sender.broadcast(msg.clone()).await.unwrap();
+        sender.broadcast(other.clone()).await.unwrap();
+        drop(sender);
+
+        let mut forwarded = vec![];
+        while let Some(ReplicaOutbound::Broadcast(msg)) = node_rx.recv().await {
+            forwarded.push(msg);
+        }
+        assert_eq!(forwarded, vec![msg, other]);
+    }
 }
@@ -0,0 +1,79 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+use super::{crypto::QuorumCertificate, replica::ViewNumber};
+
+/// Voting state a replica must remember across restarts to avoid voting twice in a view
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SafetyState {
+    pub last_voted_view: ViewNumber,
+    pub locked_qc: QuorumCertificate,
+}
+
+/// Snapshots the safety state to a file, replacing the previous snapshot atomically
+pub struct SafetyStore {
+    path: PathBuf,
+}
+
+impl SafetyStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn for_node(node_id: usize) -> Self {
+        Self::new(config::retrieve_data_dir().join(format!("safety_state_{}.json", node_id)))
+    }
+
+    /// Returns None if no snapshot has been written yet
+    pub fn load(&self) -> io::Result<Option<SafetyState>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let state = serde_json::from_slice(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, state: &SafetyState) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let bytes = serde_json::to_vec(state)?;
+
+        // write to a temporary file first so a crash never leaves a partial snapshot
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_state_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "superliquid_safety_round_trip_{}.json",
+            std::process::id()
+        ));
+        let store = SafetyStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        let state = SafetyState {
+            last_voted_view: 7,
+            locked_qc: QuorumCertificate::create_genesis_qc(),
+        };
+        store.save(&state).unwrap();
+        assert_eq!(store.load().unwrap(), Some(state));
+
+        fs::remove_file(path).unwrap();
+    }
+}
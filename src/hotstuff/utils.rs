@@ -3,6 +3,8 @@ use super::{
     replica::ViewNumber,
 };
 
+use std::collections::HashSet;
+
 use ed25519::Signature;
 use hex::{decode as hex_decode, encode as hex_encode};
 
@@ -25,11 +27,17 @@ pub(crate) fn has_quorum_votes_for_view(
         return false;
     };
 
-    return msgs
+    // a validator's vote counts once, however often it was delivered
+    let signers: HashSet<_> = msgs
         .iter()
-        .filter(|m| matches!(m, HotStuffMessage::Vote { view, .. } if *view == curr_view))
-        .count()
-        >= quorum_threhold;
+        .filter_map(|m| match m {
+            HotStuffMessage::Vote {
+                view, partial_sig, ..
+            } if *view == curr_view => Some(partial_sig.signer_id),
+            _ => None,
+        })
+        .collect();
+    signers.len() >= quorum_threhold
 }
 
 pub(crate) fn has_quorum_for_new_view(
@@ -41,11 +49,35 @@ pub(crate) fn has_quorum_for_new_view(
         return false;
     };
 
-    return msgs
+    let senders: HashSet<_> = msgs
+        .iter()
+        .filter_map(|m| match m {
+            HotStuffMessage::NewView { view, sender, .. } if *view == curr_view => Some(*sender),
+            _ => None,
+        })
+        .collect();
+    senders.len() >= quorum_threhold
+}
+
+pub(crate) fn has_quorum_timeouts_for_view(
+    messages: Option<&Vec<HotStuffMessage>>,
+    curr_view: ViewNumber,
+    quorum_threhold: usize,
+) -> bool {
+    let Some(msgs) = messages else {
+        return false;
+    };
+
+    let signers: HashSet<_> = msgs
         .iter()
-        .filter(|m| matches!(m, HotStuffMessage::NewView { view, .. } if *view == curr_view))
-        .count()
-        >= quorum_threhold;
+        .filter_map(|m| match m {
+            HotStuffMessage::Timeout {
+                view, partial_sig, ..
+            } if *view == curr_view => Some(partial_sig.signer_id),
+            _ => None,
+        })
+        .collect();
+    signers.len() >= quorum_threhold
 }
 
 pub(crate) fn sig_to_string(sig: &Signature) -> String {
@@ -1,40 +1,57 @@
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::node::logger::log_module($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log_at!($crate::node::logger::LogLevel::Error, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log_at!($crate::node::logger::LogLevel::Warn, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log_at!($crate::node::logger::LogLevel::Info, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log_at!($crate::node::logger::LogLevel::Debug, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => { $crate::log_at!($crate::node::logger::LogLevel::Trace, $($arg)*) };
+}
+
 #[macro_export]
 macro_rules! replica_log {
-    ($node_id:expr, $($arg:tt)*) => {{
-        use chrono::Local;
-        let now = Local::now().format("%H:%M:%S%.3f");
-        let prefix = format!(
-            "\x1b[90m[{}]\x1b[0m \x1b[94m[Replica {}]\x1b[0m",
-            now, $node_id
-        );
-        println!("{} {}", prefix, format!($($arg)*));
-    }};
+    ($node_id:expr, $($arg:tt)*) => {
+        $crate::log_info!("\x1b[94m[Replica {}]\x1b[0m {}", $node_id, format_args!($($arg)*))
+    };
 }
 
 #[macro_export]
 macro_rules! replica_debug {
-    ($node_id:expr, $curr_view:expr, $($arg:tt)*) => {{
-        if std::env::var("REPLICA_DEBUG").map(|v| v == "true").unwrap_or(false) {
-        use chrono::Local;
-        let now = Local::now().format("%H:%M:%S%.3f");
-        let prefix = format!(
-            "\x1b[90m[{}]\x1b[0m \x1b[91m[Replica {} @ view {}]\x1b[0m",
-            now, $node_id, $curr_view
-        );
-        println!("{} {}", prefix, format!($($arg)*));
-        }
-    }};
+    ($node_id:expr, $curr_view:expr, $($arg:tt)*) => {
+        $crate::log_debug!(
+            "\x1b[91m[Replica {} @ view {}]\x1b[0m {}",
+            $node_id,
+            $curr_view,
+            format_args!($($arg)*)
+        )
+    };
 }
 
 #[macro_export]
 macro_rules! pacemaker_log {
-    ($($arg:tt)*) => {{
-        use chrono::Local;
-        let now = Local::now().format("%H:%M:%S%.3f");
-        let prefix = format!(
-            "\x1b[90m[{}]\x1b[0m \x1b[96m[Pacemaker]\x1b[0m",
-            now,
-        );
-        println!("{} {}", prefix, format!($($arg)*));
-    }};
+    ($($arg:tt)*) => {
+        $crate::log_info!("\x1b[96m[Pacemaker]\x1b[0m {}", format_args!($($arg)*))
+    };
 }
@@ -1,17 +1,20 @@
 use superliquid::{
-    config, console,
-    node::{runner::run_node, state::PeerInfo},
+    config::{self, NetworkConfig},
+    console,
+    node::{logger, runner::run_node, state::PeerInfo},
 };
 
 use std::env;
 
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-    let ip = "127.0.0.1";
-    let base_port = 6400;
-    let client_port = 8000;
+    logger::set_filter(config::retrieve_log_filter());
+
     let num_nodes = config::retrieve_num_validators();
+    let mut network = NetworkConfig::from_env(num_nodes);
+
+    let raw_args: Vec<String> = env::args().collect();
+    let args = network.apply_args(&raw_args);
 
     let node_index = args
         .get(2)
@@ -22,27 +25,39 @@ async fn main() {
         panic!("Invalid node index. Must be 0 to {}", num_nodes - 1);
     }
 
-    let ports: Vec<u16> = (0..num_nodes).map(|i| base_port + (i as u16)).collect();
-    let curr_port = ports[node_index];
-    let consensus_addr = format!("{}:{}", ip, curr_port);
-    let client_addr = format!("{}:{}", ip, client_port);
-
-    let peers = ports
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| *i != node_index)
-        .map(|(i, port)| (i, format!("{}:{}", ip, port)))
-        .map(|(i, addr)| PeerInfo {
+    let genesis = config::retrieve_genesis();
+
+    let consensus_addr = genesis
+        .and_then(|genesis| genesis.listen_addr(node_index, &network.listen_ip))
+        .unwrap_or_else(|| network.consensus_listen_addr(node_index));
+    let client_addr = network.client_addr();
+    let metrics_addr = config::retrieve_metrics_base_port().map(|base_port| {
+        let port = base_port
+            .checked_add(node_index as u16)
+            .expect("METRICS_BASE_PORT + node id overflows the port range");
+        format!("{}:{}", network.listen_ip, port)
+    });
+
+    let peers = (0..num_nodes)
+        .filter(|i| *i != node_index)
+        .map(|i| PeerInfo {
             peer_id: i,
-            peer_addr: addr,
+            peer_addr: match genesis {
+                Some(genesis) => genesis.validators[i].address.clone(),
+                None => network.peer_addr(i),
+            },
         })
         .collect();
 
     let _ = match args.get(1).map(|s| s.as_str()) {
-        Some("node") => run_node(client_addr, consensus_addr, peers, node_index).await,
+        Some("node") => {
+            run_node(client_addr, consensus_addr, metrics_addr, peers, node_index).await
+        }
         Some("console") => console::run_console(&client_addr).await,
         _ => {
-            eprintln!("Usage: cargo run -- [node|console] [number]");
+            eprintln!(
+                "Usage: cargo run -- [node|console] [number] [--ip <ip>] [--base-port <port>] [--client-port <port>]"
+            );
             Ok(())
         }
     };
@@ -1,26 +1,40 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::Mutex;
 
+use crate::config;
+use crate::hotstuff::block::{Block, BlockHash};
+use crate::hotstuff::hexstring;
 use crate::hotstuff::message::HotStuffMessage;
 use crate::network;
 use crate::node::state::PeerId;
 use crate::state::asset::{Asset, AssetId};
+use crate::state::order::{OrderDirection, OrderId};
+use crate::state::receipt::{Finality, TransactionReceipt};
 use crate::state::spot_clearinghouse::MarketId;
-use crate::state::spot_market::MarketInfo;
-use crate::state::state::AccountInfoWithBalances;
+use crate::state::spot_market::{BestPriceSnapshot, FirmQuote, MarketInfo, OrderBookDepth};
+use crate::state::state::{
+    AccountInfoWithBalances, AssetBalanceSummary, OrderLock, OrderQueryResult, OrderSimulation,
+};
 use crate::types::message::Message;
-use crate::types::transaction::{PublicKeyHash, Sha256Hash, SignedTransaction};
+use crate::types::transaction::{OrderSpec, PublicKeyHash, Sha256Hash, SignedTransaction};
 use std::io::{Error, ErrorKind, Result};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum AppMessage {
     Query,
     SubmitTransaction(SignedTransaction),
+    /// Hash a client's submitted transaction is tracked by, and its receipt keyed by
+    TransactionSubmitted(Sha256Hash),
     Response(Vec<SignedTransaction>),
     Drip(PublicKeyHash, AssetId),
     Ack,
+    /// Sent to a client in place of a response when the node won't serve its request
+    ClientRejected(ClientRejection),
     AccountQuery(PublicKeyHash),
     AccountQueryResponse(AccountInfoWithBalances),
 
@@ -32,22 +46,361 @@ pub enum AppMessage {
 
     AssetQuery,
     AssetQueryResponse(Vec<Asset>),
+
+    /// Per-asset total, available and locked balances of an account
+    BalanceSummaryQuery(PublicKeyHash),
+    BalanceSummaryQueryResponse(Vec<AssetBalanceSummary>),
+
+    /// Taker fees accrued by the fee collector, per asset
+    FeeBalanceQuery,
+    FeeBalanceQueryResponse(Vec<AssetBalanceSummary>),
+
+    /// Top `depth` price levels on each side of a market's book
+    OrderBookQuery(MarketId, usize),
+    OrderBookQueryResponse(Option<OrderBookDepth>),
+
+    /// Recent best bid and ask changes of a market, oldest first
+    BestPriceHistoryQuery(MarketId),
+    BestPriceHistoryQueryResponse(Option<Vec<BestPriceSnapshot>>),
+
+    OrderQuery(PublicKeyHash, OrderId),
+    OrderQueryResponse(Option<OrderQueryResult>),
+
+    /// Markets an account has open orders in or holds a nonzero balance of an asset of
+    AccountMarketsQuery(PublicKeyHash),
+    AccountMarketsQueryResponse(Vec<MarketId>),
+
+    /// Balance locked by each of an account's open orders
+    OrderLocksQuery(PublicKeyHash),
+    OrderLocksQueryResponse(Vec<OrderLock>),
+
+    ReceiptQuery(Sha256Hash),
+    ReceiptQueryResponse(Option<TransactionReceipt>),
+
+    /// Price the book gives a taker of a size in base lots, without placing an order
+    QuoteQuery(MarketId, OrderDirection, u64),
+    QuoteQueryResponse(Option<FirmQuote>),
+
+    /// Dry run of an order against the current state, without submitting it
+    SimulateOrder(PublicKeyHash, OrderSpec),
+    SimulateOrderResponse(OrderSimulation),
+
+    /// Liveness check that never touches the ledger
+    HealthQuery,
+    HealthQueryResponse(NodeHealth),
+
+    /// Turns the connection into a stream of `AccountUpdate`s for the account
+    SubscribeAccount(PublicKeyHash),
+    /// Account's info and balances after a committed block changed them
+    AccountUpdate(AccountInfoWithBalances),
+}
+
+/// Why a node refused to serve a client
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRejection {
+    /// The node is serving as many client connections as it allows; the connection is closed
+    TooManyConnections,
+    /// The connection exceeded its request rate; the request was dropped
+    RateLimited,
 }
 
+/// Snapshot of a node's progress, cheap enough to poll from a load balancer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeHealth {
+    pub committed_height: u64,
+    pub view: u64,
+    pub mempool_size: usize,
+    pub peer_count: usize,
+}
+
+/// Lets a lagging replica fetch the blocks it missed from a peer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SyncMessage {
+    /// Committed blocks from `from_height` onwards, along with the uncommitted chain
+    /// leading up to `target_block`
+    BlockRequest {
+        from_height: u64,
+        target_block: BlockHash,
+        sender: PeerId,
+    },
+    BlockResponse {
+        from_height: u64,
+        committed_blocks: Vec<Block>,
+        pending_blocks: Vec<Block>,
+    },
+}
+
+pub type HandshakeChallenge = [u8; 32];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ControlMessage {
-    Hello { peer_id: usize },
+    /// Opens a connection, asking the accepting replica to prove its identity by signing `challenge`
+    Hello {
+        peer_id: usize,
+        challenge: HandshakeChallenge,
+        // codecs the initiator can decode, absent from peers that predate compression
+        #[serde(default)]
+        codecs: Vec<MessageCodec>,
+    },
+    /// Accepting replica's proof of identity, along with its own challenge for the initiator
+    HelloAck {
+        peer_id: usize,
+        challenge: HandshakeChallenge,
+        #[serde(
+            serialize_with = "hexstring::serialize_signature",
+            deserialize_with = "hexstring::deserialize_signature"
+        )]
+        signature: Signature,
+        // codec both ends use for the connection
+        #[serde(default)]
+        codec: MessageCodec,
+    },
+    /// Initiator's proof of identity
+    Auth {
+        #[serde(
+            serialize_with = "hexstring::serialize_signature",
+            deserialize_with = "hexstring::deserialize_signature"
+        )]
+        signature: Signature,
+    },
+    /// Liveness probe, answered with `Pong`
+    Ping,
+    Pong,
     End, // Terminate connection
 }
 
+/// Encoding of a peer message's JSON, agreed on during the handshake. Either end decodes
+/// every codec it supports regardless, so only the sender needs to know the outcome
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageCodec {
+    #[default]
+    Plain,
+    Deflate,
+}
+
+/// First byte of a deflated frame. Plain frames are JSON objects, so start with `{`
+const DEFLATE_FRAME_TAG: u8 = 0x01;
+/// Messages smaller than this are sent plain, as compressing them gains little
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+impl MessageCodec {
+    /// Codecs this build can decode, most preferred first
+    pub fn supported() -> Vec<MessageCodec> {
+        if cfg!(feature = "compression") {
+            vec![MessageCodec::Deflate, MessageCodec::Plain]
+        } else {
+            vec![MessageCodec::Plain]
+        }
+    }
+
+    /// Our most preferred codec the peer also offered, plain if there is none
+    pub fn negotiate(offered: &[MessageCodec]) -> MessageCodec {
+        Self::supported()
+            .into_iter()
+            .find(|codec| offered.contains(codec))
+            .unwrap_or_default()
+    }
+
+    /// Frame bytes carrying `json`
+    fn encode(self, json: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            MessageCodec::Deflate if json.len() >= COMPRESSION_THRESHOLD => deflate(&json),
+            _ => Ok(json),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+fn deflate(json: &[u8]) -> Result<Vec<u8>> {
+    use flate2::{Compression, write::DeflateEncoder};
+    use std::io::Write;
+
+    let mut encoder = DeflateEncoder::new(vec![DEFLATE_FRAME_TAG], Compression::fast());
+    encoder.write_all(json)?;
+    encoder.finish()
+}
+
+#[cfg(not(feature = "compression"))]
+fn deflate(_json: &[u8]) -> Result<Vec<u8>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "Built without the compression feature",
+    ))
+}
+
+/// JSON carried by `frame`. Inflated frames are held to the same size cap as plain ones
+#[cfg(feature = "compression")]
+fn decode_frame(frame: Vec<u8>) -> Result<Vec<u8>> {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    if frame.first() != Some(&DEFLATE_FRAME_TAG) {
+        return Ok(frame);
+    }
+
+    let limit = max_frame_size() as u64;
+    let mut json = vec![];
+    DeflateDecoder::new(&frame[1..])
+        .take(limit + 1)
+        .read_to_end(&mut json)?;
+    if json.len() as u64 > limit {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Inflated frame exceeds limit of {} bytes", limit),
+        ));
+    }
+    Ok(json)
+}
+
+#[cfg(not(feature = "compression"))]
+fn decode_frame(frame: Vec<u8>) -> Result<Vec<u8>> {
+    if frame.first() == Some(&DEFLATE_FRAME_TAG) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Received a deflated frame without the compression feature",
+        ));
+    }
+    Ok(frame)
+}
+
+/// How often peers are pinged, and how many intervals of silence mark a peer as down
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub miss_threshold: u32,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, miss_threshold: u32) -> Self {
+        Self {
+            interval,
+            miss_threshold,
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(
+            config::retrieve_heartbeat_interval(),
+            config::retrieve_heartbeat_miss_threshold(),
+        )
+    }
+
+    /// Longest a peer may stay silent before it is considered down
+    pub fn deadline(&self) -> Duration {
+        self.interval * self.miss_threshold
+    }
+}
+
+/// A replica's own key along with the configured validator keys, indexed by peer id
+pub struct HandshakeIdentity {
+    pub peer_id: PeerId,
+    signing_key: SigningKey,
+    validator_keys: Vec<VerifyingKey>,
+}
+
+impl HandshakeIdentity {
+    pub fn new(
+        peer_id: PeerId,
+        signing_key: SigningKey,
+        validator_keys: Vec<VerifyingKey>,
+    ) -> Self {
+        Self {
+            peer_id,
+            signing_key,
+            validator_keys,
+        }
+    }
+
+    fn sign(&self, challenge: &HandshakeChallenge, recipient: PeerId) -> Signature {
+        let hash = handshake_hash(challenge, self.peer_id, recipient);
+        self.signing_key.sign(&hash)
+    }
+
+    /// Checks that `signer` is a known validator and signed `challenge` for us
+    fn verify(
+        &self,
+        challenge: &HandshakeChallenge,
+        signer: PeerId,
+        signature: &Signature,
+    ) -> Result<()> {
+        let Some(signer_key) = self.validator_keys.get(signer) else {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("Unknown validator {}", signer),
+            ));
+        };
+
+        let hash = handshake_hash(challenge, signer, self.peer_id);
+        signer_key.verify_strict(&hash, signature).map_err(|_| {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                format!("Invalid handshake signature from validator {}", signer),
+            )
+        })
+    }
+}
+
+/// Binds the signed challenge to both ends so it cannot be replayed on another connection
+fn handshake_hash(challenge: &HandshakeChallenge, signer: PeerId, recipient: PeerId) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(b"superliquid-handshake");
+    hasher.update(challenge);
+    hasher.update((signer as u64).to_be_bytes());
+    hasher.update((recipient as u64).to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn max_frame_size() -> usize {
+    static MAX_FRAME_SIZE: OnceLock<usize> = OnceLock::new();
+    *MAX_FRAME_SIZE.get_or_init(config::retrieve_max_frame_size)
+}
+
+/// Errors if the frame exceeds the cap, which should be treated as a reason to disconnect
 pub async fn receive_message(reader: Arc<Mutex<OwnedReadHalf>>) -> Result<Option<Message>> {
-    network::receive_json::<Message, OwnedReadHalf>(reader).await
+    let frame = {
+        let mut reader = reader.lock().await;
+        network::receive_data(&mut *reader, max_frame_size()).await?
+    };
+    let Some(frame) = frame else {
+        return Ok(None);
+    };
+
+    let json = decode_frame(frame)?;
+    let message =
+        serde_json::from_slice(&json).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some(message))
+}
+
+/// Refuses to send frames the receiving end would reject
+async fn send_frame(writer: Arc<Mutex<OwnedWriteHalf>>, frame: &[u8]) -> Result<()> {
+    if frame.len() > max_frame_size() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Frame of {} bytes exceeds limit of {} bytes",
+                frame.len(),
+                max_frame_size()
+            ),
+        ));
+    }
+
+    let _ = network::send_data(writer, frame).await;
+    Ok(())
 }
 
 pub async fn send_message(writer: Arc<Mutex<OwnedWriteHalf>>, message: &Message) -> Result<()> {
     let json = serde_json::to_vec(&message)?;
-    let _ = network::send_data(writer, &json).await;
-    Ok(())
+    send_frame(writer, &json).await
+}
+
+/// Sends `message` encoded with the codec negotiated with the peer
+pub async fn send_message_with_codec(
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    message: &Message,
+    codec: MessageCodec,
+) -> Result<()> {
+    let frame = codec.encode(serde_json::to_vec(&message)?)?;
+    send_frame(writer, &frame).await
 }
 
 pub async fn send_hotstuff_message(
@@ -55,31 +408,107 @@ pub async fn send_hotstuff_message(
     message: &HotStuffMessage,
 ) -> Result<()> {
     let json = serde_json::to_vec(&message)?;
-    let _ = network::send_data(writer, &json).await;
-    Ok(())
+    send_frame(writer, &json).await
 }
 
-// Used for replicas to identify inbound connections
+/// Opens an authenticated connection to `expected_peer`. Both sides sign each other's challenge,
+/// so the connection is only used once the peer has proven it holds its validator key
 pub async fn send_hello(
     writer: Arc<Mutex<OwnedWriteHalf>>,
     reader: Arc<Mutex<OwnedReadHalf>>,
-    peer_id: PeerId,
-) -> Result<()> {
-    let msg = ControlMessage::Hello { peer_id };
-    send_message(writer, &&Message::Connection(msg)).await?;
+    identity: &HandshakeIdentity,
+    expected_peer: PeerId,
+) -> Result<MessageCodec> {
+    let challenge: HandshakeChallenge = rand::random();
+    let msg = ControlMessage::Hello {
+        peer_id: identity.peer_id,
+        challenge,
+        codecs: MessageCodec::supported(),
+    };
+    send_message(writer.clone(), &Message::Connection(msg)).await?;
+
+    let (peer_challenge, signature, codec) = match receive_message(reader.clone()).await? {
+        Some(Message::Connection(ControlMessage::HelloAck {
+            peer_id,
+            challenge: peer_challenge,
+            signature,
+            codec,
+        })) if peer_id == expected_peer => (peer_challenge, signature, codec),
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected HelloAck from {}, got {:?}", expected_peer, other),
+            ));
+        }
+    };
+    identity.verify(&challenge, expected_peer, &signature)?;
+
+    let msg = ControlMessage::Auth {
+        signature: identity.sign(&peer_challenge, expected_peer),
+    };
+    send_message(writer, &Message::Connection(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::Ack)) => Ok(codec),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Ack, got {:?}", other),
+        )),
+    }
+}
+
+/// Accepting side of `send_hello`. Returns the authenticated peer id and the codec to
+/// send it messages with
+pub async fn accept_hello(
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    identity: &HandshakeIdentity,
+) -> Result<(PeerId, MessageCodec)> {
+    let (peer_id, peer_challenge, codecs) = match receive_message(reader.clone()).await? {
+        Some(Message::Connection(ControlMessage::Hello {
+            peer_id,
+            challenge,
+            codecs,
+        })) => (peer_id, challenge, codecs),
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected Hello, got {:?}", other),
+            ));
+        }
+    };
 
-    let msg = receive_message(reader).await?;
-    match msg {
-        None | Some(Message::Application(AppMessage::Ack)) => {
-            return Ok(());
+    if peer_id == identity.peer_id || peer_id >= identity.validator_keys.len() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Unknown validator {}", peer_id),
+        ));
+    }
+
+    let challenge: HandshakeChallenge = rand::random();
+    let codec = MessageCodec::negotiate(&codecs);
+    let msg = ControlMessage::HelloAck {
+        peer_id: identity.peer_id,
+        challenge,
+        signature: identity.sign(&peer_challenge, peer_id),
+        codec,
+    };
+    send_message(writer.clone(), &Message::Connection(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Connection(ControlMessage::Auth { signature })) => {
+            identity.verify(&challenge, peer_id, &signature)?;
         }
-        Some(other) => {
+        other => {
             return Err(Error::new(
                 ErrorKind::InvalidData,
-                format!("Expected Ack or None, got {:?}", other),
+                format!("Expected Auth, got {:?}", other),
             ));
         }
     }
+
+    send_ack(writer).await?;
+    Ok((peer_id, codec))
 }
 
 pub async fn send_transaction(
@@ -90,6 +519,31 @@ pub async fn send_transaction(
     send_message(writer, &Message::Application(msg)).await
 }
 
+/// Submits `tx` on behalf of a client, returning the hash to query its receipt by
+pub async fn submit_transaction(
+    tx: SignedTransaction,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Sha256Hash> {
+    send_transaction(writer, tx).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::TransactionSubmitted(tx_hash))) => Ok(tx_hash),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected TransactionSubmitted, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_transaction_submitted(
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    tx_hash: Sha256Hash,
+) -> Result<()> {
+    let msg = AppMessage::TransactionSubmitted(tx_hash);
+    send_message(writer, &Message::Application(msg)).await
+}
+
 pub async fn send_drip(
     writer: Arc<Mutex<OwnedWriteHalf>>,
     pk_bytes: &Sha256Hash,
@@ -172,6 +626,257 @@ pub async fn send_market_info_query(
     }
 }
 
+pub async fn send_order_book_query(
+    market_id: MarketId,
+    depth: usize,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Option<OrderBookDepth>> {
+    let msg = AppMessage::OrderBookQuery(market_id, depth);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::OrderBookQueryResponse(order_book))) => {
+            Ok(order_book)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_best_price_history_query(
+    market_id: MarketId,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Option<Vec<BestPriceSnapshot>>> {
+    let msg = AppMessage::BestPriceHistoryQuery(market_id);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::BestPriceHistoryQueryResponse(history))) => {
+            Ok(history)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_balance_summary_query(
+    account_public_key: PublicKeyHash,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Vec<AssetBalanceSummary>> {
+    let msg = AppMessage::BalanceSummaryQuery(account_public_key);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::BalanceSummaryQueryResponse(balance_summary))) => {
+            Ok(balance_summary)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_fee_balance_query(
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Vec<AssetBalanceSummary>> {
+    let msg = AppMessage::FeeBalanceQuery;
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::FeeBalanceQueryResponse(fee_balance))) => {
+            Ok(fee_balance)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_account_markets_query(
+    account_public_key: PublicKeyHash,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Vec<MarketId>> {
+    let msg = AppMessage::AccountMarketsQuery(account_public_key);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::AccountMarketsQueryResponse(market_ids))) => {
+            Ok(market_ids)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_order_locks_query(
+    account_public_key: PublicKeyHash,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Vec<OrderLock>> {
+    let msg = AppMessage::OrderLocksQuery(account_public_key);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::OrderLocksQueryResponse(order_locks))) => {
+            Ok(order_locks)
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_quote_query(
+    market_id: MarketId,
+    side: OrderDirection,
+    size: u64,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Option<FirmQuote>> {
+    let msg = AppMessage::QuoteQuery(market_id, side, size);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::QuoteQueryResponse(quote))) => Ok(quote),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_simulate_order(
+    account_public_key: PublicKeyHash,
+    order: OrderSpec,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<OrderSimulation> {
+    let msg = AppMessage::SimulateOrder(account_public_key, order);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::SimulateOrderResponse(simulation))) => Ok(simulation),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_order_query(
+    account_public_key: PublicKeyHash,
+    order_id: OrderId,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Option<OrderQueryResult>> {
+    let msg = AppMessage::OrderQuery(account_public_key, order_id);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::OrderQueryResponse(order))) => Ok(order),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_receipt_query(
+    tx_hash: Sha256Hash,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<Option<TransactionReceipt>> {
+    let msg = AppMessage::ReceiptQuery(tx_hash);
+    send_message(writer, &Message::Application(msg)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::ReceiptQueryResponse(receipt))) => Ok(receipt),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+pub async fn send_health_query(
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<NodeHealth> {
+    send_message(writer, &Message::Application(AppMessage::HealthQuery)).await?;
+
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::HealthQueryResponse(health))) => Ok(health),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected Response, got {:?}", other),
+        )),
+    }
+}
+
+/// Subscribes to an account, after which the connection only carries its updates,
+/// read with `receive_account_update`
+pub async fn subscribe_account(
+    account_public_key: PublicKeyHash,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+) -> Result<()> {
+    let msg = AppMessage::SubscribeAccount(account_public_key);
+    send_message(writer, &Message::Application(msg)).await
+}
+
+/// Next update of a subscribed account, None once the node closes the stream
+pub async fn receive_account_update(
+    reader: Arc<Mutex<OwnedReadHalf>>,
+) -> Result<Option<AccountInfoWithBalances>> {
+    match receive_message(reader).await? {
+        Some(Message::Application(AppMessage::AccountUpdate(account_info))) => {
+            Ok(Some(account_info))
+        }
+        None => Ok(None),
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected AccountUpdate, got {:?}", other),
+        )),
+    }
+}
+
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Polls until the receipt of a submitted transaction is committed, giving up with None
+/// after `timeout`
+pub async fn await_receipt(
+    tx_hash: Sha256Hash,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    timeout: Duration,
+) -> Result<Option<TransactionReceipt>> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(receipt) = send_receipt_query(tx_hash, reader.clone(), writer.clone()).await?
+            && receipt.finality == Finality::Committed
+        {
+            return Ok(Some(receipt));
+        }
+        if tokio::time::Instant::now() + RECEIPT_POLL_INTERVAL > deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+    }
+}
+
 pub async fn send_query(
     reader: Arc<Mutex<OwnedReadHalf>>,
     writer: Arc<Mutex<OwnedWriteHalf>>,
@@ -193,6 +898,49 @@ pub async fn send_end(writer: Arc<Mutex<OwnedWriteHalf>>) -> Result<()> {
     send_message(writer, &&Message::Connection(msg)).await
 }
 
+pub async fn send_ping(writer: Arc<Mutex<OwnedWriteHalf>>) -> Result<()> {
+    send_message(writer, &Message::Connection(ControlMessage::Ping)).await
+}
+
+pub async fn send_pong(writer: Arc<Mutex<OwnedWriteHalf>>) -> Result<()> {
+    send_message(writer, &Message::Connection(ControlMessage::Pong)).await
+}
+
+/// Pings the peer every heartbeat interval until sending fails
+pub async fn run_heartbeat(writer: Arc<Mutex<OwnedWriteHalf>>, heartbeat: Heartbeat) -> Result<()> {
+    let mut ticker = tokio::time::interval(heartbeat.interval);
+    loop {
+        ticker.tick().await;
+        send_ping(writer.clone()).await?;
+    }
+}
+
+/// Like `receive_message`, but fails with `TimedOut` once the peer has been silent
+/// for more than the heartbeat deadline
+pub async fn receive_message_with_heartbeat(
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    heartbeat: Heartbeat,
+) -> Result<Option<Message>> {
+    match tokio::time::timeout(heartbeat.deadline(), receive_message(reader)).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "Peer missed {} heartbeats of {:?}",
+                heartbeat.miss_threshold, heartbeat.interval
+            ),
+        )),
+    }
+}
+
+pub async fn send_client_rejected(
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    reason: ClientRejection,
+) -> Result<()> {
+    let msg = AppMessage::ClientRejected(reason);
+    send_message(writer, &Message::Application(msg)).await
+}
+
 pub async fn send_ack(writer: Arc<Mutex<OwnedWriteHalf>>) -> Result<()> {
     let msg = AppMessage::Ack;
     send_message(writer, &Message::Application(msg)).await
@@ -228,6 +976,21 @@ mod tests {
                         crate::types::transaction::UnsignedTransaction::CancelOrder(
                             _cancel_order_transaction,
                         ) => panic!("Expected order"),
+                        crate::types::transaction::UnsignedTransaction::CancelOrderAtPrice(
+                            _cancel_order_transaction,
+                        ) => panic!("Expected order"),
+                        crate::types::transaction::UnsignedTransaction::BatchOrder(
+                            _batch_order_transaction,
+                        ) => panic!("Expected order"),
+                        crate::types::transaction::UnsignedTransaction::ReduceOrder(
+                            _reduce_order_transaction,
+                        ) => panic!("Expected order"),
+                        crate::types::transaction::UnsignedTransaction::SetMarketStatus(
+                            _set_market_status_transaction,
+                        ) => panic!("Expected order"),
+                        crate::types::transaction::UnsignedTransaction::TransferAll(
+                            _transfer_all_transaction,
+                        ) => panic!("Expected order"),
                     }
                 }
                 _ => panic!("Expected Transaction"),
@@ -241,6 +1004,95 @@ mod tests {
         send_transaction(writer, tx).await
     }
 
+    #[tokio::test]
+    async fn test_submit_transaction_returns_its_hash() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, writer) = socket.into_split();
+            let reader = Arc::new(Mutex::new(reader));
+            let writer = Arc::new(Mutex::new(writer));
+
+            match receive_message(reader).await.unwrap() {
+                Some(Message::Application(AppMessage::SubmitTransaction(signed_tx))) => {
+                    send_transaction_submitted(writer, signed_tx.hash())
+                        .await
+                        .unwrap();
+                }
+                other => panic!("Expected Transaction, got {:?}", other),
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let (reader, writer) = stream.into_split();
+        let tx = make_alice_transaction();
+        let expected = tx.hash();
+
+        let tx_hash = submit_transaction(
+            tx,
+            Arc::new(Mutex::new(reader)),
+            Arc::new(Mutex::new(writer)),
+        )
+        .await?;
+        assert_eq!(tx_hash, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_await_receipt_polls_until_transaction_executes() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, writer) = socket.into_split();
+            let reader = Arc::new(Mutex::new(reader));
+            let writer = Arc::new(Mutex::new(writer));
+
+            // unknown on the first query, in an uncommitted block on the second,
+            // executed as order 11 on the third
+            for query in 0..3 {
+                let Some(Message::Application(AppMessage::ReceiptQuery(tx_hash))) =
+                    receive_message(reader.clone()).await.unwrap()
+                else {
+                    panic!("Expected ReceiptQuery");
+                };
+                let receipt = match query {
+                    0 => None,
+                    1 => Some(TransactionReceipt::pending(tx_hash)),
+                    _ => Some(TransactionReceipt {
+                        order_id: Some(11),
+                        ..TransactionReceipt::new(
+                            tx_hash,
+                            crate::types::transaction::TransactionStatus::Executed,
+                        )
+                    }),
+                };
+                let msg = AppMessage::ReceiptQueryResponse(receipt);
+                send_message(writer.clone(), &Message::Application(msg))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let (reader, writer) = stream.into_split();
+        let receipt = await_receipt(
+            [7; 32],
+            Arc::new(Mutex::new(reader)),
+            Arc::new(Mutex::new(writer)),
+            Duration::from_secs(5),
+        )
+        .await?
+        .unwrap();
+
+        assert_eq!(receipt.tx_hash, [7; 32]);
+        assert_eq!(receipt.order_id, Some(11));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_send_and_receive_query_response() -> Result<()> {
         use std::sync::Arc;
@@ -297,8 +1149,289 @@ mod tests {
             ) => {
                 panic!("Expected transaction")
             }
+            crate::types::transaction::UnsignedTransaction::CancelOrderAtPrice(
+                _cancel_order_transaction,
+            ) => {
+                panic!("Expected transaction")
+            }
+            crate::types::transaction::UnsignedTransaction::BatchOrder(
+                _batch_order_transaction,
+            ) => {
+                panic!("Expected transaction")
+            }
+            crate::types::transaction::UnsignedTransaction::ReduceOrder(
+                _reduce_order_transaction,
+            ) => {
+                panic!("Expected transaction")
+            }
+            crate::types::transaction::UnsignedTransaction::SetMarketStatus(
+                _set_market_status_transaction,
+            ) => {
+                panic!("Expected transaction")
+            }
+            crate::types::transaction::UnsignedTransaction::TransferAll(
+                _transfer_all_transaction,
+            ) => {
+                panic!("Expected transaction")
+            }
         }
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_oversized_frame_drops_connection() -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, _) = socket.into_split();
+            receive_message(Arc::new(Mutex::new(reader))).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await?;
+        let claimed_len = (max_frame_size() as u32 + 1).to_be_bytes();
+        stream.write_all(&claimed_len).await?;
+
+        let result = server.await.unwrap();
+        let err = result.expect_err("Expected oversized frame to be rejected");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    mod test_compression {
+        use super::*;
+
+        #[cfg(feature = "compression")]
+        fn large_proposal() -> Message {
+            use crate::{
+                hotstuff::crypto::QuorumCertificate,
+                test_utils::test_helpers::make_alice_transaction,
+            };
+
+            let (genesis, _) = Block::create_genesis_block();
+            let transactions = (0..200).map(|_| make_alice_transaction()).collect();
+            let block = Block::create_leaf(
+                &genesis,
+                transactions,
+                1,
+                QuorumCertificate::create_genesis_qc(),
+                [0; 32],
+                [0; 32],
+            );
+            Message::HotStuff(HotStuffMessage::create_proposal(block, 1, 0, 1))
+        }
+
+        #[test]
+        fn test_peer_without_compression_negotiates_plain() {
+            // older peers send no codec list at all
+            assert_eq!(MessageCodec::negotiate(&[]), MessageCodec::Plain);
+            assert_eq!(
+                MessageCodec::negotiate(&[MessageCodec::Plain]),
+                MessageCodec::Plain
+            );
+        }
+
+        #[test]
+        fn test_small_messages_are_sent_plain() {
+            let json = serde_json::to_vec(&Message::Application(AppMessage::Ack)).unwrap();
+            let frame = MessageCodec::Deflate.encode(json.clone()).unwrap();
+            assert_eq!(frame, json);
+        }
+
+        #[cfg(feature = "compression")]
+        #[tokio::test]
+        async fn test_compressed_proposal_decodes_to_original_block() -> Result<()> {
+            let proposal = large_proposal();
+            let json = serde_json::to_vec(&proposal)?;
+            let frame = MessageCodec::Deflate.encode(json.clone())?;
+            assert_eq!(frame[0], DEFLATE_FRAME_TAG);
+            assert!(frame.len() < json.len() / 2);
+
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = listener.local_addr()?;
+            let sent = proposal.clone();
+            tokio::spawn(async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (_, writer) = socket.into_split();
+                send_message_with_codec(Arc::new(Mutex::new(writer)), &sent, MessageCodec::Deflate)
+                    .await
+                    .unwrap();
+            });
+
+            let (reader, _writer) = TcpStream::connect(addr).await?.into_split();
+            let received = receive_message(Arc::new(Mutex::new(reader))).await?;
+            let (
+                Some(Message::HotStuff(HotStuffMessage::Proposal { node: received, .. })),
+                Message::HotStuff(HotStuffMessage::Proposal { node: original, .. }),
+            ) = (received, proposal)
+            else {
+                panic!("Expected a proposal");
+            };
+            assert_eq!(received, original);
+            Ok(())
+        }
+    }
+
+    mod test_handshake {
+        use super::*;
+
+        fn identity(
+            peer_id: PeerId,
+            seed: u8,
+            validator_keys: Vec<VerifyingKey>,
+        ) -> HandshakeIdentity {
+            HandshakeIdentity::new(peer_id, SigningKey::from_bytes(&[seed; 32]), validator_keys)
+        }
+
+        fn validator_keys() -> Vec<VerifyingKey> {
+            (0..4u8)
+                .map(|seed| SigningKey::from_bytes(&[seed; 32]).verifying_key())
+                .collect()
+        }
+
+        /// Runs `send_hello` from `initiator` against `accept_hello` on `acceptor`
+        async fn run_handshake(
+            initiator: HandshakeIdentity,
+            acceptor: HandshakeIdentity,
+            expected_peer: PeerId,
+        ) -> (Result<MessageCodec>, Result<(PeerId, MessageCodec)>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = tokio::spawn(async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (reader, writer) = socket.into_split();
+                accept_hello(
+                    Arc::new(Mutex::new(reader)),
+                    Arc::new(Mutex::new(writer)),
+                    &acceptor,
+                )
+                .await
+            });
+
+            let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            let reader = Arc::new(Mutex::new(reader));
+            let writer = Arc::new(Mutex::new(writer));
+            let client_result = send_hello(writer.clone(), reader, &initiator, expected_peer).await;
+
+            // closing our end unblocks an acceptor still waiting on us
+            drop(writer);
+            (client_result, server.await.unwrap())
+        }
+
+        #[tokio::test]
+        async fn test_valid_handshake() {
+            let (client, server) = run_handshake(
+                identity(1, 1, validator_keys()),
+                identity(2, 2, validator_keys()),
+                2,
+            )
+            .await;
+
+            let client_codec = client.expect("Expected initiator to authenticate");
+            let (peer_id, server_codec) = server.expect("Expected acceptor to authenticate");
+            assert_eq!(peer_id, 1);
+            // both ends settle on the same codec, the most preferred one this build supports
+            assert_eq!(client_codec, server_codec);
+            assert_eq!(client_codec, MessageCodec::supported()[0]);
+        }
+
+        #[tokio::test]
+        async fn test_unknown_key_is_rejected() {
+            // claims to be validator 1 but signs with a key outside the validator set
+            let (client, server) = run_handshake(
+                identity(1, 42, validator_keys()),
+                identity(2, 2, validator_keys()),
+                2,
+            )
+            .await;
+
+            assert_eq!(server.unwrap_err().kind(), ErrorKind::PermissionDenied);
+            assert!(client.is_err());
+        }
+
+        #[tokio::test]
+        async fn test_unknown_peer_id_is_rejected() {
+            let (client, server) = run_handshake(
+                identity(7, 7, validator_keys()),
+                identity(2, 2, validator_keys()),
+                2,
+            )
+            .await;
+
+            assert_eq!(server.unwrap_err().kind(), ErrorKind::PermissionDenied);
+            assert!(client.is_err());
+        }
+
+        #[tokio::test]
+        async fn test_impersonated_acceptor_is_rejected() {
+            // acceptor signs with a key outside the set while claiming to be validator 2
+            let (client, _) = run_handshake(
+                identity(1, 1, validator_keys()),
+                identity(2, 42, validator_keys()),
+                2,
+            )
+            .await;
+
+            assert_eq!(client.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        }
+    }
+
+    mod test_heartbeat {
+        use super::*;
+
+        const HEARTBEAT: Heartbeat = Heartbeat {
+            interval: Duration::from_millis(20),
+            miss_threshold: 3,
+        };
+
+        async fn connected_pair() -> (TcpStream, TcpStream) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = TcpStream::connect(addr).await.unwrap();
+            let (server, _) = listener.accept().await.unwrap();
+            (client, server)
+        }
+
+        #[tokio::test]
+        async fn test_silent_peer_is_declared_down() {
+            let (_silent_peer, socket) = connected_pair().await;
+            let (reader, _writer) = socket.into_split();
+
+            let started = tokio::time::Instant::now();
+            let result =
+                receive_message_with_heartbeat(Arc::new(Mutex::new(reader)), HEARTBEAT).await;
+
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::TimedOut);
+            assert!(started.elapsed() >= HEARTBEAT.deadline());
+        }
+
+        #[tokio::test]
+        async fn test_pinging_peer_stays_up() {
+            let (peer, socket) = connected_pair().await;
+            let (_peer_reader, peer_writer) = peer.into_split();
+            let heartbeat =
+                tokio::spawn(run_heartbeat(Arc::new(Mutex::new(peer_writer)), HEARTBEAT));
+
+            let (reader, _writer) = socket.into_split();
+            let reader = Arc::new(Mutex::new(reader));
+
+            // keep reading for well past the deadline, which only works if pings keep arriving
+            let until = tokio::time::Instant::now() + HEARTBEAT.deadline() * 3;
+            while tokio::time::Instant::now() < until {
+                let msg = receive_message_with_heartbeat(reader.clone(), HEARTBEAT)
+                    .await
+                    .expect("Expected pinging peer to stay up");
+                assert!(matches!(
+                    msg,
+                    Some(Message::Connection(ControlMessage::Ping))
+                ));
+            }
+            heartbeat.abort();
+        }
+    }
 }
@@ -1,6 +1,11 @@
-use std::{io::ErrorKind, sync::Arc};
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+    io::ErrorKind,
+    sync::Arc,
+};
 
-use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     sync::Mutex,
@@ -10,11 +15,62 @@ use serde_json;
 
 const LEN_BUF_LEN: usize = 4;
 
+/// Bounded set of recently seen message hashes, used to avoid relaying the same message twice.
+/// The oldest hash is forgotten once the capacity is reached
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecentlySeen<T: Eq + Hash = [u8; 32]> {
+    hashes: HashSet<T>,
+    order: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T: Copy + Eq + Hash> RecentlySeen<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Returns false if `hash` was already seen
+    pub fn insert(&mut self, hash: T) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+
+        self.order.push_back(hash);
+        while self.order.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            self.hashes.remove(&oldest);
+        }
+        true
+    }
+
+    pub fn contains(&self, hash: &T) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Forgets `hash` so it can be inserted again, returning false if it was not seen.
+    /// Linear in the number of hashes remembered
+    pub fn remove(&mut self, hash: &T) -> bool {
+        if !self.hashes.remove(hash) {
+            return false;
+        }
+        self.order.retain(|seen| seen != hash);
+        true
+    }
+}
+
 pub async fn send_data<W>(stream: Arc<Mutex<W>>, data: &[u8]) -> std::io::Result<()>
 where
     W: AsyncWrite + Unpin,
 {
-    let data_len = (data.len() as u32).to_be_bytes();
+    let data_len = u32::try_from(data.len())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "Frame too large to send"))?
+        .to_be_bytes();
     let mut stream = stream.lock().await;
     stream.write_all(&data_len).await?;
     stream.write_all(data).await?;
@@ -22,8 +78,11 @@ where
     Ok(())
 }
 
+/// Reads one length-prefixed frame. Frames longer than `max_len` are rejected before
+/// allocating, leaving the stream unusable so the caller should drop the connection
 pub async fn receive_data<W: AsyncRead + Unpin>(
     stream: &mut W,
+    max_len: usize,
 ) -> std::io::Result<Option<Vec<u8>>> {
     let mut len_buf: [u8; 4] = [0u8; LEN_BUF_LEN];
 
@@ -36,6 +95,15 @@ pub async fn receive_data<W: AsyncRead + Unpin>(
     }
 
     let msg_len = u32::from_be_bytes(len_buf) as usize;
+    if msg_len > max_len {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Frame of {} bytes exceeds limit of {} bytes",
+                msg_len, max_len
+            ),
+        ));
+    }
 
     let mut resp_buf = vec![0u8; msg_len];
 
@@ -44,13 +112,13 @@ pub async fn receive_data<W: AsyncRead + Unpin>(
     Ok(Some(resp_buf))
 }
 
-pub async fn receive_json<T, R>(reader: Arc<Mutex<R>>) -> std::io::Result<Option<T>>
+pub async fn receive_json<T, R>(reader: Arc<Mutex<R>>, max_len: usize) -> std::io::Result<Option<T>>
 where
     T: DeserializeOwned,
     R: AsyncRead + Unpin,
 {
     let mut guard = reader.lock().await;
-    let raw_bytes_opt: Option<Vec<u8>> = receive_data(&mut *guard).await?;
+    let raw_bytes_opt: Option<Vec<u8>> = receive_data(&mut *guard, max_len).await?;
 
     match raw_bytes_opt {
         Some(raw_bytes) => {
@@ -71,6 +139,38 @@ mod tests {
     use std::io::Result;
     use tokio::io::{AsyncReadExt, duplex};
 
+    const TEST_MAX_LEN: usize = 1024;
+
+    #[test]
+    fn test_recently_seen_is_bounded() {
+        let mut seen = RecentlySeen::new(2);
+
+        assert!(seen.insert([1; 32]));
+        assert!(!seen.insert([1; 32]));
+        assert!(seen.insert([2; 32]));
+        assert!(seen.insert([3; 32]));
+
+        // the oldest hash was forgotten to make room
+        assert!(seen.insert([1; 32]));
+        assert!(!seen.insert([3; 32]));
+    }
+
+    #[test]
+    fn test_recently_seen_remove() {
+        let mut seen = RecentlySeen::new(2);
+        assert!(seen.insert([1; 32]));
+        assert!(seen.insert([2; 32]));
+
+        assert!(seen.remove(&[1; 32]));
+        assert!(!seen.remove(&[1; 32]));
+        assert!(!seen.contains(&[1; 32]));
+
+        // the removed hash no longer takes up room, so nothing else is forgotten
+        assert!(seen.insert([3; 32]));
+        assert!(seen.contains(&[2; 32]));
+        assert!(seen.contains(&[3; 32]));
+    }
+
     async fn test_send_data_helper(payload: &[u8]) -> Result<()> {
         let expected_len = payload.len();
 
@@ -113,7 +213,7 @@ mod tests {
         })
         .await?;
 
-        let recieved_payload_opt = receive_data(&mut server_end).await?;
+        let recieved_payload_opt = receive_data(&mut server_end, TEST_MAX_LEN).await?;
 
         let recieved_payload = recieved_payload_opt.expect("Expected Some, got None");
 
@@ -154,7 +254,7 @@ mod tests {
         .await
         .unwrap();
 
-        let result = receive_data(&mut server_end).await;
+        let result = receive_data(&mut server_end, TEST_MAX_LEN).await;
         let result = result.expect("Expect Some, got None");
         assert!(
             result.is_none(),
@@ -176,13 +276,53 @@ mod tests {
         .await
         .unwrap();
 
-        let result = receive_data(&mut server_end).await;
+        let result = receive_data(&mut server_end, TEST_MAX_LEN).await;
         assert!(
             result.is_err(),
             "Expected an error due to incomplete payload"
         );
     }
 
+    #[tokio::test]
+    async fn test_receive_data_at_max_len() -> Result<()> {
+        let payload = vec![7u8; 16];
+        let (client_end, mut server_end) = duplex(1024);
+
+        send_data(Arc::new(Mutex::new(client_end)), &payload).await?;
+
+        let received = receive_data(&mut server_end, payload.len()).await?;
+        assert_eq!(received, Some(payload));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_receive_data_over_max_len_is_rejected() -> Result<()> {
+        let payload = vec![7u8; 17];
+        let (client_end, mut server_end) = duplex(1024);
+
+        send_data(Arc::new(Mutex::new(client_end)), &payload).await?;
+
+        let err = receive_data(&mut server_end, 16)
+            .await
+            .expect_err("Expected over-cap frame to be rejected");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_receive_data_rejects_huge_length_without_payload() -> Result<()> {
+        let (mut client_end, mut server_end) = duplex(1024);
+
+        // Only the length prefix is sent, so the reader must reject it rather than wait to allocate
+        client_end.write_all(&u32::MAX.to_be_bytes()).await?;
+
+        let err = receive_data(&mut server_end, TEST_MAX_LEN)
+            .await
+            .expect_err("Expected over-cap frame to be rejected");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_receive_json_success() -> std::io::Result<()> {
         use serde::{Deserialize, Serialize};
@@ -209,7 +349,7 @@ mod tests {
 
         let server_end = Arc::new(Mutex::new(server_end));
 
-        let result_opt: Option<MyData> = receive_json(server_end).await?;
+        let result_opt: Option<MyData> = receive_json(server_end, TEST_MAX_LEN).await?;
         let result = result_opt.expect("Expeced Some, got None");
         assert_eq!(result, payload);
         Ok(())
@@ -228,7 +368,8 @@ mod tests {
         });
 
         let server_end = Arc::new(Mutex::new(server_end));
-        let result: Result<Option<serde_json::Value>> = receive_json(server_end).await;
+        let result: Result<Option<serde_json::Value>> =
+            receive_json(server_end, TEST_MAX_LEN).await;
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
         Ok(())
@@ -249,7 +390,8 @@ mod tests {
         });
 
         let server_end = Arc::new(Mutex::new(server_end));
-        let result: std::io::Result<Option<serde_json::Value>> = receive_json(server_end).await;
+        let result: std::io::Result<Option<serde_json::Value>> =
+            receive_json(server_end, TEST_MAX_LEN).await;
         assert!(result.is_err());
         Ok(())
     }
@@ -275,7 +417,8 @@ mod tests {
         });
 
         let server_end = Arc::new(Mutex::new(server_end));
-        let result: std::io::Result<Option<ExpectedStruct>> = receive_json(server_end).await;
+        let result: std::io::Result<Option<ExpectedStruct>> =
+            receive_json(server_end, TEST_MAX_LEN).await;
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
         Ok(())
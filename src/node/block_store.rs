@@ -0,0 +1,232 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use crate::{config, hotstuff::block::Block};
+
+const LEN_BUF_LEN: usize = 4;
+
+/// Append-only log of committed blocks, stored as length-prefixed bincode frames
+pub struct BlockStore {
+    file: File,
+}
+
+impl BlockStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        Ok(Self { file })
+    }
+
+    pub fn for_node(node_id: usize) -> io::Result<Self> {
+        Self::open(config::retrieve_data_dir().join(format!("blocks_{}.bin", node_id)))
+    }
+
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let data =
+            bincode::serialize(block).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let data_len = (data.len() as u32).to_be_bytes();
+
+        let mut frame = Vec::with_capacity(LEN_BUF_LEN + data.len());
+        frame.extend_from_slice(&data_len);
+        frame.extend_from_slice(&data);
+
+        self.file.write_all(&frame)?;
+        self.file.sync_data()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    /// Drops every stored block, once they are all covered by a checkpoint
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+
+    /// Reads back every complete block in commit order.
+    /// A partial or corrupt trailing frame, e.g. from a crash mid-write, is truncated
+    /// so later appends start from the last good block.
+    pub fn load_blocks(&mut self) -> io::Result<Vec<Block>> {
+        let mut bytes = vec![];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut blocks = vec![];
+        let mut offset = 0;
+
+        while offset + LEN_BUF_LEN <= bytes.len() {
+            let mut len_buf = [0u8; LEN_BUF_LEN];
+            len_buf.copy_from_slice(&bytes[offset..offset + LEN_BUF_LEN]);
+            let frame_len = u32::from_be_bytes(len_buf) as usize;
+
+            let frame_start = offset + LEN_BUF_LEN;
+            let Some(frame) = bytes.get(frame_start..frame_start + frame_len) else {
+                break;
+            };
+
+            let Ok(block) = bincode::deserialize::<Block>(frame) else {
+                break;
+            };
+
+            blocks.push(block);
+            offset = frame_start + frame_len;
+        }
+
+        if offset < bytes.len() {
+            self.file.set_len(offset as u64)?;
+        }
+
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hotstuff::crypto::QuorumCertificate,
+        state::state::LedgerState,
+        test_utils::test_helpers::{get_alice_pk_str, get_bob_pk_str},
+        types::transaction::{
+            PublicKeyHash, SignedTransaction, TransactionStatus, TransferTransaction,
+            UnsignedTransaction,
+        },
+    };
+    use ed25519_dalek::SigningKey;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "superliquid_block_store_{}_{}.bin",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn create_faucet_txn(
+        faucet_sk: &mut SigningKey,
+        to: PublicKeyHash,
+        amount: u128,
+        nonce: u64,
+    ) -> SignedTransaction {
+        let unsigned = UnsignedTransaction::Transfer(TransferTransaction {
+            from: faucet_sk.verifying_key().to_bytes(),
+            to,
+            amount,
+            asset_id: 0,
+            nonce,
+            status: TransactionStatus::Pending,
+            valid_until: None,
+        });
+        unsigned.sign(faucet_sk)
+    }
+
+    fn create_block(transactions: Vec<SignedTransaction>, view_number: u64) -> Block {
+        Block::Normal {
+            parent_id: [0; 32],
+            merkle_root: Block::hash_transactions(&transactions),
+            transactions,
+            view_number,
+            justify: QuorumCertificate::mock(view_number),
+            state_root: [0; 32],
+            executed_block_hash: [0; 32],
+        }
+    }
+
+    /// Commits `num_blocks` faucet transfers to a ledger, appending each block to the store
+    fn commit_blocks(store: &mut BlockStore, num_blocks: u64) -> LedgerState {
+        let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+        let mut ledger_state = LedgerState::new();
+
+        for nonce in 0..num_blocks {
+            let to = if nonce % 2 == 0 {
+                get_alice_pk_str().to_bytes()
+            } else {
+                get_bob_pk_str().to_bytes()
+            };
+            let txn = create_faucet_txn(&mut faucet_sk, to, 100 + nonce as u128, nonce);
+            let block = create_block(vec![txn], nonce + 1);
+
+            // like the replica, execute a copy and persist the block as proposed
+            ledger_state.apply_block(&mut block.clone());
+            store.append(&block).unwrap();
+        }
+
+        ledger_state
+    }
+
+    #[test]
+    fn test_replayed_blocks_rebuild_ledger_state() {
+        let path = temp_path("replay");
+        let _ = fs::remove_file(&path);
+
+        let mut store = BlockStore::open(&path).unwrap();
+        let ledger_state = commit_blocks(&mut store, 5);
+        drop(store);
+
+        let mut reopened = BlockStore::open(&path).unwrap();
+        let mut blocks = reopened.load_blocks().unwrap();
+        assert_eq!(blocks.len(), 5);
+
+        let mut replayed_state = LedgerState::new();
+        for block in blocks.iter_mut() {
+            replayed_state.apply_block(block);
+        }
+
+        for pk in [get_alice_pk_str().to_bytes(), get_bob_pk_str().to_bytes()] {
+            let expected = ledger_state.get_account_info_with_balances_or_default(&pk);
+            let replayed = replayed_state.get_account_info_with_balances_or_default(&pk);
+            let expected_balance = expected.spot_balances.find_asset_id(0).unwrap();
+            let replayed_balance = replayed.spot_balances.find_asset_id(0).unwrap();
+            assert_eq!(
+                replayed_balance.available_balance,
+                expected_balance.available_balance
+            );
+            assert_eq!(
+                replayed_balance.total_balance,
+                expected_balance.total_balance
+            );
+        }
+        assert_eq!(replayed_state.state_root(), ledger_state.state_root());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_partial_trailing_frame_is_truncated() {
+        let path = temp_path("partial");
+        let _ = fs::remove_file(&path);
+
+        let mut store = BlockStore::open(&path).unwrap();
+        commit_blocks(&mut store, 3);
+        let good_len = fs::metadata(&path).unwrap().len();
+
+        // simulate a crash midway through writing a frame
+        store.file.write_all(&[0, 0, 1, 0, 42, 42]).unwrap();
+        drop(store);
+
+        let mut reopened = BlockStore::open(&path).unwrap();
+        assert_eq!(reopened.load_blocks().unwrap().len(), 3);
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+
+        // appends continue from the last good block
+        reopened
+            .append(&create_block(vec![], 4))
+            .expect("Expected append to succeed");
+        assert_eq!(reopened.load_blocks().unwrap().len(), 4);
+
+        fs::remove_file(path).unwrap();
+    }
+}
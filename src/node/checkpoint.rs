@@ -0,0 +1,63 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, hotstuff::block::Block, state::state::LedgerState};
+
+/// Ledger snapshot taken right after `block`, the `height`th committed block, was executed
+#[derive(Deserialize)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub block: Block,
+    pub ledger_state: LedgerState,
+}
+
+/// Borrowed form of `Checkpoint`, so the live ledger can be written without cloning it
+#[derive(Serialize)]
+pub struct CheckpointRef<'a> {
+    pub height: u64,
+    pub block: &'a Block,
+    pub ledger_state: &'a LedgerState,
+}
+
+/// Keeps the latest checkpoint in a file, replacing the previous one atomically
+pub struct CheckpointStore {
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn for_node(node_id: usize) -> Self {
+        Self::new(config::retrieve_data_dir().join(format!("checkpoint_{}.bin", node_id)))
+    }
+
+    /// Returns None if no checkpoint has been written yet
+    pub fn load(&self) -> io::Result<Option<Checkpoint>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let checkpoint = bincode::deserialize(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(checkpoint))
+    }
+
+    pub fn save(&self, checkpoint: &CheckpointRef) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let bytes = bincode::serialize(checkpoint)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // write to a temporary file first so a crash never leaves a partial checkpoint
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
@@ -6,39 +6,86 @@ use tokio::{
     sync::{Mutex, mpsc, oneshot},
 };
 
+use crate::state::order::OrderDirection;
+use crate::state::order::OrderId;
 use crate::state::spot_clearinghouse::MarketId;
-use crate::state::spot_market::MarketInfo;
+use crate::state::spot_market::{BestPriceSnapshot, FirmQuote, MarketInfo, OrderBookDepth};
 use crate::{
-    message_protocol::{self, AppMessage, ControlMessage},
-    node::{peer::broadcast::broadcast_transaction, state::Node},
+    message_protocol::{self, AppMessage, ClientRejection, ControlMessage, NodeHealth},
+    node::{logger::LogLevel, peer::broadcast::broadcast_transaction, state::Node},
     state::{
         asset::{Asset, AssetId},
-        state::AccountInfoWithBalances,
+        receipt::TransactionReceipt,
+        state::{
+            AccountInfoWithBalances, AssetBalanceSummary, OrderLock, OrderQueryResult,
+            OrderSimulation,
+        },
     },
     types::{
         message::{Message, ReplicaInBound, mpsc_error},
         transaction::{
-            PublicKeyHash, SignedTransaction, TransactionStatus, TransferTransaction,
-            UnsignedTransaction,
+            OrderSpec, PublicKeyHash, Sha256Hash, SignedTransaction, TransactionStatus,
+            TransferTransaction, UnsignedTransaction,
         },
     },
 };
 
-use super::listener::ClientSocket;
+use super::listener::{ClientSocket, RequestRateLimiter};
 
 pub enum ClientQuery {
     AccountQuery(PublicKeyHash),
+    AccountMarketsQuery(PublicKeyHash),
     AssetQuery,
+    BalanceSummaryQuery(PublicKeyHash),
+    FeeBalanceQuery,
     MarketInfoQuery(MarketId),
     MarketsQuery,
+    OrderBookQuery {
+        market_id: MarketId,
+        depth: usize,
+    },
+    BestPriceHistoryQuery(MarketId),
+    OrderQuery {
+        public_key: PublicKeyHash,
+        order_id: OrderId,
+    },
+    OrderLocksQuery(PublicKeyHash),
+    // `size` in base lots
+    QuoteQuery {
+        market_id: MarketId,
+        side: OrderDirection,
+        size: u64,
+    },
+    ReceiptQuery(Sha256Hash),
+    SimulateOrder {
+        public_key: PublicKeyHash,
+        order: OrderSpec,
+    },
+    /// Answered by the replica, without the ledger
+    HealthQuery,
+    /// Registers for the account's updates, answered by the replica
+    SubscribeAccount(PublicKeyHash),
 }
 
 #[derive(Debug)]
 pub enum ClientResponse {
     AccountQueryReponse(AccountInfoWithBalances),
+    AccountMarketsQueryResponse(Vec<MarketId>),
     AssetQueryResponse(Vec<Asset>),
+    BalanceSummaryQueryResponse(Vec<AssetBalanceSummary>),
+    FeeBalanceQueryResponse(Vec<AssetBalanceSummary>),
     MarketInfoQueryResponse(Option<MarketInfo>),
     MarketsQueryResponse(Vec<MarketInfo>),
+    OrderBookQueryResponse(Option<OrderBookDepth>),
+    BestPriceHistoryQueryResponse(Option<Vec<BestPriceSnapshot>>),
+    OrderQueryResponse(Option<OrderQueryResult>),
+    OrderLocksQueryResponse(Vec<OrderLock>),
+    QuoteQueryResponse(Option<FirmQuote>),
+    ReceiptQueryResponse(Option<TransactionReceipt>),
+    SimulateOrderResponse(OrderSimulation),
+    // peer count is left for the node to fill in
+    HealthQueryResponse(NodeHealth),
+    AccountSubscription(mpsc::Receiver<AccountInfoWithBalances>),
 }
 
 pub struct QueryRequest {
@@ -50,12 +97,26 @@ pub(super) async fn handle_client_connection(
     socket: ClientSocket,
     node: Arc<Node>,
     to_replica_tx: mpsc::Sender<ReplicaInBound>,
+    mut rate_limiter: RequestRateLimiter,
 ) -> Result<()> {
     loop {
         let message = message_protocol::receive_message(socket.reader.clone()).await?;
+        let is_request = matches!(message, Some(Message::Application(_)));
+        if is_request && !rate_limiter.try_acquire() {
+            message_protocol::send_client_rejected(
+                socket.writer.clone(),
+                ClientRejection::RateLimited,
+            )
+            .await?;
+            continue;
+        }
+
         match message {
             Some(Message::Application(AppMessage::SubmitTransaction(tx))) => {
+                let tx_hash = tx.hash();
                 handle_transaction(&node, tx, to_replica_tx.clone()).await?;
+                message_protocol::send_transaction_submitted(socket.writer.clone(), tx_hash)
+                    .await?;
             }
             Some(Message::Application(AppMessage::Query)) => {
                 handle_query(socket.writer.clone(), &node).await?;
@@ -66,9 +127,20 @@ pub(super) async fn handle_client_connection(
             Some(Message::Application(AppMessage::AccountQuery(pk))) => {
                 handle_account_query(socket.writer.clone(), pk, to_replica_tx.clone()).await?;
             }
+            Some(Message::Application(AppMessage::AccountMarketsQuery(pk))) => {
+                handle_account_markets_query(pk, socket.writer.clone(), to_replica_tx.clone())
+                    .await?;
+            }
             Some(Message::Application(AppMessage::AssetQuery)) => {
                 handle_asset_query(socket.writer.clone(), to_replica_tx.clone()).await?;
             }
+            Some(Message::Application(AppMessage::BalanceSummaryQuery(pk))) => {
+                handle_balance_summary_query(pk, socket.writer.clone(), to_replica_tx.clone())
+                    .await?;
+            }
+            Some(Message::Application(AppMessage::FeeBalanceQuery)) => {
+                handle_fee_balance_query(socket.writer.clone(), to_replica_tx.clone()).await?;
+            }
             Some(Message::Application(AppMessage::MarketsQuery)) => {
                 handle_markets_query(socket.writer.clone(), to_replica_tx.clone()).await?;
             }
@@ -76,7 +148,56 @@ pub(super) async fn handle_client_connection(
                 handle_market_query(market_id, socket.writer.clone(), to_replica_tx.clone())
                     .await?;
             }
-            Some(Message::Connection(ControlMessage::End)) => {
+            Some(Message::Application(AppMessage::OrderBookQuery(market_id, depth))) => {
+                handle_order_book_query(
+                    market_id,
+                    depth,
+                    socket.writer.clone(),
+                    to_replica_tx.clone(),
+                )
+                .await?;
+            }
+            Some(Message::Application(AppMessage::BestPriceHistoryQuery(market_id))) => {
+                handle_best_price_history_query(
+                    market_id,
+                    socket.writer.clone(),
+                    to_replica_tx.clone(),
+                )
+                .await?;
+            }
+            Some(Message::Application(AppMessage::OrderQuery(pk, order_id))) => {
+                handle_order_query(pk, order_id, socket.writer.clone(), to_replica_tx.clone())
+                    .await?;
+            }
+            Some(Message::Application(AppMessage::OrderLocksQuery(pk))) => {
+                handle_order_locks_query(pk, socket.writer.clone(), to_replica_tx.clone()).await?;
+            }
+            Some(Message::Application(AppMessage::QuoteQuery(market_id, side, size))) => {
+                handle_quote_query(
+                    market_id,
+                    side,
+                    size,
+                    socket.writer.clone(),
+                    to_replica_tx.clone(),
+                )
+                .await?;
+            }
+            Some(Message::Application(AppMessage::ReceiptQuery(tx_hash))) => {
+                handle_receipt_query(tx_hash, socket.writer.clone(), to_replica_tx.clone()).await?;
+            }
+            Some(Message::Application(AppMessage::SimulateOrder(pk, order))) => {
+                handle_simulate_order(pk, order, socket.writer.clone(), to_replica_tx.clone())
+                    .await?;
+            }
+            Some(Message::Application(AppMessage::HealthQuery)) => {
+                handle_health_query(&node, socket.writer.clone(), to_replica_tx.clone()).await?;
+            }
+            // the connection is given over to the stream until either side closes it
+            Some(Message::Application(AppMessage::SubscribeAccount(pk))) => {
+                return handle_account_subscription(pk, &socket, to_replica_tx).await;
+            }
+            // the client hung up, freeing its connection slot
+            Some(Message::Connection(ControlMessage::End)) | None => {
                 return Ok(());
             }
             _ => {}
@@ -99,7 +220,7 @@ pub(super) async fn send_query_to_replica(
         .await
         .map_err(|e| mpsc_error("Failed to send query request to replica", e))?;
 
-    println!("Waiting for response");
+    log_trace!("Waiting for response");
     let response = response_rx
         .await
         .map_err(|e| mpsc_error("Failed to recieve response from replica", e))?;
@@ -212,6 +333,357 @@ pub(super) async fn handle_markets_query(
     Ok(())
 }
 
+pub(super) async fn handle_order_query(
+    pk_bytes: PublicKeyHash,
+    order_id: OrderId,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::OrderQuery {
+        public_key: pk_bytes,
+        order_id,
+    };
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::OrderQueryResponse(order) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::OrderQueryResponse(order)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected OrderQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_order_book_query(
+    market_id: MarketId,
+    depth: usize,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::OrderBookQuery { market_id, depth };
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::OrderBookQueryResponse(order_book) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::OrderBookQueryResponse(order_book)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected OrderBookQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_best_price_history_query(
+    market_id: MarketId,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::BestPriceHistoryQuery(market_id);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::BestPriceHistoryQueryResponse(history) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::BestPriceHistoryQueryResponse(history)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected BestPriceHistoryQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_balance_summary_query(
+    pk_bytes: PublicKeyHash,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::BalanceSummaryQuery(pk_bytes);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::BalanceSummaryQueryResponse(balance_summary) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::BalanceSummaryQueryResponse(balance_summary)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected BalanceSummaryQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_fee_balance_query(
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::FeeBalanceQuery;
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::FeeBalanceQueryResponse(fee_balance) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::FeeBalanceQueryResponse(fee_balance)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected FeeBalanceQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_account_markets_query(
+    pk_bytes: PublicKeyHash,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::AccountMarketsQuery(pk_bytes);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::AccountMarketsQueryResponse(market_ids) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::AccountMarketsQueryResponse(market_ids)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected AccountMarketsQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_order_locks_query(
+    pk_bytes: PublicKeyHash,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::OrderLocksQuery(pk_bytes);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::OrderLocksQueryResponse(order_locks) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::OrderLocksQueryResponse(order_locks)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected OrderLocksQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_receipt_query(
+    tx_hash: Sha256Hash,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::ReceiptQuery(tx_hash);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::ReceiptQueryResponse(receipt) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::ReceiptQueryResponse(receipt)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected ReceiptQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_health_query(
+    node: &Arc<Node>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let response = send_query_to_replica(ClientQuery::HealthQuery, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::HealthQueryResponse(mut health) => {
+            health.peer_count = node.peer_connections.read().await.len();
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::HealthQueryResponse(health)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected HealthQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Pushes an `AccountUpdate` to the client whenever a committed block changes the account,
+/// until the client hangs up or the ledger drops the subscription for falling behind
+pub(super) async fn handle_account_subscription(
+    pk_bytes: PublicKeyHash,
+    socket: &ClientSocket,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::SubscribeAccount(pk_bytes);
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    let mut updates = match response {
+        ClientResponse::AccountSubscription(updates) => updates,
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected AccountSubscription, got {:?}", other),
+            ));
+        }
+    };
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let Some(account_info_with_balances) = update else {
+                    return Ok(());
+                };
+                message_protocol::send_message(
+                    socket.writer.clone(),
+                    &Message::Application(AppMessage::AccountUpdate(account_info_with_balances)),
+                )
+                .await?;
+            }
+            message = message_protocol::receive_message(socket.reader.clone()) => {
+                if matches!(message?, Some(Message::Connection(ControlMessage::End)) | None) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub(super) async fn handle_quote_query(
+    market_id: MarketId,
+    side: OrderDirection,
+    size: u64,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::QuoteQuery {
+        market_id,
+        side,
+        size,
+    };
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::QuoteQueryResponse(quote) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::QuoteQueryResponse(quote)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected QuoteQueryResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn handle_simulate_order(
+    pk_bytes: PublicKeyHash,
+    order: OrderSpec,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) -> Result<()> {
+    let query = ClientQuery::SimulateOrder {
+        public_key: pk_bytes,
+        order,
+    };
+    let response = send_query_to_replica(query, to_replica_tx).await?;
+
+    match response {
+        ClientResponse::SimulateOrderResponse(simulation) => {
+            // send to client
+            message_protocol::send_message(
+                writer,
+                &Message::Application(AppMessage::SimulateOrderResponse(simulation)),
+            )
+            .await?;
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected SimulateOrderResponse, got {:?}", other),
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub(super) async fn handle_drip(
     node: &Arc<Node>,
     pk_bytes: PublicKeyHash,
@@ -251,6 +723,7 @@ pub(super) async fn handle_drip(
         asset_id,
         nonce: account_info.expected_nonce,
         status: TransactionStatus::Pending,
+        valid_until: None,
     });
 
     let drip_txn = drip_txn.sign(&mut faucet_key);
@@ -277,7 +750,10 @@ pub(crate) async fn handle_transaction(
             return Ok(());
         }
     }
-    logger.log("info", &format!("Received Transaction: {:?}", signed_tx));
+    logger.log(
+        LogLevel::Info,
+        &format!("Received Transaction: {:?}", signed_tx),
+    );
 
     broadcast_transaction(&node, signed_tx.clone()).await?;
     to_replica_tx
@@ -296,7 +772,10 @@ pub(super) async fn handle_query(
 
     let peer_addr = { writer.lock().await.peer_addr() };
 
-    logger.log("info", &format!("Received a query from {:?}", peer_addr));
+    logger.log(
+        LogLevel::Info,
+        &format!("Received a query from {:?}", peer_addr),
+    );
     let txs = {
         let transactions = node.transactions.lock().await;
         transactions.clone()
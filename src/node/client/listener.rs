@@ -6,11 +6,14 @@ use tokio::{
         TcpListener,
         tcp::{OwnedReadHalf, OwnedWriteHalf},
     },
-    sync::{Mutex, mpsc},
+    sync::{Mutex, Semaphore, mpsc},
+    time::Instant,
 };
 
 use crate::{
-    node::{client::handler::handle_client_connection, state::Node},
+    config,
+    message_protocol::{self, ClientRejection},
+    node::{client::handler::handle_client_connection, logger::LogLevel, state::Node},
     types::message::ReplicaInBound,
 };
 
@@ -25,6 +28,61 @@ impl ClientSocket {
     }
 }
 
+/// How many clients a node serves at once, and how fast each may send requests
+#[derive(Debug, Clone, Copy)]
+pub struct ClientLimits {
+    pub max_connections: usize,
+    pub requests_per_second: u32,
+}
+
+impl ClientLimits {
+    pub fn new(max_connections: usize, requests_per_second: u32) -> Self {
+        Self {
+            max_connections,
+            requests_per_second,
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(
+            config::retrieve_max_client_connections(),
+            config::retrieve_client_requests_per_second(),
+        )
+    }
+}
+
+/// Token bucket holding up to a second's worth of requests, refilled continuously
+pub struct RequestRateLimiter {
+    requests_per_second: u32,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RequestRateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            requests_per_second,
+            tokens: requests_per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token for a request, returning false if the bucket is empty
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        let capacity = self.requests_per_second as f64;
+        self.tokens = (self.tokens + elapsed * capacity).min(capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
 /// client listener handles the application level communication
 pub(crate) async fn run_client_listener(
     client_addr: String,
@@ -32,12 +90,32 @@ pub(crate) async fn run_client_listener(
     to_replica_tx: mpsc::Sender<ReplicaInBound>,
 ) -> Result<()> {
     let client_listener: TcpListener = TcpListener::bind(&client_addr).await?;
-    let logger = node.logger.clone();
 
-    logger.log("info", &format!("Listening to client on {:?}", client_addr));
+    node.logger.log(
+        LogLevel::Info,
+        &format!("Listening to client on {:?}", client_addr),
+    );
+
+    serve_clients(
+        client_listener,
+        node,
+        to_replica_tx,
+        ClientLimits::from_config(),
+    )
+    .await
+}
+
+/// Accepts clients until the listener fails, refusing connections beyond `limits.max_connections`
+pub(crate) async fn serve_clients(
+    client_listener: TcpListener,
+    node: Arc<Node>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+    limits: ClientLimits,
+) -> Result<()> {
+    let connection_slots = Arc::new(Semaphore::new(limits.max_connections));
 
     loop {
-        let (socket, _) = client_listener.accept().await?;
+        let (socket, client_addr) = client_listener.accept().await?;
         let node = node.clone();
         let to_replica_tx = to_replica_tx.clone();
         let logger = node.logger.clone();
@@ -47,13 +125,136 @@ pub(crate) async fn run_client_listener(
         let reader = Arc::new(Mutex::new(reader));
         let writer = Arc::new(Mutex::new(writer));
 
+        let Ok(connection_slot) = connection_slots.clone().try_acquire_owned() else {
+            logger.log(
+                LogLevel::Warn,
+                &format!("Refusing client {}: too many connections", client_addr),
+            );
+            // the socket is closed once the rejection is sent
+            tokio::spawn(message_protocol::send_client_rejected(
+                writer,
+                ClientRejection::TooManyConnections,
+            ));
+            continue;
+        };
+
         let client_socket = ClientSocket::new(reader, writer);
+        let rate_limiter = RequestRateLimiter::new(limits.requests_per_second);
 
         tokio::spawn(async move {
-            match handle_client_connection(client_socket, node, to_replica_tx).await {
-                Ok(()) => logger.log("info", "Successfully handled client connection"),
-                Err(e) => logger.log("info", &format!("Client Listener: Failed due to: {:?}", e)),
+            match handle_client_connection(client_socket, node, to_replica_tx, rate_limiter).await {
+                Ok(()) => logger.log(LogLevel::Info, "Successfully handled client connection"),
+                Err(e) => logger.log(
+                    LogLevel::Info,
+                    &format!("Client Listener: Failed due to: {:?}", e),
+                ),
             }
+            drop(connection_slot);
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_rate_limiter {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_requests_beyond_rate_are_refused_until_refilled() {
+            let mut rate_limiter = RequestRateLimiter::new(2);
+            assert!(rate_limiter.try_acquire());
+            assert!(rate_limiter.try_acquire());
+            assert!(!rate_limiter.try_acquire());
+
+            tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+            assert!(rate_limiter.try_acquire());
+            assert!(!rate_limiter.try_acquire());
+        }
+    }
+
+    mod test_connection_limit {
+        use std::{collections::HashMap, time::Duration};
+
+        use ed25519_dalek::SigningKey;
+        use rand::rngs::OsRng;
+        use tokio::{
+            net::TcpStream,
+            sync::{Mutex, RwLock},
+        };
+
+        use super::*;
+        use crate::{
+            message_protocol::{AppMessage, HandshakeIdentity, Heartbeat, receive_message},
+            network::RecentlySeen,
+            node::logger::StubLogger,
+            types::message::Message,
+        };
+
+        fn test_node() -> Arc<Node> {
+            Arc::new(Node {
+                id: 0,
+                faucet_key: SigningKey::generate(&mut OsRng),
+                identity: HandshakeIdentity::new(0, SigningKey::generate(&mut OsRng), vec![]),
+                heartbeat: Heartbeat::new(Duration::from_millis(500), 3),
+                peer_addrs: HashMap::new(),
+                transactions: Mutex::new(vec![]),
+                seen_transactions: Mutex::new(RecentlySeen::new(16)),
+                socket_peer_map: RwLock::new(HashMap::new()),
+                peer_connections: RwLock::new(HashMap::new()),
+                logger: Arc::new(StubLogger::new()),
+            })
+        }
+
+        #[tokio::test]
+        async fn test_connection_beyond_limit_is_refused() -> Result<()> {
+            let client_listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = client_listener.local_addr()?;
+            let (to_replica_tx, _to_replica_rx) = mpsc::channel(16);
+            tokio::spawn(serve_clients(
+                client_listener,
+                test_node(),
+                to_replica_tx,
+                ClientLimits::new(2, 10),
+            ));
+
+            let _first = TcpStream::connect(addr).await?;
+            let _second = TcpStream::connect(addr).await?;
+            let (reader, _writer) = TcpStream::connect(addr).await?.into_split();
+
+            let message = receive_message(Arc::new(Mutex::new(reader))).await?;
+            assert!(matches!(
+                message,
+                Some(Message::Application(AppMessage::ClientRejected(
+                    ClientRejection::TooManyConnections
+                )))
+            ));
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn test_closed_connection_frees_its_slot() -> Result<()> {
+            let client_listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = client_listener.local_addr()?;
+            let (to_replica_tx, _to_replica_rx) = mpsc::channel(16);
+            tokio::spawn(serve_clients(
+                client_listener,
+                test_node(),
+                to_replica_tx,
+                ClientLimits::new(1, 10),
+            ));
+
+            drop(TcpStream::connect(addr).await?);
+            // give the node time to notice the closed connection
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let (reader, _writer) = TcpStream::connect(addr).await?.into_split();
+            let reader = Arc::new(Mutex::new(reader));
+            let refused =
+                tokio::time::timeout(Duration::from_millis(200), receive_message(reader)).await;
+            assert!(refused.is_err(), "connection should have been served");
+            Ok(())
+        }
+    }
+}
@@ -1,7 +1,153 @@
+use std::{fmt, str::FromStr, sync::RwLock};
+
 use chrono::Local;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = LogFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(LogFilterError::InvalidLevel(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFilterError {
+    InvalidLevel(String),
+}
+
+/// Maximum level logged per module, e.g. `info,state::spot_market=warn,hotstuff=debug`.
+/// Module paths are relative to the crate root and match their submodules too
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LogLevel,
+    modules: Vec<(String, LogLevel)>,
+}
+
+impl LogFilter {
+    pub const fn new(default: LogLevel) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, LogFilterError> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => filter.set_module(module.trim(), level.parse()?),
+                None => filter.default = directive.parse()?,
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn set_module(&mut self, module: &str, level: LogLevel) {
+        let module = strip_crate_name(module).to_string();
+        self.modules.retain(|(existing, _)| *existing != module);
+        self.modules.push((module, level));
+    }
+
+    /// Uses the most specific directive matching `module`, falling back to the default level
+    pub fn enabled(&self, module: &str, level: LogLevel) -> bool {
+        let module = strip_crate_name(module);
+        let max_level = self
+            .modules
+            .iter()
+            .filter(|(prefix, _)| {
+                module == prefix
+                    || module
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level);
+
+        level <= max_level
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(LogLevel::Info)
+    }
+}
+
+fn strip_crate_name(module: &str) -> &str {
+    let crate_name = env!("CARGO_CRATE_NAME");
+    match module.strip_prefix(crate_name) {
+        Some("") => "",
+        Some(rest) => rest.strip_prefix("::").unwrap_or(module),
+        None => module,
+    }
+}
+
+static FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::new(LogLevel::Info));
+
+/// Replaces the process-wide filter; takes effect for all subsequent log calls
+pub fn set_filter(filter: LogFilter) {
+    *FILTER.write().unwrap() = filter;
+}
+
+pub fn enabled(module: &str, level: LogLevel) -> bool {
+    FILTER.read().unwrap().enabled(module, level)
+}
+
+fn write(tag: &str, level: LogLevel, msg: fmt::Arguments) {
+    let now = Local::now().format("%H:%M:%S%.3f");
+
+    let formatted = format!(
+        "\x1b[90m[{}]\x1b[0m \x1b[34m[{}]\x1b[0m \x1b[93m[{}]\x1b[0m {}",
+        now,
+        tag,
+        level.as_str().to_uppercase(),
+        msg
+    );
+
+    match level {
+        LogLevel::Warn | LogLevel::Error => eprintln!("{}", formatted),
+        _ => println!("{}", formatted),
+    }
+}
+
+/// Backs the `log_*!` macros; `module` is the caller's `module_path!()`
+pub fn log_module(level: LogLevel, module: &str, msg: fmt::Arguments) {
+    if !enabled(module, level) {
+        return;
+    }
+    write(strip_crate_name(module), level, msg);
+}
+
 pub trait Logger: Send + Sync {
-    fn log(&self, level: &str, msg: &str);
+    fn log(&self, level: LogLevel, msg: &str);
 }
 
 pub struct ConsoleLogger {
@@ -15,21 +161,11 @@ impl ConsoleLogger {
 }
 
 impl Logger for ConsoleLogger {
-    fn log(&self, level: &str, msg: &str) {
-        let now = Local::now().format("%H:%M:%S%.3f");
-
-        let formatted = format!(
-            "\x1b[90m[{}]\x1b[0m \x1b[34m[Node {}]\x1b[0m \x1b[93m[{}]\x1b[0m {}",
-            now,
-            self.node_id,
-            level.to_uppercase(),
-            msg
-        );
-
-        match level {
-            "warn" | "error" => eprintln!("{}", formatted),
-            _ => println!("{}", formatted),
+    fn log(&self, level: LogLevel, msg: &str) {
+        if !enabled(module_path!(), level) {
+            return;
         }
+        write(&format!("Node {}", self.node_id), level, format_args!("{}", msg));
     }
 }
 
@@ -55,7 +191,49 @@ impl StubLogger {
 
 #[cfg(test)]
 impl Logger for StubLogger {
-    fn log(&self, level: &str, msg: &str) {
-        self.logs.lock().unwrap().push((level.to_string(), msg.to_string()));
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.logs.lock().unwrap().push((level.as_str().to_string(), msg.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_log_filter {
+        use super::*;
+
+        #[test]
+        fn test_debug_suppressed_at_info_level() {
+            let filter = LogFilter::parse("info").unwrap();
+
+            assert!(!filter.enabled("superliquid::state::spot_market", LogLevel::Debug));
+            assert!(!filter.enabled("superliquid::state::spot_market", LogLevel::Trace));
+            assert!(filter.enabled("superliquid::state::spot_market", LogLevel::Info));
+            assert!(filter.enabled("superliquid::state::spot_market", LogLevel::Error));
+        }
+
+        #[test]
+        fn test_module_directives_override_default() {
+            let filter =
+                LogFilter::parse("info, state=warn, state::spot_market=error, hotstuff=debug")
+                    .unwrap();
+
+            assert!(!filter.enabled("superliquid::state::spot_clearinghouse", LogLevel::Info));
+            assert!(filter.enabled("superliquid::state::spot_clearinghouse", LogLevel::Warn));
+            assert!(!filter.enabled("superliquid::state::spot_market", LogLevel::Warn));
+            assert!(filter.enabled("superliquid::hotstuff::replica", LogLevel::Debug));
+            assert!(filter.enabled("superliquid::node::runner", LogLevel::Info));
+            // prefixes only match whole path segments
+            assert!(filter.enabled("superliquid::statement", LogLevel::Info));
+        }
+
+        #[test]
+        fn test_rejects_unknown_level() {
+            assert_eq!(
+                LogFilter::parse("info,state=loud"),
+                Err(LogFilterError::InvalidLevel("loud".to_string()))
+            );
+        }
     }
 }
@@ -0,0 +1,288 @@
+use std::{
+    fmt::Write as _,
+    io::Result,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::state::{spot_clearinghouse::MarketId, state::LedgerState};
+
+const MAX_REQUEST_HEADER_LEN: usize = 8 * 1024;
+
+type MarketValue = fn(&MarketVolume) -> u128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MarketVolume {
+    market_id: MarketId,
+    market_name: String,
+    base_volume_lots: u128,
+    quote_volume_lots: u128,
+    trade_count: u64,
+}
+
+/// Node counters and gauges, rendered in the Prometheus text format.
+/// Ledger derived values are refreshed after every executed block, so they survive restarts
+#[derive(Default)]
+pub struct Metrics {
+    blocks_committed: AtomicU64,
+    view_changes: AtomicU64,
+    mempool_size: AtomicU64,
+    orders_matched: AtomicU64,
+    markets: Mutex<Vec<MarketVolume>>,
+    // QCs each validator signed, indexed by node id
+    validator_qc_signatures: Mutex<Vec<u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn blocks_committed(&self) -> u64 {
+        self.blocks_committed.load(Ordering::Relaxed)
+    }
+
+    pub fn view_changes(&self) -> u64 {
+        self.view_changes.load(Ordering::Relaxed)
+    }
+
+    pub fn mempool_size(&self) -> u64 {
+        self.mempool_size.load(Ordering::Relaxed)
+    }
+
+    /// Resting orders filled across all markets, one per maker fill
+    pub fn orders_matched(&self) -> u64 {
+        self.orders_matched.load(Ordering::Relaxed)
+    }
+
+    /// Number of QCs each validator's signature appeared in, indexed by node id
+    pub fn validator_qc_signatures(&self) -> Vec<u64> {
+        self.validator_qc_signatures.lock().unwrap().clone()
+    }
+
+    pub fn record_view_change(&self) {
+        self.view_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_mempool_size(&self, size: usize) {
+        self.mempool_size.store(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_validator_qc_signatures(&self, counts: &[u64]) {
+        *self.validator_qc_signatures.lock().unwrap() = counts.to_vec();
+    }
+
+    /// Updates the block and market metrics from the ledger after `committed_height` blocks
+    pub fn record_committed_block(&self, committed_height: u64, ledger_state: &LedgerState) {
+        self.blocks_committed
+            .store(committed_height, Ordering::Relaxed);
+
+        let markets: Vec<MarketVolume> = ledger_state
+            .get_markets()
+            .into_iter()
+            .map(|market| MarketVolume {
+                market_id: market.market_id,
+                market_name: market.market_name,
+                base_volume_lots: market.base_volume_lots,
+                quote_volume_lots: market.quote_volume_lots,
+                trade_count: market.trade_count,
+            })
+            .collect();
+
+        let orders_matched = markets.iter().map(|market| market.trade_count).sum();
+        self.orders_matched.store(orders_matched, Ordering::Relaxed);
+        *self.markets.lock().unwrap() = markets;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "superliquid_blocks_committed_total",
+            "counter",
+            "Blocks committed and executed",
+            self.blocks_committed(),
+        );
+        write_metric(
+            &mut out,
+            "superliquid_view_changes_total",
+            "counter",
+            "Views abandoned after a timeout",
+            self.view_changes(),
+        );
+        write_metric(
+            &mut out,
+            "superliquid_mempool_size",
+            "gauge",
+            "Transactions waiting in the mempool",
+            self.mempool_size(),
+        );
+        write_metric(
+            &mut out,
+            "superliquid_orders_matched_total",
+            "counter",
+            "Resting orders filled across all markets",
+            self.orders_matched(),
+        );
+
+        let markets = self.markets.lock().unwrap();
+        let per_market: [(&str, &str, MarketValue); 3] = [
+            (
+                "superliquid_market_base_volume_lots_total",
+                "Base lots traded per market",
+                |m| m.base_volume_lots,
+            ),
+            (
+                "superliquid_market_quote_volume_lots_total",
+                "Quote lots traded per market",
+                |m| m.quote_volume_lots,
+            ),
+            ("superliquid_market_trades_total", "Fills per market", |m| {
+                m.trade_count as u128
+            }),
+        ];
+        for (name, help, value) in per_market {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for market in markets.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{market_id=\"{}\",market=\"{}\"}} {}",
+                    name,
+                    market.market_id,
+                    escape_label(&market.market_name),
+                    value(market)
+                );
+            }
+        }
+
+        let name = "superliquid_validator_qc_signatures_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} QCs each validator's signature appeared in",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (node_id, count) in self.validator_qc_signatures().iter().enumerate() {
+            let _ = writeln!(out, "{}{{validator=\"{}\"}} {}", name, node_id, count);
+        }
+
+        out
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` over plain HTTP until the listener fails
+pub async fn run_metrics_server(metrics_addr: String, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(&metrics_addr).await?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let _ = handle_metrics_request(stream, &metrics).await;
+        });
+    }
+}
+
+async fn handle_metrics_request(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_HEADER_LEN {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let response = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_metrics_server {
+        use super::*;
+
+        async fn request(addr: &str, request_line: &str) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("{}\r\nHost: localhost\r\n\r\n", request_line).as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        #[tokio::test]
+        async fn test_serves_metrics_in_text_format() {
+            let metrics = Arc::new(Metrics::new());
+            metrics.record_view_change();
+            metrics.set_mempool_size(3);
+            metrics.record_committed_block(2, &LedgerState::new());
+            metrics.set_validator_qc_signatures(&[4, 1]);
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            drop(listener);
+            tokio::spawn(run_metrics_server(addr.clone(), metrics));
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+            let response = request(&addr, "GET /metrics HTTP/1.1").await;
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.contains("superliquid_blocks_committed_total 2\n"));
+            assert!(response.contains("superliquid_view_changes_total 1\n"));
+            assert!(response.contains("superliquid_mempool_size 3\n"));
+            assert!(response.contains("superliquid_orders_matched_total 0\n"));
+            assert!(response.contains(
+                "superliquid_market_trades_total{market_id=\"0\",market=\"SUPE/USD\"} 0\n"
+            ));
+
+            assert!(
+                response.contains("superliquid_validator_qc_signatures_total{validator=\"1\"} 1\n")
+            );
+
+            let response = request(&addr, "GET / HTTP/1.1").await;
+            assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        }
+    }
+}
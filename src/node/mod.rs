@@ -1,3 +1,5 @@
+pub mod block_store;
+pub mod checkpoint;
 pub mod runner;
 pub mod state;
 
@@ -6,3 +8,4 @@ pub(crate) mod peer;
 pub(crate) mod replica;
 
 pub mod logger;
+pub mod metrics;
@@ -1,16 +1,17 @@
 use std::io::Result;
 use std::sync::Arc;
 
-use futures::future::join_all;
-
 use crate::{
     hotstuff::message::HotStuffMessage,
-    message_protocol::{send_message, send_transaction},
-    node::state::{Node, PeerId},
+    message_protocol::{AppMessage, SyncMessage},
+    node::{
+        logger::LogLevel,
+        state::{Node, PeerId},
+    },
     types::{message::Message, transaction::SignedTransaction},
 };
 
-/// Broadcast msg to all peer connections
+/// Queues msg for all peer connections
 pub(crate) async fn broadcast_hotstuff_message(
     node: &Arc<Node>,
     msg: HotStuffMessage,
@@ -18,10 +19,10 @@ pub(crate) async fn broadcast_hotstuff_message(
     let peer_connections = node.get_peer_connections_as_vec().await;
 
     for peer_socket in peer_connections {
-        let cloned_msg = msg.clone();
-        tokio::spawn(async move {
-            send_message(peer_socket.writer.clone(), &Message::HotStuff(cloned_msg)).await
-        });
+        peer_socket
+            .outbox
+            .push(Message::HotStuff(msg.clone()))
+            .await;
     }
 
     Ok(())
@@ -40,7 +41,25 @@ pub(crate) async fn send_to_peer(
     let Some(peer_connection) = peer_connection else {
         return Ok(());
     };
-    send_message(peer_connection.writer.clone(), &Message::HotStuff(msg)).await
+    peer_connection.outbox.push(Message::HotStuff(msg)).await;
+    Ok(())
+}
+
+pub(crate) async fn send_sync_message(
+    node: &Arc<Node>,
+    msg: SyncMessage,
+    peer_id: PeerId,
+) -> Result<()> {
+    let peer_connection = {
+        let peer_connections = node.peer_connections.read().await;
+        peer_connections.get(&peer_id).cloned()
+    };
+
+    let Some(peer_connection) = peer_connection else {
+        return Ok(());
+    };
+    peer_connection.outbox.push(Message::Sync(msg)).await;
+    Ok(())
 }
 
 pub(crate) async fn broadcast_transaction(node: &Arc<Node>, tx: SignedTransaction) -> Result<()> {
@@ -49,10 +68,8 @@ pub(crate) async fn broadcast_transaction(node: &Arc<Node>, tx: SignedTransactio
 
     let peer_connections = node.get_peer_connections_as_vec().await;
 
-    let mut tasks = Vec::new();
-
     logger.log(
-        "info",
+        LogLevel::Info,
         &format!(
             "broadcasting tx from node {} to {} peers",
             id,
@@ -61,25 +78,10 @@ pub(crate) async fn broadcast_transaction(node: &Arc<Node>, tx: SignedTransactio
     );
 
     for peer_socket in peer_connections {
-        let cloned_tx = tx.clone();
-        let task =
-            tokio::spawn(
-                async move { send_transaction(peer_socket.writer.clone(), cloned_tx).await },
-            );
-        tasks.push(task);
-    }
-
-    let results = join_all(tasks).await;
-    for result in results {
-        match result {
-            Ok(Ok(())) => {
-                logger.log("info", "sent transaction ");
-            }
-            Ok(Err(e)) => logger.log("Error", &format!("send_transaction error: {:?}", e)),
-            Err(e) => logger.log("Error", &format!("task panicked: {:?}", e)),
-        }
+        let msg = AppMessage::SubmitTransaction(tx.clone());
+        peer_socket.outbox.push(Message::Application(msg)).await;
     }
 
-    logger.log("info", "Finish broadcasting tx");
+    logger.log(LogLevel::Info, "Finish broadcasting tx");
     Ok(())
 }
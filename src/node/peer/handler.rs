@@ -6,49 +6,55 @@ use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::Mutex;
 use tokio::sync::mpsc;
 
-use crate::message_protocol::{ControlMessage, send_ack};
+use crate::message_protocol::ControlMessage;
 use crate::node::client::handler::handle_transaction;
-use crate::node::logger::Logger;
-use crate::node::state::PeerId;
+use crate::node::logger::LogLevel;
+use crate::node::state::{PeerId, PeerSocket};
 use crate::types::message::{Message, ReplicaInBound, mpsc_error};
 use crate::{
-    message_protocol::{self, AppMessage},
+    message_protocol::{self, AppMessage, MessageCodec},
     node::state::Node,
 };
 
 pub(super) async fn handle_handshake(
     reader: Arc<Mutex<OwnedReadHalf>>,
     writer: Arc<Mutex<OwnedWriteHalf>>,
-    logger: Arc<dyn Logger>,
-) -> Result<PeerId> {
-    let first_msg = message_protocol::receive_message(reader).await?;
-    let peer_id = match first_msg {
-        Some(Message::Connection(ControlMessage::Hello { peer_id })) => {
+    node: &Arc<Node>,
+) -> Result<(PeerId, MessageCodec)> {
+    let logger = node.logger.clone();
+    match message_protocol::accept_hello(reader, writer, &node.identity).await {
+        Ok((peer_id, codec)) => {
             logger.log(
-                "info",
-                &format!("On handshake: Connection established with peer {peer_id}"),
+                LogLevel::Info,
+                &format!(
+                    "On handshake: Connection established with peer {peer_id}, codec {codec:?}"
+                ),
             );
-            peer_id
+            Ok((peer_id, codec))
         }
-        other => {
-            logger.log("Error", &format!("Expected Hello msg, got: {:?}", other));
-            return Err(Error::new(ErrorKind::InvalidData, "Expected Hello Message"));
+        Err(e) => {
+            logger.log(
+                LogLevel::Error,
+                &format!("Rejected peer handshake: {:?}", e),
+            );
+            Err(e)
         }
-    };
-    send_ack(writer).await?;
-
-    Ok(peer_id)
+    }
 }
 
 pub(super) async fn handle_peer_connection(
     node: &Arc<Node>,
-    reader: Arc<Mutex<OwnedReadHalf>>,
+    peer_socket: &Arc<PeerSocket>,
     to_replica_tx: mpsc::Sender<ReplicaInBound>,
 ) -> Result<()> {
     let logger = node.logger.clone();
 
     loop {
-        let message = message_protocol::receive_message(reader.clone()).await;
+        let message = message_protocol::receive_message_with_heartbeat(
+            peer_socket.reader.clone(),
+            node.heartbeat,
+        )
+        .await;
         match message {
             Ok(Some(Message::HotStuff(hot_stuff_message))) => {
                 to_replica_tx
@@ -56,24 +62,39 @@ pub(super) async fn handle_peer_connection(
                     .await
                     .map_err(|e| mpsc_error("Send to replica failed", e))?;
             }
+            Ok(Some(Message::Sync(sync_message))) => {
+                to_replica_tx
+                    .send(ReplicaInBound::Sync(sync_message))
+                    .await
+                    .map_err(|e| mpsc_error("Send to replica failed", e))?;
+            }
             Ok(Some(Message::Application(app_message))) => match app_message {
                 AppMessage::SubmitTransaction(signed_tx) => {
                     handle_transaction(&node.clone(), signed_tx, to_replica_tx.clone()).await?;
                 }
                 AppMessage::Ack => (),
                 _ => logger.log(
-                    "Error",
+                    LogLevel::Error,
                     &format!("Unexpected message on peer connection: {:?}", app_message),
                 ),
             },
             Ok(Some(Message::Connection(control_message))) => match control_message {
-                ControlMessage::Hello { .. } => {
-                    // can discard any new hello messages
+                ControlMessage::Hello { .. }
+                | ControlMessage::HelloAck { .. }
+                | ControlMessage::Auth { .. } => {
+                    // can discard handshake messages once authenticated
+                }
+                ControlMessage::Ping => {
+                    message_protocol::send_pong(peer_socket.writer.clone()).await?;
+                }
+                ControlMessage::Pong => (),
+                ControlMessage::End => {
+                    logger.log(LogLevel::Info, "Peer closed the connection");
+                    return Ok(());
                 }
-                _ => {}
             },
             Ok(None) => {
-                logger.log("Error", "Expected message, but got none");
+                logger.log(LogLevel::Error, "Expected message, but got none");
                 return Err(Error::new(
                     ErrorKind::BrokenPipe,
                     "Expected message but got None instead",
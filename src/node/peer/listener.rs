@@ -1,28 +1,83 @@
 use std::sync::Arc;
 
-use std::io::Result;
+use std::io::{ErrorKind, Result};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio::{net::TcpListener, sync::mpsc};
 
+use crate::message_protocol::{MessageCodec, run_heartbeat};
+use crate::node::logger::LogLevel;
 use crate::node::peer::handler::handle_handshake;
+use crate::node::peer::outbox::run_peer_writer;
 use crate::node::state::{PeerId, PeerSocket};
 use crate::node::{
-    peer::handler::handle_peer_connection, runner::deduplicate_peer_connection, state::Node,
+    peer::handler::handle_peer_connection,
+    runner::{connect_to_peer, deduplicate_peer_connection},
+    state::Node,
 };
 use crate::types::message::ReplicaInBound;
 
-async fn drop_peer_socket(node: Arc<Node>, peer_id: PeerId) {
+async fn drop_peer_socket(node: Arc<Node>, peer_id: PeerId, peer_socket: &Arc<PeerSocket>) {
     let logger = node.logger.clone();
     {
+        // a duplicate stream closing must not remove the one still in use
         let mut peer_connections = node.peer_connections.write().await;
-        peer_connections.remove(&peer_id);
+        if peer_connections
+            .get(&peer_id)
+            .is_some_and(|socket| Arc::ptr_eq(socket, peer_socket))
+        {
+            peer_connections.remove(&peer_id);
+        }
     }
     logger.log(
-        "Info",
+        LogLevel::Info,
         &format!("Dropping peer connection to peer: {:?}", peer_id),
     );
 }
 
+/// Reads from an authenticated peer stream until it closes or the peer misses too many
+/// heartbeats, then redials the peer if no other stream to it is left
+pub(crate) fn spawn_peer_reader(
+    node: Arc<Node>,
+    peer_id: PeerId,
+    peer_socket: Arc<PeerSocket>,
+    codec: MessageCodec,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) {
+    tokio::spawn(async move {
+        let logger = node.logger.clone();
+        let heartbeat = tokio::spawn(run_heartbeat(peer_socket.writer.clone(), node.heartbeat));
+        tokio::spawn(run_peer_writer(
+            peer_socket.outbox.clone(),
+            peer_socket.writer.clone(),
+            codec,
+            logger.clone(),
+        ));
+
+        match handle_peer_connection(&node, &peer_socket, to_replica_tx.clone()).await {
+            Ok(()) => logger.log(LogLevel::Info, "Successfully handled peer connection"),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                logger.log(LogLevel::Error, &format!("Peer {} is down: {}", peer_id, e))
+            }
+            Err(e) => logger.log(
+                LogLevel::Error,
+                &format!("Peer listener: Failed due to: {:?}", e),
+            ),
+        }
+        heartbeat.abort();
+        peer_socket.outbox.close();
+
+        // drop the peerSocket after the connection has ended
+        drop_peer_socket(node.clone(), peer_id, &peer_socket).await;
+        let _ = peer_socket.writer.lock().await.shutdown().await;
+
+        let reconnect = !node.peer_connections.read().await.contains_key(&peer_id);
+        if let (true, Some(addr)) = (reconnect, node.peer_addrs.get(&peer_id)) {
+            connect_to_peer(addr.clone(), peer_id, node.clone(), to_replica_tx).await;
+        }
+    });
+}
+
 /// peer listener handles the consensus layer communication
 pub(crate) async fn run_peer_listener(
     node: Arc<Node>,
@@ -33,7 +88,7 @@ pub(crate) async fn run_peer_listener(
     let peer_listener: TcpListener = TcpListener::bind(&concensus_addr).await?;
     let logger = node.logger.clone();
     logger.log(
-        "Info",
+        LogLevel::Info,
         &format!("Listening to peers on {:?}", concensus_addr),
     );
 
@@ -41,8 +96,7 @@ pub(crate) async fn run_peer_listener(
         let (stream, _) = peer_listener.accept().await?;
         let tx_clone = to_replica_tx.clone();
         let node_clone = node.clone();
-        logger.log("Info", "Spawning peer listener");
-        let logger = logger.clone();
+        logger.log(LogLevel::Info, "Spawning peer listener");
 
         let (reader, writer) = stream.into_split();
         let writer = Arc::new(Mutex::new(writer));
@@ -50,23 +104,20 @@ pub(crate) async fn run_peer_listener(
 
         let peer_socket = Arc::new(PeerSocket::new(reader, writer));
 
-        let peer_id = handle_handshake(
-            peer_socket.reader.clone(),
-            peer_socket.writer.clone(),
-            logger.clone(),
-        )
-        .await?;
-
-        let peer_socket = deduplicate_peer_connection(peer_socket, &node, peer_id).await;
-
+        // authenticate off the accept loop so a slow or rejected peer cannot stall other connections
         tokio::spawn(async move {
-            match handle_peer_connection(&node_clone, peer_socket.reader.clone(), tx_clone).await {
-                Ok(()) => logger.log("info", "Successfully handled peer connection"),
-                Err(e) => logger.log("Error", &format!("Peer listener: Failed due to: {:?}", e)),
-            }
+            let Ok((peer_id, codec)) = handle_handshake(
+                peer_socket.reader.clone(),
+                peer_socket.writer.clone(),
+                &node_clone,
+            )
+            .await
+            else {
+                return;
+            };
 
-            // drop the peerSocket after the connection has ended
-            drop_peer_socket(node_clone, peer_id).await;
+            deduplicate_peer_connection(peer_socket.clone(), &node_clone, peer_id, false).await;
+            spawn_peer_reader(node_clone, peer_id, peer_socket, codec, tx_clone);
         });
     }
 }
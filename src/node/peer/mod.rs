@@ -1,3 +1,4 @@
 pub(super) mod broadcast;
 pub(super) mod handler;
 pub(crate) mod listener;
+pub(crate) mod outbox;
@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::{net::tcp::OwnedWriteHalf, sync::Notify};
+
+use crate::{
+    config,
+    hotstuff::message::HotStuffMessage,
+    message_protocol::{MessageCodec, send_message_with_codec},
+    node::logger::{LogLevel, Logger},
+    types::message::Message,
+};
+
+/// What happens to a message sent to a peer whose outbox is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Shed the stalest queued message, gossip before consensus messages
+    DropStale,
+    /// Wait for the peer to drain its outbox, stalling the sender
+    Block,
+}
+
+impl OverflowPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "drop" => Some(OverflowPolicy::DropStale),
+            "block" => Some(OverflowPolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Votes, proposals and timeouts make progress; everything else can be recovered by sync
+fn is_critical(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::HotStuff(
+            HotStuffMessage::Proposal { .. }
+                | HotStuffMessage::Vote { .. }
+                | HotStuffMessage::Timeout { .. }
+        )
+    )
+}
+
+#[derive(Default)]
+struct OutboxQueue {
+    critical: VecDeque<Message>,
+    gossip: VecDeque<Message>,
+    dropped: u64,
+    closed: bool,
+}
+
+impl OutboxQueue {
+    fn len(&self) -> usize {
+        self.critical.len() + self.gossip.len()
+    }
+}
+
+/// Bounded queue of messages waiting to be written to a single peer, so a slow peer
+/// can neither exhaust memory nor hold up messages to the others
+pub struct PeerOutbox {
+    capacity: usize,
+    policy: OverflowPolicy,
+    queue: Mutex<OutboxQueue>,
+    queued: Notify,
+    drained: Notify,
+}
+
+impl PeerOutbox {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            policy,
+            queue: Mutex::new(OutboxQueue::default()),
+            queued: Notify::new(),
+            drained: Notify::new(),
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(
+            config::retrieve_peer_outbox_capacity(),
+            config::retrieve_peer_outbox_policy(),
+        )
+    }
+
+    /// Queues `msg` for the peer, applying the overflow policy if the outbox is full.
+    /// Messages sent after the outbox is closed are discarded
+    pub async fn push(&self, msg: Message) {
+        loop {
+            let drained = self.drained.notified();
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.closed {
+                    return;
+                }
+
+                if queue.len() >= self.capacity {
+                    match self.policy {
+                        OverflowPolicy::Block => {}
+                        OverflowPolicy::DropStale => {
+                            queue.dropped += 1;
+                            if queue.gossip.pop_front().is_none() {
+                                if !is_critical(&msg) {
+                                    return;
+                                }
+                                queue.critical.pop_front();
+                            }
+                        }
+                    }
+                }
+
+                if queue.len() < self.capacity {
+                    if is_critical(&msg) {
+                        queue.critical.push_back(msg);
+                    } else {
+                        queue.gossip.push_back(msg);
+                    }
+                    self.queued.notify_one();
+                    return;
+                }
+            }
+            drained.await;
+        }
+    }
+
+    /// Next message to write, critical ones first. None once the outbox is closed
+    pub async fn pop(&self) -> Option<Message> {
+        loop {
+            let queued = self.queued.notified();
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.closed {
+                    return None;
+                }
+
+                let msg = match queue.critical.pop_front() {
+                    Some(msg) => Some(msg),
+                    None => queue.gossip.pop_front(),
+                };
+                if msg.is_some() {
+                    self.drained.notify_waiters();
+                    return msg;
+                }
+            }
+            queued.await;
+        }
+    }
+
+    /// Discards the queued messages and wakes any blocked senders
+    pub fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        queue.critical.clear();
+        queue.gossip.clear();
+        self.queued.notify_one();
+        self.drained.notify_waiters();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Messages shed by the overflow policy so far
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().unwrap().dropped
+    }
+}
+
+/// Writes the outbox to the peer until it is closed or a write fails
+pub(crate) async fn run_peer_writer(
+    outbox: Arc<PeerOutbox>,
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+    codec: MessageCodec,
+    logger: Arc<dyn Logger>,
+) {
+    let mut reported_dropped = 0;
+    while let Some(msg) = outbox.pop().await {
+        let dropped = outbox.dropped();
+        if dropped > reported_dropped {
+            logger.log(
+                LogLevel::Warn,
+                &format!(
+                    "Peer is lagging, shed {} queued messages",
+                    dropped - reported_dropped
+                ),
+            );
+            reported_dropped = dropped;
+        }
+
+        if let Err(e) = send_message_with_codec(writer.clone(), &msg, codec).await {
+            logger.log(
+                LogLevel::Error,
+                &format!("Failed to write to peer: {:?}", e),
+            );
+            break;
+        }
+    }
+    outbox.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+    use crate::{
+        hotstuff::crypto::QuorumCertificate, message_protocol::SyncMessage, node::state::PeerId,
+    };
+
+    fn new_view(view: u64) -> Message {
+        Message::HotStuff(HotStuffMessage::create_new_view(
+            QuorumCertificate::create_genesis_qc(),
+            view,
+            0,
+            view,
+        ))
+    }
+
+    fn sync_request(sender: PeerId) -> Message {
+        Message::Sync(SyncMessage::BlockRequest {
+            from_height: 0,
+            target_block: [0; 32],
+            sender,
+        })
+    }
+
+    fn timeout(view: u64) -> Message {
+        Message::HotStuff(HotStuffMessage::create_timeout(
+            QuorumCertificate::create_genesis_qc(),
+            view,
+            0,
+            view,
+            &mut SigningKey::from_bytes(&[1; 32]),
+        ))
+    }
+
+    fn view_of(msg: &Message) -> u64 {
+        match msg {
+            Message::HotStuff(msg) => msg.get_view_number(),
+            _ => panic!("Expected a HotStuff message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_peer_outbox_stays_bounded() {
+        let outbox = PeerOutbox::new(8, OverflowPolicy::DropStale);
+        for view in 0..1_000 {
+            outbox.push(new_view(view)).await;
+        }
+
+        assert_eq!(outbox.len(), 8);
+        assert_eq!(outbox.dropped(), 992);
+        // the stalest messages are the ones shed
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 992);
+    }
+
+    #[tokio::test]
+    async fn test_critical_messages_are_sent_first_and_dropped_last() {
+        let outbox = PeerOutbox::new(3, OverflowPolicy::DropStale);
+        outbox.push(sync_request(1)).await;
+        outbox.push(timeout(1)).await;
+        outbox.push(sync_request(2)).await;
+        // consensus messages evict the oldest gossip first
+        outbox.push(timeout(2)).await;
+        outbox.push(timeout(3)).await;
+        // gossip is shed on arrival once only consensus messages are queued
+        outbox.push(sync_request(3)).await;
+        // and only then is the stalest consensus message evicted
+        outbox.push(timeout(4)).await;
+
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 2);
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 3);
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 4);
+        assert_eq!(outbox.len(), 0);
+        assert_eq!(outbox.dropped(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_outbox_waits_for_room() {
+        let outbox = Arc::new(PeerOutbox::new(1, OverflowPolicy::Block));
+        outbox.push(new_view(1)).await;
+
+        let pushed = tokio::spawn({
+            let outbox = outbox.clone();
+            async move { outbox.push(new_view(2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!pushed.is_finished());
+
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 1);
+        pushed.await.unwrap();
+        assert_eq!(view_of(&outbox.pop().await.unwrap()), 2);
+        assert_eq!(outbox.dropped(), 0);
+    }
+}
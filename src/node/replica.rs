@@ -4,7 +4,7 @@ use tokio::sync::mpsc;
 
 use crate::types::message::ReplicaOutbound;
 
-use super::peer::broadcast::{broadcast_hotstuff_message, send_to_peer};
+use super::peer::broadcast::{broadcast_hotstuff_message, send_sync_message, send_to_peer};
 use super::state::Node;
 
 pub(super) async fn handle_replica_outbound(
@@ -17,6 +17,9 @@ pub(super) async fn handle_replica_outbound(
             ReplicaOutbound::SendTo(peer_id, msg) => {
                 send_to_peer(&node, msg, peer_id).await?;
             }
+            ReplicaOutbound::Sync(peer_id, msg) => {
+                send_sync_message(&node, msg, peer_id).await?;
+            }
         }
     }
     Ok(())
@@ -1,30 +1,30 @@
 use futures::future::join_all;
 use tokio::{
+    io::AsyncWriteExt,
     net::TcpStream,
-    sync::{Mutex, RwLock, mpsc},
+    sync::{Mutex, RwLock, broadcast, mpsc},
     time::sleep,
 };
 
 use crate::{
     config,
-    hotstuff::replica::HotStuffReplica,
-    message_protocol::send_hello,
+    hotstuff::{replica::HotStuffReplica, safety::SafetyStore},
+    message_protocol::{HandshakeIdentity, Heartbeat, send_end, send_hello},
+    network::RecentlySeen,
     types::message::{ReplicaInBound, ReplicaOutbound},
 };
 
 use super::{
+    block_store::BlockStore,
+    checkpoint::CheckpointStore,
     client::listener::run_client_listener,
-    logger::ConsoleLogger,
-    peer::listener::run_peer_listener,
+    logger::{ConsoleLogger, LogLevel},
+    metrics::run_metrics_server,
+    peer::listener::{run_peer_listener, spawn_peer_reader},
     replica::handle_replica_outbound,
-    state::{Node, PeerId, PeerInfo, PeerSocket},
-};
-use std::{
-    collections::{HashMap, HashSet},
-    io::Result,
-    sync::Arc,
-    time,
+    state::{MAX_SEEN_TRANSACTIONS, Node, PeerId, PeerInfo, PeerSocket},
 };
+use std::{collections::HashMap, io::Result, sync::Arc, time};
 
 async fn spawn_all_node_tasks(
     client_addr: String,
@@ -34,8 +34,9 @@ async fn spawn_all_node_tasks(
     to_replica_tx: mpsc::Sender<ReplicaInBound>,
     to_replica_rx: mpsc::Receiver<ReplicaInBound>,
     from_replica_rx: mpsc::Receiver<ReplicaOutbound>,
+    shutdown_rx: broadcast::Receiver<()>,
 ) -> Result<()> {
-    let handles = vec![
+    let mut listener_handles = vec![
         tokio::spawn(run_client_listener(
             client_addr.to_owned(),
             node.clone(),
@@ -48,39 +49,87 @@ async fn spawn_all_node_tasks(
         )),
     ];
 
-    tokio::spawn(async move { replica.run_replica(to_replica_rx).await });
+    let mut replica_handle =
+        tokio::spawn(async move { replica.run_replica(to_replica_rx, shutdown_rx).await });
     tokio::spawn(handle_replica_outbound(from_replica_rx, node.clone()));
 
-    let _ = join_all(handles).await;
+    // the replica only returns once it has flushed its state after a shutdown signal
+    tokio::select! {
+        _ = join_all(listener_handles.iter_mut()) => {}
+        result = &mut replica_handle => {
+            if let Ok(Err(e)) = result {
+                node.logger.log(LogLevel::Error, &format!("Replica stopped due to: {:?}", e));
+            }
+        }
+    }
+
+    for handle in listener_handles {
+        handle.abort();
+    }
+    close_peer_connections(&node).await;
     Ok(())
 }
 
+/// Tells every connected peer we are leaving, then closes the connections
+async fn close_peer_connections(node: &Arc<Node>) {
+    let peer_sockets: Vec<Arc<PeerSocket>> = {
+        let mut peer_connections = node.peer_connections.write().await;
+        peer_connections.drain().map(|(_, socket)| socket).collect()
+    };
+
+    for peer_socket in peer_sockets {
+        peer_socket.outbox.close();
+        let _ = send_end(peer_socket.writer.clone()).await;
+        let _ = peer_socket.writer.lock().await.shutdown().await;
+    }
+    node.logger.log(LogLevel::Info, "Closed peer connections");
+}
+
+/// Resolves once the process is asked to stop with SIGINT or SIGTERM
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 // It is possible for two nodes to establish connections with one another,
 // Without  Deduplication, we can have conflicting streams for the same peer.
+// Both ends prefer the stream opened by the lower id so they settle on the same one
 pub(crate) async fn deduplicate_peer_connection(
     stream: Arc<PeerSocket>,
     node: &Arc<Node>,
     peer_id: PeerId,
-) -> Arc<PeerSocket> {
-    let logger = node.logger.clone();
+    initiated: bool,
+) {
+    let preferred = initiated == (node.id < peer_id);
     let mut peer_connections = node.peer_connections.write().await;
-    match peer_connections.get(&peer_id) {
-        Some(stream) => {
-            logger.log(
-                "Info",
-                &format!("Deduplicated TCP stream with peer: {:?}", peer_id),
-            );
-            return stream.clone();
-        }
-        None => {
-            let stream_clone = stream.clone();
-            peer_connections.insert(peer_id, stream_clone);
-            return stream.clone();
-        }
+    if preferred || !peer_connections.contains_key(&peer_id) {
+        peer_connections.insert(peer_id, stream);
+    } else {
+        node.logger.log(
+            LogLevel::Info,
+            &format!("Deduplicated TCP stream with peer: {:?}", peer_id),
+        );
     }
 }
 
-pub(crate) async fn connect_to_peer(addr: String, peer_id: usize, node: Arc<Node>) {
+pub(crate) async fn connect_to_peer(
+    addr: String,
+    peer_id: usize,
+    node: Arc<Node>,
+    to_replica_tx: mpsc::Sender<ReplicaInBound>,
+) {
     let base: u32 = 100;
     let mut counts: u32 = 1;
     let max_sleep: u32 = 1000 * 60;
@@ -89,7 +138,7 @@ pub(crate) async fn connect_to_peer(addr: String, peer_id: usize, node: Arc<Node
         match TcpStream::connect(addr.clone()).await {
             Ok(stream) => {
                 logger.log(
-                    "info",
+                    LogLevel::Info,
                     &format!("Initiate: Connection established with peer {}", peer_id),
                 );
                 let socket_addr = stream
@@ -110,38 +159,60 @@ pub(crate) async fn connect_to_peer(addr: String, peer_id: usize, node: Arc<Node
 
                 let peer_socket = Arc::new(PeerSocket::new(reader, writer));
 
-                send_hello(
+                let handshake = send_hello(
                     peer_socket.writer.clone(),
                     peer_socket.reader.clone(),
-                    node.id,
+                    &node.identity,
+                    peer_id,
                 )
-                .await
-                .unwrap();
+                .await;
 
-                let _ = deduplicate_peer_connection(peer_socket, &node, peer_id).await;
-                break;
+                match handshake {
+                    Ok(codec) => {
+                        deduplicate_peer_connection(peer_socket.clone(), &node, peer_id, true)
+                            .await;
+                        spawn_peer_reader(node, peer_id, peer_socket, codec, to_replica_tx);
+                        break;
+                    }
+                    Err(e) => {
+                        logger.log(
+                            LogLevel::Error,
+                            &format!("Handshake with peer {} failed: {:?}", peer_id, e),
+                        );
+                        let _ = peer_socket.writer.lock().await.shutdown().await;
+                    }
+                }
             }
             Err(e) => {
-                logger.log("error", &format!("Failed to connect to {}: {:?}", addr, e));
-                let exp_duration = base.pow(counts);
-                let sleep_duration = exp_duration.min(max_sleep);
-                if sleep_duration != max_sleep {
-                    counts = counts.saturating_add(1);
-                }
-
-                sleep(time::Duration::from_millis(sleep_duration.into())).await;
+                logger.log(
+                    LogLevel::Error,
+                    &format!("Failed to connect to {}: {:?}", addr, e),
+                );
             }
         }
+
+        let exp_duration = base.pow(counts);
+        let sleep_duration = exp_duration.min(max_sleep);
+        if sleep_duration != max_sleep {
+            counts = counts.saturating_add(1);
+        }
+
+        sleep(time::Duration::from_millis(sleep_duration.into())).await;
     }
 }
 
-async fn connect_to_peers_background(peers: &Vec<PeerInfo>, node: &Arc<Node>) {
+async fn connect_to_peers_background(
+    peers: &Vec<PeerInfo>,
+    node: &Arc<Node>,
+    to_replica_tx: &mpsc::Sender<ReplicaInBound>,
+) {
     for peer_info in peers {
         let node_clone = node.clone();
         let peer_id = peer_info.peer_id;
         let addr = peer_info.peer_addr.clone();
+        let to_replica_tx = to_replica_tx.clone();
         tokio::spawn(async move {
-            connect_to_peer(addr, peer_id, node_clone).await;
+            connect_to_peer(addr, peer_id, node_clone, to_replica_tx).await;
         });
     }
 }
@@ -149,6 +220,7 @@ async fn connect_to_peers_background(peers: &Vec<PeerInfo>, node: &Arc<Node>) {
 pub async fn run_node(
     client_addr: String,
     consensus_addr: String,
+    metrics_addr: Option<String>,
     peers: Vec<PeerInfo>,
     node_index: usize,
 ) -> Result<()> {
@@ -156,22 +228,32 @@ pub async fn run_node(
 
     let peers = Arc::new(peers);
     let (_, sk) = config::retrieve_faucet_keys();
+    let identity = HandshakeIdentity::new(
+        node_index,
+        config::retrieve_signing_key_checked(node_index),
+        config::retrieve_validator_keys(),
+    );
     let node = Arc::new(Node {
         id: node_index,
         faucet_key: sk,
+        identity,
+        heartbeat: Heartbeat::from_config(),
+        peer_addrs: peers
+            .iter()
+            .map(|peer| (peer.peer_id, peer.peer_addr.clone()))
+            .collect(),
         transactions: Mutex::new(vec![]),
-        seen_transactions: Mutex::new(HashSet::new()),
+        seen_transactions: Mutex::new(RecentlySeen::new(MAX_SEEN_TRANSACTIONS)),
         peer_connections: RwLock::new(HashMap::new()),
         logger: Arc::new(ConsoleLogger::new(node_index)),
         socket_peer_map: RwLock::new(HashMap::new()),
     });
-    connect_to_peers_background(&peers, &node).await;
-
     // Sends messages to replica from node
     let (to_replica_tx, to_replica_rx): (
         mpsc::Sender<ReplicaInBound>,
         mpsc::Receiver<ReplicaInBound>,
     ) = mpsc::channel(1024);
+    connect_to_peers_background(&peers, &node, &to_replica_tx).await;
 
     // Recieves messages from replica to node
     let (from_replica_tx, from_replica_rx): (
@@ -179,7 +261,37 @@ pub async fn run_node(
         mpsc::Receiver<ReplicaOutbound>,
     ) = mpsc::channel(1024);
 
-    let replica = HotStuffReplica::new(node_index, to_replica_tx.clone(), from_replica_tx);
+    let mut replica = HotStuffReplica::new(node_index, to_replica_tx.clone(), from_replica_tx);
+    // rebuild state from the latest checkpoint and the blocks committed since
+    replica.restore_checkpoint(CheckpointStore::for_node(node_index))?;
+    replica.restore_blocks(BlockStore::for_node(node_index)?)?;
+    // restore voting state so a restarted replica never votes twice in a view
+    replica.restore_safety_state(SafetyStore::for_node(node_index))?;
+
+    if let Some(metrics_addr) = metrics_addr {
+        let metrics = replica.metrics();
+        let logger = node.logger.clone();
+        tokio::spawn(async move {
+            logger.log(
+                LogLevel::Info,
+                &format!("Serving metrics on {:?}", metrics_addr),
+            );
+            if let Err(e) = run_metrics_server(metrics_addr, metrics).await {
+                logger.log(LogLevel::Error, &format!("Metrics server failed: {:?}", e));
+            }
+        });
+    }
+
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let logger = node.logger.clone();
+    tokio::spawn(async move {
+        wait_for_shutdown_signal().await;
+        logger.log(
+            LogLevel::Info,
+            "Shutdown signal received, finishing the current view",
+        );
+        let _ = shutdown_tx.send(());
+    });
 
     let _ = spawn_all_node_tasks(
         client_addr,
@@ -189,6 +301,7 @@ pub async fn run_node(
         to_replica_tx,
         to_replica_rx,
         from_replica_rx,
+        shutdown_rx,
     )
     .await;
     Ok(())
@@ -1,8 +1,4 @@
-use std::{
-    collections::{HashMap, HashSet},
-    net::SocketAddr,
-    sync::Arc,
-};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use ed25519_dalek::SigningKey;
 use tokio::{
@@ -12,9 +8,16 @@ use tokio::{
 
 pub type PeerId = usize;
 
-use crate::types::transaction::SignedTransaction;
+/// Number of transaction hashes remembered to avoid relaying the same transaction twice
+pub(super) const MAX_SEEN_TRANSACTIONS: usize = 100_000;
+
+use crate::{
+    message_protocol::{HandshakeIdentity, Heartbeat},
+    network::RecentlySeen,
+    types::transaction::SignedTransaction,
+};
 
-use super::logger::Logger;
+use super::{logger::Logger, peer::outbox::PeerOutbox};
 
 pub struct PeerInfo {
     pub peer_id: PeerId,
@@ -24,11 +27,17 @@ pub struct PeerInfo {
 pub struct PeerSocket {
     pub reader: Arc<Mutex<OwnedReadHalf>>,
     pub writer: Arc<Mutex<OwnedWriteHalf>>,
+    // messages waiting to be written by the peer's writer task
+    pub(crate) outbox: Arc<PeerOutbox>,
 }
 
 impl PeerSocket {
     pub fn new(reader: Arc<Mutex<OwnedReadHalf>>, writer: Arc<Mutex<OwnedWriteHalf>>) -> Self {
-        Self { reader, writer }
+        Self {
+            reader,
+            writer,
+            outbox: Arc::new(PeerOutbox::from_config()),
+        }
     }
 }
 
@@ -36,8 +45,11 @@ impl PeerSocket {
 pub struct Node {
     pub(super) id: PeerId,
     pub(crate) faucet_key: SigningKey,
+    pub(super) identity: HandshakeIdentity,
+    pub(super) heartbeat: Heartbeat,
+    pub(super) peer_addrs: HashMap<PeerId, String>,
     pub(super) transactions: Mutex<Vec<SignedTransaction>>,
-    pub(super) seen_transactions: Mutex<HashSet<[u8; 32]>>,
+    pub(super) seen_transactions: Mutex<RecentlySeen>,
     pub(super) socket_peer_map: RwLock<HashMap<SocketAddr, PeerId>>,
     pub(super) peer_connections: RwLock<HashMap<PeerId, Arc<PeerSocket>>>, // For now, we skip peer discovery
     pub(super) logger: Arc<dyn Logger>,
@@ -10,8 +10,26 @@ pub struct Asset {
     pub asset_name: String,
     pub lot_size: u32,
     pub decimals: u8,
+    /// Ticker shown to users, empty for assets serialized before it was added
+    #[serde(default)]
+    pub symbol: String,
+    /// Number of decimals to show when displaying amounts, at most `decimals`
+    #[serde(default)]
+    pub display_decimals: u8,
 }
 
+impl Asset {
+    /// Falls back to the asset name for assets without a symbol
+    pub fn display_symbol(&self) -> &str {
+        if self.symbol.is_empty() {
+            &self.asset_name
+        } else {
+            &self.symbol
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct AssetManager {
     pub next_asset_id: AssetIdCounter,
     pub assets: Vec<Asset>,
@@ -24,6 +42,8 @@ impl AssetManager {
             asset_name: "SUPE".to_owned(),
             decimals: 4,
             lot_size: 100,
+            symbol: "SUPE".to_owned(),
+            display_decimals: 2,
         };
 
         let asset_1 = Asset {
@@ -31,6 +51,8 @@ impl AssetManager {
             asset_name: "USD".to_owned(),
             decimals: 4,
             lot_size: 100,
+            symbol: "USD".to_owned(),
+            display_decimals: 2,
         };
 
         let assets = vec![asset_0, asset_1];
@@ -41,3 +63,36 @@ impl AssetManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_asset_metadata {
+        use super::*;
+
+        #[test]
+        fn test_asset_without_metadata_deserializes_with_defaults() {
+            let json = r#"{"asset_id":0,"asset_name":"SUPE","lot_size":100,"decimals":4}"#;
+            let asset: Asset = serde_json::from_str(json).unwrap();
+
+            assert_eq!(asset.symbol, "");
+            assert_eq!(asset.display_decimals, 0);
+            assert_eq!(asset.display_symbol(), "SUPE");
+        }
+
+        #[test]
+        fn test_genesis_assets_have_symbols() {
+            let manager = AssetManager::new();
+
+            let symbols: Vec<&str> = manager.assets.iter().map(|a| a.display_symbol()).collect();
+            assert_eq!(symbols, vec!["SUPE", "USD"]);
+            assert!(
+                manager
+                    .assets
+                    .iter()
+                    .all(|a| a.display_decimals <= a.decimals)
+            );
+        }
+    }
+}
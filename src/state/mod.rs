@@ -1,5 +1,7 @@
 pub mod asset;
 pub mod order;
+pub mod receipt;
+pub mod shard;
 pub mod spot_clearinghouse;
 pub mod spot_market;
 pub mod state;
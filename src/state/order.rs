@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::types::transaction::PublicKeyHash;
+use crate::types::transaction::{PublicKeyHash, RejectReason, Sha256Hash};
 
 use super::{asset::AssetId, spot_clearinghouse::MarketId};
 
@@ -22,6 +23,9 @@ pub enum OrderDirection {
     Sell,
 }
 
+/// Hands out order ids from a counter that is part of the ledger state, so every replica
+/// executing the same blocks assigns the same ids
+#[derive(Serialize, Deserialize, Clone)]
 pub struct OrderStateManager {
     next_id: OrderId,
 }
@@ -31,6 +35,20 @@ impl OrderStateManager {
         Self { next_id: 0 }
     }
 
+    /// Id the next order will be given
+    pub fn next_id(&self) -> OrderId {
+        self.next_id
+    }
+
+    pub(crate) fn set_next_id(&mut self, next_id: OrderId) {
+        self.next_id = next_id;
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        let encoded = bincode::serialize(self).expect("bincode failed");
+        Sha256::digest(&encoded).into()
+    }
+
     pub fn new_limit_order(
         &mut self,
         market_id: MarketId,
@@ -53,6 +71,8 @@ impl OrderStateManager {
             base_lots: quote_size,
             filled_base_lots: 0,
             self_filled: 0,
+            priority: 0,
+            all_or_none: false,
         }
     }
 
@@ -108,6 +128,21 @@ pub struct CancelOrder {
 pub enum OrderType {
     Limit(OrderPriceMultiple, u64),
     Market(u64),
+    // limit order that is only ever filled whole, in a single match
+    AllOrNone(OrderPriceMultiple, u64),
+}
+
+impl OrderType {
+    /// Rejects orders priced at zero ticks or sized at zero lots
+    pub fn check(&self) -> Result<(), RejectReason> {
+        match self {
+            OrderType::Limit(0, _) | OrderType::AllOrNone(0, _) => Err(RejectReason::InvalidTick),
+            OrderType::Limit(_, 0) | OrderType::AllOrNone(_, 0) | OrderType::Market(0) => {
+                Err(RejectReason::ZeroOrderSize)
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -141,6 +176,14 @@ impl Order {
         }
     }
 
+    pub fn get_status(&self) -> &OrderStatus {
+        match self {
+            Order::Limit(limit_order) => &limit_order.common.status,
+            Order::Market(MarketOrder::Buy(order)) => &order.common.status,
+            Order::Market(MarketOrder::Sell(order)) => &order.common.status,
+        }
+    }
+
     pub fn get_id(&self) -> OrderId {
         match self {
             Order::Limit(limit_order) => limit_order.common.id,
@@ -156,14 +199,22 @@ pub struct LimitOrder {
     pub base_lots: u64,
     pub filled_base_lots: u64,
     pub self_filled: u64,
+    // time priority within a price level, lower fills first. Stamped by the market the
+    // order is placed in, see `SpotMarket::take_priority`
+    pub priority: u64,
+    // only filled by a taker that can take all of what remains, others skip over it
+    pub all_or_none: bool,
     // type
     // trigger conditions
     // tp/sl
 }
 
 impl LimitOrder {
+    /// Size still resting on the book, net of counterparty fills and self fills
     pub fn get_order_remaining(&self) -> u64 {
-        self.base_lots - self.filled_base_lots - self.self_filled
+        self.base_lots
+            .saturating_sub(self.filled_base_lots)
+            .saturating_sub(self.self_filled)
     }
 }
 
@@ -231,18 +282,30 @@ pub enum MarketOrderMatchingResults {
         base_filled_lots: u64,
         quote_lots_in: u64,
         self_fill: u64,
+        // quote lots the taker's own resting bids had locked for the self filled size
+        self_fill_quote_lots: u64,
         filled_orders: Vec<LimitOrder>,
         residual_order: Option<ResidualOrder>,
         last_executed_price: Option<u64>,
+        // size returned to the user, in base lots
+        unfilled_base_lots: u64,
+        // matching stopped because the book ran out, not because the order was satisfied
+        book_exhausted: bool,
     },
     Buy {
         order_id: OrderId,
         quote_filled_lots: u64,
         base_lots_in: u64,
         self_fill: u64,
+        // base lots the taker's own resting asks had locked for the self filled size
+        self_fill_base_lots: u64,
         filled_orders: Vec<LimitOrder>,
         residual_order: Option<ResidualOrder>,
         last_executed_price: Option<u64>,
+        // quote returned to the user, in quote lots. May be non-zero without the book running
+        // out, when less than one base lot's worth of quote is left
+        unfilled_quote_lots: u64,
+        book_exhausted: bool,
     },
 }
 
@@ -259,12 +322,28 @@ impl MarketOrderMatchingResults {
             } => *last_executed_price,
         }
     }
+
+    pub fn get_maker_fills(&self) -> (&[LimitOrder], &Option<ResidualOrder>) {
+        match self {
+            MarketOrderMatchingResults::Sell {
+                filled_orders,
+                residual_order,
+                ..
+            }
+            | MarketOrderMatchingResults::Buy {
+                filled_orders,
+                residual_order,
+                ..
+            } => (filled_orders, residual_order),
+        }
+    }
 }
 
 pub enum OrderChange {
     LimitOrderChange {
         order_id: OrderId,
         filled_lots: u64,
+        // in ticks, same as the order's price multiple
         average_execution_price: u128,
     },
     MarketOrderChange {
@@ -272,11 +351,42 @@ pub enum OrderChange {
         filled_lots: u64,
         self_fill: u64,
         average_execution_price: u64,
+        // in the order's size lots: quote for buys, base for sells
+        unfilled_lots: u64,
+        book_exhausted: bool,
     },
 }
 
+impl OrderChange {
+    pub fn get_average_execution_price(&self) -> u128 {
+        match self {
+            OrderChange::LimitOrderChange {
+                average_execution_price,
+                ..
+            } => *average_execution_price,
+            OrderChange::MarketOrderChange {
+                average_execution_price,
+                ..
+            } => *average_execution_price as u128,
+        }
+    }
+}
+
+/// What the taker paid and received over all of its fills, in asset units
+pub struct TakerSettlement {
+    pub asset_out: AssetId,
+    pub amount_out: u128,
+    pub asset_in: AssetId,
+    // fee already taken off
+    pub amount_in: u128,
+}
+
 pub struct ExecutionResults {
     pub filled_orders: Vec<LimitOrder>,
     pub residual_order: Option<ResidualOrder>,
+    // taker fee, in the asset the taker received
+    pub fee: u128,
+    // none if the order did not fill
+    pub settlement: Option<TakerSettlement>,
     pub user_order_change: Option<OrderChange>,
 }
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::transaction::{PublicKeyHash, Sha256Hash, TransactionStatus};
+
+use super::{
+    asset::AssetId,
+    order::{OrderId, OrderPriceMultiple},
+    spot_clearinghouse::MarketId,
+};
+
+pub const MAX_RECEIPTS: usize = 10_000;
+
+/// A single maker order touched by a taker order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub maker_order_id: OrderId,
+    pub maker_account: PublicKeyHash,
+    pub price_multiple: OrderPriceMultiple,
+    pub base_lots: u64,
+}
+
+/// Change to one of the submitter's total balances
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceDelta {
+    pub asset_id: AssetId,
+    // fees already taken off
+    pub amount: u128,
+    pub is_increase: bool,
+}
+
+/// Emitted to fill subscribers whenever a resting order is touched
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FillEvent {
+    pub market_id: MarketId,
+    pub taker_order_id: OrderId,
+    pub fill: Fill,
+}
+
+/// Emitted to top of book subscribers whenever a market's best bid or best ask changes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopOfBookEvent {
+    pub market_id: MarketId,
+    pub best_bid: Option<OrderPriceMultiple>,
+    pub best_ask: Option<OrderPriceMultiple>,
+}
+
+/// Whether a transaction's outcome is final
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finality {
+    // in a block consensus has not committed yet, so it may still be dropped
+    Pending,
+    // executed in a block committed by the three-chain rule, or dropped before reaching one
+    Committed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionReceipt {
+    pub tx_hash: Sha256Hash,
+    pub status: TransactionStatus,
+    pub order_id: Option<OrderId>,
+    pub fills: Vec<Fill>,
+    pub average_execution_price: u128,
+    pub fees: u128,
+    // submitter's balance changes by asset id, unchanged assets left out
+    pub balance_deltas: Vec<BalanceDelta>,
+    // per-order statuses of a batch order, empty otherwise
+    pub order_statuses: Vec<TransactionStatus>,
+    // part of a market order's size returned unfilled: quote lots for buys, base lots for sells
+    pub unfilled_lots: u64,
+    // the market order ran out of resting liquidity before it was satisfied
+    pub book_exhausted: bool,
+    pub finality: Finality,
+}
+
+impl TransactionReceipt {
+    pub fn new(tx_hash: Sha256Hash, status: TransactionStatus) -> Self {
+        Self {
+            tx_hash,
+            status,
+            order_id: None,
+            fills: vec![],
+            average_execution_price: 0,
+            fees: 0,
+            balance_deltas: vec![],
+            order_statuses: vec![],
+            unfilled_lots: 0,
+            book_exhausted: false,
+            finality: Finality::Committed,
+        }
+    }
+
+    /// Stand-in receipt for a transaction in a block that has not been committed yet
+    pub fn pending(tx_hash: Sha256Hash) -> Self {
+        Self {
+            finality: Finality::Pending,
+            ..Self::new(tx_hash, TransactionStatus::Pending)
+        }
+    }
+}
+
+/// Keeps the most recent receipts, evicting the oldest once capacity is reached
+#[derive(Serialize, Deserialize)]
+pub struct ReceiptStore {
+    capacity: usize,
+    receipts: HashMap<Sha256Hash, TransactionReceipt>,
+    insertion_order: VecDeque<Sha256Hash>,
+}
+
+impl ReceiptStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            receipts: HashMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    pub fn insert(&mut self, receipt: TransactionReceipt) {
+        let tx_hash = receipt.tx_hash;
+        if self.receipts.insert(tx_hash, receipt).is_some() {
+            return;
+        }
+
+        self.insertion_order.push_back(tx_hash);
+        while self.insertion_order.len() > self.capacity {
+            let Some(oldest) = self.insertion_order.pop_front() else {
+                break;
+            };
+            self.receipts.remove(&oldest);
+        }
+    }
+
+    /// Receipts from oldest to newest
+    pub fn into_receipts(mut self) -> Vec<TransactionReceipt> {
+        self.insertion_order
+            .into_iter()
+            .filter_map(|tx_hash| self.receipts.remove(&tx_hash))
+            .collect()
+    }
+
+    pub fn get(&self, tx_hash: &Sha256Hash) -> Option<&TransactionReceipt> {
+        self.receipts.get(tx_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.receipts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receipts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_store_evicts_oldest() {
+        let mut store = ReceiptStore::new(2);
+        store.insert(TransactionReceipt::new(
+            [0; 32],
+            TransactionStatus::Executed,
+        ));
+        store.insert(TransactionReceipt::new(
+            [1; 32],
+            TransactionStatus::Executed,
+        ));
+        store.insert(TransactionReceipt::new(
+            [2; 32],
+            TransactionStatus::Executed,
+        ));
+
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&[0; 32]).is_none());
+        assert!(store.get(&[1; 32]).is_some());
+        assert!(store.get(&[2; 32]).is_some());
+    }
+}
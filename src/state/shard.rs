@@ -0,0 +1,203 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::types::transaction::{PublicKeyHash, SignedTransaction, UnsignedTransaction};
+
+use super::{order::OrderId, spot_clearinghouse::MarketId, state::LedgerState};
+
+/// Transactions of a block whose accounts and markets no other shard touches, so shards can
+/// execute concurrently. Shards are consensus critical: they fix the order ids each one hands out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionShard {
+    // indexes into the canonically ordered block, ascending
+    pub positions: Vec<usize>,
+    pub accounts: BTreeSet<PublicKeyHash>,
+    pub markets: BTreeSet<MarketId>,
+    // upper bound on the order ids the shard can use, queued transactions included
+    pub order_ids: OrderId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum StateKey {
+    Account(PublicKeyHash),
+    Market(MarketId),
+}
+
+/// Union-find over the accounts and markets a block touches
+#[derive(Default)]
+struct Components {
+    indexes: HashMap<StateKey, usize>,
+    keys: Vec<StateKey>,
+    parents: Vec<usize>,
+}
+
+impl Components {
+    fn index(&mut self, key: StateKey) -> usize {
+        if let Some(index) = self.indexes.get(&key) {
+            return *index;
+        }
+        let index = self.keys.len();
+        self.indexes.insert(key, index);
+        self.keys.push(key);
+        self.parents.push(index);
+        index
+    }
+
+    fn root(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        self.parents[b] = a;
+    }
+}
+
+/// Accounts and markets `transaction` reads or writes directly
+fn touched_keys(transaction: &SignedTransaction) -> Vec<StateKey> {
+    match &transaction.tx {
+        UnsignedTransaction::Transfer(transfer) => vec![
+            StateKey::Account(transfer.from),
+            StateKey::Account(transfer.to),
+        ],
+        UnsignedTransaction::Order(order) => vec![
+            StateKey::Account(order.from),
+            StateKey::Market(order.market_id),
+        ],
+        UnsignedTransaction::CancelOrder(cancel) => vec![
+            StateKey::Account(cancel.from),
+            StateKey::Market(cancel.market_id),
+        ],
+        UnsignedTransaction::CancelOrderAtPrice(cancel) => vec![
+            StateKey::Account(cancel.from),
+            StateKey::Market(cancel.market_id),
+        ],
+        UnsignedTransaction::ReduceOrder(reduce) => vec![
+            StateKey::Account(reduce.from),
+            StateKey::Market(reduce.market_id),
+        ],
+        UnsignedTransaction::TransferAll(transfer) => vec![
+            StateKey::Account(transfer.from),
+            StateKey::Account(transfer.to),
+        ],
+        UnsignedTransaction::SetMarketStatus(set_status) => vec![
+            StateKey::Account(set_status.from),
+            StateKey::Market(set_status.market_id),
+        ],
+        UnsignedTransaction::BatchOrder(batch) => std::iter::once(StateKey::Account(batch.from))
+            .chain(
+                batch
+                    .orders
+                    .iter()
+                    .map(|spec| StateKey::Market(spec.market_id)),
+            )
+            .collect(),
+    }
+}
+
+/// Most order ids executing `transaction` can hand out
+fn order_id_bound(transaction: &SignedTransaction) -> OrderId {
+    match &transaction.tx {
+        UnsignedTransaction::Order(_) => 1,
+        UnsignedTransaction::BatchOrder(batch) => batch.orders.len() as OrderId,
+        UnsignedTransaction::Transfer(_)
+        | UnsignedTransaction::CancelOrder(_)
+        | UnsignedTransaction::CancelOrderAtPrice(_)
+        | UnsignedTransaction::ReduceOrder(_)
+        | UnsignedTransaction::SetMarketStatus(_)
+        | UnsignedTransaction::TransferAll(_) => 0,
+    }
+}
+
+/// Splits the canonically ordered `transactions` into shards, ordered by their first transaction.
+///
+/// Besides what a transaction names itself, a shard owns every account resting an order in one
+/// of its markets, since matching settles against them, and everything the queued transactions
+/// of its accounts touch, since those may be replayed within the block. Fees stay in their market
+/// until the block is applied, so the fee collector does not tie markets together
+pub fn plan_shards(
+    ledger_state: &LedgerState,
+    transactions: &[SignedTransaction],
+) -> Vec<ExecutionShard> {
+    let mut components = Components::default();
+    let mut transaction_indexes = Vec::with_capacity(transactions.len());
+    let mut pending: Vec<(usize, StateKey)> = vec![];
+    let mut expanded: HashSet<StateKey> = HashSet::new();
+
+    for transaction in transactions {
+        let keys = touched_keys(transaction);
+        let sender = components.index(keys[0]);
+        transaction_indexes.push(sender);
+        pending.extend(keys.into_iter().map(|key| (sender, key)));
+    }
+
+    while let Some((linked_to, key)) = pending.pop() {
+        let index = components.index(key);
+        components.union(linked_to, index);
+        if !expanded.insert(key) {
+            continue;
+        }
+
+        match key {
+            StateKey::Account(public_key) => {
+                let queued = ledger_state.queued_transactions.get(&public_key);
+                for transaction in queued.into_iter().flat_map(|queue| queue.values()) {
+                    pending.extend(
+                        touched_keys(transaction)
+                            .into_iter()
+                            .map(|key| (index, key)),
+                    );
+                }
+            }
+            StateKey::Market(market_id) => {
+                let resting = ledger_state
+                    .spot_clearinghouse
+                    .get_resting_accounts(market_id);
+                pending.extend(
+                    resting
+                        .into_iter()
+                        .map(|public_key| (index, StateKey::Account(public_key))),
+                );
+            }
+        }
+    }
+
+    let mut shards: Vec<ExecutionShard> = vec![];
+    let mut shard_by_root: HashMap<usize, usize> = HashMap::new();
+    for (position, transaction) in transactions.iter().enumerate() {
+        let root = components.root(transaction_indexes[position]);
+        let shard_index = *shard_by_root.entry(root).or_insert_with(|| {
+            shards.push(ExecutionShard {
+                positions: vec![],
+                accounts: BTreeSet::new(),
+                markets: BTreeSet::new(),
+                order_ids: 0,
+            });
+            shards.len() - 1
+        });
+        let shard = &mut shards[shard_index];
+        shard.positions.push(position);
+        shard.order_ids += order_id_bound(transaction);
+    }
+
+    for index in 0..components.keys.len() {
+        let root = components.root(index);
+        let shard = &mut shards[shard_by_root[&root]];
+        match components.keys[index] {
+            StateKey::Account(public_key) => {
+                shard.accounts.insert(public_key);
+                if let Some(queue) = ledger_state.queued_transactions.get(&public_key) {
+                    shard.order_ids += queue.values().map(order_id_bound).sum::<OrderId>();
+                }
+            }
+            StateKey::Market(market_id) => {
+                shard.markets.insert(market_id);
+            }
+        }
+    }
+
+    shards
+}
@@ -1,21 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{config, state::order::OrderDirection, types::transaction::PublicKeyHash};
+use crate::{
+    config, log_debug, log_warn,
+    state::order::OrderDirection,
+    types::transaction::{PublicKeyHash, Sha256Hash},
+};
 
 use super::{
     asset::AssetId,
     order::{
         ExecutionResults, LimitFillResult, LimitOrder, MarketOrder, MarketOrderMatchingResults,
-        Order, OrderChange, OrderStatus, ResidualOrder, UserExecutionResult,
+        Order, OrderChange, OrderStatus, ResidualOrder, TakerSettlement, UserExecutionResult,
+    },
+    receipt::TopOfBookEvent,
+    spot_market::{
+        BestPriceSnapshot, FirmQuote, LotSizes, MarketInfo, MarketStatus, OrderBookDepth,
+        SpotMarket,
     },
-    spot_market::{MarketInfo, SpotMarket},
 };
 
 pub type MarketId = usize;
 type MarketIdCounter = MarketId;
 
+/// Largest `tick_decimals` for which `10u128.pow(tick_decimals)` does not overflow
+pub const MAX_TICK_DECIMALS: u8 = 38;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketError {
+    TickDecimalsTooLarge { tick_decimals: u8, max: u8 },
+    SameAsset(AssetId),
+    UnknownAsset(AssetId),
+    ZeroLotSize,
+}
+
 #[derive(Debug)]
 pub struct MarketPrecision {
     pub base_lot_size: u32,  // base units per base lot
@@ -49,6 +68,25 @@ pub fn quote_lots_to_base_lots(
     (numerator / denominator) as u64
 }
 
+/// Average price in ticks of exchanging `base_lots` for `quote_lots`, rounded to the nearest tick
+pub fn average_price_multiple(base_lots: u64, quote_lots: u64, precision: &MarketPrecision) -> u64 {
+    if base_lots == 0 {
+        return 0;
+    }
+
+    let numerator = quote_lots as u128
+        * precision.quote_lot_size as u128
+        * 10u128.pow(precision.tick_decimals as u32);
+    let denominator = base_lots as u128 * precision.base_lot_size as u128 * precision.tick as u128;
+
+    ((numerator + denominator / 2) / denominator) as u64
+}
+
+/// Cut of `amount` a taker pays at `taker_fee_bps`, rounded down in the taker's favour
+pub fn taker_fee(amount: u128, taker_fee_bps: u64) -> u128 {
+    amount * taker_fee_bps as u128 / config::MAX_TAKER_FEE_BPS as u128
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AccountTokenBalance {
     pub asset_id: AssetId,
@@ -79,6 +117,7 @@ impl AccountBalance {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SpotClearingHouse {
     next_id: MarketIdCounter,
     accounts: HashMap<PublicKeyHash, AccountBalance>,
@@ -135,6 +174,141 @@ impl SpotClearingHouse {
             .or_insert_with(|| AccountBalance::new())
     }
 
+    pub fn get_account_keys(&self) -> impl Iterator<Item = &PublicKeyHash> {
+        self.accounts.keys()
+    }
+
+    pub fn get_market_hashes(&self) -> Vec<Sha256Hash> {
+        self.markets.iter().map(|market| market.hash()).collect()
+    }
+
+    /// Best price changes across all markets since the last call, by market id
+    pub fn take_top_of_book_events(&mut self) -> Vec<TopOfBookEvent> {
+        self.markets
+            .iter_mut()
+            .flat_map(|market| market.take_top_of_book_events())
+            .collect()
+    }
+
+    /// Holds `fee` in the market until `sweep_fees`, so fills never touch the fee collector
+    /// and markets charging fees can still execute in separate shards
+    fn collect_fee(&mut self, market_id: MarketId, asset_id: AssetId, fee: u128) {
+        if let Some(market) = self.markets.get_mut(market_id) {
+            market.accrue_fee(asset_id, fee);
+        }
+    }
+
+    /// Credits the fees every market has accrued to `fee_collector`, creating no account when
+    /// there is nothing to pay
+    pub(crate) fn sweep_fees(&mut self, fee_collector: PublicKeyHash) {
+        let accrued: Vec<(AssetId, u128)> = self
+            .markets
+            .iter_mut()
+            .flat_map(|market| market.take_accrued_fees())
+            .filter(|(_, fee)| *fee > 0)
+            .collect();
+
+        for (asset_id, fee) in accrued {
+            let account_balance = self.get_account_balance_mut(&fee_collector);
+            let fee_balance = Self::get_account_token_balance_mut(account_balance, asset_id);
+            fee_balance.total_balance += fee;
+            fee_balance.available_balance += fee;
+        }
+    }
+
+    /// Accounts with an order resting in `market_id`, empty if there is no such market
+    pub fn get_resting_accounts(&self, market_id: MarketId) -> Vec<PublicKeyHash> {
+        self.markets
+            .get(market_id)
+            .map(|market| market.get_resting_accounts().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Moves `market_ids` and the balances of `accounts` into a clearinghouse of their own,
+    /// leaving placeholders behind until `merge` brings them back. Markets not moved are
+    /// placeholders in the returned clearinghouse, so market ids keep indexing `markets`
+    pub(crate) fn split_off(
+        &mut self,
+        market_ids: &BTreeSet<MarketId>,
+        accounts: &BTreeSet<PublicKeyHash>,
+    ) -> SpotClearingHouse {
+        let markets = self
+            .markets
+            .iter_mut()
+            .map(|market| {
+                let placeholder = market.placeholder();
+                if market_ids.contains(&market.market_id) {
+                    std::mem::replace(market, placeholder)
+                } else {
+                    placeholder
+                }
+            })
+            .collect();
+
+        let accounts = accounts
+            .iter()
+            .filter_map(|public_key| {
+                self.accounts
+                    .remove(public_key)
+                    .map(|balance| (*public_key, balance))
+            })
+            .collect();
+
+        SpotClearingHouse {
+            next_id: self.next_id,
+            accounts,
+            markets,
+            asset_to_market_map: self.asset_to_market_map.clone(),
+        }
+    }
+
+    /// Copies `market_ids` and the balances of `accounts` into a clearinghouse shaped like the
+    /// one `split_off` returns, leaving this one untouched
+    pub(crate) fn clone_part(
+        &self,
+        market_ids: &BTreeSet<MarketId>,
+        accounts: &BTreeSet<PublicKeyHash>,
+    ) -> SpotClearingHouse {
+        let markets = self
+            .markets
+            .iter()
+            .map(|market| {
+                if market_ids.contains(&market.market_id) {
+                    market.clone()
+                } else {
+                    market.placeholder()
+                }
+            })
+            .collect();
+
+        let accounts = accounts
+            .iter()
+            .filter_map(|public_key| {
+                self.accounts
+                    .get(public_key)
+                    .map(|balance| (*public_key, balance.clone()))
+            })
+            .collect();
+
+        SpotClearingHouse {
+            next_id: self.next_id,
+            accounts,
+            markets,
+            asset_to_market_map: self.asset_to_market_map.clone(),
+        }
+    }
+
+    /// Returns the markets and balances taken by `split_off`
+    pub(crate) fn merge(&mut self, other: SpotClearingHouse, market_ids: &BTreeSet<MarketId>) {
+        for market in other.markets {
+            if market_ids.contains(&market.market_id) {
+                let market_id = market.market_id;
+                self.markets[market_id] = market;
+            }
+        }
+        self.accounts.extend(other.accounts);
+    }
+
     fn normalise_pair(asset_one: AssetId, asset_two: AssetId) -> (AssetId, AssetId) {
         if asset_one < asset_two {
             (asset_one, asset_two)
@@ -160,6 +334,80 @@ impl SpotClearingHouse {
         Some(market.get_market_info())
     }
 
+    /// (best bid, best ask) of the market
+    pub fn get_best_prices_from_id(
+        &self,
+        market_id: MarketId,
+    ) -> Option<(Option<u64>, Option<u64>)> {
+        let market = self.markets.get(market_id)?;
+        Some(market.get_best_prices())
+    }
+
+    /// Recent best price changes of a market, oldest first
+    pub fn get_best_price_history_from_id(
+        &self,
+        market_id: MarketId,
+    ) -> Option<Vec<BestPriceSnapshot>> {
+        let market = self.markets.get(market_id)?;
+        Some(market.get_best_price_history())
+    }
+
+    /// Adds each event to its market's best price history as of `view`
+    pub fn record_best_price_history(&mut self, view: u64, events: &[TopOfBookEvent]) {
+        for event in events {
+            if let Some(market) = self.markets.get_mut(event.market_id) {
+                market.record_best_prices(view, event.best_bid, event.best_ask);
+            }
+        }
+    }
+
+    pub fn get_market_status(&self, market_id: MarketId) -> Option<MarketStatus> {
+        self.markets.get(market_id).map(|market| market.status)
+    }
+
+    /// Returns false if there is no such market
+    pub(crate) fn set_market_status(&mut self, market_id: MarketId, status: MarketStatus) -> bool {
+        let Some(market) = self.markets.get_mut(market_id) else {
+            return false;
+        };
+        market.status = status;
+        true
+    }
+
+    /// Returns false if there is no such market
+    pub(crate) fn set_prune_threshold(
+        &mut self,
+        market_id: MarketId,
+        prune_threshold: u32,
+    ) -> bool {
+        let Some(market) = self.markets.get_mut(market_id) else {
+            return false;
+        };
+        market.prune_threshold = prune_threshold;
+        true
+    }
+
+    pub(crate) fn take_priority(&mut self, market_id: MarketId) -> Option<u64> {
+        let market = self.markets.get_mut(market_id)?;
+        Some(market.take_priority())
+    }
+
+    pub fn get_quote_from_id(
+        &self,
+        market_id: MarketId,
+        direction: OrderDirection,
+        base_lots: u64,
+        precision: &MarketPrecision,
+    ) -> Option<FirmQuote> {
+        let market = self.markets.get(market_id)?;
+        Some(market.get_quote(direction, base_lots, precision))
+    }
+
+    pub fn get_depth_from_id(&self, market_id: MarketId, depth: usize) -> Option<OrderBookDepth> {
+        let market = self.markets.get(market_id)?;
+        Some(market.get_depth(depth))
+    }
+
     pub fn get_markets(&self) -> Vec<MarketInfo> {
         self.markets
             .iter()
@@ -182,6 +430,11 @@ impl SpotClearingHouse {
         ))
     }
 
+    pub fn get_lot_sizes_from_id(&self, market_id: MarketId) -> Option<LotSizes> {
+        let market = self.markets.get(market_id)?;
+        Some(market.lot_sizes)
+    }
+
     fn create_new_market(
         &mut self,
         normalised_pair: (AssetId, AssetId),
@@ -191,6 +444,7 @@ impl SpotClearingHouse {
         quote_asset: AssetId,
         base_asset_name: String,
         quote_asset_name: String,
+        lot_sizes: LotSizes,
     ) -> MarketId {
         let market_id = self.next_id;
 
@@ -203,6 +457,7 @@ impl SpotClearingHouse {
             quote_asset_name,
             tick,
             tick_decimals,
+            lot_sizes,
         );
         self.markets.push(market);
         self.asset_to_market_map.insert(normalised_pair, market_id);
@@ -218,14 +473,30 @@ impl SpotClearingHouse {
         quote_asset_name: String,
         tick: u32,
         tick_decimals: u8,
-    ) -> MarketId {
+        lot_sizes: LotSizes,
+    ) -> Result<MarketId, MarketError> {
+        if tick_decimals > MAX_TICK_DECIMALS {
+            return Err(MarketError::TickDecimalsTooLarge {
+                tick_decimals,
+                max: MAX_TICK_DECIMALS,
+            });
+        }
+
+        if base_asset == quote_asset {
+            return Err(MarketError::SameAsset(base_asset));
+        }
+
+        if lot_sizes.base == Some(0) || lot_sizes.quote == Some(0) {
+            return Err(MarketError::ZeroLotSize);
+        }
+
         if let Some(market_id) = self.get_market_id_from_pair(base_asset, quote_asset) {
-            return market_id;
+            return Ok(market_id);
         }
 
         let normalised_pair = Self::normalise_pair(base_asset, quote_asset);
 
-        self.create_new_market(
+        Ok(self.create_new_market(
             normalised_pair,
             tick,
             tick_decimals,
@@ -233,7 +504,8 @@ impl SpotClearingHouse {
             quote_asset,
             base_asset_name,
             quote_asset_name,
-        )
+            lot_sizes,
+        ))
     }
 
     pub fn get_account_token_balance_mut(
@@ -279,7 +551,7 @@ impl SpotClearingHouse {
         let (market, account_balance) =
             self.get_market_and_account_balance(market_id, &cancel_order.common.account);
         let Some(market) = market else {
-            println!("Can't find market with id");
+            log_warn!("Can't find market with id {}", market_id);
             return false;
         };
 
@@ -307,20 +579,72 @@ impl SpotClearingHouse {
         }
     }
 
-    /// Handles order matching and resultant balance transfers if any
+    /// Takes `base_lots` off a resting order and unlocks the balance it no longer needs
+    pub fn reduce_order(
+        &mut self,
+        reduce_order: &LimitOrder,
+        base_lots: u64,
+        precision: &MarketPrecision,
+    ) -> bool {
+        let market_id = reduce_order.common.market_id;
+
+        let (market, account_balance) =
+            self.get_market_and_account_balance(market_id, &reduce_order.common.account);
+        let Some(market) = market else {
+            log_warn!("Can't find market with id {}", market_id);
+            return false;
+        };
+
+        if !market.reduce_order(reduce_order, base_lots) {
+            return false;
+        }
+
+        match reduce_order.common.direction {
+            OrderDirection::Buy => {
+                let quote_token_balance =
+                    Self::get_account_token_balance_mut(account_balance, market.quote_asset);
+
+                // unlocked as the difference of the rounded locks, so cancelling the rest
+                // later releases exactly what is left
+                let price = reduce_order.price_multiple;
+                let remaining = reduce_order.get_order_remaining();
+                let quote_lots = base_to_quote_lots(remaining, price, precision)
+                    - base_to_quote_lots(remaining - base_lots, price, precision);
+                let quote_amount = quote_lots as u128 * precision.quote_lot_size as u128;
+                quote_token_balance.available_balance += quote_amount;
+            }
+            OrderDirection::Sell => {
+                let base_token_balance =
+                    Self::get_account_token_balance_mut(account_balance, market.base_asset);
+
+                let base_amount = base_lots as u128 * precision.base_lot_size as u128;
+                base_token_balance.available_balance += base_amount;
+            }
+        }
+        true
+    }
+
+    /// Handles order matching and resultant balance transfers if any, charging the taker
+    /// `taker_fee_bps` of what it receives on each fill
     pub fn handle_order(
         &mut self,
         order: Order,
         precision: &MarketPrecision,
+        taker_fee_bps: u64,
     ) -> Option<ExecutionResults> {
         let market_id = order.get_market_id().clone();
+        // checked before any balance is looked up, since that creates the account's entry
+        if self.markets.get(market_id).is_none() {
+            log_warn!("Can't find market with id {}", market_id);
+            return None;
+        }
 
         match order {
             Order::Limit(limit_order) => {
                 let (market, account_balance) =
                     self.get_market_and_account_balance(market_id, &limit_order.common.account);
                 let Some(market) = market else {
-                    println!("Can't find market with id");
+                    log_warn!("Can't find market with id {}", market_id);
                     return None;
                 };
 
@@ -346,9 +670,10 @@ impl SpotClearingHouse {
                         expected_balance_lock = quote_amount;
                         let available = quote_token_balance.available_balance;
                         if available < quote_amount as u128 {
-                            println!(
+                            log_debug!(
                                 "Not enough balance, available: {}, needed: {}",
-                                available, quote_amount
+                                available,
+                                quote_amount
                             );
                             return None;
                         }
@@ -365,9 +690,10 @@ impl SpotClearingHouse {
 
                         let available = base_token_balance.available_balance;
                         if available < base_amount as u128 {
-                            println!(
+                            log_debug!(
                                 "Not enough balance, available: {}, needed: {}",
-                                available, base_amount
+                                available,
+                                base_amount
                             );
                             return None;
                         }
@@ -424,8 +750,9 @@ impl SpotClearingHouse {
                         let asset_in_balance =
                             Self::get_account_token_balance_mut(account_balance, user_asset_in);
                         let amount_in = in_lot_size as u128 * lots_in as u128;
-                        asset_in_balance.total_balance += amount_in;
-                        asset_in_balance.available_balance += amount_in;
+                        let fee = taker_fee(amount_in, taker_fee_bps);
+                        asset_in_balance.total_balance += amount_in - fee;
+                        asset_in_balance.available_balance += amount_in - fee;
 
                         // counterparty is symmetric to user
                         let counterparty_asset_in = user_asset_out;
@@ -442,8 +769,7 @@ impl SpotClearingHouse {
                             let account_balance =
                                 self.get_account_balance_mut(&filled_order.common.account);
 
-                            let filled_base_lots =
-                                filled_order.base_lots - filled_order.filled_base_lots;
+                            let filled_base_lots = filled_order.get_order_remaining();
 
                             let counterparty_asset_in_lots;
                             let counterparty_asset_out_lots;
@@ -545,14 +871,25 @@ impl SpotClearingHouse {
                             }
                         }
 
-                        let mut average_execution_price = lots_out / lots_in;
-                        if user_asset_in == base_asset {
-                            average_execution_price = lots_in / lots_out; // quote / base
-                        }
+                        let (base_lots, quote_lots) = if is_buy {
+                            (lots_in, lots_out)
+                        } else {
+                            (lots_out, lots_in)
+                        };
+                        let average_execution_price =
+                            average_price_multiple(base_lots, quote_lots, precision);
+                        self.collect_fee(market_id, user_asset_in, fee);
 
                         return Some(ExecutionResults {
                             filled_orders,
                             residual_order,
+                            fee,
+                            settlement: Some(TakerSettlement {
+                                asset_out: user_asset_out,
+                                amount_out,
+                                asset_in: user_asset_in,
+                                amount_in: amount_in - fee,
+                            }),
                             user_order_change: Some(OrderChange::LimitOrderChange {
                                 order_id,
                                 filled_lots: filled_size,
@@ -568,6 +905,8 @@ impl SpotClearingHouse {
                 return Some(ExecutionResults {
                     filled_orders: vec![],
                     residual_order: None,
+                    fee: 0,
+                    settlement: None,
                     user_order_change: None,
                 });
             }
@@ -575,7 +914,7 @@ impl SpotClearingHouse {
                 let (market, account_balance) =
                     self.get_market_and_account_balance(market_id, &market_order.get_account());
                 let Some(market) = market else {
-                    println!("Can't find market with id");
+                    log_warn!("Can't find market with id {}", market_id);
                     return None;
                 };
 
@@ -590,7 +929,11 @@ impl SpotClearingHouse {
                             sell_order.base_size as u128 * precision.base_lot_size as u128;
 
                         if base_token_balance.available_balance < base_needed {
-                            println!("Not enough balance");
+                            log_debug!(
+                                "Not enough balance, available: {}, needed: {}",
+                                base_token_balance.available_balance,
+                                base_needed
+                            );
                             return None;
                         }
 
@@ -606,7 +949,11 @@ impl SpotClearingHouse {
                         let quote_needed =
                             buy_order.quote_size as u128 * precision.quote_lot_size as u128;
                         if quote_token_balance.available_balance < quote_needed {
-                            println!("Not enough balance");
+                            log_debug!(
+                                "Not enough balance, available: {}, needed: {}",
+                                quote_token_balance.available_balance,
+                                quote_needed
+                            );
                         }
                         quote_token_balance.available_balance -= quote_needed;
                         lock_amount = quote_needed;
@@ -626,7 +973,10 @@ impl SpotClearingHouse {
                         filled_orders,
                         residual_order,
                         self_fill,
+                        self_fill_quote_lots,
                         last_executed_price: _,
+                        unfilled_base_lots,
+                        book_exhausted,
                     } => {
                         // Handle user balance change
                         let base_token_balance =
@@ -645,8 +995,13 @@ impl SpotClearingHouse {
 
                         let quote_amount_in =
                             quote_lots_in as u128 * precision.quote_lot_size as u128;
-                        quote_token_balance.total_balance += quote_amount_in;
-                        quote_token_balance.available_balance += quote_amount_in;
+                        let fee = taker_fee(quote_amount_in, taker_fee_bps);
+                        quote_token_balance.total_balance += quote_amount_in - fee;
+                        quote_token_balance.available_balance += quote_amount_in - fee;
+
+                        // self filled bids no longer need their quote locked
+                        quote_token_balance.available_balance +=
+                            self_fill_quote_lots as u128 * precision.quote_lot_size as u128;
 
                         let average_execution_price = {
                             if base_filled_lots > 0 {
@@ -662,8 +1017,7 @@ impl SpotClearingHouse {
                             // Buy orders
                             let account_balance =
                                 self.get_account_balance_mut(&filled_order.common.account);
-                            let filled_base_lots =
-                                filled_order.base_lots - filled_order.filled_base_lots;
+                            let filled_base_lots = filled_order.get_order_remaining();
 
                             let base_amount_in =
                                 filled_base_lots as u128 * precision.base_lot_size as u128;
@@ -723,15 +1077,25 @@ impl SpotClearingHouse {
                             }
                             None => {}
                         }
+                        self.collect_fee(market_id, quote_asset, fee);
 
                         return Some(ExecutionResults {
                             filled_orders,
                             residual_order,
+                            fee,
+                            settlement: Some(TakerSettlement {
+                                asset_out: base_asset,
+                                amount_out: base_filled_amount,
+                                asset_in: quote_asset,
+                                amount_in: quote_amount_in - fee,
+                            }),
                             user_order_change: Some(OrderChange::MarketOrderChange {
                                 order_id,
                                 filled_lots: base_filled_lots,
                                 self_fill,
                                 average_execution_price: average_execution_price,
+                                unfilled_lots: unfilled_base_lots,
+                                book_exhausted,
                             }),
                         });
                     }
@@ -742,7 +1106,10 @@ impl SpotClearingHouse {
                         base_lots_in,
                         residual_order,
                         self_fill,
+                        self_fill_base_lots,
                         last_executed_price: _,
+                        unfilled_quote_lots,
+                        book_exhausted,
                     } => {
                         let quote_token_balance = Self::get_account_token_balance_mut(
                             account_balance,
@@ -762,8 +1129,13 @@ impl SpotClearingHouse {
                             Self::get_account_token_balance_mut(account_balance, market.base_asset);
 
                         let base_amount = base_lots_in as u128 * precision.base_lot_size as u128;
-                        base_token_balance.total_balance += base_amount;
-                        base_token_balance.available_balance += base_amount;
+                        let fee = taker_fee(base_amount, taker_fee_bps);
+                        base_token_balance.total_balance += base_amount - fee;
+                        base_token_balance.available_balance += base_amount - fee;
+
+                        // self filled asks no longer need their base locked
+                        base_token_balance.available_balance +=
+                            self_fill_base_lots as u128 * precision.base_lot_size as u128;
 
                         let average_execution_price = {
                             if base_lots_in > 0 {
@@ -780,8 +1152,7 @@ impl SpotClearingHouse {
                             // Sell orders
                             let account_balance =
                                 self.get_account_balance_mut(&filled_order.common.account);
-                            let filled_base_lots =
-                                filled_order.base_lots - filled_order.filled_base_lots;
+                            let filled_base_lots = filled_order.get_order_remaining();
                             let base_amount =
                                 filled_base_lots as u128 * precision.base_lot_size as u128;
 
@@ -843,14 +1214,25 @@ impl SpotClearingHouse {
                             }
                             None => {}
                         }
+                        self.collect_fee(market_id, base_asset, fee);
+
                         return Some(ExecutionResults {
                             filled_orders,
                             residual_order,
+                            fee,
+                            settlement: Some(TakerSettlement {
+                                asset_out: quote_asset,
+                                amount_out: quote_amount,
+                                asset_in: base_asset,
+                                amount_in: base_amount - fee,
+                            }),
                             user_order_change: Some(OrderChange::MarketOrderChange {
                                 order_id,
                                 filled_lots: quote_filled_lots,
                                 self_fill,
                                 average_execution_price,
+                                unfilled_lots: unfilled_quote_lots,
+                                book_exhausted,
                             }),
                         });
                     }
@@ -863,9 +1245,12 @@ impl SpotClearingHouse {
 #[cfg(test)]
 mod tests {
     use crate::{
-        state::order::{
-            CommonOrderFields, LimitOrder, MarketBuyOrder, MarketOrder, MarketSellOrder, Order,
-            OrderDirection, OrderId, OrderStatus,
+        state::{
+            order::{
+                CommonOrderFields, LimitOrder, MarketBuyOrder, MarketOrder, MarketSellOrder, Order,
+                OrderDirection, OrderId, OrderStatus,
+            },
+            spot_market::LotSizes,
         },
         types::transaction::PublicKeyHash,
     };
@@ -884,6 +1269,8 @@ mod tests {
             base_lots: lot_size,
             filled_base_lots: 0,
             self_filled: 0,
+            priority: id,
+            all_or_none: false,
             common: CommonOrderFields {
                 id,
                 market_id: 0,
@@ -947,14 +1334,17 @@ mod tests {
             tick_decimals: tick_decimals,
         };
 
-        spot_clearinghouse.add_market(
-            base_asset,
-            quote_asset,
-            base_asset_name,
-            quote_asset_name,
-            tick,
-            tick_decimals,
-        );
+        spot_clearinghouse
+            .add_market(
+                base_asset,
+                quote_asset,
+                base_asset_name,
+                quote_asset_name,
+                tick,
+                tick_decimals,
+                LotSizes::default(),
+            )
+            .unwrap();
 
         // Fund accounts
         {
@@ -1014,20 +1404,20 @@ mod tests {
         let sell_5 = new_limit(2_800, 300, OrderDirection::Sell, 9, maker_one_public_key);
         let sell_6 = new_limit(2_500, 300, OrderDirection::Sell, 10, maker_one_public_key);
 
-        spot_clearinghouse.handle_order(Order::Limit(buy_1), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(sell_1), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(sell_2), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(buy_2), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(buy_3), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(buy_4), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(sell_3), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(sell_5), &precision);
-        spot_clearinghouse.handle_order(Order::Limit(sell_4), &precision);
-
-        spot_clearinghouse.handle_order(Order::Limit(buy_5.clone()), &precision);
+        spot_clearinghouse.handle_order(Order::Limit(buy_1), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(sell_1), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(sell_2), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(buy_2), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(buy_3), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(buy_4), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(sell_3), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(sell_5), &precision, 0);
+        spot_clearinghouse.handle_order(Order::Limit(sell_4), &precision, 0);
+
+        spot_clearinghouse.handle_order(Order::Limit(buy_5.clone()), &precision, 0);
         spot_clearinghouse.cancel_order(&buy_5, &precision);
 
-        spot_clearinghouse.handle_order(Order::Limit(sell_6.clone()), &precision);
+        spot_clearinghouse.handle_order(Order::Limit(sell_6.clone()), &precision, 0);
         spot_clearinghouse.cancel_order(&sell_6, &precision);
 
         let market = spot_clearinghouse.markets.get(0).unwrap();
@@ -1094,7 +1484,7 @@ mod tests {
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
 
             let buy_5 = new_limit(2_600, 1_800, OrderDirection::Buy, 10, user_public_key);
-            spot_clearinghouse.handle_order(Order::Limit(buy_5), &precision);
+            spot_clearinghouse.handle_order(Order::Limit(buy_5), &precision, 0);
 
             // asset user state
             {
@@ -1213,7 +1603,7 @@ mod tests {
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
 
             let buy_5 = new_limit(2_550, 1_800, OrderDirection::Buy, 10, user_public_key);
-            spot_clearinghouse.handle_order(Order::Limit(buy_5), &precision);
+            spot_clearinghouse.handle_order(Order::Limit(buy_5), &precision, 0);
 
             // asset user state
             {
@@ -1329,7 +1719,7 @@ mod tests {
             let (mut spot_clearinghouse, precision) =
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
             let sell = new_limit(2_100, 2_200, OrderDirection::Sell, 10, user_public_key);
-            spot_clearinghouse.handle_order(Order::Limit(sell), &precision);
+            spot_clearinghouse.handle_order(Order::Limit(sell), &precision, 0);
 
             // asset user state
             {
@@ -1448,7 +1838,7 @@ mod tests {
             let (mut spot_clearinghouse, precision) =
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
             let sell = new_limit(2_250, 2_200, OrderDirection::Sell, 10, user_public_key);
-            spot_clearinghouse.handle_order(Order::Limit(sell), &precision);
+            spot_clearinghouse.handle_order(Order::Limit(sell), &precision, 0);
 
             // asset user state
             {
@@ -1559,6 +1949,139 @@ mod tests {
         }
     }
 
+    mod test_add_market {
+        use crate::state::{
+            spot_clearinghouse::{
+                MAX_TICK_DECIMALS, MarketError, MarketPrecision, SpotClearingHouse,
+                base_to_quote_lots,
+            },
+            spot_market::LotSizes,
+        };
+
+        #[test]
+        fn test_add_market_accepts_max_tick_decimals() {
+            let mut spot_clearinghouse = SpotClearingHouse::new();
+            let market_id = spot_clearinghouse
+                .add_market(
+                    0,
+                    1,
+                    "".to_string(),
+                    "".to_string(),
+                    1,
+                    MAX_TICK_DECIMALS,
+                    LotSizes::default(),
+                )
+                .unwrap();
+
+            let (_, _, tick, tick_decimals) = spot_clearinghouse
+                .get_quote_base_tick_from_id(market_id)
+                .unwrap();
+            let precision = MarketPrecision {
+                base_lot_size: 1,
+                quote_lot_size: 1,
+                tick,
+                tick_decimals,
+            };
+
+            // scaling by 10^tick_decimals must not overflow during matching
+            assert_eq!(base_to_quote_lots(1, 1, &precision), 0);
+        }
+
+        #[test]
+        fn test_add_market_rejects_tick_decimals_over_max() {
+            let mut spot_clearinghouse = SpotClearingHouse::new();
+
+            for tick_decimals in [MAX_TICK_DECIMALS + 1, u8::MAX] {
+                let result = spot_clearinghouse.add_market(
+                    0,
+                    1,
+                    "".to_string(),
+                    "".to_string(),
+                    1,
+                    tick_decimals,
+                    LotSizes::default(),
+                );
+                assert_eq!(
+                    result,
+                    Err(MarketError::TickDecimalsTooLarge {
+                        tick_decimals,
+                        max: MAX_TICK_DECIMALS,
+                    })
+                );
+            }
+            assert!(spot_clearinghouse.get_markets().is_empty());
+        }
+
+        #[test]
+        fn test_add_market_rejects_same_base_and_quote() {
+            let mut spot_clearinghouse = SpotClearingHouse::new();
+
+            let result = spot_clearinghouse.add_market(
+                1,
+                1,
+                "USD".to_string(),
+                "USD".to_string(),
+                1,
+                2,
+                LotSizes::default(),
+            );
+            assert_eq!(result, Err(MarketError::SameAsset(1)));
+            assert!(spot_clearinghouse.get_markets().is_empty());
+        }
+    }
+
+    mod test_average_execution_price {
+        use crate::state::{
+            order::{Order, OrderDirection},
+            spot_clearinghouse::average_price_multiple,
+        };
+
+        use super::{new_limit, test_setup};
+
+        #[test]
+        fn test_limit_buy_average_price_across_levels() {
+            let user_public_key = [0; 32];
+            let (mut spot_clearinghouse, precision) = test_setup(user_public_key, [1; 32], [2; 32]);
+
+            // fills 600 + 1_000 at 2_500 and 400 at 2_600
+            let user_buy = new_limit(2_600, 2_000, OrderDirection::Buy, 11, user_public_key);
+            let results = spot_clearinghouse
+                .handle_order(Order::Limit(user_buy), &precision, 0)
+                .unwrap();
+
+            // (1_600 * 2_500 + 400 * 2_600) / 2_000
+            let order_change = results.user_order_change.unwrap();
+            assert_eq!(order_change.get_average_execution_price(), 2_520);
+        }
+
+        #[test]
+        fn test_limit_sell_average_price_across_levels() {
+            let user_public_key = [0; 32];
+            let (mut spot_clearinghouse, precision) = test_setup(user_public_key, [1; 32], [2; 32]);
+
+            // fills 1_000 at 2_450 and 500 at 2_300
+            let user_sell = new_limit(2_300, 1_500, OrderDirection::Sell, 11, user_public_key);
+            let results = spot_clearinghouse
+                .handle_order(Order::Limit(user_sell), &precision, 0)
+                .unwrap();
+
+            // (1_000 * 2_450 + 500 * 2_300) / 1_500
+            let order_change = results.user_order_change.unwrap();
+            assert_eq!(order_change.get_average_execution_price(), 2_400);
+        }
+
+        #[test]
+        fn test_average_price_rounds_to_nearest_tick() {
+            let (_, precision) = test_setup([0; 32], [1; 32], [2; 32]);
+
+            // one base lot each at 2_500, 2_500 and 2_600 ticks
+            assert_eq!(average_price_multiple(3, 7_600, &precision), 2_533);
+            assert_eq!(average_price_multiple(2, 5_101, &precision), 2_551);
+            assert_eq!(average_price_multiple(1, 1, &precision), 1);
+            assert_eq!(average_price_multiple(0, 0, &precision), 0);
+        }
+    }
+
     mod test_market_execution_side_effects {
         use crate::state::order::Order;
 
@@ -1573,7 +2096,7 @@ mod tests {
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
 
             let buy = new_market_buy(10, 2_500_500, user_public_key);
-            spot_clearinghouse.handle_order(Order::Market(buy), &precision);
+            spot_clearinghouse.handle_order(Order::Market(buy), &precision, 0);
 
             // asset user state
             {
@@ -1690,7 +2213,7 @@ mod tests {
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
 
             let buy = new_market_buy(10, 50_000_000, user_public_key);
-            spot_clearinghouse.handle_order(Order::Market(buy), &precision);
+            spot_clearinghouse.handle_order(Order::Market(buy), &precision, 0);
 
             // asset user state
             {
@@ -1811,7 +2334,7 @@ mod tests {
             let (mut spot_clearinghouse, precision) =
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
             let sell = new_market_sell(10, 2_200, user_public_key);
-            spot_clearinghouse.handle_order(Order::Market(sell), &precision);
+            spot_clearinghouse.handle_order(Order::Market(sell), &precision, 0);
 
             // asset user state
             {
@@ -1931,7 +2454,7 @@ mod tests {
             let (mut spot_clearinghouse, precision) =
                 test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
             let sell = new_market_sell(10, 10_000, user_public_key);
-            spot_clearinghouse.handle_order(Order::Market(sell), &precision);
+            spot_clearinghouse.handle_order(Order::Market(sell), &precision, 0);
 
             // asset user state
             {
@@ -2042,4 +2565,62 @@ mod tests {
             }
         }
     }
+
+    mod test_unknown_market {
+        use crate::state::order::{MarketOrder, Order, OrderDirection};
+
+        use super::{new_limit, new_market_buy, test_setup};
+
+        #[test]
+        fn test_order_on_unknown_market_touches_no_balance() {
+            let user_public_key = [0; 32];
+            let maker_one_public_key = [1; 32];
+            let maker_two_public_key = [2; 32];
+            let stranger_public_key = [9; 32];
+            let (mut spot_clearinghouse, precision) =
+                test_setup(user_public_key, maker_one_public_key, maker_two_public_key);
+            let balances = |spot_clearinghouse: &super::SpotClearingHouse| {
+                spot_clearinghouse
+                    .get_account_balance_or_default(&user_public_key)
+                    .asset_balances
+                    .iter()
+                    .map(|balance| (balance.total_balance, balance.available_balance))
+                    .collect::<Vec<_>>()
+            };
+            let user_balances = balances(&spot_clearinghouse);
+
+            let mut limit = new_limit(2_500, 100, OrderDirection::Buy, 20, user_public_key);
+            limit.common.market_id = 7;
+            assert!(
+                spot_clearinghouse
+                    .handle_order(Order::Limit(limit), &precision, 0)
+                    .is_none()
+            );
+
+            let mut limit = new_limit(2_500, 100, OrderDirection::Sell, 21, stranger_public_key);
+            limit.common.market_id = 7;
+            assert!(
+                spot_clearinghouse
+                    .handle_order(Order::Limit(limit), &precision, 0)
+                    .is_none()
+            );
+
+            let mut buy = new_market_buy(22, 2_500, stranger_public_key);
+            if let MarketOrder::Buy(buy) = &mut buy {
+                buy.common.market_id = 7;
+            }
+            assert!(
+                spot_clearinghouse
+                    .handle_order(Order::Market(buy), &precision, 0)
+                    .is_none()
+            );
+
+            assert_eq!(balances(&spot_clearinghouse), user_balances);
+            assert!(
+                !spot_clearinghouse
+                    .accounts
+                    .contains_key(&stranger_public_key)
+            );
+        }
+    }
 }
@@ -1,16 +1,52 @@
+use std::collections::{HashMap, VecDeque};
+
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config, log_error,
+    types::transaction::{PublicKeyHash, Sha256Hash},
+};
 
 use super::{
     asset::AssetId,
     order::{
         LimitFillResult, LimitOrder, MarketBuyOrder, MarketOrder, MarketOrderMatchingResults,
-        MarketSellOrder, OrderDirection, OrderPriceMultiple, OrderStatus, ResidualOrder,
+        MarketSellOrder, OrderDirection, OrderId, OrderPriceMultiple, OrderStatus, ResidualOrder,
         UserExecutionResult,
     },
-    spot_clearinghouse::{MarketId, MarketPrecision, base_to_quote_lots, quote_lots_to_base_lots},
+    receipt::TopOfBookEvent,
+    spot_clearinghouse::{
+        MarketId, MarketPrecision, average_price_multiple, base_to_quote_lots,
+        quote_lots_to_base_lots,
+    },
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Units per lot of a market's base and quote asset, `None` uses the asset's own lot size
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LotSizes {
+    pub base: Option<u32>,
+    pub quote: Option<u32>,
+}
+
+/// Operations a market accepts, switched by the admin with a `SetMarketStatus` transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarketStatus {
+    #[default]
+    Active,
+    /// No new orders and no cancels
+    Halted,
+    /// Resting orders can be cancelled or reduced, but no new orders are placed
+    CancelOnly,
+}
+
+impl MarketStatus {
+    pub fn accepts_cancels(&self) -> bool {
+        *self != MarketStatus::Halted
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct LevelInfo {
     pub price: u64,
     pub volume: u64,
@@ -23,21 +59,186 @@ pub struct MarketInfo {
     pub last_executed_price: Option<u64>,
     pub best_asks_info: Option<LevelInfo>,
     pub best_bids_info: Option<LevelInfo>,
+    // in ticks, mid_price and spread need both sides of the book
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+    pub mid_price: Option<u64>, // rounded down to a whole tick
+    pub spread: Option<u64>,
     pub tick: u32,
     pub tick_decimals: u8,
     pub base_name: String,
     pub quote_name: String,
     pub base_asset_id: AssetId,
     pub quote_asset_id: AssetId,
+    pub base_volume_lots: u128,
+    pub quote_volume_lots: u128,
+    pub trade_count: u64,
+    pub status: MarketStatus,
+}
+
+/// Largest number of price levels returned per side by a depth query
+pub const MAX_ORDER_BOOK_DEPTH: usize = 100;
+
+/// Most best price changes a market remembers, the oldest are dropped first
+pub const BEST_PRICE_HISTORY_CAPACITY: usize = 1_000;
+
+/// Human readable price of `price_multiple` ticks of `tick` units, each unit being
+/// 10^-`tick_decimals`
+pub fn format_price(price_multiple: u64, tick: u32, tick_decimals: u8) -> String {
+    let raw_price_units = price_multiple as u128 * tick as u128;
+    let factor = 10u128.pow(tick_decimals as u32);
+    let whole = raw_price_units / factor;
+    if tick_decimals == 0 {
+        return whole.to_string();
+    }
+
+    let fractional = raw_price_units % factor;
+    format!(
+        "{}.{:0>width$}",
+        whole,
+        fractional,
+        width = tick_decimals as usize
+    )
+}
+
+/// Inverse of `format_price`. Digits past `tick_decimals` are rounded half up, and the
+/// price must then be a whole number of ticks
+pub fn parse_price(price: &str, tick: u32, tick_decimals: u8) -> Option<u64> {
+    let price = price.trim();
+    let (whole, fractional) = price.split_once('.').unwrap_or((price, ""));
+    let is_digits = |digits: &str| digits.bytes().all(|digit| digit.is_ascii_digit());
+    if (whole.is_empty() && fractional.is_empty()) || !is_digits(whole) || !is_digits(fractional) {
+        return None;
+    }
+
+    let decimals = tick_decimals as usize;
+    let kept = &fractional[..fractional.len().min(decimals)];
+    let mut raw_price_units: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    raw_price_units = raw_price_units.checked_mul(10u128.pow(tick_decimals as u32))?;
+    if !kept.is_empty() {
+        let scale = 10u128.pow((decimals - kept.len()) as u32);
+        raw_price_units += kept.parse::<u128>().ok()? * scale;
+    }
+    if fractional
+        .as_bytes()
+        .get(decimals)
+        .is_some_and(|digit| *digit >= b'5')
+    {
+        raw_price_units += 1;
+    }
+
+    let tick = tick as u128;
+    if tick == 0 || raw_price_units % tick != 0 {
+        return None; // not an exact multiple
+    }
+    u64::try_from(raw_price_units / tick).ok()
+}
+
+/// Aggregated price levels on each side of the book, best price first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderBookDepth {
+    pub market_id: MarketId,
+    pub bids: Vec<LevelInfo>,
+    pub asks: Vec<LevelInfo>,
+}
+
+/// Single executable price for a size, as a taker would get it from the book right now
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FirmQuote {
+    pub market_id: MarketId,
+    pub direction: OrderDirection,
+    // requested size, in base lots
+    pub base_lots: u64,
+    // at most `base_lots`, less if the book runs out
+    pub available_base_lots: u64,
+    // quote lots paid or received for the available size
+    pub quote_lots: u64,
+    // in ticks, rounded to the nearest tick
+    pub average_price: u64,
+    pub fully_available: bool,
+}
+
+/// Best prices of a market after a change, in ticks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BestPriceSnapshot {
+    // view of the block that moved them
+    pub view: u64,
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Level {
     pub price: u64,
     pub volume: u64,
     pub orders: Vec<LimitOrder>,
     pub cancelled: u32,
 }
+
+impl Level {
+    /// Counts `cancelled` cancelled orders leaving the level through matching
+    fn drain_cancelled(&mut self, cancelled: u32) {
+        self.cancelled = self.cancelled.saturating_sub(cancelled);
+        self.check_cancelled();
+    }
+
+    /// Takes the first `end` orders, which matching has gone past, out of the level. The all
+    /// or none orders at `skipped` stay at the front of the level in their original order
+    fn take_matched(&mut self, end: usize, skipped: &[usize]) -> Vec<LimitOrder> {
+        if skipped.is_empty() {
+            return self.orders.drain(..end).collect();
+        }
+
+        let (kept, matched): (Vec<_>, Vec<_>) = self
+            .orders
+            .drain(..end)
+            .enumerate()
+            .partition(|(index, _)| skipped.binary_search(index).is_ok());
+        self.orders
+            .splice(0..0, kept.into_iter().map(|(_, order)| order));
+        matched.into_iter().map(|(_, order)| order).collect()
+    }
+
+    /// The cancelled count can never exceed the orders still resting in the level
+    fn check_cancelled(&self) {
+        if self.cancelled as usize <= self.orders.len() {
+            return;
+        }
+
+        let message = format!(
+            "Level at price {} counts {} cancelled orders but holds {}",
+            self.price,
+            self.cancelled,
+            self.orders.len()
+        );
+        if cfg!(debug_assertions) {
+            panic!("{}", message);
+        }
+        log_error!("{}", message);
+    }
+}
+#[derive(Serialize)]
+struct HashableMarket<'a> {
+    market_id: MarketId,
+    base_asset: AssetId,
+    quote_asset: AssetId,
+    tick: u32,
+    tick_decimals: u8,
+    lot_sizes: LotSizes,
+    last_executed_price: Option<u64>,
+    base_volume_lots: u128,
+    quote_volume_lots: u128,
+    trade_count: u64,
+    status: MarketStatus,
+    bids_levels: &'a Vec<Level>,
+    asks_levels: &'a Vec<Level>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SpotMarket {
     pub market_id: MarketId,
     pub asset_one: AssetId,
@@ -49,11 +250,38 @@ pub struct SpotMarket {
     pub tick: u32,
     pub tick_decimals: u8,
     pub last_executed_price: Option<u64>,
-    // pub lot_size: (),
+    // lifetime totals over fills against resting orders, self fills excluded
+    pub base_volume_lots: u128,
+    pub quote_volume_lots: u128,
+    pub trade_count: u64,
+    // priority handed to the next order placed, see `take_priority`
+    pub next_priority: u64,
+    pub lot_sizes: LotSizes,
+    pub status: MarketStatus,
+    // recent best price changes, oldest first, see `record_best_prices`
+    best_price_history: VecDeque<BestPriceSnapshot>,
+    // taker fees charged since the ledger last swept them into the fee collector, in base
+    // and quote units. Always zero between blocks
+    accrued_base_fees: u128,
+    accrued_quote_fees: u128,
 
     // levels are in reverse order, best prices are at the end
     pub bids_levels: Vec<Level>, // 0, 1, 2, ..
     pub asks_levels: Vec<Level>, // 10, 9, 8, ..
+
+    // percentage of a level's orders that may be cancelled before the level is compacted,
+    // set from the ledger parameters when the market is added
+    pub(crate) prune_threshold: u32,
+
+    // (level index, order index) of resting orders on either side, as of when they were last
+    // placed or moved. Fills and new levels shift positions, so entries are only hints that
+    // cancels revalidate against the book. Rebuilt lazily after a restore
+    #[serde(skip)]
+    order_locations: HashMap<OrderId, (usize, usize)>,
+
+    // best price changes not yet taken by the ledger, see `take_top_of_book_events`
+    #[serde(skip)]
+    top_of_book_events: Vec<TopOfBookEvent>,
 }
 
 impl SpotMarket {
@@ -66,6 +294,7 @@ impl SpotMarket {
         quote_asset_name: String,
         tick: u32,
         tick_decimals: u8,
+        lot_sizes: LotSizes,
     ) -> Self {
         Self {
             market_id,
@@ -75,15 +304,32 @@ impl SpotMarket {
             quote_asset,
             bids_levels: vec![],
             asks_levels: vec![],
+            prune_threshold: config::DEFAULT_LEVEL_PRUNE_THRESHOLD,
+            order_locations: HashMap::new(),
+            top_of_book_events: vec![],
+            best_price_history: VecDeque::new(),
+            accrued_base_fees: 0,
+            accrued_quote_fees: 0,
             tick,
             tick_decimals,
             last_executed_price: None,
+            base_volume_lots: 0,
+            quote_volume_lots: 0,
+            trade_count: 0,
+            next_priority: 0,
+            lot_sizes,
+            status: MarketStatus::Active,
             base_asset_name: base_asset_name,
             quote_asset_name: quote_asset_name.to_string(),
         }
     }
 
-    fn add_order_with_cmp<F>(levels: &mut Vec<Level>, order: LimitOrder, mut compare: F)
+    /// Returns where the order was placed as (level index, order index)
+    fn add_order_with_cmp<F>(
+        levels: &mut Vec<Level>,
+        order: LimitOrder,
+        mut compare: F,
+    ) -> (usize, usize)
     where
         F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
     {
@@ -96,9 +342,11 @@ impl SpotMarket {
             let mid_price = levels[mid].price;
 
             if price == mid_price {
-                levels[mid].volume += order.base_lots - order.filled_base_lots;
-                levels[mid].orders.push(order);
-                return;
+                levels[mid].volume += order.get_order_remaining();
+                return (
+                    mid,
+                    Self::insert_by_priority(&mut levels[mid].orders, order),
+                );
             } else {
                 if compare(price, mid_price) == std::cmp::Ordering::Less {
                     right = mid;
@@ -112,37 +360,37 @@ impl SpotMarket {
             left,
             Level {
                 price,
-                volume: order.base_lots - order.filled_base_lots,
+                volume: order.get_order_remaining(),
                 orders: vec![order],
                 cancelled: 0,
             },
-        )
+        );
+        (left, 0)
     }
 
-    fn mark_order_as_cancelled(orders: &mut Vec<LimitOrder>, order: &LimitOrder) -> bool {
-        let order_id = order.common.id;
-        let mut left = 0;
-        let mut right = orders.len();
-        while left < right {
-            let mid = left + (right - left) / 2;
-            let mid_id = orders[mid].common.id;
-
-            if order_id == mid_id {
-                if orders[mid].common.status == OrderStatus::Cancelled {
-                    return false;
-                }
-                orders[mid].common.status = OrderStatus::Cancelled;
-                return true;
-            } else if order_id < mid_id {
-                right = mid;
-            } else {
-                left = mid + 1;
+    /// Keeps `orders` in time priority, which matching drains from the front and
+    /// `mark_order_as_cancelled` relies on. Orders of equal priority keep insertion order.
+    fn insert_by_priority(orders: &mut Vec<LimitOrder>, order: LimitOrder) -> usize {
+        let priority = order.priority;
+        match orders.last() {
+            Some(last) if last.priority > priority => {
+                let index = orders.partition_point(|o| o.priority <= priority);
+                orders.insert(index, order);
+                index
+            }
+            _ => {
+                orders.push(order);
+                orders.len() - 1
             }
         }
-        return false;
     }
 
-    fn cancel_order_with_cmp<F>(levels: &mut Vec<Level>, order: &LimitOrder, mut compare: F) -> u64
+    /// Finds `order` by binary searching for its price level, then its priority within the level
+    fn search_order<F>(
+        levels: &[Level],
+        order: &LimitOrder,
+        mut compare: F,
+    ) -> Option<(usize, usize)>
     where
         F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
     {
@@ -155,59 +403,172 @@ impl SpotMarket {
             let mid_price = levels[mid].price;
 
             if price == mid_price {
-                let level = &mut levels[mid];
-                if !Self::mark_order_as_cancelled(&mut level.orders, order) {
-                    return 0;
-                }
+                let orders = &levels[mid].orders;
+                let start = orders.partition_point(|o| o.priority < order.priority);
+                let offset = orders[start..]
+                    .iter()
+                    .take_while(|o| o.priority == order.priority)
+                    .position(|o| o.common.id == order.common.id)?;
+                return Some((mid, start + offset));
+            } else if compare(price, mid_price) == std::cmp::Ordering::Less {
+                right = mid;
+            } else {
+                left = mid + 1;
+            }
+        }
+        None
+    }
 
-                level.cancelled += 1;
-                let unfilled_size = order.base_lots - order.filled_base_lots;
-                level.volume -= unfilled_size;
+    /// Position of `order` in `levels`, taken from `order_locations` when the entry still points
+    /// at the order and searched for otherwise
+    fn locate_order<F>(
+        levels: &[Level],
+        order_locations: &HashMap<OrderId, (usize, usize)>,
+        order: &LimitOrder,
+        compare: F,
+    ) -> Option<(usize, usize)>
+    where
+        F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
+    {
+        let id = order.common.id;
+        if let Some(&(level_index, order_index)) = order_locations.get(&id) {
+            let resting = levels
+                .get(level_index)
+                .and_then(|level| level.orders.get(order_index));
+            if resting.is_some_and(|resting| resting.common.id == id) {
+                return Some((level_index, order_index));
+            }
+        }
+        Self::search_order(levels, order, compare)
+    }
 
-                if level.volume == 0 {
-                    levels.remove(mid);
-                } else if level.cancelled > (level.orders.len() / 2) as u32 {
-                    // prune when vector is sparse enough
-                    level
-                        .orders
-                        .retain(|order| order.common.status != OrderStatus::Cancelled);
-                    level.cancelled = 0;
-                }
+    fn cancel_order_with_cmp<F>(
+        levels: &mut Vec<Level>,
+        order_locations: &mut HashMap<OrderId, (usize, usize)>,
+        prune_threshold: u32,
+        order: &LimitOrder,
+        compare: F,
+    ) -> u64
+    where
+        F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
+    {
+        let Some((level_index, order_index)) =
+            Self::locate_order(levels, order_locations, order, compare)
+        else {
+            return 0;
+        };
 
-                return unfilled_size;
-            } else {
-                if compare(price, mid_price) == std::cmp::Ordering::Less {
-                    right = mid;
-                } else {
-                    left = mid + 1;
-                }
+        let level = &mut levels[level_index];
+        let resting = &mut level.orders[order_index];
+        if resting.common.status == OrderStatus::Cancelled {
+            return 0;
+        }
+        resting.common.status = OrderStatus::Cancelled;
+        order_locations.remove(&order.common.id);
+
+        level.cancelled += 1;
+        let unfilled_size = order.get_order_remaining();
+        level.volume -= unfilled_size;
+
+        if level.volume == 0 {
+            for order in levels.remove(level_index).orders {
+                order_locations.remove(&order.common.id);
+            }
+        } else if level.cancelled as u64 * 100 > level.orders.len() as u64 * prune_threshold as u64
+        {
+            // prune when vector is sparse enough
+            level
+                .orders
+                .retain(|order| order.common.status != OrderStatus::Cancelled);
+            level.cancelled = 0;
+            for (index, order) in level.orders.iter().enumerate() {
+                order_locations.insert(order.common.id, (level_index, index));
             }
+        } else {
+            level.check_cancelled();
+        }
+
+        unfilled_size
+    }
+
+    /// Shrinks a resting order in place, keeping its priority. Only reductions short of the
+    /// unfilled remainder are applied, a full reduction is a cancel
+    fn reduce_order_with_cmp<F>(
+        levels: &mut [Level],
+        order_locations: &HashMap<OrderId, (usize, usize)>,
+        order: &LimitOrder,
+        base_lots: u64,
+        compare: F,
+    ) -> bool
+    where
+        F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
+    {
+        let Some((level_index, order_index)) =
+            Self::locate_order(levels, order_locations, order, compare)
+        else {
+            return false;
+        };
+
+        let level = &mut levels[level_index];
+        let resting = &mut level.orders[order_index];
+        if resting.common.status == OrderStatus::Cancelled
+            || base_lots >= resting.get_order_remaining()
+        {
+            return false;
         }
-        return 0;
+        resting.base_lots -= base_lots;
+        level.volume -= base_lots;
+        true
+    }
+
+    /// Drops the index entries of orders that have left the book through matching
+    fn forget_orders(&mut self, orders: &[LimitOrder]) {
+        for order in orders {
+            self.order_locations.remove(&order.common.id);
+        }
+    }
+
+    /// Stamps the next order placed in the market, later orders get a higher priority
+    pub(crate) fn take_priority(&mut self) -> u64 {
+        let priority = self.next_priority;
+        self.next_priority += 1;
+        priority
     }
 
     pub fn add_bid(&mut self, order: LimitOrder) {
-        Self::add_order_with_cmp(&mut self.bids_levels, order, |a, b| {
+        let id = order.common.id;
+        let location = Self::add_order_with_cmp(&mut self.bids_levels, order, |a, b| {
             a.partial_cmp(&b).unwrap()
         });
+        self.order_locations.insert(id, location);
     }
 
     pub fn add_ask(&mut self, order: LimitOrder) {
-        Self::add_order_with_cmp(&mut self.asks_levels, order, |a, b| {
+        let id = order.common.id;
+        let location = Self::add_order_with_cmp(&mut self.asks_levels, order, |a, b| {
             b.partial_cmp(&a).unwrap()
         });
+        self.order_locations.insert(id, location);
     }
 
     pub fn cancel_bid(&mut self, order: &LimitOrder) -> u64 {
-        Self::cancel_order_with_cmp(&mut self.bids_levels, order, |a, b| {
-            a.partial_cmp(&b).unwrap()
-        })
+        Self::cancel_order_with_cmp(
+            &mut self.bids_levels,
+            &mut self.order_locations,
+            self.prune_threshold,
+            order,
+            |a, b| a.partial_cmp(&b).unwrap(),
+        )
     }
 
     pub fn cancel_ask(&mut self, order: &LimitOrder) -> u64 {
-        Self::cancel_order_with_cmp(&mut self.asks_levels, order, |a, b| {
-            b.partial_cmp(&a).unwrap()
-        })
+        Self::cancel_order_with_cmp(
+            &mut self.asks_levels,
+            &mut self.order_locations,
+            self.prune_threshold,
+            order,
+            |a, b| b.partial_cmp(&a).unwrap(),
+        )
     }
 
     pub fn execute_limit<F>(
@@ -231,77 +592,78 @@ impl SpotMarket {
 
         let order_price = order.price_multiple;
         let mut remaining_base_amount = order.base_lots;
-        while !levels.is_empty() && remaining_base_amount > 0 {
-            let level = levels.last_mut();
-            let Some(level) = level else {
+        // levels are walked from the best price down, levels left holding only skipped all or
+        // none orders stay where they are
+        let mut level_index = levels.len();
+        while level_index > 0 && remaining_base_amount > 0 {
+            level_index -= 1;
+            let level = &mut levels[level_index];
+            let level_price = level.price;
+            if compare(order_price, level_price) == std::cmp::Ordering::Greater {
                 break;
-            };
+            }
 
-            let level_price = level.price;
-            let level_filled = remaining_base_amount;
+            let mut level_filled = 0;
             let mut cancelled_seen = 0;
+            let mut skipped = vec![];
 
-            match compare(order_price, level_price) {
-                std::cmp::Ordering::Less | std::cmp::Ordering::Equal => {
-                    // fill the current level orders
-                    let mut to_drain_end_index = 0;
-                    for order in level.orders.iter_mut() {
-                        if order.common.status == OrderStatus::Cancelled {
-                            cancelled_seen += 1;
-                            to_drain_end_index += 1;
-                            continue;
-                        }
-
-                        let order_remaining = order.get_order_remaining();
+            // fill the current level orders
+            let mut to_drain_end_index = 0;
+            for (index, order) in level.orders.iter_mut().enumerate() {
+                if order.common.status == OrderStatus::Cancelled {
+                    cancelled_seen += 1;
+                    to_drain_end_index += 1;
+                    continue;
+                }
 
-                        let curr_filled_base_amount = remaining_base_amount.min(order_remaining);
-                        remaining_base_amount -= curr_filled_base_amount;
-                        last_executed_price = Some(level_price);
+                let order_remaining = order.get_order_remaining();
+                if order.all_or_none && order_remaining > remaining_base_amount {
+                    skipped.push(index);
+                    to_drain_end_index += 1;
+                    continue;
+                }
 
-                        if is_buy {
-                            lots_in += curr_filled_base_amount;
-                            lots_out +=
-                                base_to_quote_lots(curr_filled_base_amount, level_price, precision);
-                        } else {
-                            lots_out += curr_filled_base_amount;
-                            lots_in +=
-                                base_to_quote_lots(curr_filled_base_amount, level_price, precision);
-                        }
+                let curr_filled_base_amount = remaining_base_amount.min(order_remaining);
+                remaining_base_amount -= curr_filled_base_amount;
+                level_filled += curr_filled_base_amount;
+                last_executed_price = Some(level_price);
 
-                        if curr_filled_base_amount == order_remaining {
-                            // Include current index
-                            to_drain_end_index += 1;
-                        }
+                if is_buy {
+                    lots_in += curr_filled_base_amount;
+                    lots_out += base_to_quote_lots(curr_filled_base_amount, level_price, precision);
+                } else {
+                    lots_out += curr_filled_base_amount;
+                    lots_in += base_to_quote_lots(curr_filled_base_amount, level_price, precision);
+                }
 
-                        if remaining_base_amount <= 0 {
-                            if curr_filled_base_amount < order_remaining {
-                                // Partial fill
-                                residual_order = Some(ResidualOrder {
-                                    order_id: order.common.id,
-                                    price_multiple: level_price,
-                                    account_public_key: order.common.account,
-                                    filled_base_lots: curr_filled_base_amount,
-                                    self_fill: 0,
-                                });
-                                order.filled_base_lots += curr_filled_base_amount;
-                            }
-                            break;
-                        }
-                    }
+                if curr_filled_base_amount == order_remaining {
+                    // Include current index
+                    to_drain_end_index += 1;
+                }
 
-                    if to_drain_end_index < level.orders.len() {
-                        filled_orders
-                            .append(&mut level.orders.drain(0..to_drain_end_index).collect());
-                        level.volume -= level_filled;
-                        level.cancelled -= cancelled_seen;
-                        break;
+                if remaining_base_amount <= 0 {
+                    if curr_filled_base_amount < order_remaining {
+                        // Partial fill
+                        residual_order = Some(ResidualOrder {
+                            order_id: order.common.id,
+                            price_multiple: level_price,
+                            account_public_key: order.common.account,
+                            filled_base_lots: curr_filled_base_amount,
+                            self_fill: 0,
+                        });
+                        order.filled_base_lots += curr_filled_base_amount;
                     }
-                    // reached the end of the level without fully filling the order
-                    // remove this level from the orderbook
-                    filled_orders.append(&mut level.orders);
-                    levels.pop();
+                    break;
                 }
-                _ => break,
+            }
+
+            filled_orders.append(&mut level.take_matched(to_drain_end_index, &skipped));
+            if level.orders.is_empty() {
+                // the order consumed the whole level, remove it from the orderbook
+                levels.remove(level_index);
+            } else {
+                level.volume -= level_filled;
+                level.drain_cancelled(cancelled_seen);
             }
         }
 
@@ -323,6 +685,37 @@ impl SpotMarket {
         };
     }
 
+    /// Lots a limit order at `price` could take from `levels` right now, skipping the all or
+    /// none orders it cannot fill whole just as `execute_limit` would
+    fn fillable_base_lots<F>(
+        levels: &[Level],
+        price: OrderPriceMultiple,
+        base_lots: u64,
+        mut compare: F,
+    ) -> u64
+    where
+        F: FnMut(OrderPriceMultiple, OrderPriceMultiple) -> std::cmp::Ordering,
+    {
+        let mut remaining = base_lots;
+        for level in levels.iter().rev() {
+            if remaining == 0 || compare(price, level.price) == std::cmp::Ordering::Greater {
+                break;
+            }
+            let resting = level
+                .orders
+                .iter()
+                .filter(|order| order.common.status != OrderStatus::Cancelled);
+            for order in resting {
+                let order_remaining = order.get_order_remaining();
+                if order.all_or_none && order_remaining > remaining {
+                    continue;
+                }
+                remaining -= remaining.min(order_remaining);
+            }
+        }
+        base_lots - remaining
+    }
+
     pub fn execute_market_buy_order(
         levels: &mut Vec<Level>,
         buy_order: MarketBuyOrder,
@@ -335,18 +728,20 @@ impl SpotMarket {
         let mut residual_order: Option<ResidualOrder> = None;
         let mut base_lots_in: u64 = 0;
         let mut self_fill_quotes: u64 = 0;
+        let mut self_fill_base_lots: u64 = 0;
         let mut remaining_quote_lots = buy_order.quote_size;
         let mut last_executed_price: Option<u64> = None;
+        let mut priced_out = false;
 
-        while !levels.is_empty() && remaining_quote_lots > 0 {
-            let level = levels.last_mut();
-            let Some(level) = level else {
-                break;
-            };
+        let mut level_index = levels.len();
+        while level_index > 0 && remaining_quote_lots > 0 {
+            level_index -= 1;
+            let level = &mut levels[level_index];
 
             let mut level_filled = 0;
             let level_price = level.price;
             let mut level_cancelled = 0;
+            let mut skipped = vec![];
 
             let mut to_drain_end_index = 0;
 
@@ -355,10 +750,11 @@ impl SpotMarket {
                 quote_lots_to_base_lots(remaining_quote_lots, level_price, &precision);
 
             if remaining_base_lots == 0 {
+                priced_out = true;
                 break;
             }
 
-            for order in level.orders.iter_mut() {
+            for (index, order) in level.orders.iter_mut().enumerate() {
                 if order.common.status == OrderStatus::Cancelled {
                     level_cancelled += 1;
                     to_drain_end_index += 1;
@@ -366,6 +762,11 @@ impl SpotMarket {
                 }
 
                 let order_base_remaining = order.get_order_remaining();
+                if order.all_or_none && order_base_remaining > remaining_base_lots {
+                    skipped.push(index);
+                    to_drain_end_index += 1;
+                    continue;
+                }
 
                 if order.common.account == buy_order.common.account {
                     // self trade
@@ -375,6 +776,7 @@ impl SpotMarket {
 
                     order.self_filled += reduce_base;
                     self_fill_quotes += reduce_quote;
+                    self_fill_base_lots += reduce_base;
 
                     remaining_base_lots -= reduce_base;
                     remaining_quote_lots -= reduce_quote;
@@ -437,24 +839,25 @@ impl SpotMarket {
                 }
             }
 
-            if to_drain_end_index < level.orders.len() {
-                filled_orders.append(&mut level.orders.drain(0..to_drain_end_index).collect());
+            filled_orders.append(&mut level.take_matched(to_drain_end_index, &skipped));
+            if level.orders.is_empty() {
+                // the order consumed the whole level, remove it from the orderbook
+                levels.remove(level_index);
+            } else {
                 level.volume -= level_filled;
-                level.cancelled -= level_cancelled;
-                break;
+                level.drain_cancelled(level_cancelled);
             }
-            // reached the end of the level without fully filling the order
-            // remove this level from the orderbook
-            filled_orders.append(&mut level.orders);
-            levels.pop();
         }
 
         // Return execution results for clearinghouse to settle
         return MarketOrderMatchingResults::Buy {
+            unfilled_quote_lots: remaining_quote_lots,
+            book_exhausted: !priced_out && level_index == 0 && remaining_quote_lots > 0,
             quote_filled_lots: buy_order.quote_size - remaining_quote_lots - self_fill_quotes,
             base_lots_in,
             filled_orders,
             self_fill: self_fill_quotes,
+            self_fill_base_lots,
             residual_order,
             order_id: buy_order.common.id,
             last_executed_price,
@@ -474,21 +877,22 @@ impl SpotMarket {
         let mut maker_partial_fill: Option<ResidualOrder> = None;
         let mut quote_lots_in: u64 = 0;
         let mut self_fill: u64 = 0;
+        let mut self_fill_quote_lots: u64 = 0;
         let mut last_executed_price: Option<u64> = None;
 
         let mut remaining_base_lots = sell_order.base_size;
 
-        while !levels.is_empty() && remaining_base_lots > 0 {
-            let level = levels.last_mut();
-            let Some(level) = level else {
-                break;
-            };
+        let mut level_index = levels.len();
+        while level_index > 0 && remaining_base_lots > 0 {
+            level_index -= 1;
+            let level = &mut levels[level_index];
             let level_price = level.price;
-            let level_filled = remaining_base_lots;
+            let level_remaining_before = remaining_base_lots;
             let mut cancelled_seen = 0;
+            let mut skipped = vec![];
 
             let mut to_drain_end_index = 0;
-            for order in level.orders.iter_mut() {
+            for (index, order) in level.orders.iter_mut().enumerate() {
                 if order.common.status == OrderStatus::Cancelled {
                     cancelled_seen += 1;
                     to_drain_end_index += 1;
@@ -496,11 +900,17 @@ impl SpotMarket {
                 }
 
                 let order_remaining = order.get_order_remaining();
+                if order.all_or_none && order_remaining > remaining_base_lots {
+                    skipped.push(index);
+                    to_drain_end_index += 1;
+                    continue;
+                }
 
                 if order.common.account == sell_order.common.account {
                     // self trade
                     let reduce = remaining_base_lots.min(order_remaining);
                     self_fill += reduce;
+                    self_fill_quote_lots += base_to_quote_lots(reduce, level_price, precision);
                     remaining_base_lots -= reduce;
                     order.self_filled += reduce;
 
@@ -554,25 +964,26 @@ impl SpotMarket {
                 }
             }
 
-            if to_drain_end_index < level.orders.len() {
-                filled_orders.append(&mut level.orders.drain(0..to_drain_end_index).collect());
-                level.volume -= level_filled;
-                level.cancelled -= cancelled_seen;
-                break;
+            filled_orders.append(&mut level.take_matched(to_drain_end_index, &skipped));
+            if level.orders.is_empty() {
+                // the order consumed the whole level, remove it from the orderbook
+                levels.remove(level_index);
+            } else {
+                level.volume -= level_remaining_before - remaining_base_lots;
+                level.drain_cancelled(cancelled_seen);
             }
-            // reached the end of the level without fully filling the order
-            // remove this level from the orderbook
-            filled_orders.append(&mut level.orders);
-            levels.pop();
         }
 
         // Return execution results for clearinghouse to settle
         return MarketOrderMatchingResults::Sell {
+            unfilled_base_lots: remaining_base_lots,
+            book_exhausted: level_index == 0 && remaining_base_lots > 0,
             filled_orders,
             residual_order: maker_partial_fill,
             base_filled_lots: sell_order.base_size - remaining_base_lots - self_fill,
             quote_lots_in,
             self_fill,
+            self_fill_quote_lots,
             order_id: sell_order.common.id,
             last_executed_price,
         };
@@ -583,6 +994,7 @@ impl SpotMarket {
         order: MarketOrder,
         precision: &MarketPrecision,
     ) -> MarketOrderMatchingResults {
+        let best_prices = self.get_best_prices();
         let result = match order {
             MarketOrder::Sell(sell_order) => {
                 Self::execute_market_sell_order(&mut self.bids_levels, sell_order, precision)
@@ -592,10 +1004,115 @@ impl SpotMarket {
             }
         };
         self.set_last_executed_price(result.get_last_executed_price());
+        let (filled_orders, residual_order) = result.get_maker_fills();
+        self.forget_orders(filled_orders);
+        self.record_trades(filled_orders, residual_order, precision);
+        self.check_not_crossed();
+        self.record_top_of_book(best_prices);
         result
     }
 
     pub fn add_limit_order(
+        &mut self,
+        order: LimitOrder,
+        base_asset: AssetId,
+        quote_asset: AssetId,
+        precision: &MarketPrecision,
+    ) -> Option<LimitFillResult> {
+        let best_prices = self.get_best_prices();
+        let result = self.match_limit_order(order, base_asset, quote_asset, precision);
+        if let Some(result) = &result {
+            self.forget_orders(&result.filled_orders);
+            self.record_trades(&result.filled_orders, &result.residual_order, precision);
+        }
+        self.check_not_crossed();
+        self.record_top_of_book(best_prices);
+        result
+    }
+
+    /// Queues a top of book event if the best prices moved away from `previous`
+    fn record_top_of_book(&mut self, previous: (Option<u64>, Option<u64>)) {
+        let (best_bid, best_ask) = self.get_best_prices();
+        if (best_bid, best_ask) == previous {
+            return;
+        }
+        self.top_of_book_events.push(TopOfBookEvent {
+            market_id: self.market_id,
+            best_bid,
+            best_ask,
+        });
+    }
+
+    /// Best price changes since the last call, oldest first
+    pub fn take_top_of_book_events(&mut self) -> Vec<TopOfBookEvent> {
+        std::mem::take(&mut self.top_of_book_events)
+    }
+
+    /// Appends the best prices as of `view` to the history, dropping the oldest entry once
+    /// `BEST_PRICE_HISTORY_CAPACITY` is reached
+    pub fn record_best_prices(&mut self, view: u64, best_bid: Option<u64>, best_ask: Option<u64>) {
+        if self.best_price_history.len() == BEST_PRICE_HISTORY_CAPACITY {
+            self.best_price_history.pop_front();
+        }
+        self.best_price_history.push_back(BestPriceSnapshot {
+            view,
+            best_bid,
+            best_ask,
+        });
+    }
+
+    /// Recent best price changes, oldest first
+    pub fn get_best_price_history(&self) -> Vec<BestPriceSnapshot> {
+        self.best_price_history.iter().copied().collect()
+    }
+
+    /// Adds each maker order filled by a taker to the lifetime volume and trade count.
+    /// Cancelled makers and fully self-filled makers did not trade.
+    fn record_trades(
+        &mut self,
+        filled_orders: &[LimitOrder],
+        residual_order: &Option<ResidualOrder>,
+        precision: &MarketPrecision,
+    ) {
+        let filled = filled_orders
+            .iter()
+            .filter(|order| order.common.status != OrderStatus::Cancelled)
+            .map(|order| (order.get_order_remaining(), order.price_multiple));
+        let residual = residual_order
+            .iter()
+            .map(|residual| (residual.filled_base_lots, residual.price_multiple));
+
+        for (base_lots, price) in filled.chain(residual).filter(|(lots, _)| *lots > 0) {
+            self.base_volume_lots += base_lots as u128;
+            self.quote_volume_lots += base_to_quote_lots(base_lots, price, precision) as u128;
+            self.trade_count += 1;
+        }
+    }
+
+    /// An all or none order only takes from the book if it is filled whole right away,
+    /// otherwise it rests untouched. Other orders can always match
+    fn can_match_whole(&self, order: &LimitOrder) -> bool {
+        if !order.all_or_none {
+            return true;
+        }
+        let fillable = match order.common.direction {
+            OrderDirection::Buy => Self::fillable_base_lots(
+                &self.asks_levels,
+                order.price_multiple,
+                order.base_lots,
+                |a, b| b.partial_cmp(&a).unwrap(),
+            ),
+            OrderDirection::Sell => Self::fillable_base_lots(
+                &self.bids_levels,
+                order.price_multiple,
+                order.base_lots,
+                |a, b| a.partial_cmp(&b).unwrap(),
+            ),
+        };
+        fillable == order.base_lots
+    }
+
+    fn match_limit_order(
         &mut self,
         mut order: LimitOrder,
         base_asset: AssetId,
@@ -611,7 +1128,7 @@ impl SpotMarket {
                     return None;
                 };
 
-                if best_ask_price <= order.price_multiple {
+                if best_ask_price <= order.price_multiple && self.can_match_whole(&order) {
                     // Attempt to execute order at a better price
 
                     let result = Self::execute_limit(
@@ -643,7 +1160,7 @@ impl SpotMarket {
                     return None;
                 };
 
-                if best_bid_price >= order.price_multiple {
+                if best_bid_price >= order.price_multiple && self.can_match_whole(&order) {
                     // Attempt to execute order at a better price
                     let result = Self::execute_limit(
                         &mut self.bids_levels,
@@ -670,11 +1187,35 @@ impl SpotMarket {
         }
     }
 
-    pub fn cancel_order(&mut self, order: &LimitOrder) -> u64 {
+    /// Takes `base_lots` off a resting order, returning false if it isn't resting with more
+    /// than that left
+    pub fn reduce_order(&mut self, order: &LimitOrder, base_lots: u64) -> bool {
         match order.common.direction {
+            OrderDirection::Buy => Self::reduce_order_with_cmp(
+                &mut self.bids_levels,
+                &self.order_locations,
+                order,
+                base_lots,
+                |a, b| a.partial_cmp(&b).unwrap(),
+            ),
+            OrderDirection::Sell => Self::reduce_order_with_cmp(
+                &mut self.asks_levels,
+                &self.order_locations,
+                order,
+                base_lots,
+                |a, b| b.partial_cmp(&a).unwrap(),
+            ),
+        }
+    }
+
+    pub fn cancel_order(&mut self, order: &LimitOrder) -> u64 {
+        let best_prices = self.get_best_prices();
+        let unfilled_base = match order.common.direction {
             OrderDirection::Buy => self.cancel_bid(order),
             OrderDirection::Sell => self.cancel_ask(order),
-        }
+        };
+        self.record_top_of_book(best_prices);
+        unfilled_base
     }
 
     pub fn get_best_prices(&self) -> (Option<u64>, Option<u64>) {
@@ -684,6 +1225,49 @@ impl SpotMarket {
         (best_bid, best_ask)
     }
 
+    /// Invariant: the best bid is always strictly below the best ask, since an order
+    /// that reaches the other side is matched before any remainder rests. All or none
+    /// orders are left out, they may rest across the book until a taker can fill them whole
+    pub fn is_crossed(&self) -> bool {
+        match self.get_best_divisible_prices() {
+            (Some(best_bid), Some(best_ask)) => best_bid >= best_ask,
+            _ => false,
+        }
+    }
+
+    /// (best bid, best ask) among open orders that can be partially filled
+    fn get_best_divisible_prices(&self) -> (Option<u64>, Option<u64>) {
+        let best_price = |levels: &[Level]| {
+            levels
+                .iter()
+                .rev()
+                .find(|level| {
+                    level.orders.iter().any(|order| {
+                        order.common.status != OrderStatus::Cancelled && !order.all_or_none
+                    })
+                })
+                .map(|level| level.price)
+        };
+        (best_price(&self.bids_levels), best_price(&self.asks_levels))
+    }
+
+    /// Panics in debug builds and logs in release builds if the book is crossed
+    fn check_not_crossed(&self) {
+        if !self.is_crossed() {
+            return;
+        }
+
+        let (best_bid, best_ask) = self.get_best_divisible_prices();
+        let message = format!(
+            "Market {} order book is crossed: best bid {:?} >= best ask {:?}",
+            self.market_id, best_bid, best_ask
+        );
+        if cfg!(debug_assertions) {
+            panic!("{}", message);
+        }
+        log_error!("{}", message);
+    }
+
     pub fn get_last_executed_price(&self) -> Option<u64> {
         self.last_executed_price
     }
@@ -694,6 +1278,140 @@ impl SpotMarket {
         }
     }
 
+    /// Accounts with an order on either side of the book, cancelled ones included
+    pub fn get_resting_accounts(&self) -> impl Iterator<Item = &PublicKeyHash> {
+        self.bids_levels
+            .iter()
+            .chain(self.asks_levels.iter())
+            .flat_map(|level| level.orders.iter())
+            .map(|order| &order.common.account)
+    }
+
+    /// Same market with empty books, left in place of a market that was moved out
+    pub(crate) fn placeholder(&self) -> Self {
+        Self::new(
+            self.market_id,
+            (self.asset_one, self.asset_two),
+            self.base_asset,
+            self.quote_asset,
+            self.base_asset_name.clone(),
+            self.quote_asset_name.clone(),
+            self.tick,
+            self.tick_decimals,
+            self.lot_sizes,
+        )
+    }
+
+    pub(crate) fn accrue_fee(&mut self, asset_id: AssetId, fee: u128) {
+        if asset_id == self.base_asset {
+            self.accrued_base_fees += fee;
+        } else {
+            self.accrued_quote_fees += fee;
+        }
+    }
+
+    /// Fees accrued since the last call, as (asset id, amount) for base then quote
+    pub(crate) fn take_accrued_fees(&mut self) -> [(AssetId, u128); 2] {
+        [
+            (self.base_asset, std::mem::take(&mut self.accrued_base_fees)),
+            (
+                self.quote_asset,
+                std::mem::take(&mut self.accrued_quote_fees),
+            ),
+        ]
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        let hashable = HashableMarket {
+            market_id: self.market_id,
+            base_asset: self.base_asset,
+            quote_asset: self.quote_asset,
+            tick: self.tick,
+            tick_decimals: self.tick_decimals,
+            lot_sizes: self.lot_sizes,
+            last_executed_price: self.last_executed_price,
+            base_volume_lots: self.base_volume_lots,
+            quote_volume_lots: self.quote_volume_lots,
+            trade_count: self.trade_count,
+            status: self.status,
+            bids_levels: &self.bids_levels,
+            asks_levels: &self.asks_levels,
+        };
+
+        let encoded = bincode::serialize(&hashable).expect("bincode failed");
+        Sha256::digest(&encoded).into()
+    }
+
+    /// Returns up to `depth` levels per side, capped at `MAX_ORDER_BOOK_DEPTH`
+    pub fn get_depth(&self, depth: usize) -> OrderBookDepth {
+        let depth = depth.min(MAX_ORDER_BOOK_DEPTH);
+        let top_levels = |levels: &Vec<Level>| {
+            levels
+                .iter()
+                .rev()
+                .take(depth)
+                .map(|level| LevelInfo {
+                    price: level.price,
+                    volume: level.volume,
+                })
+                .collect()
+        };
+
+        OrderBookDepth {
+            market_id: self.market_id,
+            bids: top_levels(&self.bids_levels),
+            asks: top_levels(&self.asks_levels),
+        }
+    }
+
+    /// Walks the side a `direction` taker of `base_lots` would match against, without
+    /// touching the book. Fills are priced per resting order, as in matching. No account
+    /// is involved, so self trades are not accounted for
+    pub fn get_quote(
+        &self,
+        direction: OrderDirection,
+        base_lots: u64,
+        precision: &MarketPrecision,
+    ) -> FirmQuote {
+        let levels = match direction {
+            OrderDirection::Buy => &self.asks_levels,
+            OrderDirection::Sell => &self.bids_levels,
+        };
+
+        let mut remaining_base_lots = base_lots;
+        let mut quote_lots = 0;
+        let resting = levels.iter().rev().flat_map(|level| {
+            level
+                .orders
+                .iter()
+                .filter(|order| order.common.status != OrderStatus::Cancelled)
+                .map(move |order| (level.price, order.get_order_remaining(), order.all_or_none))
+        });
+        for (price, order_remaining, all_or_none) in resting {
+            if remaining_base_lots == 0 {
+                break;
+            }
+            // a taker this size would skip over it
+            if all_or_none && order_remaining > remaining_base_lots {
+                continue;
+            }
+            let filled = remaining_base_lots.min(order_remaining);
+            quote_lots += base_to_quote_lots(filled, price, precision);
+            remaining_base_lots -= filled;
+        }
+
+        let available_base_lots = base_lots - remaining_base_lots;
+        FirmQuote {
+            market_id: self.market_id,
+            direction,
+            base_lots,
+            available_base_lots,
+            quote_lots,
+            average_price: average_price_multiple(available_base_lots, quote_lots, precision),
+            fully_available: remaining_base_lots == 0,
+        }
+    }
+
     pub fn get_market_info(&self) -> MarketInfo {
         let best_ask_info = self.asks_levels.last().map(|level| LevelInfo {
             price: level.price,
@@ -705,18 +1423,32 @@ impl SpotMarket {
             volume: level.volume,
         });
 
+        let (best_bid, best_ask) = self.get_best_prices();
+        let (mid_price, spread) = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) if bid < ask => (Some(bid + (ask - bid) / 2), Some(ask - bid)),
+            _ => (None, None),
+        };
+
         MarketInfo {
             market_id: self.market_id,
             market_name: format!("{}/{}", self.base_asset_name, self.quote_asset_name),
             last_executed_price: self.last_executed_price,
             best_asks_info: best_ask_info,
             best_bids_info: best_bid_info,
+            best_bid,
+            best_ask,
+            mid_price,
+            spread,
             tick: self.tick,
             tick_decimals: self.tick_decimals,
             base_name: self.base_asset_name.clone(),
             quote_name: self.quote_asset_name.clone(),
             base_asset_id: self.base_asset,
             quote_asset_id: self.quote_asset,
+            base_volume_lots: self.base_volume_lots,
+            quote_volume_lots: self.quote_volume_lots,
+            trade_count: self.trade_count,
+            status: self.status,
         }
     }
 }
@@ -741,6 +1473,8 @@ mod tests {
             base_lots: lot_size,
             filled_base_lots: 0,
             self_filled: 0,
+            priority: id,
+            all_or_none: false,
             common: CommonOrderFields {
                 id,
                 market_id: 0,
@@ -815,8 +1549,89 @@ mod tests {
                 tick_decimals,
                 tick,
                 last_executed_price: None,
+                base_volume_lots: 0,
+                quote_volume_lots: 0,
+                trade_count: 0,
+                next_priority: 0,
+                status: MarketStatus::Active,
+                lot_sizes: LotSizes::default(),
                 base_asset_name: "".to_string(),
                 quote_asset_name: "".to_string(),
+                prune_threshold: config::DEFAULT_LEVEL_PRUNE_THRESHOLD,
+                order_locations: HashMap::new(),
+                top_of_book_events: vec![],
+                best_price_history: VecDeque::new(),
+                accrued_base_fees: 0,
+                accrued_quote_fees: 0,
+            }
+        }
+    }
+
+    mod test_price_format {
+        use crate::state::spot_market::{format_price, parse_price};
+
+        #[test]
+        fn test_format_price() {
+            assert_eq!(format_price(2_500, 100, 2), "2500.00");
+            assert_eq!(format_price(2_505, 1, 2), "25.05");
+            assert_eq!(format_price(7, 5, 3), "0.035");
+            assert_eq!(format_price(42, 1, 0), "42");
+            assert_eq!(format_price(3, 25, 1), "7.5");
+            assert_eq!(format_price(0, 100, 3), "0.000");
+            assert_eq!(
+                format_price(u64::MAX, u32::MAX, 0),
+                (u64::MAX as u128 * u32::MAX as u128).to_string()
+            );
+        }
+
+        #[test]
+        fn test_parse_price() {
+            assert_eq!(parse_price("2500.00", 100, 2), Some(2_500));
+            assert_eq!(parse_price("2500", 100, 2), Some(2_500));
+            assert_eq!(parse_price(" 25.05 ", 1, 2), Some(2_505));
+            assert_eq!(parse_price(".035", 5, 3), Some(7));
+            assert_eq!(parse_price("7.5", 25, 1), Some(3));
+            assert_eq!(parse_price("42", 1, 0), Some(42));
+
+            // not a whole number of ticks
+            assert_eq!(parse_price("0.036", 5, 3), None);
+            assert_eq!(parse_price("2500.5", 100, 2), None);
+
+            assert_eq!(parse_price("", 1, 2), None);
+            assert_eq!(parse_price(".", 1, 2), None);
+            assert_eq!(parse_price("-1", 1, 2), None);
+            assert_eq!(parse_price("1.2.3", 1, 2), None);
+            assert_eq!(parse_price("1e3", 1, 2), None);
+            assert_eq!(parse_price("1", 0, 2), None);
+            assert_eq!(parse_price("99999999999999999999999", 1, 0), None);
+        }
+
+        #[test]
+        fn test_parse_price_rounds_extra_digits_half_up() {
+            assert_eq!(parse_price("25.054", 1, 2), Some(2_505));
+            assert_eq!(parse_price("25.055", 1, 2), Some(2_506));
+            assert_eq!(parse_price("25.0549", 1, 2), Some(2_505));
+            assert_eq!(parse_price("0.0349", 5, 3), Some(7));
+            assert_eq!(parse_price("41.5", 1, 0), Some(42));
+            assert_eq!(parse_price("41.4", 1, 0), Some(41));
+            // rounding onto a price between ticks is still rejected
+            assert_eq!(parse_price("0.0355", 5, 3), None);
+        }
+
+        #[test]
+        fn test_parse_price_inverts_format_price() {
+            for (tick, tick_decimals) in [(1, 0), (1, 2), (5, 3), (25, 1), (100, 2), (100, 3)] {
+                for price_multiple in [0, 1, 7, 99, 2_500, 1_000_001] {
+                    let formatted = format_price(price_multiple, tick, tick_decimals);
+                    assert_eq!(
+                        parse_price(&formatted, tick, tick_decimals),
+                        Some(price_multiple),
+                        "{} with tick {} and {} decimals",
+                        formatted,
+                        tick,
+                        tick_decimals
+                    );
+                }
             }
         }
     }
@@ -824,9 +1639,9 @@ mod tests {
     mod test_limit_orders {
         use crate::{
             state::{
-                order::{OrderDirection, OrderStatus},
-                spot_clearinghouse::MarketPrecision,
-                spot_market::{Level, SpotMarket, tests::new_limit},
+                order::{OrderDirection, OrderId, OrderStatus},
+                spot_clearinghouse::{MarketPrecision, base_to_quote_lots},
+                spot_market::{Level, LevelInfo, SpotMarket, tests::new_limit},
             },
             types::transaction::PublicKeyHash,
         };
@@ -882,6 +1697,137 @@ mod tests {
             assert_eq!(market.asks_levels[0].volume, 18); // price 1000
         }
 
+        #[test]
+        fn test_get_depth_returns_best_levels_first() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            setup_test_market(&mut market, &mp);
+
+            let depth = market.get_depth(2);
+            let prices =
+                |levels: &Vec<LevelInfo>| levels.iter().map(|l| l.price).collect::<Vec<_>>();
+
+            assert_eq!(prices(&depth.asks), vec![2_500, 2_550]);
+            assert_eq!(prices(&depth.bids), vec![2_400, 2_300]);
+            assert_eq!(depth.asks[0].volume, 1_900);
+            assert_eq!(depth.asks[1].volume, 600);
+
+            let full_depth = market.get_depth(10);
+            assert_eq!(full_depth.asks.len(), 4);
+            assert_eq!(full_depth.bids.len(), 4);
+        }
+
+        #[test]
+        fn test_healthy_book_is_not_crossed() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            setup_test_market(&mut market, &mp);
+            assert!(!market.is_crossed());
+
+            // a buy through the best ask is matched rather than resting on top of it
+            let taker = [2; 32];
+            market.add_limit_helper(new_limit(2_550, 2_600, OrderDirection::Buy, 13, taker), &mp);
+            assert!(!market.is_crossed());
+            assert_eq!(market.get_best_prices(), (Some(2_550), Some(2_700)));
+        }
+
+        #[test]
+        fn test_market_info_reports_spread_and_mid_price() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            let account = PublicKeyHash::default();
+
+            market.add_limit_helper(new_limit(2_400, 10, OrderDirection::Buy, 1, account), &mp);
+            market.add_limit_helper(new_limit(2_450, 10, OrderDirection::Buy, 2, account), &mp);
+
+            let info = market.get_market_info();
+            assert_eq!(info.best_bid, Some(2_450));
+            assert_eq!(info.best_ask, None);
+            assert_eq!(info.mid_price, None);
+            assert_eq!(info.spread, None);
+
+            market.add_limit_helper(new_limit(2_525, 10, OrderDirection::Sell, 3, account), &mp);
+            market.add_limit_helper(new_limit(2_600, 10, OrderDirection::Sell, 4, account), &mp);
+
+            let info = market.get_market_info();
+            assert_eq!(info.best_bid, Some(2_450));
+            assert_eq!(info.best_ask, Some(2_525));
+            assert_eq!(info.spread, Some(75));
+            // 2_487.5 rounds down
+            assert_eq!(info.mid_price, Some(2_487));
+        }
+
+        #[test]
+        fn test_market_info_accumulates_volume_and_trade_count() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            let maker = [1; 32];
+            let taker = [2; 32];
+
+            let cancelled = new_limit(2_500, 50, OrderDirection::Sell, 2, maker);
+            market.add_limit_helper(new_limit(2_500, 100, OrderDirection::Sell, 1, maker), &mp);
+            market.add_limit_helper(cancelled.clone(), &mp);
+            market.add_limit_helper(new_limit(2_600, 200, OrderDirection::Sell, 3, maker), &mp);
+            market.add_limit_helper(new_limit(2_450, 50, OrderDirection::Buy, 4, maker), &mp);
+            market.cancel_order(&cancelled);
+
+            // fills order 1 and half of order 3, skipping the cancelled order 2
+            let quote_size =
+                base_to_quote_lots(100, 2_500, &mp) + base_to_quote_lots(100, 2_600, &mp);
+            market.handle_market_order(make_market_buy_order(5, quote_size, taker), &mp);
+
+            // fills 30 of order 4
+            market.add_limit_helper(new_limit(2_400, 30, OrderDirection::Sell, 6, taker), &mp);
+
+            // self fill against the rest of order 3 is not a trade
+            let self_quote = base_to_quote_lots(50, 2_600, &mp);
+            market.handle_market_order(make_market_buy_order(7, self_quote, maker), &mp);
+
+            let info = market.get_market_info();
+            assert_eq!(info.trade_count, 3);
+            assert_eq!(info.base_volume_lots, 100 + 100 + 30);
+            assert_eq!(
+                info.quote_volume_lots,
+                (base_to_quote_lots(100, 2_500, &mp)
+                    + base_to_quote_lots(100, 2_600, &mp)
+                    + base_to_quote_lots(30, 2_450, &mp)) as u128
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "order book is crossed")]
+        fn test_crossed_book_is_detected() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mm = [1; 32];
+
+            // bypass matching to force a crossed book
+            market.add_bid(new_limit(2_600, 100, OrderDirection::Buy, 1, mm));
+            market.add_ask(new_limit(2_500, 100, OrderDirection::Sell, 2, mm));
+            assert!(market.is_crossed());
+
+            market.check_not_crossed();
+        }
+
         fn setup_test_market(market: &mut SpotMarket, mp: &MarketPrecision) {
             let mm = [1; 32];
             // Sells
@@ -911,10 +1857,10 @@ mod tests {
         mod test_limit_execution {
             use crate::{
                 state::{
-                    order::{OrderDirection, OrderStatus},
+                    order::{OrderDirection, OrderId, OrderStatus},
                     spot_clearinghouse::MarketPrecision,
                     spot_market::{
-                        SpotMarket,
+                        Level, SpotMarket,
                         tests::{assert_user_execution_result, new_limit},
                     },
                 },
@@ -923,6 +1869,170 @@ mod tests {
 
             use super::setup_test_market;
 
+            fn level_state(level: &Level) -> (Vec<(OrderId, u64)>, u64, u32) {
+                let orders = level
+                    .orders
+                    .iter()
+                    .map(|order| (order.common.id, order.filled_base_lots))
+                    .collect();
+                (orders, level.volume, level.cancelled)
+            }
+
+            #[test]
+            fn test_partial_fill_behind_cancelled_order_keeps_queue_position() {
+                let mut market = SpotMarket::test_new(100, 2);
+                let mp = MarketPrecision {
+                    base_lot_size: 100,
+                    quote_lot_size: 100,
+                    tick: market.tick,
+                    tick_decimals: market.tick_decimals,
+                };
+                let maker = [1; 32];
+                let taker = [2; 32];
+
+                let order_1 = new_limit(2_500, 100, OrderDirection::Sell, 1, maker);
+                market.add_limit_helper(order_1.clone(), &mp);
+                market.add_limit_helper(new_limit(2_500, 100, OrderDirection::Sell, 2, maker), &mp);
+                market.add_limit_helper(new_limit(2_500, 100, OrderDirection::Sell, 3, maker), &mp);
+                market.cancel_order(&order_1);
+
+                // [cancelled 1, 2, 3]
+                assert_eq!(
+                    level_state(&market.asks_levels[0]),
+                    (vec![(1, 0), (2, 0), (3, 0)], 200, 1)
+                );
+
+                let result = market
+                    .add_limit_order(
+                        new_limit(2_500, 60, OrderDirection::Buy, 4, taker),
+                        0,
+                        1,
+                        &mp,
+                    )
+                    .expect("Expected the buy to match");
+
+                // the cancelled order is drained, order 2 stays at the front
+                assert_eq!(
+                    level_state(&market.asks_levels[0]),
+                    (vec![(2, 60), (3, 0)], 140, 0)
+                );
+                assert_eq!(market.bids_levels.len(), 0);
+
+                let drained: Vec<(OrderId, OrderStatus)> = result
+                    .filled_orders
+                    .iter()
+                    .map(|order| (order.common.id, order.common.status.clone()))
+                    .collect();
+                assert_eq!(drained, vec![(1, OrderStatus::Cancelled)]);
+
+                let residual = result.residual_order.expect("Expected residual order");
+                assert_eq!(residual.order_id, 2);
+                assert_eq!(residual.filled_base_lots, 60);
+                assert_eq!(result.user_order.filled_size, 60);
+            }
+
+            #[test]
+            fn test_same_price_orders_fill_in_priority_order_not_id_order() {
+                let mut market = SpotMarket::test_new(100, 2);
+                let mp = MarketPrecision {
+                    base_lot_size: 100,
+                    quote_lot_size: 100,
+                    tick: market.tick,
+                    tick_decimals: market.tick_decimals,
+                };
+                let maker = [1; 32];
+                let taker = [2; 32];
+
+                // order 2 was re-queued behind order 3
+                let mut requeued = new_limit(2_500, 100, OrderDirection::Sell, 2, maker);
+                requeued.priority = 5;
+                let mut oldest = new_limit(2_500, 100, OrderDirection::Sell, 3, maker);
+                oldest.priority = 4;
+                market.add_limit_helper(requeued.clone(), &mp);
+                market.add_limit_helper(oldest, &mp);
+                assert_eq!(
+                    level_state(&market.asks_levels[0]),
+                    (vec![(3, 0), (2, 0)], 200, 0)
+                );
+
+                let result = market
+                    .add_limit_order(
+                        new_limit(2_500, 60, OrderDirection::Buy, 6, taker),
+                        0,
+                        1,
+                        &mp,
+                    )
+                    .expect("Expected the buy to match");
+                let residual = result.residual_order.expect("Expected residual order");
+                assert_eq!(residual.order_id, 3);
+
+                // cancelling finds the order by priority
+                market.cancel_order(&requeued);
+                assert_eq!(
+                    level_state(&market.asks_levels[0]),
+                    (vec![(3, 60), (2, 0)], 40, 1)
+                );
+            }
+
+            #[test]
+            fn test_residual_after_cancelled_order_mid_level() {
+                let mut market = SpotMarket::test_new(100, 2);
+                let mp = MarketPrecision {
+                    base_lot_size: 100,
+                    quote_lot_size: 100,
+                    tick: market.tick,
+                    tick_decimals: market.tick_decimals,
+                };
+                let maker = [1; 32];
+                let taker = [2; 32];
+
+                let order_3 = new_limit(2_400, 100, OrderDirection::Buy, 3, maker);
+                market.add_limit_helper(new_limit(2_400, 100, OrderDirection::Buy, 1, maker), &mp);
+                market.add_limit_helper(new_limit(2_400, 100, OrderDirection::Buy, 2, maker), &mp);
+                market.add_limit_helper(order_3.clone(), &mp);
+                market.add_limit_helper(new_limit(2_400, 100, OrderDirection::Buy, 4, maker), &mp);
+
+                // partially fill order 1, then cancel order 3 behind it
+                market.add_limit_helper(new_limit(2_400, 30, OrderDirection::Sell, 5, taker), &mp);
+                market.cancel_order(&order_3);
+
+                // [1 (30 filled), 2, cancelled 3, 4]
+                assert_eq!(
+                    level_state(&market.bids_levels[0]),
+                    (vec![(1, 30), (2, 0), (3, 0), (4, 0)], 270, 1)
+                );
+
+                // finishes orders 1 and 2, skips 3, leaves 4 partially filled
+                let result = market
+                    .add_limit_order(
+                        new_limit(2_400, 70 + 100 + 50, OrderDirection::Sell, 6, taker),
+                        0,
+                        1,
+                        &mp,
+                    )
+                    .expect("Expected the sell to match");
+
+                assert_eq!(level_state(&market.bids_levels[0]), (vec![(4, 50)], 50, 0));
+                assert_eq!(market.asks_levels.len(), 0);
+
+                let drained: Vec<(OrderId, u64)> = result
+                    .filled_orders
+                    .iter()
+                    .map(|order| (order.common.id, order.get_order_remaining()))
+                    .collect();
+                // remaining size of each drained order is what this taker filled
+                assert_eq!(drained, vec![(1, 70), (2, 100), (3, 100)]);
+                assert_eq!(
+                    result.filled_orders[2].common.status,
+                    OrderStatus::Cancelled
+                );
+
+                let residual = result.residual_order.expect("Expected residual order");
+                assert_eq!(residual.order_id, 4);
+                assert_eq!(residual.filled_base_lots, 50);
+                assert_eq!(result.user_order.filled_size, 220);
+            }
+
             #[test]
             fn test_limit_buy_above_best_ask_price_fully_filled() {
                 let base_asset = 0;
@@ -1486,8 +2596,13 @@ mod tests {
                         filled_orders,
                         residual_order,
                         self_fill: _,
+                        self_fill_base_lots: _,
                         last_executed_price,
+                        unfilled_quote_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_quote_lots, 2_000);
+                        assert!(book_exhausted);
                         assert_eq!(market.get_best_prices(), (Some(2500), None));
                         assert_eq!(order_id, 3);
                         assert_eq!(last_executed_price, None);
@@ -1538,8 +2653,13 @@ mod tests {
                         filled_orders,
                         residual_order,
                         self_fill: _,
+                        self_fill_base_lots: _,
                         last_executed_price,
+                        unfilled_quote_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_quote_lots, 1_650);
+                        assert!(!book_exhausted);
                         // Check market state
                         {
                             assert_eq!(market.get_best_prices(), (Some(2400), Some(2550)));
@@ -1608,8 +2728,13 @@ mod tests {
                         filled_orders,
                         residual_order,
                         self_fill: _,
+                        self_fill_base_lots: _,
                         last_executed_price,
+                        unfilled_quote_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_quote_lots, 100);
+                        assert!(!book_exhausted);
                         // Check market state
                         {
                             assert_eq!(market.get_best_prices(), (Some(2400), Some(2550)));
@@ -1676,8 +2801,13 @@ mod tests {
                         filled_orders,
                         residual_order,
                         self_fill: _,
+                        self_fill_quote_lots: _,
                         last_executed_price,
+                        unfilled_base_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_base_lots, 2_400);
+                        assert!(book_exhausted);
                         assert_eq!(market.get_best_prices(), (None, Some(2_500)));
                         assert_eq!(order_id, 3);
                         assert_eq!(last_executed_price, None);
@@ -1729,8 +2859,13 @@ mod tests {
                         base_filled_lots,
                         quote_lots_in,
                         self_fill: _,
+                        self_fill_quote_lots: _,
                         last_executed_price,
+                        unfilled_base_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_base_lots, 0);
+                        assert!(!book_exhausted);
                         // Check market state
                         {
                             assert_eq!(market.get_best_prices(), (Some(2_200), Some(2_500)));
@@ -1804,8 +2939,13 @@ mod tests {
                         base_filled_lots,
                         quote_lots_in,
                         self_fill: _,
+                        self_fill_quote_lots: _,
                         last_executed_price,
+                        unfilled_base_lots,
+                        book_exhausted,
                     } => {
+                        assert_eq!(unfilled_base_lots, 0);
+                        assert!(!book_exhausted);
                         // Check market state
                         {
                             assert_eq!(market.get_best_prices(), (Some(2_200), Some(2_500)));
@@ -1991,6 +3131,55 @@ mod tests {
             assert_bid_level(&bids_levels[0], 0, 2, expected_level_volume);
         }
 
+        #[test]
+        fn test_cancels_order_inserted_out_of_id_order_after_prune() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+
+            let account = PublicKeyHash::default();
+
+            for id in [10, 20, 30, 40, 50] {
+                market.add_limit_helper(new_limit(1, 2, OrderDirection::Buy, id, account), &mp);
+            }
+
+            // cancelling 3 of 5 prunes the level down to ids 10 and 50
+            for id in [20, 30, 40] {
+                market.cancel_order(&new_limit(1, 2, OrderDirection::Buy, id, account));
+            }
+            assert_eq!(market.bids_levels[0].orders.len(), 2);
+            assert_eq!(market.bids_levels[0].cancelled, 0);
+
+            // ids lower than the newest resting order
+            market.add_limit_helper(new_limit(1, 3, OrderDirection::Buy, 35, account), &mp);
+            market.add_limit_helper(new_limit(1, 4, OrderDirection::Buy, 5, account), &mp);
+
+            let ids: Vec<OrderId> = market.bids_levels[0]
+                .orders
+                .iter()
+                .map(|o| o.common.id)
+                .collect();
+            assert_eq!(ids, vec![5, 10, 35, 50]);
+
+            let cancelled = market.cancel_order(&new_limit(1, 3, OrderDirection::Buy, 35, account));
+            assert_eq!(cancelled, 3);
+            let cancelled = market.cancel_order(&new_limit(1, 4, OrderDirection::Buy, 5, account));
+            assert_eq!(cancelled, 4);
+
+            assert_eq!(market.bids_levels[0].volume, 4);
+            let cancelled_ids: Vec<OrderId> = market.bids_levels[0]
+                .orders
+                .iter()
+                .filter(|o| o.common.status == OrderStatus::Cancelled)
+                .map(|o| o.common.id)
+                .collect();
+            assert_eq!(cancelled_ids, vec![5, 35]);
+        }
+
         #[test]
         fn test_self_fills_buys_correctly() {
             let mut market = SpotMarket::test_new(100, 2);
@@ -2026,10 +3215,15 @@ mod tests {
                     base_filled_lots,
                     quote_lots_in,
                     self_fill,
+                    self_fill_quote_lots: _,
                     filled_orders,
                     residual_order,
                     last_executed_price,
+                    unfilled_base_lots,
+                    book_exhausted,
                 } => {
+                    assert_eq!(unfilled_base_lots, 0);
+                    assert!(!book_exhausted);
                     assert_eq!(self_fill, 40);
                     assert_eq!(base_filled_lots, 0);
                     assert_eq!(quote_lots_in, 0);
@@ -2094,12 +3288,17 @@ mod tests {
                     order_id: _,
 
                     self_fill,
+                    self_fill_base_lots: _,
                     filled_orders,
                     residual_order,
                     quote_filled_lots,
                     base_lots_in,
                     last_executed_price,
+                    unfilled_quote_lots,
+                    book_exhausted,
                 } => {
+                    assert_eq!(unfilled_quote_lots, 0);
+                    assert!(!book_exhausted);
                     assert_eq!(self_fill, 400);
                     assert_eq!(base_lots_in, 0);
                     assert_eq!(quote_filled_lots, 0);
@@ -2130,4 +3329,457 @@ mod tests {
             }
         }
     }
+
+    mod test_order_locations {
+        use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+        use super::*;
+
+        #[test]
+        fn test_indexed_cancels_match_book_search_on_deep_book() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            let account = PublicKeyHash::default();
+            let mut rng = StdRng::seed_from_u64(2099);
+
+            // 50 levels a side, 10 orders per level, placed out of price and priority order
+            let mut orders: Vec<LimitOrder> = (0..1_000)
+                .map(|id| {
+                    let (direction, price) = if id % 2 == 0 {
+                        (OrderDirection::Buy, 1 + (id / 2) % 50)
+                    } else {
+                        (OrderDirection::Sell, 101 + (id / 2) % 50)
+                    };
+                    new_limit(price, rng.gen_range(1..10), direction, id, account)
+                })
+                .collect();
+            orders.shuffle(&mut rng);
+            for order in &orders {
+                market.add_limit_helper(order.clone(), &mp);
+            }
+            assert_eq!(market.order_locations.len(), 1_000);
+
+            orders.shuffle(&mut rng);
+            let mut searched = market.clone();
+            for (step, order) in orders.iter().enumerate() {
+                if step % 37 == 0 {
+                    // fills drain the best levels and shift every position behind them
+                    let sell = make_market_sell_order(10_000 + step as OrderId, 25, account);
+                    market.handle_market_order(sell.clone(), &mp);
+                    searched.handle_market_order(sell, &mp);
+                }
+
+                // cancel with the resting copy, which carries any partial fill
+                let resting = searched
+                    .bids_levels
+                    .iter()
+                    .chain(searched.asks_levels.iter())
+                    .flat_map(|level| level.orders.iter())
+                    .find(|resting| resting.common.id == order.common.id)
+                    .cloned()
+                    .unwrap_or_else(|| order.clone());
+
+                searched.order_locations.clear();
+                assert_eq!(
+                    market.cancel_order(&resting),
+                    searched.cancel_order(&resting)
+                );
+                assert_eq!(market.hash(), searched.hash());
+            }
+
+            assert!(market.bids_levels.is_empty());
+            assert!(market.asks_levels.is_empty());
+            assert!(market.order_locations.is_empty());
+        }
+
+        #[test]
+        fn test_index_is_rebuilt_after_prune() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            let account = PublicKeyHash::default();
+
+            for id in 1..=5 {
+                market.add_limit_helper(new_limit(1, 2, OrderDirection::Buy, id, account), &mp);
+            }
+            // cancelling 3 of 5 prunes the level down to ids 4 and 5
+            for id in 1..=3 {
+                market.cancel_order(&new_limit(1, 2, OrderDirection::Buy, id, account));
+            }
+
+            assert_eq!(market.bids_levels[0].orders.len(), 2);
+            assert_eq!(market.order_locations.len(), 2);
+            assert_eq!(market.order_locations[&4], (0, 0));
+            assert_eq!(market.order_locations[&5], (0, 1));
+        }
+    }
+
+    mod test_level_pruning {
+        use rand::{Rng, SeedableRng, rngs::StdRng};
+
+        use super::*;
+
+        fn precision(market: &SpotMarket) -> MarketPrecision {
+            MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            }
+        }
+
+        fn assert_cancelled_counts(market: &SpotMarket) {
+            for level in market.bids_levels.iter().chain(market.asks_levels.iter()) {
+                let cancelled = level
+                    .orders
+                    .iter()
+                    .filter(|order| order.common.status == OrderStatus::Cancelled)
+                    .count();
+                assert_eq!(level.cancelled as usize, cancelled);
+                assert!(cancelled <= level.orders.len());
+            }
+        }
+
+        fn resting_order(market: &SpotMarket, id: OrderId) -> Option<LimitOrder> {
+            market
+                .bids_levels
+                .iter()
+                .chain(market.asks_levels.iter())
+                .flat_map(|level| level.orders.iter())
+                .find(|order| {
+                    order.common.id == id && order.common.status != OrderStatus::Cancelled
+                })
+                .cloned()
+        }
+
+        #[test]
+        fn test_interleaved_cancels_and_fills_keep_cancelled_counts() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = precision(&market);
+            let mut rng = StdRng::seed_from_u64(2101);
+            let mut placed: Vec<OrderId> = vec![];
+
+            for id in 0..3_000 {
+                match rng.gen_range(0..10) {
+                    // a few deep levels either side of 100, crossing now and then
+                    0..=5 => {
+                        let direction = if rng.gen_bool(0.5) {
+                            OrderDirection::Buy
+                        } else {
+                            OrderDirection::Sell
+                        };
+                        let price = match direction {
+                            OrderDirection::Buy => rng.gen_range(92..=101),
+                            OrderDirection::Sell => rng.gen_range(99..=108),
+                        };
+                        let account = [rng.gen_range(0..4); 32];
+                        let order = new_limit(price, rng.gen_range(1..20), direction, id, account);
+                        market.add_limit_helper(order, &mp);
+                        placed.push(id);
+                    }
+                    6..=8 => {
+                        if placed.is_empty() {
+                            continue;
+                        }
+                        let id = placed.swap_remove(rng.gen_range(0..placed.len()));
+                        if let Some(order) = resting_order(&market, id) {
+                            market.cancel_order(&order);
+                        }
+                    }
+                    _ => {
+                        let account = [9; 32];
+                        let order = if rng.gen_bool(0.5) {
+                            make_market_buy_order(id, rng.gen_range(100..3_000), account)
+                        } else {
+                            make_market_sell_order(id, rng.gen_range(1..30), account)
+                        };
+                        market.handle_market_order(order, &mp);
+                    }
+                }
+                assert_cancelled_counts(&market);
+            }
+        }
+
+        #[test]
+        fn test_prune_threshold_decides_when_levels_are_compacted() {
+            let account = PublicKeyHash::default();
+            let cancelled_after = |prune_threshold: u32, cancels: OrderId| {
+                let mut market = SpotMarket::test_new(100, 2);
+                market.prune_threshold = prune_threshold;
+                let mp = precision(&market);
+                for id in 1..=4 {
+                    market.add_limit_helper(new_limit(1, 2, OrderDirection::Buy, id, account), &mp);
+                }
+                for id in 1..=cancels {
+                    market.cancel_order(&new_limit(1, 2, OrderDirection::Buy, id, account));
+                }
+                let level = &market.bids_levels[0];
+                (level.orders.len(), level.cancelled)
+            };
+
+            // the default compacts once more than half the level is cancelled
+            assert_eq!(cancelled_after(50, 2), (4, 2));
+            assert_eq!(cancelled_after(50, 3), (1, 0));
+            // compacting on every cancel
+            assert_eq!(cancelled_after(0, 1), (3, 0));
+            // never compacting
+            assert_eq!(cancelled_after(100, 3), (4, 3));
+        }
+
+        #[test]
+        fn test_draining_more_cancelled_orders_than_counted_saturates() {
+            let mut level = Level {
+                price: 1,
+                volume: 2,
+                orders: vec![new_limit(
+                    1,
+                    2,
+                    OrderDirection::Buy,
+                    1,
+                    PublicKeyHash::default(),
+                )],
+                cancelled: 0,
+            };
+            level.drain_cancelled(0);
+            assert_eq!(level.cancelled, 0);
+
+            level.cancelled = 1;
+            level.drain_cancelled(3);
+            assert_eq!(level.cancelled, 0);
+        }
+    }
+
+    mod test_top_of_book {
+        use super::*;
+
+        #[test]
+        fn test_best_price_changes_are_emitted_and_no_ops_suppressed() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            };
+            let maker = [1; 32];
+            let taker = [2; 32];
+
+            let best_bid = new_limit(2_400, 10, OrderDirection::Buy, 1, maker);
+            let worse_bid = new_limit(2_300, 10, OrderDirection::Buy, 2, maker);
+            market.add_limit_helper(best_bid.clone(), &mp);
+            market.add_limit_helper(worse_bid.clone(), &mp);
+            market.add_limit_helper(new_limit(2_600, 10, OrderDirection::Sell, 3, maker), &mp);
+            market.add_limit_helper(new_limit(2_500, 10, OrderDirection::Sell, 4, maker), &mp);
+            market.add_limit_helper(new_limit(2_550, 10, OrderDirection::Sell, 5, maker), &mp);
+            market.cancel_order(&worse_bid);
+            // takes the whole of the best ask
+            let quote_size = base_to_quote_lots(10, 2_500, &mp);
+            market.handle_market_order(make_market_buy_order(6, quote_size, taker), &mp);
+            market.cancel_order(&best_bid);
+            market.add_limit_helper(new_limit(2_560, 10, OrderDirection::Sell, 7, maker), &mp);
+
+            let transitions: Vec<(Option<u64>, Option<u64>)> = market
+                .take_top_of_book_events()
+                .iter()
+                .map(|event| (event.best_bid, event.best_ask))
+                .collect();
+            assert_eq!(
+                transitions,
+                vec![
+                    (Some(2_400), None),
+                    (Some(2_400), Some(2_600)),
+                    (Some(2_400), Some(2_500)),
+                    (Some(2_400), Some(2_550)),
+                    (None, Some(2_550)),
+                ]
+            );
+            assert!(market.take_top_of_book_events().is_empty());
+        }
+
+        #[test]
+        fn test_best_price_history_drops_oldest_past_capacity() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let extra = 3;
+            for view in 0..(BEST_PRICE_HISTORY_CAPACITY + extra) as u64 {
+                market.record_best_prices(view, Some(2_000 + view), None);
+            }
+
+            let history = market.get_best_price_history();
+            assert_eq!(history.len(), BEST_PRICE_HISTORY_CAPACITY);
+            assert_eq!(
+                history[0],
+                BestPriceSnapshot {
+                    view: extra as u64,
+                    best_bid: Some(2_000 + extra as u64),
+                    best_ask: None,
+                }
+            );
+            assert_eq!(
+                history.last().unwrap().view,
+                (BEST_PRICE_HISTORY_CAPACITY + extra - 1) as u64
+            );
+        }
+    }
+
+    mod test_all_or_none {
+        use super::*;
+
+        fn new_all_or_none(
+            price_tick: u64,
+            lot_size: u64,
+            direction: OrderDirection,
+            id: OrderId,
+            account: PublicKeyHash,
+        ) -> LimitOrder {
+            let mut order = new_limit(price_tick, lot_size, direction, id, account);
+            order.all_or_none = true;
+            order
+        }
+
+        fn precision(market: &SpotMarket) -> MarketPrecision {
+            MarketPrecision {
+                base_lot_size: 100,
+                quote_lot_size: 100,
+                tick: market.tick,
+                tick_decimals: market.tick_decimals,
+            }
+        }
+
+        fn filled_ids(result: &LimitFillResult) -> Vec<OrderId> {
+            result
+                .filled_orders
+                .iter()
+                .map(|order| order.common.id)
+                .collect()
+        }
+
+        #[test]
+        fn test_small_taker_skips_all_or_none_maker() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = precision(&market);
+            let (maker, taker) = ([1; 32], [2; 32]);
+
+            market.add_limit_helper(
+                new_all_or_none(2_500, 10, OrderDirection::Sell, 1, maker),
+                &mp,
+            );
+            market.add_limit_helper(new_limit(2_500, 5, OrderDirection::Sell, 2, maker), &mp);
+            market.add_limit_helper(new_limit(2_600, 5, OrderDirection::Sell, 3, maker), &mp);
+
+            // the all or none ask is first in line but too big for the taker
+            let taker_order = new_limit(2_500, 3, OrderDirection::Buy, 4, taker);
+            let result = market.add_limit_order(taker_order, 0, 1, &mp).unwrap();
+            assert!(filled_ids(&result).is_empty());
+            let residual = result.residual_order.unwrap();
+            assert_eq!((residual.order_id, residual.filled_base_lots), (2, 3));
+            assert_eq!(result.user_order.filled_size, 3);
+
+            let level = market.asks_levels.last().unwrap();
+            assert_eq!(level.price, 2_500);
+            assert_eq!(level.volume, 12);
+            assert_eq!(level.orders[0].common.id, 1);
+            assert_eq!(level.orders[0].filled_base_lots, 0);
+
+            // looks past it into the next level, and rests across it without crossing the book
+            let taker_order = new_limit(2_600, 8, OrderDirection::Buy, 5, taker);
+            let result = market.add_limit_order(taker_order, 0, 1, &mp).unwrap();
+            assert_eq!(filled_ids(&result), vec![2, 3]);
+            assert_eq!(result.user_order.filled_size, 7);
+            assert_eq!(market.get_best_prices(), (Some(2_600), Some(2_500)));
+            assert!(!market.is_crossed());
+            assert_eq!(market.asks_levels.len(), 1);
+            assert_eq!(market.asks_levels[0].volume, 10);
+        }
+
+        #[test]
+        fn test_large_taker_fills_all_or_none_maker() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = precision(&market);
+            let (maker, taker) = ([1; 32], [2; 32]);
+
+            market.add_limit_helper(
+                new_all_or_none(2_500, 10, OrderDirection::Sell, 1, maker),
+                &mp,
+            );
+            market.add_limit_helper(new_limit(2_500, 5, OrderDirection::Sell, 2, maker), &mp);
+
+            let taker_order = new_limit(2_500, 12, OrderDirection::Buy, 3, taker);
+            let result = market.add_limit_order(taker_order, 0, 1, &mp).unwrap();
+            assert_eq!(filled_ids(&result), vec![1]);
+            let residual = result.residual_order.unwrap();
+            assert_eq!((residual.order_id, residual.filled_base_lots), (2, 2));
+            assert_eq!(result.user_order.filled_size, 12);
+
+            let level = market.asks_levels.last().unwrap();
+            assert_eq!(level.volume, 3);
+            assert_eq!(level.orders.len(), 1);
+            assert_eq!(market.get_best_prices(), (None, Some(2_500)));
+        }
+
+        #[test]
+        fn test_market_sell_skips_all_or_none_bid() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = precision(&market);
+            let (maker, taker) = ([1; 32], [2; 32]);
+
+            market.add_limit_helper(
+                new_all_or_none(2_400, 10, OrderDirection::Buy, 1, maker),
+                &mp,
+            );
+            market.add_limit_helper(new_limit(2_300, 5, OrderDirection::Buy, 2, maker), &mp);
+
+            let result = market.handle_market_order(make_market_sell_order(3, 4, taker), &mp);
+            let MarketOrderMatchingResults::Sell {
+                base_filled_lots,
+                residual_order,
+                book_exhausted,
+                ..
+            } = result
+            else {
+                panic!("expected sell results");
+            };
+            assert_eq!(base_filled_lots, 4);
+            assert_eq!(residual_order.unwrap().order_id, 2);
+            assert!(!book_exhausted);
+            assert_eq!(market.bids_levels.len(), 2);
+            assert_eq!(market.bids_levels[1].volume, 10);
+            assert_eq!(market.bids_levels[0].volume, 1);
+        }
+
+        #[test]
+        fn test_all_or_none_taker_rests_unless_filled_whole() {
+            let mut market = SpotMarket::test_new(100, 2);
+            let mp = precision(&market);
+            let (maker, taker) = ([1; 32], [2; 32]);
+
+            market.add_limit_helper(new_limit(2_500, 5, OrderDirection::Sell, 1, maker), &mp);
+
+            // only 5 of the 8 lots are on offer, so it takes none of them
+            let aon_bid = new_all_or_none(2_500, 8, OrderDirection::Buy, 2, taker);
+            assert!(market.add_limit_order(aon_bid, 0, 1, &mp).is_none());
+            assert_eq!(market.get_best_prices(), (Some(2_500), Some(2_500)));
+            assert!(!market.is_crossed());
+
+            // too small a seller skips it, a large enough one fills it whole
+            let small_ask = new_limit(2_500, 3, OrderDirection::Sell, 3, maker);
+            let result = market.add_limit_order(small_ask, 0, 1, &mp).unwrap();
+            assert_eq!(result.user_order.filled_size, 0);
+            assert_eq!(market.asks_levels.last().unwrap().volume, 8);
+
+            let large_ask = new_limit(2_500, 8, OrderDirection::Sell, 4, maker);
+            let result = market.add_limit_order(large_ask, 0, 1, &mp).unwrap();
+            assert_eq!(filled_ids(&result), vec![2]);
+            assert_eq!(result.user_order.filled_size, 8);
+            assert!(market.bids_levels.is_empty());
+        }
+    }
 }
@@ -1,13 +1,18 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tokio::sync::mpsc;
 
 use crate::{
-    config,
+    config::{self, LedgerParams},
     hotstuff::block::Block,
+    network::RecentlySeen,
     node::client::handler::{ClientQuery, ClientResponse},
     types::transaction::{
-        CancelOrderTransaction, OrderTransaction, PublicKeyHash, PublicKeyString,
-        SignedTransaction, TransactionStatus, TransferTransaction, UnsignedTransaction,
+        BatchOrderTransaction, CancelOrderAtPriceTransaction, CancelOrderTransaction, OrderSpec,
+        OrderTransaction, PublicKeyHash, PublicKeyString, ReduceOrderTransaction, RejectReason,
+        SetMarketStatusTransaction, Sha256Hash, SignedTransaction, TransactionStatus,
+        TransferAllTransaction, TransferTransaction, UnsignedTransaction,
     },
 };
 
@@ -15,23 +20,51 @@ use super::{
     asset::{Asset, AssetId, AssetManager},
     order::{
         self, ExecutionResults, LimitOrder, MarketOrder, Order, OrderDirection, OrderId,
-        OrderStateManager, OrderStatus, ResidualOrder,
+        OrderPriceMultiple, OrderStateManager, OrderStatus, ResidualOrder, TakerSettlement,
     },
+    receipt::{
+        BalanceDelta, Fill, FillEvent, MAX_RECEIPTS, ReceiptStore, TopOfBookEvent,
+        TransactionReceipt,
+    },
+    shard::{ExecutionShard, plan_shards},
     spot_clearinghouse::{
-        AccountBalance, AccountTokenBalance, MarketId, MarketPrecision, SpotClearingHouse,
+        AccountBalance, MarketError, MarketId, MarketPrecision, SpotClearingHouse,
+        base_to_quote_lots,
+    },
+    spot_market::{
+        BestPriceSnapshot, FirmQuote, LotSizes, MarketInfo, MarketStatus, OrderBookDepth,
     },
-    spot_market::MarketInfo,
     transaction_delta::{AssetDelta, TransferDelta},
 };
 
 pub type Balance = u128;
 pub type Nonce = u64;
 
+// Max number of future nonce transactions buffered per account
+pub const MAX_QUEUED_TRANSACTIONS_PER_ACCOUNT: usize = 16;
+
+// Max number of orders in a single batch order transaction
+pub const MAX_BATCH_ORDERS: usize = 32;
+
+// Number of recently applied block hashes kept to skip replayed blocks.
+// Older replays are still rejected transaction by transaction on their nonces
+pub const MAX_APPLIED_BLOCKS: usize = 10_000;
+
+// Updates an account subscriber can fall behind by before it is dropped
+pub const ACCOUNT_SUBSCRIBER_BUFFER: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AccountInfo {
     pub expected_nonce: Nonce,
-    pub open_orders: Vec<LimitOrder>, // sorted by orderId
+    #[serde(
+        serialize_with = "serialize_open_orders",
+        deserialize_with = "deserialize_open_orders"
+    )]
+    pub open_orders: BTreeMap<OrderId, LimitOrder>, // sorted by orderId
     pub completed_orders: Vec<Order>, // sorted by completion
+    best_open_bid: Option<u64>,       // highest price among open buys
+    best_open_ask: Option<u64>,       // lowest price among open sells
+    last_faucet_drip: Option<u64>,    // view of the last faucet transfer received
     _private: (),                     // prevent creation of accountinfo outside of this struct
 }
 
@@ -46,6 +79,78 @@ pub struct AccountInfoWithBalancesRef<'a> {
     pub spot_balances: &'a AccountBalance,
 }
 
+#[derive(Serialize)]
+struct HashableAccount<'a> {
+    public_key: &'a PublicKeyHash,
+    account_info: Option<&'a AccountInfo>,
+    spot_balances: Option<&'a AccountBalance>,
+}
+
+/// An account's balance of a single asset, across every market it trades in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AssetBalanceSummary {
+    pub asset_id: AssetId,
+    pub total: u128,
+    pub available: u128,
+    pub locked: u128,
+}
+
+/// Balance held by an open order for its remaining size
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderLock {
+    pub order_id: OrderId,
+    pub market_id: MarketId,
+    pub direction: OrderDirection,
+    pub asset_id: AssetId,
+    pub amount: u128,
+}
+
+/// Change in one of an account's asset balances, signed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AssetBalanceDelta {
+    pub asset_id: AssetId,
+    pub total: i128,
+    pub available: i128,
+}
+
+/// What an order would do if it were executed against the current state
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderSimulation {
+    pub status: TransactionStatus,
+    pub order_id: Option<OrderId>,
+    pub fills: Vec<Fill>,
+    pub average_execution_price: u128,
+    pub unfilled_lots: u64,
+    pub book_exhausted: bool,
+    // base and quote asset of the market, in that order
+    pub balance_deltas: Vec<AssetBalanceDelta>,
+}
+
+/// How a transaction in an applied block fared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionOutcome {
+    pub tx_hash: Sha256Hash,
+    pub status: TransactionStatus,
+    // the sender and its next expected nonce, only if the transaction executed
+    pub next_nonce: Option<(PublicKeyHash, Nonce)>,
+}
+
+impl TransactionOutcome {
+    fn new(transaction: &SignedTransaction, next_nonce: Option<(PublicKeyHash, Nonce)>) -> Self {
+        Self {
+            tx_hash: transaction.hash,
+            status: transaction.get_status(),
+            next_nonce,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderQueryResult {
+    pub order: Order,
+    pub remaining_base_lots: u64,
+}
+
 impl<'a> From<AccountInfoWithBalancesRef<'a>> for AccountInfoWithBalances {
     fn from(r: AccountInfoWithBalancesRef<'a>) -> Self {
         Self {
@@ -55,12 +160,39 @@ impl<'a> From<AccountInfoWithBalancesRef<'a>> for AccountInfoWithBalances {
     }
 }
 
+/// Open orders go over the wire and into the state root as a list sorted by id
+fn serialize_open_orders<S>(
+    open_orders: &BTreeMap<OrderId, LimitOrder>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(open_orders.values())
+}
+
+fn deserialize_open_orders<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<OrderId, LimitOrder>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let open_orders = Vec::<LimitOrder>::deserialize(deserializer)?;
+    Ok(open_orders
+        .into_iter()
+        .map(|order| (order.common.id, order))
+        .collect())
+}
+
 impl AccountInfo {
     pub(crate) fn new() -> Self {
         Self {
             expected_nonce: 0,
-            open_orders: vec![],
+            open_orders: BTreeMap::new(),
             completed_orders: vec![],
+            best_open_bid: None,
+            best_open_ask: None,
+            last_faucet_drip: None,
             _private: (),
         }
     }
@@ -68,16 +200,82 @@ impl AccountInfo {
     fn create_faucet() -> Self {
         Self {
             expected_nonce: 0,
-            open_orders: vec![],
+            open_orders: BTreeMap::new(),
             completed_orders: vec![],
+            best_open_bid: None,
+            best_open_ask: None,
+            last_faucet_drip: None,
             _private: (),
         }
     }
 
     fn get_open_order(&self, order_id: OrderId) -> Option<&LimitOrder> {
-        self.open_orders
+        self.open_orders.get(&order_id)
+    }
+
+    /// Oldest open order resting at `price` on the `direction` side of `market_id`
+    fn find_cancel_order(
+        &self,
+        market_id: MarketId,
+        direction: &OrderDirection,
+        price: OrderPriceMultiple,
+    ) -> Option<&LimitOrder> {
+        // open orders are sorted by id, so the first match is the oldest
+        self.open_orders.values().find(|order| {
+            order.common.market_id == market_id
+                && &order.common.direction == direction
+                && order.price_multiple == price
+        })
+    }
+
+    fn get_completed_order(&self, order_id: OrderId) -> Option<&Order> {
+        self.completed_orders
             .iter()
-            .find(|&order| order.common.id == order_id)
+            .find(|&order| order.get_id() == order_id)
+    }
+
+    fn add_open_order(&mut self, order: LimitOrder) {
+        let price = order.price_multiple;
+        match order.common.direction {
+            OrderDirection::Buy => {
+                self.best_open_bid = Some(self.best_open_bid.map_or(price, |bid| bid.max(price)))
+            }
+            OrderDirection::Sell => {
+                self.best_open_ask = Some(self.best_open_ask.map_or(price, |ask| ask.min(price)))
+            }
+        }
+        self.open_orders.insert(order.common.id, order);
+    }
+
+    /// Removes the open order with `order_id`, rescanning only if it was the best on its side
+    fn remove_open_order(&mut self, order_id: OrderId) -> Option<LimitOrder> {
+        let order = self.open_orders.remove(&order_id)?;
+        let direction = &order.common.direction;
+        let best = match direction {
+            OrderDirection::Buy => self.best_open_bid,
+            OrderDirection::Sell => self.best_open_ask,
+        };
+
+        if best == Some(order.price_multiple) {
+            let prices = self
+                .open_orders
+                .values()
+                .filter(|o| &o.common.direction == direction)
+                .map(|o| o.price_multiple);
+            match direction {
+                OrderDirection::Buy => self.best_open_bid = prices.max(),
+                OrderDirection::Sell => self.best_open_ask = prices.min(),
+            }
+        }
+        Some(order)
+    }
+
+    /// Whether an order at `price` would cross one of the account's open orders, in any market
+    fn would_self_cross(&self, direction: &OrderDirection, price: u64) -> bool {
+        match direction {
+            OrderDirection::Buy => self.best_open_ask.is_some_and(|ask| price >= ask),
+            OrderDirection::Sell => self.best_open_bid.is_some_and(|bid| price <= bid),
+        }
     }
 }
 
@@ -97,12 +295,59 @@ pub enum ExecError {
     ResourceNotFound(Resource),
 }
 
+/// State an all-or-nothing batch can touch, restored when one of its orders fails: the batch's
+/// markets and the accounts trading in them
+struct OrderExecutionSnapshot {
+    market_ids: BTreeSet<MarketId>,
+    account_keys: BTreeSet<PublicKeyHash>,
+    // `None` for accounts the batch would create
+    accounts: Vec<(PublicKeyHash, Option<AccountInfo>)>,
+    order_manager: OrderStateManager,
+    spot_clearinghouse: SpotClearingHouse,
+}
+
+/// Accounts, balances, order books and the asset and order managers of a ledger,
+/// enough to bootstrap a node without replaying blocks. Receipts are not carried over
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LedgerSnapshot {
+    pub accounts: HashMap<PublicKeyHash, AccountInfo>,
+    pub asset_manager: AssetManager,
+    pub order_manager: OrderStateManager,
+    pub spot_clearinghouse: SpotClearingHouse,
+    pub queued_transactions: HashMap<PublicKeyHash, BTreeMap<Nonce, SignedTransaction>>,
+    pub current_view: u64,
+    pub params: LedgerParams,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct LedgerState {
     pub accounts: HashMap<PublicKeyHash, AccountInfo>,
     pub asset_manager: AssetManager,
     pub order_manager: OrderStateManager,
     pub spot_clearinghouse: SpotClearingHouse,
     pub perps_clearinghouse: (),
+    pub receipts: ReceiptStore,
+    // transactions waiting on a nonce gap to be filled, keyed by account then nonce
+    pub queued_transactions: HashMap<PublicKeyHash, BTreeMap<Nonce, SignedTransaction>>,
+    // rules set at genesis, committed to the state root
+    pub params: LedgerParams,
+    faucet: PublicKeyHash,
+    // view of the last applied block, the only clock deadlines are checked against
+    current_view: u64,
+    // hashes of the most recently applied blocks, so a replayed block is not applied twice
+    applied_blocks: RecentlySeen,
+    #[serde(skip)]
+    fill_subscribers: Vec<mpsc::UnboundedSender<FillEvent>>,
+    // fills of the block being applied, sent to subscribers once it is done
+    #[serde(skip)]
+    pending_fills: Vec<FillEvent>,
+    #[serde(skip)]
+    top_of_book_subscribers: Vec<mpsc::UnboundedSender<TopOfBookEvent>>,
+    #[serde(skip)]
+    account_subscribers: HashMap<PublicKeyHash, Vec<mpsc::Sender<AccountInfoWithBalances>>>,
+    // workers executing the shards of a block, does not affect the resulting state
+    #[serde(skip, default = "config::retrieve_matching_threads")]
+    matching_threads: usize,
 }
 
 impl LedgerState {
@@ -110,29 +355,153 @@ impl LedgerState {
         let (pk, _) = config::retrieve_faucet_keys();
         let mut accounts: HashMap<PublicKeyHash, AccountInfo> = HashMap::new();
         accounts.insert(pk.to_bytes(), AccountInfo::create_faucet());
-        let asset_manager = AssetManager::new();
-
-        let asset_0 = asset_manager.assets.get(0).expect("Asset 0 to be present");
-        let asset_1 = asset_manager.assets.get(1).expect("Asset 1 to be present");
 
         let mut spot_clearinghouse = SpotClearingHouse::new();
         spot_clearinghouse.add_faucet_account();
 
-        spot_clearinghouse.add_market(
-            0,
-            1,
-            asset_0.asset_name.clone(),
-            asset_1.asset_name.clone(),
-            100,
-            3,
-        );
-
-        LedgerState {
+        let mut ledger_state = LedgerState {
             accounts,
             asset_manager: AssetManager::new(),
             order_manager: OrderStateManager::new(),
             spot_clearinghouse: spot_clearinghouse,
             perps_clearinghouse: (),
+            receipts: ReceiptStore::new(MAX_RECEIPTS),
+            queued_transactions: HashMap::new(),
+            params: config::retrieve_ledger_params(),
+            faucet: pk.to_bytes(),
+            current_view: 0,
+            applied_blocks: RecentlySeen::new(MAX_APPLIED_BLOCKS),
+            fill_subscribers: vec![],
+            pending_fills: vec![],
+            top_of_book_subscribers: vec![],
+            account_subscribers: HashMap::new(),
+            matching_threads: config::retrieve_matching_threads(),
+        };
+
+        ledger_state
+            .add_market(0, 1, 100, 3, LotSizes::default())
+            .expect("Genesis market to be valid");
+        ledger_state
+    }
+
+    pub fn snapshot(&self) -> LedgerSnapshot {
+        LedgerSnapshot {
+            accounts: self.accounts.clone(),
+            asset_manager: self.asset_manager.clone(),
+            order_manager: self.order_manager.clone(),
+            spot_clearinghouse: self.spot_clearinghouse.clone(),
+            queued_transactions: self.queued_transactions.clone(),
+            current_view: self.current_view,
+            params: self.params.clone(),
+        }
+    }
+
+    /// Ledger picking up from `snapshot`, with an empty receipt store
+    pub fn restore(snapshot: LedgerSnapshot) -> Self {
+        LedgerState {
+            accounts: snapshot.accounts,
+            asset_manager: snapshot.asset_manager,
+            order_manager: snapshot.order_manager,
+            spot_clearinghouse: snapshot.spot_clearinghouse,
+            perps_clearinghouse: (),
+            receipts: ReceiptStore::new(MAX_RECEIPTS),
+            queued_transactions: snapshot.queued_transactions,
+            params: snapshot.params,
+            faucet: config::retrieve_faucet_keys().0.to_bytes(),
+            current_view: snapshot.current_view,
+            applied_blocks: RecentlySeen::new(MAX_APPLIED_BLOCKS),
+            fill_subscribers: vec![],
+            pending_fills: vec![],
+            top_of_book_subscribers: vec![],
+            account_subscribers: HashMap::new(),
+            matching_threads: config::retrieve_matching_threads(),
+        }
+    }
+
+    /// View of the last applied block. Deadlines are checked against it rather than the
+    /// wall clock, so every replica expires the same transactions
+    pub fn current_view(&self) -> u64 {
+        self.current_view
+    }
+
+    /// Streams a `FillEvent` for every resting order touched by an executed order,
+    /// in the order the fills happened
+    pub fn subscribe_fills(&mut self) -> mpsc::UnboundedReceiver<FillEvent> {
+        let (fill_tx, fill_rx) = mpsc::unbounded_channel();
+        self.fill_subscribers.push(fill_tx);
+        fill_rx
+    }
+
+    fn emit_fills(&mut self, market_id: MarketId, taker_order_id: OrderId, fills: &[Fill]) {
+        self.pending_fills
+            .extend(fills.iter().map(|fill| FillEvent {
+                market_id,
+                taker_order_id,
+                fill: fill.clone(),
+            }));
+    }
+
+    fn flush_fills(&mut self) {
+        for event in std::mem::take(&mut self.pending_fills) {
+            // drop subscribers that have gone away
+            self.fill_subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+
+    /// Streams a `TopOfBookEvent` whenever an order, fill or cancel moves a market's best
+    /// bid or best ask, sent once the block that moved it is applied
+    pub fn subscribe_top_of_book(&mut self) -> mpsc::UnboundedReceiver<TopOfBookEvent> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        self.top_of_book_subscribers.push(event_tx);
+        event_rx
+    }
+
+    fn flush_top_of_book(&mut self) {
+        let events = self.spot_clearinghouse.take_top_of_book_events();
+        self.spot_clearinghouse
+            .record_best_price_history(self.current_view, &events);
+        for event in events {
+            self.top_of_book_subscribers
+                .retain(|subscriber| subscriber.send(event).is_ok());
+        }
+    }
+
+    /// Streams the account's info and balances after every block that changes them.
+    /// A subscriber that falls `ACCOUNT_SUBSCRIBER_BUFFER` updates behind is dropped
+    pub fn subscribe_account(
+        &mut self,
+        public_key: PublicKeyHash,
+    ) -> mpsc::Receiver<AccountInfoWithBalances> {
+        let (update_tx, update_rx) = mpsc::channel(ACCOUNT_SUBSCRIBER_BUFFER);
+        let subscribers = self.account_subscribers.entry(public_key).or_default();
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        subscribers.push(update_tx);
+        update_rx
+    }
+
+    fn subscribed_account_hashes(&self) -> Vec<(PublicKeyHash, Sha256Hash)> {
+        self.account_subscribers
+            .keys()
+            .map(|public_key| (*public_key, self.hash_account(public_key)))
+            .collect()
+    }
+
+    /// Pushes the new state of each subscribed account whose hash moved from `before`
+    fn flush_account_updates(&mut self, before: Vec<(PublicKeyHash, Sha256Hash)>) {
+        for (public_key, hash_before) in before {
+            if self.hash_account(&public_key) == hash_before {
+                continue;
+            }
+            let update = self.get_account_info_with_balances_or_default(&public_key);
+            let Some(subscribers) = self.account_subscribers.get_mut(&public_key) else {
+                continue;
+            };
+            // drop subscribers that have gone away or fallen too far behind
+            subscribers.retain(|subscriber| subscriber.try_send(update.clone()).is_ok());
+            if subscribers.is_empty() {
+                self.account_subscribers.remove(&public_key);
+            }
         }
     }
 
@@ -148,6 +517,65 @@ impl LedgerState {
         self.spot_clearinghouse.get_markets()
     }
 
+    /// Lists a market trading `base_asset` against `quote_asset`, both of which must be known
+    pub fn add_market(
+        &mut self,
+        base_asset: AssetId,
+        quote_asset: AssetId,
+        tick: u32,
+        tick_decimals: u8,
+        lot_sizes: LotSizes,
+    ) -> Result<MarketId, MarketError> {
+        let asset_name = |asset_id: AssetId| {
+            self.asset_manager
+                .assets
+                .get(asset_id as usize)
+                .map(|asset| asset.asset_name.clone())
+                .ok_or(MarketError::UnknownAsset(asset_id))
+        };
+        let base_asset_name = asset_name(base_asset)?;
+        let quote_asset_name = asset_name(quote_asset)?;
+
+        let market_id = self.spot_clearinghouse.add_market(
+            base_asset,
+            quote_asset,
+            base_asset_name,
+            quote_asset_name,
+            tick,
+            tick_decimals,
+            lot_sizes,
+        )?;
+        self.spot_clearinghouse
+            .set_prune_threshold(market_id, self.params.level_prune_threshold);
+        Ok(market_id)
+    }
+
+    pub fn get_order_book_depth(
+        &self,
+        market_id: MarketId,
+        depth: usize,
+    ) -> Option<OrderBookDepth> {
+        self.spot_clearinghouse.get_depth_from_id(market_id, depth)
+    }
+
+    /// Recent best bid and ask changes of a market, oldest first
+    pub fn get_best_price_history(&self, market_id: MarketId) -> Option<Vec<BestPriceSnapshot>> {
+        self.spot_clearinghouse
+            .get_best_price_history_from_id(market_id)
+    }
+
+    /// Price a taker of `base_lots` on the `direction` side would get from the book right now
+    pub fn get_quote(
+        &self,
+        market_id: MarketId,
+        direction: OrderDirection,
+        base_lots: u64,
+    ) -> Option<FirmQuote> {
+        let (_, _, precision) = self.get_market_precision(market_id).ok()?;
+        self.spot_clearinghouse
+            .get_quote_from_id(market_id, direction, base_lots, &precision)
+    }
+
     pub(crate) fn get_account_info_with_balances_or_default(
         &self,
         public_key: &PublicKeyHash,
@@ -173,6 +601,261 @@ impl LedgerState {
         });
     }
 
+    pub(crate) fn get_order(
+        &self,
+        public_key: &PublicKeyHash,
+        order_id: OrderId,
+    ) -> Option<OrderQueryResult> {
+        let account_info = self.accounts.get(public_key)?;
+
+        if let Some(limit_order) = account_info.get_open_order(order_id) {
+            return Some(OrderQueryResult {
+                order: Order::Limit(limit_order.clone()),
+                remaining_base_lots: limit_order.get_order_remaining(),
+            });
+        }
+
+        let order = account_info.get_completed_order(order_id)?;
+        // Completed market orders never rest on the book
+        let remaining_base_lots = match order {
+            Order::Limit(limit_order) => limit_order.get_order_remaining(),
+            Order::Market(_) => 0,
+        };
+
+        Some(OrderQueryResult {
+            order: order.clone(),
+            remaining_base_lots,
+        })
+    }
+
+    fn hash_account(&self, public_key: &PublicKeyHash) -> Sha256Hash {
+        let hashable = HashableAccount {
+            public_key,
+            account_info: self.accounts.get(public_key),
+            spot_balances: self.spot_clearinghouse.get_account_balance(public_key),
+        };
+
+        let encoded = bincode::serialize(&hashable).expect("bincode failed");
+        Sha256::digest(&encoded).into()
+    }
+
+    /// Merkle root over every account (info and balances), every order book and the order id
+    /// counter.
+    /// Accounts are sorted by public key so that all replicas derive the same root.
+    pub fn state_root(&self) -> Sha256Hash {
+        let mut public_keys: Vec<&PublicKeyHash> = self
+            .accounts
+            .keys()
+            .chain(self.spot_clearinghouse.get_account_keys())
+            .collect();
+        public_keys.sort();
+        public_keys.dedup();
+
+        let mut leaves: Vec<Sha256Hash> = public_keys
+            .into_iter()
+            .map(|public_key| self.hash_account(public_key))
+            .collect();
+        leaves.extend(self.spot_clearinghouse.get_market_hashes());
+        leaves.push(self.order_manager.hash());
+        let params = bincode::serialize(&self.params).expect("bincode failed");
+        leaves.push(Sha256::digest(&params).into());
+
+        Block::compute_merkle_root(leaves)
+    }
+
+    /// Per-asset balances of an account, sorted by asset id
+    pub fn get_balance_summary(&self, public_key: &PublicKeyHash) -> Vec<AssetBalanceSummary> {
+        let Some(account_balance) = self.spot_clearinghouse.get_account_balance(public_key) else {
+            return vec![];
+        };
+
+        let mut summary: Vec<AssetBalanceSummary> = account_balance
+            .asset_balances
+            .iter()
+            .map(|token_balance| AssetBalanceSummary {
+                asset_id: token_balance.asset_id,
+                total: token_balance.total_balance,
+                available: token_balance.available_balance,
+                locked: token_balance.locked_balance(),
+            })
+            .collect();
+        summary.sort_by_key(|asset_summary| asset_summary.asset_id);
+        summary
+    }
+
+    /// Taker fees accrued so far, as the fee collector's balance summary
+    pub fn get_fee_balance(&self) -> Vec<AssetBalanceSummary> {
+        self.get_balance_summary(&self.params.fee_collector)
+    }
+
+    /// Runs `spec` for `from` against a throwaway copy of the target market and the accounts
+    /// trading in it, leaving the ledger untouched. The nonce is not checked.
+    pub fn simulate_order(&self, from: &PublicKeyHash, spec: OrderSpec) -> OrderSimulation {
+        let market_ids = BTreeSet::from([spec.market_id]);
+        let account_keys = self.order_execution_accounts(*from, &market_ids);
+        let mut scratch = LedgerState {
+            accounts: account_keys
+                .iter()
+                .filter_map(|public_key| {
+                    self.accounts
+                        .get(public_key)
+                        .map(|account| (*public_key, account.clone()))
+                })
+                .collect(),
+            asset_manager: self.asset_manager.clone(),
+            order_manager: self.order_manager.clone(),
+            spot_clearinghouse: self
+                .spot_clearinghouse
+                .clone_part(&market_ids, &account_keys),
+            perps_clearinghouse: (),
+            receipts: ReceiptStore::new(0),
+            queued_transactions: HashMap::new(),
+            params: self.params.clone(),
+            faucet: self.faucet,
+            current_view: self.current_view,
+            applied_blocks: RecentlySeen::new(0),
+            fill_subscribers: vec![],
+            pending_fills: vec![],
+            top_of_book_subscribers: vec![],
+            account_subscribers: HashMap::new(),
+            matching_threads: 1,
+        };
+
+        let mut receipt =
+            TransactionReceipt::new(Sha256Hash::default(), TransactionStatus::Executed);
+        let market_id = spec.market_id;
+        let status = match scratch.execute_order(
+            *from,
+            market_id,
+            spec.direction,
+            spec.order_type,
+            &mut receipt,
+        ) {
+            Ok(()) => TransactionStatus::Executed,
+            Err(status) => status,
+        };
+
+        let balance_deltas = match self
+            .spot_clearinghouse
+            .get_quote_base_tick_from_id(market_id)
+        {
+            Some((quote_asset, base_asset, _, _)) => [base_asset, quote_asset]
+                .into_iter()
+                .map(|asset_id| {
+                    let (total_before, available_before) = self.balance_of(from, asset_id);
+                    let (total_after, available_after) = scratch.balance_of(from, asset_id);
+                    AssetBalanceDelta {
+                        asset_id,
+                        total: total_after as i128 - total_before as i128,
+                        available: available_after as i128 - available_before as i128,
+                    }
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        OrderSimulation {
+            status,
+            order_id: receipt.order_id,
+            fills: receipt.fills,
+            average_execution_price: receipt.average_execution_price,
+            unfilled_lots: receipt.unfilled_lots,
+            book_exhausted: receipt.book_exhausted,
+            balance_deltas,
+        }
+    }
+
+    /// (total, available) balance of `asset_id` held by `public_key`
+    fn balance_of(&self, public_key: &PublicKeyHash, asset_id: AssetId) -> (Balance, Balance) {
+        self.spot_clearinghouse
+            .get_account_balance(public_key)
+            .and_then(|balance| balance.find_asset_id(asset_id))
+            .map_or((0, 0), |token_balance| {
+                (token_balance.total_balance, token_balance.available_balance)
+            })
+    }
+
+    /// Markets the account has open orders in, or trades an asset it holds, sorted by id
+    pub fn get_account_markets(&self, public_key: &PublicKeyHash) -> Vec<MarketId> {
+        let mut market_ids: BTreeSet<MarketId> = self
+            .accounts
+            .get(public_key)
+            .map(|account_info| {
+                account_info
+                    .open_orders
+                    .values()
+                    .map(|order| order.common.market_id)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let Some(account_balance) = self.spot_clearinghouse.get_account_balance(public_key) {
+            let held_assets: Vec<AssetId> = account_balance
+                .asset_balances
+                .iter()
+                .filter(|balance| balance.total_balance > 0)
+                .map(|balance| balance.asset_id)
+                .collect();
+
+            market_ids.extend(
+                self.get_markets()
+                    .into_iter()
+                    .filter(|market| {
+                        held_assets.contains(&market.base_asset_id)
+                            || held_assets.contains(&market.quote_asset_id)
+                    })
+                    .map(|market| market.market_id),
+            );
+        }
+
+        market_ids.into_iter().collect()
+    }
+
+    /// Amount each of the account's open orders locks: quote for buys, base for sells
+    pub fn get_order_locks(&self, public_key: &PublicKeyHash) -> Vec<OrderLock> {
+        let Some(account_info) = self.accounts.get(public_key) else {
+            return vec![];
+        };
+
+        account_info
+            .open_orders
+            .values()
+            .filter_map(|order| {
+                let market_id = order.common.market_id;
+                let (quote_asset, base_asset, precision) =
+                    self.get_market_precision(market_id).ok()?;
+                let remaining_lots = order.get_order_remaining();
+
+                let (asset_id, amount) = match order.common.direction {
+                    OrderDirection::Buy => {
+                        let quote_lots =
+                            base_to_quote_lots(remaining_lots, order.price_multiple, &precision);
+                        (
+                            quote_asset,
+                            quote_lots as u128 * precision.quote_lot_size as u128,
+                        )
+                    }
+                    OrderDirection::Sell => (
+                        base_asset,
+                        remaining_lots as u128 * precision.base_lot_size as u128,
+                    ),
+                };
+
+                Some(OrderLock {
+                    order_id: order.common.id,
+                    market_id,
+                    direction: order.common.direction.clone(),
+                    asset_id,
+                    amount,
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_receipt(&self, tx_hash: &Sha256Hash) -> Option<TransactionReceipt> {
+        self.receipts.get(tx_hash).cloned()
+    }
+
     pub(crate) fn get_account_info_or_default(&self, public_key: &PublicKeyHash) -> AccountInfo {
         self.accounts.get(public_key).cloned().unwrap_or_default()
     }
@@ -191,35 +874,21 @@ impl LedgerState {
         self.spot_clearinghouse.get_account_balance_mut(public_key)
     }
 
-    fn get_order_position_from_open_orders(
-        account_info: &mut AccountInfo,
-        order_id: OrderId,
-    ) -> Option<usize> {
-        account_info
-            .open_orders
-            .iter()
-            .position(|order| order.common.id == order_id)
-    }
-
-    fn is_self_cross(
-        open_orders: &Vec<LimitOrder>,
-        curr_direction: &OrderDirection,
-        curr_price: u64,
-    ) -> bool {
-        match curr_direction {
-            OrderDirection::Buy => open_orders.iter().any(|order| {
-                order.common.direction == OrderDirection::Sell && curr_price >= order.price_multiple
-            }),
-            OrderDirection::Sell => open_orders.iter().any(|order| {
-                order.common.direction == OrderDirection::Buy && curr_price <= order.price_multiple
-            }),
-        }
-    }
-
     fn prepare_transfer_transaction(
         &mut self,
         transaction: &TransferTransaction,
     ) -> Result<TransferDelta, ExecError> {
+        if self
+            .asset_manager
+            .assets
+            .get(transaction.asset_id as usize)
+            .is_none()
+        {
+            return Err(ExecError::ResourceNotFound(Resource::Asset(
+                transaction.asset_id,
+            )));
+        }
+
         let from_account_balances = self.get_account_spot_balances_mut(&transaction.from);
 
         let from = PublicKeyString::from_bytes(transaction.from);
@@ -279,40 +948,29 @@ impl LedgerState {
         from_account_info.expected_nonce += 1;
         let expected_nonce = from_account_info.expected_nonce;
 
+        // balances are kept in the order assets were received, so look them up by asset id
         let from_account_balances = self.get_account_spot_balances_mut(&asset_out.account);
-
-        let from_token_balance =
-            &mut from_account_balances.asset_balances[asset_out.asset_id as usize];
-
+        let from_token_balance = SpotClearingHouse::get_account_token_balance_mut(
+            from_account_balances,
+            asset_out.asset_id,
+        );
         from_token_balance.available_balance -= asset_out.amount;
 
         let to_account_balances = self.get_account_spot_balances_mut(&asset_in.account);
+        let to_token_balance = SpotClearingHouse::get_account_token_balance_mut(
+            to_account_balances,
+            asset_in.asset_id,
+        );
+        to_token_balance.total_balance += asset_in.amount;
+        to_token_balance.available_balance += asset_in.amount;
 
-        let to_token_balance_opt = to_account_balances
-            .asset_balances
-            .iter_mut()
-            .find(|a| a.asset_id == asset_in.asset_id);
-
-        match to_token_balance_opt {
-            Some(account_balance) => {
-                account_balance.total_balance += asset_in.amount;
-                account_balance.available_balance += asset_in.amount;
-            }
-
-            None => to_account_balances
-                .asset_balances
-                .push(AccountTokenBalance {
-                    asset_id: asset_in.asset_id,
-                    available_balance: asset_in.amount,
-                    total_balance: asset_in.amount,
-                }),
-        }
         expected_nonce
     }
 
     pub(crate) fn handle_order_transaction(
         &mut self,
         transaction: &mut OrderTransaction,
+        tx_hash: Sha256Hash,
     ) -> Option<(PublicKeyHash, Nonce)> {
         let market_id = transaction.market_id;
         let user_account = transaction.from;
@@ -323,34 +981,96 @@ impl LedgerState {
         // check nonce
         let from_account_info = self.get_account_info_mut(&transaction.from);
         if nonce < from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Duplicate nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
             return None;
         }
 
         if nonce > from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Out of order nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        let mut receipt = TransactionReceipt::new(tx_hash, TransactionStatus::Executed);
+        if let Err(status) =
+            self.execute_order(user_account, market_id, direction, order_type, &mut receipt)
+        {
+            transaction.status = status;
             return None;
         }
+        if let Some(order_id) = receipt.order_id {
+            self.emit_fills(market_id, order_id, &receipt.fills);
+        }
+        self.receipts.insert(receipt);
+
+        let account = self.get_account_info_mut(&user_account);
+        account.expected_nonce += 1;
+        transaction.status = TransactionStatus::Executed;
+
+        return Some((user_account, account.expected_nonce));
+    }
 
+    /// Places an order for `user_account`, recording its fills on `receipt`.
+    /// Does not check or advance the account's nonce.
+    fn execute_order(
+        &mut self,
+        user_account: PublicKeyHash,
+        market_id: MarketId,
+        direction: OrderDirection,
+        order_type: order::OrderType,
+        receipt: &mut TransactionReceipt,
+    ) -> Result<(), TransactionStatus> {
+        order_type.check().map_err(TransactionStatus::Rejected)?;
+        // nothing, not even an order id, may be spent on an unknown market
+        let (_, _, precision) = self
+            .get_market_precision(market_id)
+            .map_err(TransactionStatus::Error)?;
+        self.check_market_accepts_orders(market_id)?;
+
+        let all_or_none = matches!(order_type, order::OrderType::AllOrNone(..));
         let order = match order_type {
-            order::OrderType::Limit(price, quote_size) => {
-                if Self::is_self_cross(&from_account_info.open_orders, &direction, price) {
-                    transaction.status = TransactionStatus::Rejected("Self Cross".to_string());
-                    return None;
+            order::OrderType::Limit(price, quote_size)
+            | order::OrderType::AllOrNone(price, quote_size) => {
+                let from_account_info = self.get_account_info_mut(&user_account);
+                if from_account_info.would_self_cross(&direction, price) {
+                    return Err(TransactionStatus::Rejected(RejectReason::SelfCross));
+                }
+
+                if from_account_info.open_orders.len() >= self.params.max_open_orders {
+                    return Err(TransactionStatus::Rejected(
+                        RejectReason::OpenOrderLimitReached,
+                    ));
                 }
 
-                let order = self.order_manager.new_limit_order(
+                let mut order = self.order_manager.new_limit_order(
                     market_id,
                     user_account,
                     direction,
                     price,
                     quote_size,
                 );
+                order.priority = self
+                    .spot_clearinghouse
+                    .take_priority(market_id)
+                    .unwrap_or_default();
+                order.all_or_none = all_or_none;
                 let account_info = self.get_account_info_mut(&user_account);
-                account_info.open_orders.push(order.clone());
+                account_info.add_open_order(order.clone());
                 Order::Limit(order)
             }
             order::OrderType::Market(order_size) => {
+                // against an empty side the order would execute with no fills
+                if let Some((best_bid, best_ask)) =
+                    self.spot_clearinghouse.get_best_prices_from_id(market_id)
+                {
+                    let opposing_best = match direction {
+                        OrderDirection::Buy => best_ask,
+                        OrderDirection::Sell => best_bid,
+                    };
+                    if opposing_best.is_none() {
+                        return Err(TransactionStatus::Rejected(RejectReason::NoLiquidity));
+                    }
+                }
+
                 let order = self.order_manager.new_market_order(
                     market_id,
                     user_account,
@@ -361,36 +1081,15 @@ impl LedgerState {
                 Order::Market(order)
             }
         };
-        let Some((quote_asset, base_asset, tick, tick_decimals)) = self
-            .spot_clearinghouse
-            .get_quote_base_tick_from_id(market_id)
-        else {
-            transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Market(market_id)));
-            return None;
-        };
-        let Some(quote_asset) = self.asset_manager.assets.get(quote_asset as usize) else {
-            transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Asset(quote_asset)));
-            return None;
-        };
-        let Some(base_asset) = self.asset_manager.assets.get(base_asset as usize) else {
-            transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Asset(base_asset)));
-            return None;
-        };
-
-        let precision = MarketPrecision {
-            base_lot_size: base_asset.lot_size,
-            quote_lot_size: quote_asset.lot_size,
-            tick,
-            tick_decimals,
-        };
 
         // Transaction should be atomic here
-        let result = self
-            .spot_clearinghouse
-            .handle_order(order.clone(), &precision);
+        let result = self.spot_clearinghouse.handle_order(
+            order.clone(),
+            &precision,
+            self.params.taker_fee_bps,
+        );
+
+        receipt.order_id = Some(order.get_id());
 
         // Update changes to respective account infos
         match result {
@@ -398,9 +1097,29 @@ impl LedgerState {
                 let ExecutionResults {
                     filled_orders,
                     residual_order,
+                    fee,
+                    settlement,
                     user_order_change,
                 } = result;
 
+                receipt.fills = Self::collect_fills(&filled_orders, &residual_order);
+                receipt.fees = fee;
+                if let Some(settlement) = &settlement {
+                    receipt.balance_deltas = Self::settlement_balance_deltas(settlement);
+                }
+                if let Some(order_change) = &user_order_change {
+                    receipt.average_execution_price = order_change.get_average_execution_price();
+                    if let order::OrderChange::MarketOrderChange {
+                        unfilled_lots,
+                        book_exhausted,
+                        ..
+                    } = order_change
+                    {
+                        receipt.unfilled_lots = *unfilled_lots;
+                        receipt.book_exhausted = *book_exhausted;
+                    }
+                }
+
                 match user_order_change {
                     Some(order_change) => match order_change {
                         order::OrderChange::LimitOrderChange {
@@ -409,13 +1128,9 @@ impl LedgerState {
                             average_execution_price: _,
                         } => {
                             let account_info = self.get_account_info_mut(&user_account);
-                            let limit_order_index =
-                                Self::get_order_position_from_open_orders(account_info, order_id)
-                                    .expect("No open order with order_id");
-
                             let limit_order = account_info
                                 .open_orders
-                                .get_mut(limit_order_index)
+                                .get_mut(&order_id)
                                 .expect("No open order with order_id");
 
                             let remaining_size = limit_order.get_order_remaining();
@@ -426,8 +1141,9 @@ impl LedgerState {
                             } else {
                                 // fulled filled
                                 limit_order.common.status = OrderStatus::Filled;
-                                let limit_order =
-                                    account_info.open_orders.remove(limit_order_index);
+                                let limit_order = account_info
+                                    .remove_open_order(order_id)
+                                    .expect("No open order with order_id");
                                 account_info
                                     .completed_orders
                                     .push(Order::Limit(limit_order));
@@ -438,9 +1154,12 @@ impl LedgerState {
                             filled_lots,
                             average_execution_price,
                             self_fill,
+                            unfilled_lots: _,
+                            book_exhausted: _,
                         } => match order {
+                            // self filled size counts towards the order being satisfied
                             Order::Market(MarketOrder::Buy(mut order)) => {
-                                if filled_lots < order.quote_size {
+                                if filled_lots + self_fill < order.quote_size {
                                     order.common.status = OrderStatus::PartiallyFilled;
                                 } else {
                                     order.common.status = OrderStatus::Filled;
@@ -456,7 +1175,7 @@ impl LedgerState {
                                     .push(Order::Market(MarketOrder::Buy(order)));
                             }
                             Order::Market(MarketOrder::Sell(mut order)) => {
-                                if filled_lots < order.base_size {
+                                if filled_lots + self_fill < order.base_size {
                                     order.common.status = OrderStatus::PartiallyFilled;
                                 } else {
                                     order.common.status = OrderStatus::Filled;
@@ -481,13 +1200,8 @@ impl LedgerState {
                 for filled_order in filled_orders.iter() {
                     let order_account = self.get_account_info_mut(&filled_order.common.account);
 
-                    let removed = Self::get_order_position_from_open_orders(
-                        order_account,
-                        filled_order.common.id,
-                    )
-                    .map(|i| order_account.open_orders.remove(i));
-
-                    let Some(mut removed) = removed else {
+                    let Some(mut removed) = order_account.remove_open_order(filled_order.common.id)
+                    else {
                         continue;
                     };
 
@@ -510,8 +1224,7 @@ impl LedgerState {
                         let order_account = self.get_account_info_mut(&account_public_key);
                         let order = order_account
                             .open_orders
-                            .iter_mut()
-                            .find(|order| order.common.id == order_id)
+                            .get_mut(&order_id)
                             .expect("Cant find open order with order id");
                         order.filled_base_lots += filled_base_lots;
                         order.self_filled += self_fill;
@@ -525,15 +1238,317 @@ impl LedgerState {
                 // do nothing
             }
         }
-        let account = self.get_account_info_mut(&user_account);
-        account.expected_nonce += 1;
-        transaction.status = TransactionStatus::Executed;
-
-        return Some((user_account, account.expected_nonce));
+        Ok(())
     }
 
-    pub(crate) fn handle_cancel_order_transaction(
-        &mut self,
+    /// Quote asset, base asset and lot/tick precision of a market
+    fn get_market_precision(
+        &self,
+        market_id: MarketId,
+    ) -> Result<(AssetId, AssetId, MarketPrecision), ExecError> {
+        let Some((quote_asset, base_asset, tick, tick_decimals)) = self
+            .spot_clearinghouse
+            .get_quote_base_tick_from_id(market_id)
+        else {
+            return Err(ExecError::ResourceNotFound(Resource::Market(market_id)));
+        };
+        let Some(quote) = self.asset_manager.assets.get(quote_asset as usize) else {
+            return Err(ExecError::ResourceNotFound(Resource::Asset(quote_asset)));
+        };
+        let Some(base) = self.asset_manager.assets.get(base_asset as usize) else {
+            return Err(ExecError::ResourceNotFound(Resource::Asset(base_asset)));
+        };
+        let lot_sizes = self
+            .spot_clearinghouse
+            .get_lot_sizes_from_id(market_id)
+            .unwrap_or_default();
+
+        let precision = MarketPrecision {
+            base_lot_size: lot_sizes.base.unwrap_or(base.lot_size),
+            quote_lot_size: lot_sizes.quote.unwrap_or(quote.lot_size),
+            tick,
+            tick_decimals,
+        };
+        Ok((quote_asset, base_asset, precision))
+    }
+
+    /// Checks that an order in a batch is well formed and that the account can fund it
+    fn precheck_order(
+        &self,
+        from: &PublicKeyHash,
+        spec: &OrderSpec,
+    ) -> Result<(), TransactionStatus> {
+        let (quote_asset, base_asset, precision) = self
+            .get_market_precision(spec.market_id)
+            .map_err(TransactionStatus::Error)?;
+        spec.order_type
+            .check()
+            .map_err(TransactionStatus::Rejected)?;
+
+        let (asset_id, need) = match (&spec.order_type, &spec.direction) {
+            (
+                order::OrderType::Limit(price, base_lots)
+                | order::OrderType::AllOrNone(price, base_lots),
+                OrderDirection::Buy,
+            ) => {
+                let quote_lots = base_to_quote_lots(*base_lots, *price, &precision);
+                (
+                    quote_asset,
+                    quote_lots as u128 * precision.quote_lot_size as u128,
+                )
+            }
+            (order::OrderType::Limit(_, base_lots), OrderDirection::Sell)
+            | (order::OrderType::AllOrNone(_, base_lots), OrderDirection::Sell)
+            | (order::OrderType::Market(base_lots), OrderDirection::Sell) => (
+                base_asset,
+                *base_lots as u128 * precision.base_lot_size as u128,
+            ),
+            (order::OrderType::Market(quote_lots), OrderDirection::Buy) => (
+                quote_asset,
+                *quote_lots as u128 * precision.quote_lot_size as u128,
+            ),
+        };
+
+        let have = self
+            .spot_clearinghouse
+            .get_account_balance(from)
+            .and_then(|balance| balance.find_asset_id(asset_id))
+            .map_or(0, |token_balance| token_balance.available_balance);
+
+        if have < need {
+            return Err(TransactionStatus::Error(ExecError::InsufficientFunds {
+                from: PublicKeyString::from_bytes(*from),
+                have,
+                need,
+            }));
+        }
+        Ok(())
+    }
+
+    fn snapshot_order_execution(
+        &self,
+        from: PublicKeyHash,
+        orders: &[OrderSpec],
+    ) -> OrderExecutionSnapshot {
+        let market_ids: BTreeSet<MarketId> = orders.iter().map(|spec| spec.market_id).collect();
+        let account_keys = self.order_execution_accounts(from, &market_ids);
+        let accounts = account_keys
+            .iter()
+            .map(|public_key| (*public_key, self.accounts.get(public_key).cloned()))
+            .collect();
+
+        OrderExecutionSnapshot {
+            spot_clearinghouse: self
+                .spot_clearinghouse
+                .clone_part(&market_ids, &account_keys),
+            market_ids,
+            account_keys,
+            accounts,
+            order_manager: self.order_manager.clone(),
+        }
+    }
+
+    /// Accounts executing orders for `from` in `market_ids` can touch: `from` itself and every
+    /// account resting in those markets, since matching settles against them
+    fn order_execution_accounts(
+        &self,
+        from: PublicKeyHash,
+        market_ids: &BTreeSet<MarketId>,
+    ) -> BTreeSet<PublicKeyHash> {
+        let mut account_keys: BTreeSet<PublicKeyHash> = market_ids
+            .iter()
+            .flat_map(|market_id| self.spot_clearinghouse.get_resting_accounts(*market_id))
+            .collect();
+        account_keys.insert(from);
+        account_keys
+    }
+
+    fn restore_order_execution(&mut self, snapshot: OrderExecutionSnapshot) {
+        // drops whatever the batch left in the touched markets and balances
+        self.spot_clearinghouse
+            .split_off(&snapshot.market_ids, &snapshot.account_keys);
+        self.spot_clearinghouse
+            .merge(snapshot.spot_clearinghouse, &snapshot.market_ids);
+        for (public_key, account) in snapshot.accounts {
+            match account {
+                Some(account) => self.accounts.insert(public_key, account),
+                None => self.accounts.remove(&public_key),
+            };
+        }
+        self.order_manager = snapshot.order_manager;
+    }
+
+    /// Executes each order of the batch in sequence under a single nonce.
+    /// Failed orders are recorded in `order_statuses`; with `all_or_nothing`,
+    /// the first failure rolls back the whole batch and leaves the nonce unused.
+    pub(crate) fn handle_batch_order_transaction(
+        &mut self,
+        transaction: &mut BatchOrderTransaction,
+        tx_hash: Sha256Hash,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        let user_account = transaction.from;
+        let nonce = transaction.nonce;
+
+        // check nonce
+        let from_account_info = self.get_account_info_mut(&transaction.from);
+        if nonce < from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
+            return None;
+        }
+
+        if nonce > from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        if transaction.orders.is_empty() {
+            transaction.status = TransactionStatus::Rejected(RejectReason::EmptyBatch);
+            return None;
+        }
+
+        if transaction.orders.len() > MAX_BATCH_ORDERS {
+            transaction.status = TransactionStatus::Rejected(RejectReason::BatchTooLarge);
+            return None;
+        }
+
+        let snapshot = transaction
+            .all_or_nothing
+            .then(|| self.snapshot_order_execution(user_account, &transaction.orders));
+
+        let mut receipt = TransactionReceipt::new(tx_hash, TransactionStatus::Executed);
+        let mut order_statuses = Vec::with_capacity(transaction.orders.len());
+        // held back until the batch can no longer be rolled back
+        let mut fill_events = vec![];
+
+        for (index, spec) in transaction.orders.iter().enumerate() {
+            let mut order_receipt = TransactionReceipt::new(tx_hash, TransactionStatus::Executed);
+            let result = self.precheck_order(&user_account, spec).and_then(|_| {
+                self.execute_order(
+                    user_account,
+                    spec.market_id,
+                    spec.direction.clone(),
+                    spec.order_type.clone(),
+                    &mut order_receipt,
+                )
+            });
+
+            match result {
+                Ok(()) => {
+                    if let Some(order_id) = order_receipt.order_id {
+                        fill_events.push((spec.market_id, order_id, order_receipt.fills.clone()));
+                    }
+                    receipt.fills.append(&mut order_receipt.fills);
+                    receipt.fees += order_receipt.fees;
+                    for delta in order_receipt.balance_deltas {
+                        Self::add_balance_delta(&mut receipt.balance_deltas, delta);
+                    }
+                    order_statuses.push(TransactionStatus::Executed);
+                }
+                Err(status) => {
+                    order_statuses.push(status);
+                    if let Some(snapshot) = snapshot {
+                        self.restore_order_execution(snapshot);
+                        transaction.order_statuses = order_statuses;
+                        transaction.status =
+                            TransactionStatus::Rejected(RejectReason::BatchOrderFailed(index));
+                        return None;
+                    }
+                }
+            }
+        }
+
+        for (market_id, order_id, fills) in fill_events {
+            self.emit_fills(market_id, order_id, &fills);
+        }
+        receipt.order_statuses = order_statuses.clone();
+        self.receipts.insert(receipt);
+
+        let account = self.get_account_info_mut(&user_account);
+        account.expected_nonce += 1;
+        transaction.order_statuses = order_statuses;
+        transaction.status = TransactionStatus::Executed;
+
+        Some((user_account, account.expected_nonce))
+    }
+
+    /// Taker's total balance changes from a settlement, sorted by asset id
+    fn settlement_balance_deltas(settlement: &TakerSettlement) -> Vec<BalanceDelta> {
+        let mut deltas = vec![];
+        let paid = BalanceDelta {
+            asset_id: settlement.asset_out,
+            amount: settlement.amount_out,
+            is_increase: false,
+        };
+        let received = BalanceDelta {
+            asset_id: settlement.asset_in,
+            amount: settlement.amount_in,
+            is_increase: true,
+        };
+        Self::add_balance_delta(&mut deltas, paid);
+        Self::add_balance_delta(&mut deltas, received);
+        deltas
+    }
+
+    /// Nets `delta` into the delta of the same asset, keeping `deltas` sorted by asset id
+    /// and leaving out assets that net to zero
+    fn add_balance_delta(deltas: &mut Vec<BalanceDelta>, delta: BalanceDelta) {
+        let index = match deltas.binary_search_by_key(&delta.asset_id, |other| other.asset_id) {
+            Ok(index) => index,
+            Err(index) => {
+                if delta.amount > 0 {
+                    deltas.insert(index, delta);
+                }
+                return;
+            }
+        };
+
+        let existing = &mut deltas[index];
+        if existing.is_increase == delta.is_increase {
+            existing.amount += delta.amount;
+        } else if existing.amount >= delta.amount {
+            existing.amount -= delta.amount;
+        } else {
+            existing.amount = delta.amount - existing.amount;
+            existing.is_increase = delta.is_increase;
+        }
+        if existing.amount == 0 {
+            deltas.remove(index);
+        }
+    }
+
+    /// Builds the per-maker fills of a taker order from its execution results
+    fn collect_fills(
+        filled_orders: &[LimitOrder],
+        residual_order: &Option<ResidualOrder>,
+    ) -> Vec<Fill> {
+        let mut fills: Vec<Fill> = filled_orders
+            .iter()
+            .filter(|order| order.common.status != OrderStatus::Cancelled)
+            .map(|order| Fill {
+                maker_order_id: order.common.id,
+                maker_account: order.common.account,
+                price_multiple: order.price_multiple,
+                base_lots: order.get_order_remaining(),
+            })
+            .filter(|fill| fill.base_lots > 0)
+            .collect();
+
+        if let Some(residual_order) = residual_order
+            && residual_order.filled_base_lots > 0
+        {
+            fills.push(Fill {
+                maker_order_id: residual_order.order_id,
+                maker_account: residual_order.account_public_key,
+                price_multiple: residual_order.price_multiple,
+                base_lots: residual_order.filled_base_lots,
+            });
+        }
+
+        fills
+    }
+
+    pub(crate) fn handle_cancel_order_transaction(
+        &mut self,
         transaction: &mut CancelOrderTransaction,
     ) -> Option<(PublicKeyHash, Nonce)> {
         let market_id = transaction.market_id;
@@ -544,12 +1559,17 @@ impl LedgerState {
         let from_account_info = self.get_account_info_or_default(&transaction.from);
         // todo should change the clone
         if nonce < from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Duplicate nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
             return None;
         }
 
         if nonce > from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Out of order nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        if let Err(status) = self.check_market_accepts_cancels(market_id) {
+            transaction.status = status;
             return None;
         }
 
@@ -561,47 +1581,225 @@ impl LedgerState {
             return None;
         };
 
-        let Some((quote_asset, base_asset, tick, tick_decimals)) = self
-            .spot_clearinghouse
-            .get_quote_base_tick_from_id(market_id)
-        else {
-            transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Market(market_id)));
+        if let Err(status) = self.cancel_open_order(user_account, market_id, order) {
+            transaction.status = status;
             return None;
-        };
-        let Some(quote_asset) = self.asset_manager.assets.get(quote_asset as usize) else {
-            transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Asset(quote_asset)));
+        }
+
+        let account = self.get_account_info_mut(&user_account);
+        account.expected_nonce += 1;
+        transaction.status = TransactionStatus::Executed;
+
+        return Some((user_account, account.expected_nonce));
+    }
+
+    pub(crate) fn handle_cancel_order_at_price_transaction(
+        &mut self,
+        transaction: &mut CancelOrderAtPriceTransaction,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        let market_id = transaction.market_id;
+        let user_account = transaction.from;
+        let nonce = transaction.nonce;
+
+        let from_account_info = self.get_account_info_or_default(&transaction.from);
+        if nonce < from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
+            return None;
+        }
+
+        if nonce > from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        if let Err(status) = self.check_market_accepts_cancels(market_id) {
+            transaction.status = status;
+            return None;
+        }
+
+        let order = from_account_info.find_cancel_order(
+            market_id,
+            &transaction.direction,
+            transaction.price,
+        );
+
+        let Some(order) = order else {
+            transaction.status = TransactionStatus::Rejected(RejectReason::NoMatchingOrder);
             return None;
         };
-        let Some(base_asset) = self.asset_manager.assets.get(base_asset as usize) else {
+
+        if let Err(status) = self.cancel_open_order(user_account, market_id, order) {
+            transaction.status = status;
+            return None;
+        }
+
+        let account = self.get_account_info_mut(&user_account);
+        account.expected_nonce += 1;
+        transaction.status = TransactionStatus::Executed;
+
+        Some((user_account, account.expected_nonce))
+    }
+
+    /// Rejects new orders on a halted or cancel only market. Unknown markets are left for the
+    /// caller to report
+    fn check_market_accepts_orders(&self, market_id: MarketId) -> Result<(), TransactionStatus> {
+        match self.spot_clearinghouse.get_market_status(market_id) {
+            Some(MarketStatus::Halted) => {
+                Err(TransactionStatus::Rejected(RejectReason::MarketHalted))
+            }
+            Some(MarketStatus::CancelOnly) => {
+                Err(TransactionStatus::Rejected(RejectReason::MarketCancelOnly))
+            }
+            Some(MarketStatus::Active) | None => Ok(()),
+        }
+    }
+
+    /// Rejects cancels and reductions on a halted market
+    fn check_market_accepts_cancels(&self, market_id: MarketId) -> Result<(), TransactionStatus> {
+        match self.spot_clearinghouse.get_market_status(market_id) {
+            Some(status) if !status.accepts_cancels() => {
+                Err(TransactionStatus::Rejected(RejectReason::MarketHalted))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Switches which operations a market accepts, only the admin may
+    pub(crate) fn handle_set_market_status_transaction(
+        &mut self,
+        transaction: &mut SetMarketStatusTransaction,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        let user_account = transaction.from;
+        let nonce = transaction.nonce;
+
+        let from_account_info = self.get_account_info_or_default(&transaction.from);
+        if nonce < from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
+            return None;
+        }
+
+        if nonce > from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        if user_account != self.params.admin {
+            transaction.status = TransactionStatus::Rejected(RejectReason::Unauthorized);
+            return None;
+        }
+
+        let market_id = transaction.market_id;
+        if !self
+            .spot_clearinghouse
+            .set_market_status(market_id, transaction.market_status)
+        {
             transaction.status =
-                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Asset(base_asset)));
+                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Market(market_id)));
             return None;
-        };
+        }
 
-        let precision = MarketPrecision {
-            base_lot_size: base_asset.lot_size,
-            quote_lot_size: quote_asset.lot_size,
-            tick,
-            tick_decimals,
-        };
+        let account = self.get_account_info_mut(&user_account);
+        account.expected_nonce += 1;
+        transaction.status = TransactionStatus::Executed;
+
+        Some((user_account, account.expected_nonce))
+    }
+
+    /// Pulls `order` off the book and moves it to the account's completed orders.
+    /// Does not check or advance the account's nonce.
+    fn cancel_open_order(
+        &mut self,
+        user_account: PublicKeyHash,
+        market_id: MarketId,
+        order: &LimitOrder,
+    ) -> Result<(), TransactionStatus> {
+        let (_, _, precision) = self
+            .get_market_precision(market_id)
+            .map_err(TransactionStatus::Error)?;
 
         if self.spot_clearinghouse.cancel_order(order, &precision) {
             let account = self.get_account_info_mut(&user_account);
-            let pos = account
-                .open_orders
-                .iter()
-                .position(|o| o.common.id == order.common.id)?;
-            account.open_orders.remove(pos);
-            account.completed_orders.push(Order::Limit(order.clone()));
+            if account.remove_open_order(order.common.id).is_some() {
+                account.completed_orders.push(Order::Limit(order.clone()));
+            }
+        };
+        Ok(())
+    }
+
+    pub(crate) fn handle_reduce_order_transaction(
+        &mut self,
+        transaction: &mut ReduceOrderTransaction,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        let market_id = transaction.market_id;
+        let user_account = transaction.from;
+        let nonce = transaction.nonce;
+
+        let from_account_info = self.get_account_info_or_default(&transaction.from);
+        if nonce < from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
+            return None;
+        }
+
+        if nonce > from_account_info.expected_nonce {
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        if let Err(status) = self.check_market_accepts_cancels(market_id) {
+            transaction.status = status;
+            return None;
+        }
+
+        let order = from_account_info
+            .get_open_order(transaction.order_id)
+            .filter(|order| order.common.market_id == market_id);
+        let Some(order) = order else {
+            transaction.status = TransactionStatus::Rejected(RejectReason::NoMatchingOrder);
+            return None;
         };
 
+        let base_lots = transaction.base_lots;
+        let remaining = order.get_order_remaining();
+        if base_lots == 0 {
+            transaction.status = TransactionStatus::Rejected(RejectReason::ZeroOrderSize);
+            return None;
+        }
+        if base_lots > remaining {
+            transaction.status =
+                TransactionStatus::Rejected(RejectReason::ReductionExceedsRemaining);
+            return None;
+        }
+
+        if base_lots == remaining {
+            if let Err(status) = self.cancel_open_order(user_account, market_id, order) {
+                transaction.status = status;
+                return None;
+            }
+        } else {
+            let precision = match self.get_market_precision(market_id) {
+                Ok((_, _, precision)) => precision,
+                Err(err) => {
+                    transaction.status = TransactionStatus::Error(err);
+                    return None;
+                }
+            };
+            if self
+                .spot_clearinghouse
+                .reduce_order(order, base_lots, &precision)
+                && let Some(open_order) = self
+                    .get_account_info_mut(&user_account)
+                    .open_orders
+                    .get_mut(&order.common.id)
+            {
+                open_order.base_lots -= base_lots;
+            }
+        }
+
         let account = self.get_account_info_mut(&user_account);
         account.expected_nonce += 1;
         transaction.status = TransactionStatus::Executed;
 
-        return Some((user_account, account.expected_nonce));
+        Some((user_account, account.expected_nonce))
     }
 
     pub(crate) fn handle_transfer_transaction(
@@ -611,12 +1809,18 @@ impl LedgerState {
         let from_account_info = self.get_account_info_mut(&transaction.from);
 
         if transaction.nonce < from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Duplicate Nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::DuplicateNonce);
             return None;
         }
 
         if transaction.nonce > from_account_info.expected_nonce {
-            transaction.status = TransactionStatus::Rejected("Out of order nonce".to_string());
+            transaction.status = TransactionStatus::Rejected(RejectReason::OutOfOrderNonce);
+            return None;
+        }
+
+        let is_drip = transaction.from == self.faucet && self.params.faucet_cooldown > 0;
+        if is_drip && !self.faucet_cooldown_elapsed(&transaction.to) {
+            transaction.status = TransactionStatus::Rejected(RejectReason::FaucetCooldown);
             return None;
         }
 
@@ -624,6 +1828,10 @@ impl LedgerState {
         match res {
             Ok(delta) => {
                 let expected_nonce = self.commit_transfer_transaction(delta);
+                if is_drip {
+                    self.get_account_info_mut(&transaction.to).last_faucet_drip =
+                        Some(self.current_view);
+                }
                 transaction.status = TransactionStatus::Executed;
                 Some((transaction.from, expected_nonce))
             }
@@ -634,31 +1842,396 @@ impl LedgerState {
         }
     }
 
-    pub(crate) fn apply(
+    /// Transfers the sender's available balance of the asset as it stands now, recording the
+    /// amount moved on the transaction's receipt
+    pub(crate) fn handle_transfer_all_transaction(
         &mut self,
-        transactions: &mut Vec<SignedTransaction>,
-    ) -> Vec<Option<(PublicKeyHash, Nonce)>> {
-        let mut account_nonces: Vec<Option<(PublicKeyHash, Nonce)>> = vec![];
+        transaction: &mut TransferAllTransaction,
+        tx_hash: Sha256Hash,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        let available = self
+            .spot_clearinghouse
+            .get_account_balance(&transaction.from)
+            .and_then(|balance| balance.find_asset_id(transaction.asset_id))
+            .map_or(0, |token_balance| token_balance.available_balance);
+        if available == 0 {
+            transaction.status = TransactionStatus::Rejected(RejectReason::NothingToTransfer);
+            return None;
+        }
 
-        for transaction in transactions.iter_mut() {
-            match &mut transaction.tx {
-                UnsignedTransaction::Transfer(transfer_transaction) => {
-                    account_nonces.push(self.handle_transfer_transaction(transfer_transaction))
-                }
-                UnsignedTransaction::Order(order_transaction) => {
-                    account_nonces.push(self.handle_order_transaction(order_transaction))
-                }
-                UnsignedTransaction::CancelOrder(cancel_order_transaction) => {
-                    account_nonces
-                        .push(self.handle_cancel_order_transaction(cancel_order_transaction));
-                }
-            }
+        let mut transfer = TransferTransaction {
+            from: transaction.from,
+            to: transaction.to,
+            amount: available,
+            asset_id: transaction.asset_id,
+            status: TransactionStatus::Pending,
+            nonce: transaction.nonce,
+            valid_until: transaction.valid_until,
+        };
+        let res = self.handle_transfer_transaction(&mut transfer);
+        transaction.status = transfer.status;
+
+        if res.is_some() {
+            let mut receipt = TransactionReceipt::new(tx_hash, TransactionStatus::Executed);
+            receipt.balance_deltas = vec![BalanceDelta {
+                asset_id: transaction.asset_id,
+                amount: available,
+                is_increase: false,
+            }];
+            self.receipts.insert(receipt);
         }
-        return account_nonces;
+        res
     }
 
-    pub(crate) fn apply_block(&mut self, block: &mut Block) -> Vec<Option<(PublicKeyHash, Nonce)>> {
-        return self.apply(block.transactions_mut());
+    /// Whether `recipient` has waited the faucet cooldown since its last drip
+    fn faucet_cooldown_elapsed(&self, recipient: &PublicKeyHash) -> bool {
+        self.accounts
+            .get(recipient)
+            .and_then(|account| account.last_faucet_drip)
+            .is_none_or(|view| self.current_view >= view + self.params.faucet_cooldown)
+    }
+
+    /// Nonce and balance plausibility checks, run before a transaction executes so a block
+    /// padded with transactions that can only fail is cheap to validate. Nothing is touched
+    /// for a rejected transaction: no account entry, order id or book priority
+    fn screen_transaction(&self, transaction: &SignedTransaction) -> Result<(), TransactionStatus> {
+        let from = transaction.get_from_account();
+        let expected_nonce = self
+            .accounts
+            .get(&from)
+            .map_or(0, |account| account.expected_nonce);
+        if transaction.get_nonce() < expected_nonce {
+            return Err(TransactionStatus::Rejected(RejectReason::DuplicateNonce));
+        }
+        if transaction.get_nonce() > expected_nonce {
+            return Err(TransactionStatus::Rejected(RejectReason::OutOfOrderNonce));
+        }
+
+        match &transaction.tx {
+            UnsignedTransaction::Order(order) => self.precheck_order(
+                &from,
+                &OrderSpec {
+                    market_id: order.market_id,
+                    direction: order.direction.clone(),
+                    order_type: order.order_type.clone(),
+                },
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn execute_transaction(
+        &mut self,
+        transaction: &mut SignedTransaction,
+    ) -> Option<(PublicKeyHash, Nonce)> {
+        if let Err(status) = self.screen_transaction(transaction) {
+            transaction.set_status(status);
+            return None;
+        }
+
+        match &mut transaction.tx {
+            UnsignedTransaction::Transfer(transfer_transaction) => {
+                self.handle_transfer_transaction(transfer_transaction)
+            }
+            UnsignedTransaction::Order(order_transaction) => {
+                self.handle_order_transaction(order_transaction, transaction.hash)
+            }
+            UnsignedTransaction::CancelOrder(cancel_order_transaction) => {
+                self.handle_cancel_order_transaction(cancel_order_transaction)
+            }
+            UnsignedTransaction::CancelOrderAtPrice(cancel_order_transaction) => {
+                self.handle_cancel_order_at_price_transaction(cancel_order_transaction)
+            }
+            UnsignedTransaction::BatchOrder(batch_order_transaction) => {
+                self.handle_batch_order_transaction(batch_order_transaction, transaction.hash)
+            }
+            UnsignedTransaction::ReduceOrder(reduce_order_transaction) => {
+                self.handle_reduce_order_transaction(reduce_order_transaction)
+            }
+            UnsignedTransaction::SetMarketStatus(set_market_status_transaction) => {
+                self.handle_set_market_status_transaction(set_market_status_transaction)
+            }
+            UnsignedTransaction::TransferAll(transfer_all_transaction) => {
+                self.handle_transfer_all_transaction(transfer_all_transaction, transaction.hash)
+            }
+        }
+    }
+
+    /// Buffers a transaction whose nonce is ahead of the account's expected nonce.
+    /// Returns false if the account's queue is full.
+    fn queue_transaction(&mut self, transaction: &SignedTransaction) -> bool {
+        let queue = self
+            .queued_transactions
+            .entry(transaction.get_from_account())
+            .or_default();
+
+        let nonce = transaction.get_nonce();
+        if !queue.contains_key(&nonce) && queue.len() >= MAX_QUEUED_TRANSACTIONS_PER_ACCOUNT {
+            return false;
+        }
+        queue.insert(nonce, transaction.clone());
+        true
+    }
+
+    /// Executes queued transactions for an account for as long as the nonces are contiguous
+    fn replay_queued_transactions(
+        &mut self,
+        account: PublicKeyHash,
+        mut expected_nonce: Nonce,
+        outcomes: &mut Vec<TransactionOutcome>,
+    ) {
+        loop {
+            let Some(queue) = self.queued_transactions.get_mut(&account) else {
+                return;
+            };
+
+            // drop stale entries that can never execute
+            *queue = queue.split_off(&expected_nonce);
+            let next = queue.remove(&expected_nonce);
+            if queue.is_empty() {
+                self.queued_transactions.remove(&account);
+            }
+
+            let Some(mut transaction) = next else {
+                return;
+            };
+            if transaction.is_expired(self.current_view) {
+                // the nonce stays unused, so later queued nonces keep waiting on it
+                transaction.set_status(TransactionStatus::Rejected(RejectReason::Expired));
+                outcomes.push(TransactionOutcome::new(&transaction, None));
+                return;
+            }
+
+            let result = self.execute_transaction(&mut transaction);
+            outcomes.push(TransactionOutcome::new(&transaction, result));
+            match result {
+                Some((_, next_nonce)) => expected_nonce = next_nonce,
+                None => return,
+            }
+        }
+    }
+
+    /// Executes `transactions` as part of the block of `current_view`.
+    /// Transactions whose deadline has passed are rejected without executing.
+    /// Returns an outcome per transaction executed or queued, including replayed queued ones
+    pub(crate) fn apply(
+        &mut self,
+        transactions: &mut Vec<SignedTransaction>,
+    ) -> Vec<TransactionOutcome> {
+        let mut outcomes: Vec<TransactionOutcome> = vec![];
+
+        for transaction in transactions.iter_mut() {
+            if transaction.is_expired(self.current_view) {
+                transaction.set_status(TransactionStatus::Rejected(RejectReason::Expired));
+                outcomes.push(TransactionOutcome::new(transaction, None));
+                continue;
+            }
+
+            let from = transaction.get_from_account();
+            let expected_nonce = self
+                .accounts
+                .get(&from)
+                .map_or(0, |account| account.expected_nonce);
+
+            if transaction.get_nonce() > expected_nonce && self.queue_transaction(transaction) {
+                // Executed once the nonce gap is filled
+                outcomes.push(TransactionOutcome::new(transaction, None));
+                continue;
+            }
+
+            let result = self.execute_transaction(transaction);
+            outcomes.push(TransactionOutcome::new(transaction, result));
+
+            if let Some((account, next_nonce)) = result {
+                self.replay_queued_transactions(account, next_nonce, &mut outcomes);
+            }
+        }
+        return outcomes;
+    }
+
+    /// Executes the block's transactions in canonical order (see `SignedTransaction::canonical_cmp`),
+    /// so every replica reaches the same state whatever order the proposer listed them in.
+    /// Consensus critical. The block keeps its own order, so its hash is unchanged.
+    /// A block that was already applied is skipped, an empty block only advances the view.
+    /// A block carrying any transaction with an invalid signature is rejected as a whole
+    pub(crate) fn apply_block(&mut self, block: &mut Block) -> Vec<TransactionOutcome> {
+        if !self.applied_blocks.insert(block.hash()) {
+            return vec![];
+        }
+
+        self.current_view = block.view_number();
+        if block.is_empty() {
+            return vec![];
+        }
+
+        // a leader can bypass the mempool, so signatures are checked again before executing
+        if !block
+            .transactions()
+            .iter()
+            .all(SignedTransaction::verify_sender)
+        {
+            return Self::reject_block(block);
+        }
+        let transactions = block.transactions_mut();
+
+        let mut indexed: Vec<(usize, SignedTransaction)> = std::mem::take(transactions)
+            .into_iter()
+            .enumerate()
+            .collect();
+        indexed.sort_by(|(_, a), (_, b)| a.canonical_cmp(b));
+        let (positions, ordered): (Vec<usize>, Vec<SignedTransaction>) =
+            indexed.into_iter().unzip();
+
+        let accounts_before = self.subscribed_account_hashes();
+        let (ordered, outcomes) = self.apply_sharded(ordered);
+        self.spot_clearinghouse
+            .sweep_fees(self.params.fee_collector);
+        self.flush_fills();
+        self.flush_top_of_book();
+        self.flush_account_updates(accounts_before);
+
+        let mut restored: Vec<Option<SignedTransaction>> = vec![None; ordered.len()];
+        for (position, transaction) in positions.into_iter().zip(ordered) {
+            restored[position] = Some(transaction);
+        }
+        *transactions = restored.into_iter().flatten().collect();
+
+        outcomes
+    }
+
+    /// Rejects every transaction in the block without executing any of them
+    fn reject_block(block: &mut Block) -> Vec<TransactionOutcome> {
+        block
+            .transactions_mut()
+            .iter_mut()
+            .map(|transaction| {
+                let reason = if transaction.verify_sender() {
+                    RejectReason::InvalidBlock
+                } else {
+                    RejectReason::InvalidSignature
+                };
+                transaction.set_status(TransactionStatus::Rejected(reason));
+                TransactionOutcome::new(transaction, None)
+            })
+            .collect()
+    }
+
+    /// Executes the canonically ordered block shard by shard (see `plan_shards`). Each shard
+    /// hands out order ids from where the previous shard's reservation ends, so shards are
+    /// independent and running them on several workers gives the same state as running them
+    /// one after another. Returns the transactions in the order they were given
+    fn apply_sharded(
+        &mut self,
+        ordered: Vec<SignedTransaction>,
+    ) -> (Vec<SignedTransaction>, Vec<TransactionOutcome>) {
+        let shards = plan_shards(self, &ordered);
+
+        let mut slots: Vec<Option<SignedTransaction>> = ordered.into_iter().map(Some).collect();
+        let mut first_order_id = self.order_manager.next_id();
+        let mut work: Vec<(OrderId, Vec<SignedTransaction>)> = vec![];
+        for shard in shards.iter() {
+            let transactions = shard
+                .positions
+                .iter()
+                .filter_map(|position| slots[*position].take())
+                .collect();
+            work.push((first_order_id, transactions));
+            first_order_id += shard.order_ids;
+        }
+
+        let mut outcomes = vec![];
+        let mut executed: Vec<Vec<SignedTransaction>> = vec![];
+        if self.matching_threads <= 1 || shards.len() <= 1 {
+            for (first_order_id, mut transactions) in work {
+                self.order_manager.set_next_id(first_order_id);
+                outcomes.extend(self.apply(&mut transactions));
+                executed.push(transactions);
+            }
+        } else {
+            let fragments: Vec<(LedgerState, Vec<SignedTransaction>)> = shards
+                .iter()
+                .zip(work)
+                .map(|(shard, (first_order_id, transactions))| {
+                    (self.split_off(shard, first_order_id), transactions)
+                })
+                .collect();
+
+            let results = run_on_workers(fragments, self.matching_threads);
+            for (shard, (fragment, transactions, shard_outcomes)) in shards.iter().zip(results) {
+                self.order_manager
+                    .set_next_id(fragment.order_manager.next_id());
+                self.merge(fragment, shard);
+                outcomes.extend(shard_outcomes);
+                executed.push(transactions);
+            }
+        }
+
+        for (shard, transactions) in shards.iter().zip(executed) {
+            for (position, transaction) in shard.positions.iter().zip(transactions) {
+                slots[*position] = Some(transaction);
+            }
+        }
+        (slots.into_iter().flatten().collect(), outcomes)
+    }
+
+    /// Moves the state `shard` touches into a ledger of its own, which hands out order ids
+    /// from `first_order_id`. `merge` puts it back
+    fn split_off(&mut self, shard: &ExecutionShard, first_order_id: OrderId) -> LedgerState {
+        let mut order_manager = OrderStateManager::new();
+        order_manager.set_next_id(first_order_id);
+
+        LedgerState {
+            accounts: shard
+                .accounts
+                .iter()
+                .filter_map(|public_key| {
+                    self.accounts
+                        .remove(public_key)
+                        .map(|account_info| (*public_key, account_info))
+                })
+                .collect(),
+            asset_manager: self.asset_manager.clone(),
+            order_manager,
+            spot_clearinghouse: self
+                .spot_clearinghouse
+                .split_off(&shard.markets, &shard.accounts),
+            perps_clearinghouse: (),
+            receipts: ReceiptStore::new(usize::MAX),
+            queued_transactions: shard
+                .accounts
+                .iter()
+                .filter_map(|public_key| {
+                    self.queued_transactions
+                        .remove(public_key)
+                        .map(|queue| (*public_key, queue))
+                })
+                .collect(),
+            params: self.params.clone(),
+            faucet: self.faucet,
+            current_view: self.current_view,
+            applied_blocks: RecentlySeen::new(0),
+            fill_subscribers: vec![],
+            pending_fills: vec![],
+            top_of_book_subscribers: vec![],
+            account_subscribers: HashMap::new(),
+            matching_threads: 1,
+        }
+    }
+
+    fn merge(&mut self, fragment: LedgerState, shard: &ExecutionShard) {
+        self.accounts.extend(fragment.accounts);
+        self.spot_clearinghouse
+            .merge(fragment.spot_clearinghouse, &shard.markets);
+        for receipt in fragment.receipts.into_receipts() {
+            self.receipts.insert(receipt);
+        }
+        self.queued_transactions
+            .extend(fragment.queued_transactions);
+        self.pending_fills.extend(fragment.pending_fills);
+    }
+
+    pub fn set_matching_threads(&mut self, matching_threads: usize) {
+        self.matching_threads = matching_threads;
     }
 
     pub fn handle_query(&self, query: ClientQuery) -> ClientResponse {
@@ -668,6 +2241,14 @@ impl LedgerState {
                     self.get_account_info_with_balances_or_default(&public_key);
                 ClientResponse::AccountQueryReponse(account_info_with_balances)
             }
+            crate::node::client::handler::ClientQuery::BalanceSummaryQuery(public_key) => {
+                let balance_summary = self.get_balance_summary(&public_key);
+                ClientResponse::BalanceSummaryQueryResponse(balance_summary)
+            }
+            crate::node::client::handler::ClientQuery::FeeBalanceQuery => {
+                let fee_balance = self.get_fee_balance();
+                ClientResponse::FeeBalanceQueryResponse(fee_balance)
+            }
             crate::node::client::handler::ClientQuery::AssetQuery => {
                 let asset_info = self.get_asset_info();
                 ClientResponse::AssetQueryResponse(asset_info)
@@ -676,31 +2257,131 @@ impl LedgerState {
                 let market_info = self.get_market_info(market_id);
                 ClientResponse::MarketInfoQueryResponse(market_info)
             }
+            crate::node::client::handler::ClientQuery::OrderBookQuery { market_id, depth } => {
+                let depth = self.get_order_book_depth(market_id, depth);
+                ClientResponse::OrderBookQueryResponse(depth)
+            }
+            crate::node::client::handler::ClientQuery::BestPriceHistoryQuery(market_id) => {
+                let history = self.get_best_price_history(market_id);
+                ClientResponse::BestPriceHistoryQueryResponse(history)
+            }
+            crate::node::client::handler::ClientQuery::QuoteQuery {
+                market_id,
+                side,
+                size,
+            } => {
+                let quote = self.get_quote(market_id, side, size);
+                ClientResponse::QuoteQueryResponse(quote)
+            }
             crate::node::client::handler::ClientQuery::MarketsQuery => {
                 let market_infos = self.get_markets();
                 ClientResponse::MarketsQueryResponse(market_infos)
             }
+            crate::node::client::handler::ClientQuery::OrderQuery {
+                public_key,
+                order_id,
+            } => {
+                let order = self.get_order(&public_key, order_id);
+                ClientResponse::OrderQueryResponse(order)
+            }
+            crate::node::client::handler::ClientQuery::AccountMarketsQuery(public_key) => {
+                let market_ids = self.get_account_markets(&public_key);
+                ClientResponse::AccountMarketsQueryResponse(market_ids)
+            }
+            crate::node::client::handler::ClientQuery::OrderLocksQuery(public_key) => {
+                let order_locks = self.get_order_locks(&public_key);
+                ClientResponse::OrderLocksQueryResponse(order_locks)
+            }
+            crate::node::client::handler::ClientQuery::SimulateOrder { public_key, order } => {
+                let simulation = self.simulate_order(&public_key, order);
+                ClientResponse::SimulateOrderResponse(simulation)
+            }
+            crate::node::client::handler::ClientQuery::ReceiptQuery(tx_hash) => {
+                let receipt = self.get_receipt(&tx_hash);
+                ClientResponse::ReceiptQueryResponse(receipt)
+            }
+            crate::node::client::handler::ClientQuery::HealthQuery
+            | crate::node::client::handler::ClientQuery::SubscribeAccount(_) => {
+                unreachable!("health queries and subscriptions are answered by the replica")
+            }
         }
     }
 }
 
+type ShardResult = (LedgerState, Vec<SignedTransaction>, Vec<TransactionOutcome>);
+
+/// Applies each fragment's transactions, spreading the fragments over up to `threads` workers.
+/// Results are returned in the order of `fragments`
+fn run_on_workers(
+    fragments: Vec<(LedgerState, Vec<SignedTransaction>)>,
+    threads: usize,
+) -> Vec<ShardResult> {
+    let threads = threads.min(fragments.len()).max(1);
+    let mut groups: Vec<Vec<(usize, LedgerState, Vec<SignedTransaction>)>> =
+        (0..threads).map(|_| vec![]).collect();
+    for (index, (fragment, transactions)) in fragments.into_iter().enumerate() {
+        groups[index % threads].push((index, fragment, transactions));
+    }
+
+    let mut results: Vec<(usize, ShardResult)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = groups
+            .into_iter()
+            .map(|group| {
+                scope.spawn(move || {
+                    group
+                        .into_iter()
+                        .map(|(index, mut fragment, mut transactions)| {
+                            let outcomes = fragment.apply(&mut transactions);
+                            (index, (fragment, transactions, outcomes))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Matching worker panicked"))
+            .collect()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
 #[cfg(test)]
 mod tests {
     mod test_spot_clearinghouse {
+        use std::collections::BTreeSet;
+
         use ed25519_dalek::SigningKey;
+        use tokio::sync::mpsc;
 
         use crate::{
             config,
             hotstuff::{block::Block, crypto::QuorumCertificate},
+            node::client::handler::{ClientQuery, ClientResponse},
             state::{
+                asset::Asset,
                 order::{Order, OrderDirection, OrderId, OrderStatus, OrderType},
-                spot_clearinghouse::{MarketId, MarketPrecision},
-                state::{AccountInfo, LedgerState, Nonce},
+                receipt::{BalanceDelta, FillEvent, TopOfBookEvent},
+                shard::plan_shards,
+                spot_clearinghouse::{
+                    MarketId, MarketPrecision, SpotClearingHouse, base_to_quote_lots, taker_fee,
+                },
+                spot_market::{BestPriceSnapshot, LotSizes, MarketStatus},
+                state::{
+                    ACCOUNT_SUBSCRIBER_BUFFER, AccountInfo, AccountInfoWithBalances,
+                    AssetBalanceSummary, ExecError, LedgerState,
+                    MAX_QUEUED_TRANSACTIONS_PER_ACCOUNT, Nonce, Resource,
+                },
             },
             test_utils::test_helpers::{get_alice_sk, get_bob_sk, get_carol_sk},
             types::transaction::{
-                CancelOrderTransaction, OrderTransaction, PublicKeyHash, SignedTransaction,
-                TransactionStatus, TransferTransaction, UnsignedTransaction,
+                BatchOrderTransaction, CancelOrderAtPriceTransaction, CancelOrderTransaction,
+                OrderSpec, OrderTransaction, PublicKeyHash, ReduceOrderTransaction, RejectReason,
+                SetMarketStatusTransaction, Sha256Hash, SignedTransaction, TransactionStatus,
+                TransferAllTransaction, TransferTransaction, UnsignedTransaction,
             },
         };
 
@@ -719,11 +2400,12 @@ mod tests {
                 asset_id,
                 nonce,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
             unsigned.sign(faucet_sk)
         }
 
-        fn _create_transfer_txn(
+        fn create_transfer_txn(
             sk: &mut SigningKey,
             to: PublicKeyHash,
             amount: u128,
@@ -739,6 +2421,24 @@ mod tests {
                 asset_id,
                 nonce,
                 status: TransactionStatus::Pending,
+                valid_until: None,
+            });
+            unsigned.sign(sk)
+        }
+
+        fn create_transfer_all_txn(
+            sk: &mut SigningKey,
+            to: PublicKeyHash,
+            asset_id: u32,
+            nonce: Nonce,
+        ) -> SignedTransaction {
+            let unsigned = UnsignedTransaction::TransferAll(TransferAllTransaction {
+                from: sk.verifying_key().to_bytes(),
+                to,
+                asset_id,
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: None,
             });
             unsigned.sign(sk)
         }
@@ -759,6 +2459,7 @@ mod tests {
                 order_type,
                 status: TransactionStatus::Pending,
                 nonce,
+                valid_until: None,
             });
             unsigned.sign(sk)
         }
@@ -777,17 +2478,127 @@ mod tests {
                 order_id,
                 status: TransactionStatus::Pending,
                 nonce,
+                valid_until: None,
+            });
+            unsigned.sign(sk)
+        }
+
+        fn create_cancel_at_price_txn(
+            sk: &mut SigningKey,
+            market_id: MarketId,
+            direction: OrderDirection,
+            price: u64,
+            nonce: Nonce,
+        ) -> SignedTransaction {
+            let unsigned = UnsignedTransaction::CancelOrderAtPrice(CancelOrderAtPriceTransaction {
+                from: sk.verifying_key().to_bytes(),
+                market_id,
+                direction,
+                price,
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: None,
+            });
+            unsigned.sign(sk)
+        }
+
+        fn create_reduce_txn(
+            sk: &mut SigningKey,
+            market_id: MarketId,
+            order_id: OrderId,
+            base_lots: u64,
+            nonce: Nonce,
+        ) -> SignedTransaction {
+            let unsigned = UnsignedTransaction::ReduceOrder(ReduceOrderTransaction {
+                from: sk.verifying_key().to_bytes(),
+                market_id,
+                order_id,
+                base_lots,
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: None,
+            });
+            unsigned.sign(sk)
+        }
+
+        fn create_set_market_status_txn(
+            sk: &mut SigningKey,
+            market_id: MarketId,
+            market_status: MarketStatus,
+            nonce: Nonce,
+        ) -> SignedTransaction {
+            let unsigned = UnsignedTransaction::SetMarketStatus(SetMarketStatusTransaction {
+                from: sk.verifying_key().to_bytes(),
+                market_id,
+                market_status,
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: None,
+            });
+            unsigned.sign(sk)
+        }
+
+        fn create_batch_order_txn(
+            sk: &mut SigningKey,
+            orders: Vec<OrderSpec>,
+            all_or_nothing: bool,
+            nonce: Nonce,
+        ) -> SignedTransaction {
+            let binding = sk.verifying_key();
+            let pk = binding.as_bytes();
+            let unsigned = UnsignedTransaction::BatchOrder(BatchOrderTransaction {
+                from: *pk,
+                orders,
+                all_or_nothing,
+                status: TransactionStatus::Pending,
+                order_statuses: vec![],
+                nonce,
+                valid_until: None,
             });
             unsigned.sign(sk)
         }
 
+        fn order_spec(direction: OrderDirection, order_type: OrderType) -> OrderSpec {
+            OrderSpec {
+                market_id: 0,
+                direction,
+                order_type,
+            }
+        }
+
+        fn batch_order_statuses(transaction: &SignedTransaction) -> &Vec<TransactionStatus> {
+            let UnsignedTransaction::BatchOrder(batch) = &transaction.tx else {
+                panic!("Expected batch order transaction");
+            };
+            &batch.order_statuses
+        }
+
+        /// Balance of `asset_id` held outside the faucet
+        fn total_supply(ledger_state: &LedgerState, asset_id: u32) -> u128 {
+            let (faucet_pk, _) = config::retrieve_faucet_keys();
+            let clearinghouse = &ledger_state.spot_clearinghouse;
+            clearinghouse
+                .get_account_keys()
+                .filter(|public_key| **public_key != faucet_pk.to_bytes())
+                .filter_map(|public_key| clearinghouse.get_account_balance(public_key))
+                .filter_map(|balance| balance.find_asset_id(asset_id))
+                .map(|token_balance| token_balance.total_balance)
+                .sum()
+        }
+
         fn create_block(transactions: Vec<SignedTransaction>) -> Block {
+            create_block_in_view(transactions, 0)
+        }
+
+        fn create_block_in_view(transactions: Vec<SignedTransaction>, view_number: u64) -> Block {
             Block::Normal {
                 parent_id: [0; 32],
+                merkle_root: Block::hash_transactions(&transactions),
                 transactions,
-                view_number: 0,
-                justify: QuorumCertificate::mock(0),
-                merkle_root: [0; 32],
+                view_number,
+                justify: QuorumCertificate::mock(view_number),
+                state_root: [0; 32],
+                executed_block_hash: [0; 32],
             }
         }
 
@@ -891,14 +2702,18 @@ mod tests {
             const DEFAULT_BASE: u128 = 1_000_000_000;
             const DEFAULT_QUOTE: u128 = 1_000_000_000_000;
 
-            ledger_state.spot_clearinghouse.add_market(
-                base,
-                quote,
-                base_asset_name,
-                quote_asset_name,
-                tick,
-                tick_decimals,
-            );
+            ledger_state
+                .spot_clearinghouse
+                .add_market(
+                    base,
+                    quote,
+                    base_asset_name,
+                    quote_asset_name,
+                    tick,
+                    tick_decimals,
+                    LotSizes::default(),
+                )
+                .unwrap();
 
             let user_sk = get_alice_sk();
             let mut mm_1_sk = get_bob_sk();
@@ -1091,21 +2906,26 @@ mod tests {
 
                 let mm_1_cancel_1 = create_cancel_txn(&mut mm_1_sk, 0, 10, mm_1_nonce);
 
-                let mut block_1 = create_block(vec![mm_1_buy_1, mm_2_buy_1]);
-                let mut block_2 = create_block(vec![mm_1_buy_2, mm_2_buy_2]);
-                let mut block_3 = create_block(vec![mm_1_sell_1, mm_2_buy_3]);
-                let mut block_4 = create_block(vec![mm_2_sell_1, mm_1_sell_2]);
-                let mut block_5 = create_block(vec![mm_1_sell_3, mm_2_sell_2]);
-                let mut block_6 = create_block(vec![mm_2_cancel_1, mm_1_sell_4]);
-                let mut block_7 = create_block(vec![mm_1_cancel_1]);
-
-                ledger_state.apply_block(&mut block_1);
-                ledger_state.apply_block(&mut block_2);
-                ledger_state.apply_block(&mut block_3);
-                ledger_state.apply_block(&mut block_4);
-                ledger_state.apply_block(&mut block_5);
-                ledger_state.apply_block(&mut block_6);
-                ledger_state.apply_block(&mut block_7);
+                // one transaction per block, as a block executes in canonical order
+                // rather than the order listed, and the ids above depend on it
+                let transactions = vec![
+                    mm_1_buy_1,
+                    mm_2_buy_1,
+                    mm_1_buy_2,
+                    mm_2_buy_2,
+                    mm_1_sell_1,
+                    mm_2_buy_3,
+                    mm_2_sell_1,
+                    mm_1_sell_2,
+                    mm_1_sell_3,
+                    mm_2_sell_2,
+                    mm_2_cancel_1,
+                    mm_1_sell_4,
+                    mm_1_cancel_1,
+                ];
+                for transaction in transactions {
+                    ledger_state.apply_block(&mut create_block(vec![transaction]));
+                }
 
                 // Check account info state
                 {
@@ -1115,7 +2935,7 @@ mod tests {
 
                     assert_eq!(open_orders.len(), 5);
 
-                    let is_cancelled_still_in_open = open_orders.iter().find(|o| o.common.id == 10);
+                    let is_cancelled_still_in_open = open_orders.get(&10);
                     assert!(is_cancelled_still_in_open.is_none());
 
                     assert_eq!(completed_orders.len(), 1);
@@ -1129,8 +2949,7 @@ mod tests {
 
                     assert_eq!(open_orders.len(), 4);
 
-                    let is_cancelled_still_in_open =
-                        open_orders.iter().find(|order| order.common.id == 5);
+                    let is_cancelled_still_in_open = open_orders.get(&5);
                     assert!(is_cancelled_still_in_open.is_none());
 
                     assert_eq!(completed_orders.len(), 1);
@@ -1322,9 +3141,9 @@ mod tests {
                 let open_orders = &user_account_info.open_orders;
                 let completed_orders = &user_account_info.completed_orders;
                 assert_eq!(open_orders.len(), 1);
-                assert_eq!(open_orders[0].filled_base_lots, 1600);
+                assert_eq!(open_orders.values().next().unwrap().filled_base_lots, 1600);
                 assert_eq!(completed_orders.len(), 0);
-                assert_eq!(open_orders[0].common.id, 11);
+                assert_eq!(open_orders.values().next().unwrap().common.id, 11);
             }
 
             // Check mm state
@@ -1390,10 +3209,10 @@ mod tests {
                 let completed_orders = &user_account_info.completed_orders;
 
                 assert_eq!(open_orders.len(), 1);
-                assert_eq!(open_orders[0].filled_base_lots, 1000);
+                assert_eq!(open_orders.values().next().unwrap().filled_base_lots, 1000);
 
                 assert_eq!(completed_orders.len(), 0);
-                assert_eq!(open_orders[0].common.id, 11);
+                assert_eq!(open_orders.values().next().unwrap().common.id, 11);
             }
 
             // Check mm state
@@ -1476,10 +3295,10 @@ mod tests {
                 let completed_orders = &user_account_info.completed_orders;
 
                 assert_eq!(open_orders.len(), 1);
-                assert_eq!(open_orders[0].filled_base_lots, 1500);
+                assert_eq!(open_orders.values().next().unwrap().filled_base_lots, 1500);
 
                 assert_eq!(completed_orders.len(), 0);
-                assert_eq!(open_orders[0].common.id, 12);
+                assert_eq!(open_orders.values().next().unwrap().common.id, 12);
             }
 
             // Check mm state
@@ -1559,12 +3378,12 @@ mod tests {
 
                 assert_eq!(completed_orders.len(), 0);
                 assert_eq!(open_orders.len(), 1);
-                assert_eq!(open_orders[0].common.id, 11);
+                assert_eq!(open_orders.values().next().unwrap().common.id, 11);
             }
 
             assert_eq!(
                 block_2.transactions()[0].get_status(),
-                TransactionStatus::Rejected("Self Cross".to_string())
+                TransactionStatus::Rejected(RejectReason::SelfCross)
             )
         }
 
@@ -1610,13 +3429,13 @@ mod tests {
 
                 assert_eq!(completed_orders.len(), 0);
                 assert_eq!(open_orders.len(), 1);
-                assert_eq!(open_orders[0].common.id, 11);
-                assert_eq!(open_orders[0].filled_base_lots, 1000)
+                assert_eq!(open_orders.values().next().unwrap().common.id, 11);
+                assert_eq!(open_orders.values().next().unwrap().filled_base_lots, 1000)
             }
 
             assert_eq!(
                 block_2.transactions()[0].get_status(),
-                TransactionStatus::Rejected("Self Cross".to_string())
+                TransactionStatus::Rejected(RejectReason::SelfCross)
             )
         }
 
@@ -1911,6 +3730,78 @@ mod tests {
             }
         }
 
+        #[test]
+        pub fn test_market_buy_satisfied_partly_by_self_fill_is_filled() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // id 11 - queued behind mm1's order 4 and mm2's order 6 at 2_500
+            let user_sell = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_500, 300),
+                0,
+            );
+            ledger_state.apply_block(&mut create_block(vec![user_sell]));
+
+            // id 12 - takes the 1_600 lots of orders 4 and 6, and self fills the last 300
+            let user_buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(1_900 * 250),
+                1,
+            );
+            ledger_state.apply_block(&mut create_block(vec![user_buy]));
+
+            let completed_order = ledger_state.get_order(&user_pk, 12).unwrap().order;
+            assert_completed_market_buy(&completed_order, 12, 1_600 * 250, 300 * 250, 1_900 * 250);
+            assert_eq!(completed_order.get_status(), &OrderStatus::Filled);
+        }
+
+        #[test]
+        pub fn test_order_on_unknown_market_leaves_state_untouched() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let state_root = ledger_state.state_root();
+            let balances = ledger_state.get_balance_summary(&user_pk);
+            let next_id = ledger_state.order_manager.next_id();
+
+            let unknown_market = 7;
+            let limit = create_order_txn(
+                &mut user_sk,
+                unknown_market,
+                OrderDirection::Buy,
+                OrderType::Limit(2_500, 100),
+                0,
+            );
+            let market = create_order_txn(
+                &mut user_sk,
+                unknown_market,
+                OrderDirection::Buy,
+                OrderType::Market(2_500),
+                0,
+            );
+            let mut block = create_block(vec![limit, market]);
+            ledger_state.apply_block(&mut block);
+
+            for transaction in block.transactions() {
+                assert_eq!(
+                    transaction.get_status(),
+                    TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Market(
+                        unknown_market
+                    )))
+                );
+            }
+            assert_eq!(ledger_state.get_balance_summary(&user_pk), balances);
+            assert!(ledger_state.accounts[&user_pk].open_orders.is_empty());
+            assert_eq!(ledger_state.order_manager.next_id(), next_id);
+            assert_eq!(ledger_state.state_root(), state_root);
+        }
+
         #[test]
         pub fn test_market_sell_with_user_self_fill() {
             let mut ledger_state = test_setup();
@@ -2011,5 +3902,3550 @@ mod tests {
                 assert_eq!(completed_orders[0].get_id(), 5);
             }
         }
+
+        #[test]
+        pub fn test_order_query_open_partially_filled_order() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // id 11 - partially filled by mm1 - order id 4 & mm2 - order id 6
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_550, 1700),
+                0,
+            );
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+
+            let response = ledger_state.handle_query(ClientQuery::OrderQuery {
+                public_key: user_pk,
+                order_id: 11,
+            });
+            let ClientResponse::OrderQueryResponse(Some(result)) = response else {
+                panic!("Expected order query response with order 11");
+            };
+
+            assert_eq!(result.order.get_id(), 11);
+            assert_eq!(result.remaining_base_lots, 100);
+            let Order::Limit(limit_order) = result.order else {
+                panic!("Expected limit order");
+            };
+            assert_eq!(limit_order.filled_base_lots, 1600);
+        }
+
+        #[test]
+        pub fn test_order_query_completed_order() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+
+            // id 11 - fully fills mm1 - order id 4
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_550, 1700),
+                0,
+            );
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+
+            let mm_1_pk = get_bob_sk().verifying_key().to_bytes();
+            let result = ledger_state
+                .get_order(&mm_1_pk, 4)
+                .expect("Expected completed order 4");
+
+            assert_eq!(result.remaining_base_lots, 0);
+            let Order::Limit(limit_order) = result.order else {
+                panic!("Expected limit order");
+            };
+            assert_eq!(limit_order.common.status, OrderStatus::Filled);
+            assert_eq!(limit_order.filled_base_lots, 600);
+        }
+
+        #[test]
+        pub fn test_order_query_not_found() {
+            let ledger_state = test_setup();
+            let mm_1_pk = get_bob_sk().verifying_key().to_bytes();
+
+            // order id 1 belongs to mm2
+            let response = ledger_state.handle_query(ClientQuery::OrderQuery {
+                public_key: mm_1_pk,
+                order_id: 1,
+            });
+            assert!(matches!(response, ClientResponse::OrderQueryResponse(None)));
+        }
+
+        #[test]
+        pub fn test_limit_order_receipt_reports_assigned_order_id() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let user_buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(1_000, 10),
+                0,
+            );
+            let mut block = create_block(vec![user_buy]);
+            ledger_state.apply_block(&mut block);
+
+            let receipt = ledger_state
+                .get_receipt(&block.transactions()[0].hash)
+                .unwrap();
+            let order_id = receipt.order_id.unwrap();
+            let open_orders = &ledger_state.accounts[&user_pk].open_orders;
+            assert_eq!(open_orders.keys().collect::<Vec<_>>(), vec![&order_id]);
+            assert_eq!(open_orders[&order_id].price_multiple, 1_000);
+
+            // the returned id is enough to cancel the order
+            let cancel = create_cancel_txn(&mut user_sk, 0, order_id, 1);
+            let mut block = create_block(vec![cancel]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert!(ledger_state.accounts[&user_pk].open_orders.is_empty());
+        }
+
+        #[test]
+        pub fn test_same_price_orders_fill_oldest_first() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let mut mm_1_sk = get_bob_sk();
+            let mut mm_2_sk = get_carol_sk();
+
+            // id 11 from mm2 then id 12 from mm1, alone at 2_650
+            let mm_2_sell = create_order_txn(
+                &mut mm_2_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_650, 200),
+                6,
+            );
+            let mm_1_sell = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_650, 200),
+                7,
+            );
+            ledger_state.apply_block(&mut create_block(vec![mm_2_sell]));
+            ledger_state.apply_block(&mut create_block(vec![mm_1_sell]));
+
+            // clears 2_500 and 2_600, then takes 100 of the 2_650 level
+            let user_buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_650, 2_900),
+                0,
+            );
+            let mut block = create_block(vec![user_buy]);
+            ledger_state.apply_block(&mut block);
+
+            let receipt = ledger_state
+                .get_receipt(&block.transactions()[0].hash)
+                .unwrap();
+            let at_level: Vec<OrderId> = receipt
+                .fills
+                .iter()
+                .filter(|fill| fill.price_multiple == 2_650)
+                .map(|fill| fill.maker_order_id)
+                .collect();
+            assert_eq!(at_level, vec![11]);
+
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let mm_2_pk = mm_2_sk.verifying_key().to_bytes();
+            assert_eq!(
+                ledger_state
+                    .get_order(&mm_2_pk, 11)
+                    .unwrap()
+                    .remaining_base_lots,
+                100
+            );
+            assert_eq!(
+                ledger_state
+                    .get_order(&mm_1_pk, 12)
+                    .unwrap()
+                    .remaining_base_lots,
+                200
+            );
+        }
+
+        fn open_order_ids(ledger_state: &LedgerState, public_key: &PublicKeyHash) -> Vec<OrderId> {
+            ledger_state.accounts[public_key]
+                .open_orders
+                .values()
+                .map(|order| order.common.id)
+                .collect()
+        }
+
+        #[test]
+        pub fn test_cancel_at_price_cancels_unique_match() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+
+            // mm1's only sell at 2_700 is order 7
+            let cancel =
+                create_cancel_at_price_txn(&mut mm_1_sk, 0, OrderDirection::Sell, 2_700, 7);
+            let mut block = create_block(vec![cancel]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert_eq!(ledger_state.accounts[&mm_1_pk].expected_nonce, 8);
+            assert!(!open_order_ids(&ledger_state, &mm_1_pk).contains(&7));
+            assert!(open_order_ids(&ledger_state, &mm_1_pk).contains(&8));
+            assert!(ledger_state.get_order(&mm_1_pk, 7).is_some());
+            let depth = ledger_state.get_order_book_depth(0, 10).unwrap();
+            assert!(depth.asks.iter().all(|level| level.price != 2_700));
+        }
+
+        #[test]
+        pub fn test_cancel_at_price_cancels_oldest_of_several_matches() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+
+            // id 11, behind order 7 at the same price
+            let sell = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_700, 200),
+                7,
+            );
+            ledger_state.apply_block(&mut create_block(vec![sell]));
+            assert!(open_order_ids(&ledger_state, &mm_1_pk).contains(&11));
+
+            let cancel =
+                create_cancel_at_price_txn(&mut mm_1_sk, 0, OrderDirection::Sell, 2_700, 8);
+            let mut block = create_block(vec![cancel]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            let open_orders = open_order_ids(&ledger_state, &mm_1_pk);
+            assert!(!open_orders.contains(&7));
+            assert!(open_orders.contains(&11));
+            let depth = ledger_state.get_order_book_depth(0, 10).unwrap();
+            let level = depth
+                .asks
+                .iter()
+                .find(|level| level.price == 2_700)
+                .unwrap();
+            assert_eq!(level.volume, 200);
+        }
+
+        #[test]
+        pub fn test_cancel_at_price_without_match_is_rejected() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let open_orders = open_order_ids(&ledger_state, &mm_1_pk);
+
+            // 2_600 is mm2's price, and mm1 has no buy at 2_700
+            let other_account =
+                create_cancel_at_price_txn(&mut mm_1_sk, 0, OrderDirection::Sell, 2_600, 7);
+            let other_side =
+                create_cancel_at_price_txn(&mut mm_1_sk, 0, OrderDirection::Buy, 2_700, 7);
+            for cancel in [other_account, other_side] {
+                let mut block = create_block(vec![cancel]);
+                ledger_state.apply_block(&mut block);
+                assert_eq!(
+                    block.transactions()[0].get_status(),
+                    TransactionStatus::Rejected(RejectReason::NoMatchingOrder)
+                );
+            }
+
+            assert_eq!(ledger_state.accounts[&mm_1_pk].expected_nonce, 7);
+            assert_eq!(open_order_ids(&ledger_state, &mm_1_pk), open_orders);
+        }
+
+        fn level_volume(ledger_state: &LedgerState, direction: OrderDirection, price: u64) -> u64 {
+            let depth = ledger_state.get_order_book_depth(0, 10).unwrap();
+            let levels = match direction {
+                OrderDirection::Buy => depth.bids,
+                OrderDirection::Sell => depth.asks,
+            };
+            levels
+                .iter()
+                .find(|level| level.price == price)
+                .map_or(0, |level| level.volume)
+        }
+
+        fn available_balance(
+            ledger_state: &LedgerState,
+            public_key: &PublicKeyHash,
+            asset_id: usize,
+        ) -> u128 {
+            ledger_state
+                .get_account_info_with_balances_or_default(public_key)
+                .spot_balances
+                .asset_balances[asset_id]
+                .available_balance
+        }
+
+        #[test]
+        pub fn test_reduce_order_keeps_priority_and_unlocks_balance() {
+            let mut ledger_state = test_setup();
+            let mut mm_2_sk = get_carol_sk();
+            let mut user_sk = get_alice_sk();
+            let mm_2_pk = mm_2_sk.verifying_key().to_bytes();
+            let quote_before = available_balance(&ledger_state, &mm_2_pk, 1);
+
+            // order 1 is first in the queue at 2_300, ahead of order 3
+            let reduce = create_reduce_txn(&mut mm_2_sk, 0, 1, 300, 6);
+            let mut block = create_block(vec![reduce]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert_eq!(ledger_state.accounts[&mm_2_pk].expected_nonce, 7);
+            assert_eq!(
+                ledger_state
+                    .get_order(&mm_2_pk, 1)
+                    .unwrap()
+                    .remaining_base_lots,
+                400
+            );
+            assert_eq!(level_volume(&ledger_state, OrderDirection::Buy, 2_300), 800);
+
+            let (_, _, precision) = ledger_state.get_market_precision(0).unwrap();
+            let freed_quote_lots = base_to_quote_lots(700, 2_300, &precision)
+                - base_to_quote_lots(400, 2_300, &precision);
+            assert_eq!(
+                available_balance(&ledger_state, &mm_2_pk, 1),
+                quote_before + freed_quote_lots as u128 * precision.quote_lot_size as u128
+            );
+
+            // clears 2_450, then takes 200 at 2_300 from the front of the queue
+            let user_sell = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_300, 1_200),
+                0,
+            );
+            let mut block = create_block(vec![user_sell]);
+            ledger_state.apply_block(&mut block);
+
+            let receipt = ledger_state
+                .get_receipt(&block.transactions()[0].hash)
+                .unwrap();
+            let at_level: Vec<OrderId> = receipt
+                .fills
+                .iter()
+                .filter(|fill| fill.price_multiple == 2_300)
+                .map(|fill| fill.maker_order_id)
+                .collect();
+            assert_eq!(at_level, vec![1]);
+            assert_eq!(
+                ledger_state
+                    .get_order(&mm_2_pk, 1)
+                    .unwrap()
+                    .remaining_base_lots,
+                200
+            );
+        }
+
+        #[test]
+        pub fn test_reduce_order_beyond_remaining_is_rejected() {
+            let mut ledger_state = test_setup();
+            let mut mm_2_sk = get_carol_sk();
+            let mm_2_pk = mm_2_sk.verifying_key().to_bytes();
+            let quote_before = available_balance(&ledger_state, &mm_2_pk, 1);
+
+            let reduce = create_reduce_txn(&mut mm_2_sk, 0, 1, 701, 6);
+            let mut block = create_block(vec![reduce]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::ReductionExceedsRemaining)
+            );
+            assert_eq!(ledger_state.accounts[&mm_2_pk].expected_nonce, 6);
+            assert_eq!(
+                ledger_state
+                    .get_order(&mm_2_pk, 1)
+                    .unwrap()
+                    .remaining_base_lots,
+                700
+            );
+            assert_eq!(
+                level_volume(&ledger_state, OrderDirection::Buy, 2_300),
+                1_100
+            );
+            assert_eq!(available_balance(&ledger_state, &mm_2_pk, 1), quote_before);
+        }
+
+        #[test]
+        pub fn test_reduce_order_by_remaining_cancels_it() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let base_before = available_balance(&ledger_state, &mm_1_pk, 0);
+
+            // order 7 is mm1's only sell at 2_700
+            let reduce = create_reduce_txn(&mut mm_1_sk, 0, 7, 700, 7);
+            let mut block = create_block(vec![reduce]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert!(!open_order_ids(&ledger_state, &mm_1_pk).contains(&7));
+            assert_eq!(level_volume(&ledger_state, OrderDirection::Sell, 2_700), 0);
+            let (_, _, precision) = ledger_state.get_market_precision(0).unwrap();
+            assert_eq!(
+                available_balance(&ledger_state, &mm_1_pk, 0),
+                base_before + 700 * precision.base_lot_size as u128
+            );
+        }
+
+        #[test]
+        pub fn test_asset_query_returns_symbol_and_display_decimals() {
+            let ledger_state = test_setup();
+
+            let ClientResponse::AssetQueryResponse(assets) =
+                ledger_state.handle_query(ClientQuery::AssetQuery)
+            else {
+                panic!("Expected asset query response");
+            };
+
+            let metadata: Vec<(&str, u8)> = assets
+                .iter()
+                .map(|asset| (asset.symbol.as_str(), asset.display_decimals))
+                .collect();
+            assert_eq!(metadata, vec![("SUPE", 2), ("USD", 2)]);
+        }
+
+        #[test]
+        pub fn test_transfer_of_unknown_asset_is_rejected() {
+            let mut ledger_state = test_setup();
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let user_pk = get_alice_sk().verifying_key().to_bytes();
+            let unknown_asset = ledger_state.asset_manager.assets.len() as u32;
+
+            let transfer = create_transfer_txn(&mut faucet_sk, user_pk, 1_000, unknown_asset, 6);
+            let mut block = create_block(vec![transfer]);
+            ledger_state.apply_block(&mut block);
+
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Error(ExecError::ResourceNotFound(Resource::Asset(
+                    unknown_asset
+                )))
+            );
+            let faucet_pk = faucet_sk.verifying_key().to_bytes();
+            assert_eq!(ledger_state.accounts[&faucet_pk].expected_nonce, 6);
+        }
+
+        #[test]
+        pub fn test_faucet_cooldown_limits_drips_per_recipient() {
+            let mut ledger_state = LedgerState::new();
+            ledger_state.params.faucet_cooldown = 3;
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let faucet_pk = faucet_sk.verifying_key().to_bytes();
+            let user_pk = get_alice_sk().verifying_key().to_bytes();
+            let other_pk = get_bob_sk().verifying_key().to_bytes();
+
+            let mut drip = |ledger_state: &mut LedgerState, to, nonce, view| {
+                let transfer = create_faucet_txn(&mut faucet_sk, to, 1, 1_000, nonce);
+                let mut block = create_block_in_view(vec![transfer], view);
+                ledger_state.apply_block(&mut block);
+                block.transactions()[0].get_status()
+            };
+
+            assert_eq!(
+                drip(&mut ledger_state, user_pk, 0, 10),
+                TransactionStatus::Executed
+            );
+
+            // within the cooldown the same recipient is turned away, others are not
+            assert_eq!(
+                drip(&mut ledger_state, user_pk, 1, 12),
+                TransactionStatus::Rejected(RejectReason::FaucetCooldown)
+            );
+            assert_eq!(ledger_state.accounts[&faucet_pk].expected_nonce, 1);
+            assert_eq!(
+                drip(&mut ledger_state, other_pk, 1, 12),
+                TransactionStatus::Executed
+            );
+
+            assert_eq!(
+                drip(&mut ledger_state, user_pk, 2, 13),
+                TransactionStatus::Executed
+            );
+            let balances = ledger_state.get_account_info_with_balances_or_default(&user_pk);
+            assert_eq!(
+                balances
+                    .spot_balances
+                    .find_asset_id(1)
+                    .unwrap()
+                    .available_balance,
+                2_000
+            );
+        }
+
+        #[test]
+        pub fn test_transfer_of_asset_held_without_lower_asset_ids() {
+            let mut ledger_state = LedgerState::new();
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let recipient_pk = get_bob_sk().verifying_key().to_bytes();
+
+            // the user only ever holds asset 1, so it is their first balance entry
+            let mut block_1 = create_block(vec![create_faucet_txn(
+                &mut faucet_sk,
+                user_pk,
+                1,
+                1_000,
+                0,
+            )]);
+            ledger_state.apply_block(&mut block_1);
+
+            let transfer = create_transfer_txn(&mut user_sk, recipient_pk, 400, 1, 0);
+            let mut block_2 = create_block(vec![transfer]);
+            ledger_state.apply_block(&mut block_2);
+
+            assert_eq!(
+                block_2.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            let balances = &ledger_state.get_account_info_with_balances_or_default(&user_pk);
+            let user_balance = balances.spot_balances.find_asset_id(1).unwrap();
+            assert_eq!(user_balance.available_balance, 600);
+        }
+
+        #[test]
+        pub fn test_transfer_debits_asset_by_id_not_position() {
+            let mut ledger_state = LedgerState::new();
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let recipient_pk = get_bob_sk().verifying_key().to_bytes();
+
+            // receiving asset 1 first puts it at position 0 of the user's balances
+            let mut block_1 = create_block(vec![
+                create_faucet_txn(&mut faucet_sk, user_pk, 1, 5_000, 0),
+                create_faucet_txn(&mut faucet_sk, user_pk, 0, 1_000, 1),
+            ]);
+            ledger_state.apply_block(&mut block_1);
+            let balances = ledger_state.get_account_info_with_balances_or_default(&user_pk);
+            let asset_order: Vec<u32> = balances
+                .spot_balances
+                .asset_balances
+                .iter()
+                .map(|balance| balance.asset_id)
+                .collect();
+            assert_eq!(asset_order, vec![1, 0]);
+
+            let mut block_2 = create_block(vec![create_transfer_txn(
+                &mut user_sk,
+                recipient_pk,
+                300,
+                0,
+                0,
+            )]);
+            ledger_state.apply_block(&mut block_2);
+            assert_eq!(
+                block_2.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+
+            let balances = ledger_state.get_account_info_with_balances_or_default(&user_pk);
+            let spot_balances = &balances.spot_balances;
+            assert_eq!(
+                spot_balances.find_asset_id(0).unwrap().available_balance,
+                700
+            );
+            assert_eq!(
+                spot_balances.find_asset_id(1).unwrap().available_balance,
+                5_000
+            );
+
+            let balances = ledger_state.get_account_info_with_balances_or_default(&recipient_pk);
+            let recipient_balance = balances.spot_balances.find_asset_id(0).unwrap();
+            assert_eq!(recipient_balance.available_balance, 300);
+            assert_eq!(recipient_balance.total_balance, 300);
+        }
+
+        #[test]
+        pub fn test_market_buy_receipt_lists_fills_across_levels() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+
+            // id 11 - consumes order 4 & 6 at 2_500 and part of order 9 at 2_600
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(1_600 * 250 + 400 * 260),
+                0,
+            );
+            let tx_hash = user_buy_1.hash;
+
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+
+            let response = ledger_state.handle_query(ClientQuery::ReceiptQuery(tx_hash));
+            let ClientResponse::ReceiptQueryResponse(Some(receipt)) = response else {
+                panic!("Expected receipt for market buy");
+            };
+
+            assert_eq!(receipt.status, TransactionStatus::Executed);
+            assert_eq!(receipt.order_id, Some(11));
+            assert_eq!(receipt.fees, 0);
+
+            let fills: Vec<(OrderId, u64, u64)> = receipt
+                .fills
+                .iter()
+                .map(|fill| (fill.maker_order_id, fill.price_multiple, fill.base_lots))
+                .collect();
+            assert_eq!(
+                fills,
+                vec![(4, 2_500, 600), (6, 2_500, 1_000), (9, 2_600, 400)]
+            );
+
+            // (1_600 * 250 + 400 * 260) quote lots / 2_000 base lots
+            assert_eq!(receipt.average_execution_price, 252);
+        }
+
+        #[test]
+        pub fn test_market_buy_receipt_reports_unfilled_remainder() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+
+            // id 11 - spends exactly its quote on orders 4, 6 and part of 9
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(1_600 * 250 + 400 * 260),
+                0,
+            );
+            // id 12 - more quote than the remaining asks can absorb
+            let quote_size = 10_000_000;
+            let user_buy_2 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(quote_size),
+                1,
+            );
+            let tx_hash_1 = user_buy_1.hash;
+            let tx_hash_2 = user_buy_2.hash;
+
+            let mut block_1 = create_block(vec![user_buy_1]);
+            let mut block_2 = create_block(vec![user_buy_2]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            let filled = ledger_state.get_receipt(&tx_hash_1).unwrap();
+            assert_eq!(filled.unfilled_lots, 0);
+            assert!(!filled.book_exhausted);
+
+            let exhausted = ledger_state.get_receipt(&tx_hash_2).unwrap();
+            assert_eq!(exhausted.status, TransactionStatus::Executed);
+            assert!(exhausted.book_exhausted);
+            let depth = ledger_state.get_order_book_depth(0, 10).unwrap();
+            assert!(depth.asks.is_empty());
+
+            // a base lot costs price / 10 quote lots in this market
+            let spent: u64 = exhausted
+                .fills
+                .iter()
+                .map(|fill| fill.base_lots * fill.price_multiple / 10)
+                .sum();
+            assert_eq!(exhausted.unfilled_lots, quote_size - spent);
+        }
+
+        #[test]
+        pub fn test_market_orders_against_empty_book_are_rejected() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // a fresh market with nothing resting on either side
+            ledger_state.asset_manager.assets.push(Asset {
+                asset_id: 2,
+                asset_name: "ALT".to_string(),
+                lot_size: 100,
+                decimals: 4,
+                symbol: "ALT".to_string(),
+                display_decimals: 2,
+            });
+            ledger_state.asset_manager.next_asset_id = 3;
+            let empty_market_id = ledger_state
+                .spot_clearinghouse
+                .add_market(
+                    2,
+                    1,
+                    "ALT".to_string(),
+                    "USD".to_string(),
+                    10,
+                    2,
+                    LotSizes::default(),
+                )
+                .unwrap();
+
+            // enough ALT that only the empty book stands in the way of the sell
+            let user_balance = ledger_state.get_account_spot_balances_mut(&user_pk);
+            let alt_balance = SpotClearingHouse::get_account_token_balance_mut(user_balance, 2);
+            alt_balance.total_balance += 1_000_000;
+            alt_balance.available_balance += 1_000_000;
+
+            let completed_before = ledger_state.accounts[&user_pk].completed_orders.len();
+
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                empty_market_id,
+                OrderDirection::Buy,
+                OrderType::Market(1_000),
+                0,
+            );
+            let user_sell_1 = create_order_txn(
+                &mut user_sk,
+                empty_market_id,
+                OrderDirection::Sell,
+                OrderType::Market(100),
+                0,
+            );
+            let mut block_1 = create_block(vec![user_buy_1]);
+            let mut block_2 = create_block(vec![user_sell_1]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            let no_liquidity = TransactionStatus::Rejected(RejectReason::NoLiquidity);
+            assert_eq!(block_1.transactions()[0].get_status(), no_liquidity);
+            assert_eq!(block_2.transactions()[0].get_status(), no_liquidity);
+
+            let user_account_info = &ledger_state.accounts[&user_pk];
+            assert_eq!(user_account_info.expected_nonce, 0);
+            assert_eq!(user_account_info.completed_orders.len(), completed_before);
+        }
+
+        #[test]
+        pub fn test_simulate_order_matches_real_submission() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let balances = |ledger_state: &LedgerState| {
+                let balance = ledger_state
+                    .spot_clearinghouse
+                    .get_account_balance(&user_pk)
+                    .unwrap();
+                [0, 1].map(|asset_id| {
+                    let token_balance = balance.find_asset_id(asset_id).unwrap();
+                    (token_balance.total_balance, token_balance.available_balance)
+                })
+            };
+
+            // takes the asks at 2_500 and 2_600, then rests the remainder at 2_650
+            let spec = OrderSpec {
+                market_id: 0,
+                direction: OrderDirection::Buy,
+                order_type: OrderType::Limit(2_650, 3_000),
+            };
+
+            let state_root_before = ledger_state.state_root();
+            let balances_before = balances(&ledger_state);
+            let simulation = ledger_state.simulate_order(&user_pk, spec.clone());
+            assert_eq!(ledger_state.state_root(), state_root_before);
+
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                spec.market_id,
+                spec.direction,
+                spec.order_type,
+                0,
+            );
+            let tx_hash = user_buy_1.hash;
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+            let receipt = ledger_state.get_receipt(&tx_hash).unwrap();
+            let balances_after = balances(&ledger_state);
+
+            assert_eq!(simulation.status, receipt.status);
+            assert_eq!(simulation.order_id, receipt.order_id);
+            assert_eq!(simulation.fills, receipt.fills);
+            assert!(!simulation.fills.is_empty());
+            assert_eq!(
+                simulation.average_execution_price,
+                receipt.average_execution_price
+            );
+            assert_eq!(simulation.unfilled_lots, receipt.unfilled_lots);
+            assert_eq!(simulation.book_exhausted, receipt.book_exhausted);
+
+            let deltas: Vec<(i128, i128)> = simulation
+                .balance_deltas
+                .iter()
+                .map(|delta| (delta.total, delta.available))
+                .collect();
+            let expected: Vec<(i128, i128)> = (0..2)
+                .map(|i| {
+                    (
+                        balances_after[i].0 as i128 - balances_before[i].0 as i128,
+                        balances_after[i].1 as i128 - balances_before[i].1 as i128,
+                    )
+                })
+                .collect();
+            assert_eq!(deltas, expected);
+        }
+
+        #[test]
+        pub fn test_order_rejections_carry_typed_reason() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_450, 2_000),
+                0,
+            );
+            let user_sell_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_450, 1_000),
+                1,
+            );
+            let mut block_1 = create_block(vec![user_buy_1]);
+            let mut block_2 = create_block(vec![user_sell_1]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            let user_nonce = ledger_state
+                .get_account_info_or_default(&user_sk.verifying_key().to_bytes())
+                .expected_nonce;
+            let user_buy_2 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(0, 100),
+                user_nonce,
+            );
+            let mut block_3 = create_block(vec![user_buy_2]);
+            ledger_state.apply_block(&mut block_3);
+
+            // rejected orders leave no receipt, the reason is on the transaction
+            assert_eq!(
+                block_2.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::SelfCross)
+            );
+            assert_eq!(
+                block_3.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::InvalidTick)
+            );
+        }
+
+        #[test]
+        pub fn test_quote_matches_actual_fill() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // orders 4 and 6 at 2_500, then 400 of order 9 at 2_600
+            let response = ledger_state.handle_query(ClientQuery::QuoteQuery {
+                market_id: 0,
+                side: OrderDirection::Buy,
+                size: 2_000,
+            });
+            let ClientResponse::QuoteQueryResponse(Some(quote)) = response else {
+                panic!("Expected quote for market 0");
+            };
+            assert!(quote.fully_available);
+            assert_eq!(quote.available_base_lots, 2_000);
+            assert_eq!(quote.quote_lots, 1_600 * 250 + 400 * 260);
+            assert_eq!(quote.average_price, 2_520);
+
+            let (quote_before, _) = ledger_state.balance_of(&user_pk, 1);
+            let user_buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_600, 2_000),
+                0,
+            );
+            let mut block = create_block(vec![user_buy]);
+            ledger_state.apply_block(&mut block);
+            let (quote_after, _) = ledger_state.balance_of(&user_pk, 1);
+
+            let receipt = ledger_state
+                .get_receipt(&block.transactions()[0].hash)
+                .unwrap();
+            let filled: u64 = receipt.fills.iter().map(|fill| fill.base_lots).sum();
+            assert_eq!(filled, quote.available_base_lots);
+            assert_eq!(receipt.average_execution_price, quote.average_price as u128);
+            let quote_lot_size = ledger_state.asset_manager.assets[1].lot_size as u128;
+            assert_eq!(
+                quote_before - quote_after,
+                quote.quote_lots as u128 * quote_lot_size
+            );
+        }
+
+        #[test]
+        pub fn test_quote_reports_partial_availability() {
+            let ledger_state = test_setup();
+
+            // more than all the bids resting in the book
+            let quote = ledger_state
+                .get_quote(0, OrderDirection::Sell, 1_000_000)
+                .unwrap();
+            assert!(!quote.fully_available);
+            assert_eq!(quote.base_lots, 1_000_000);
+            let bid_volume: u64 = ledger_state
+                .get_order_book_depth(0, 10)
+                .unwrap()
+                .bids
+                .iter()
+                .map(|level| level.volume)
+                .sum();
+            assert_eq!(quote.available_base_lots, bid_volume);
+
+            assert!(
+                ledger_state
+                    .get_quote(7, OrderDirection::Buy, 100)
+                    .is_none()
+            );
+        }
+
+        #[test]
+        pub fn test_simulate_order_reports_rejection() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_450, 2_000),
+                0,
+            );
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+
+            let simulation = ledger_state.simulate_order(
+                &user_pk,
+                OrderSpec {
+                    market_id: 0,
+                    direction: OrderDirection::Sell,
+                    order_type: OrderType::Limit(2_450, 1_000),
+                },
+            );
+
+            assert_eq!(
+                simulation.status,
+                TransactionStatus::Rejected(RejectReason::SelfCross)
+            );
+            assert!(simulation.fills.is_empty());
+            assert!(
+                simulation
+                    .balance_deltas
+                    .iter()
+                    .all(|delta| delta.total == 0 && delta.available == 0)
+            );
+        }
+
+        #[test]
+        pub fn test_market_buy_emits_fill_events_per_level() {
+            let mut ledger_state = test_setup();
+            let mut fill_rx = ledger_state.subscribe_fills();
+
+            let mut user_sk = get_alice_sk();
+
+            // id 11 - consumes order 4 & 6 at 2_500 and part of order 9 at 2_600
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(1_600 * 250 + 400 * 260),
+                0,
+            );
+
+            let mut block_1 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+
+            let mut events = vec![];
+            while let Ok(event) = fill_rx.try_recv() {
+                events.push(event);
+            }
+
+            assert!(events.iter().all(|event| event.market_id == 0));
+            assert!(events.iter().all(|event| event.taker_order_id == 11));
+            let fills: Vec<(OrderId, u64, u64)> = events
+                .iter()
+                .map(|event| {
+                    (
+                        event.fill.maker_order_id,
+                        event.fill.price_multiple,
+                        event.fill.base_lots,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                fills,
+                vec![(4, 2_500, 600), (6, 2_500, 1_000), (9, 2_600, 400)]
+            );
+        }
+
+        #[test]
+        pub fn test_out_of_order_nonces_execute_once_gap_is_filled() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let mut order_txns: Vec<SignedTransaction> = [0, 2, 1]
+                .iter()
+                .map(|&nonce| {
+                    create_order_txn(
+                        &mut user_sk,
+                        0,
+                        OrderDirection::Buy,
+                        OrderType::Limit(2_000 + nonce * 10, 100),
+                        nonce,
+                    )
+                })
+                .collect();
+
+            let mut block_1 = create_block(vec![order_txns.remove(0), order_txns.remove(0)]);
+            ledger_state.apply_block(&mut block_1);
+
+            // nonce 2 is waiting on nonce 1
+            {
+                let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+                assert_eq!(user_account_info.expected_nonce, 1);
+                assert_eq!(user_account_info.open_orders.len(), 1);
+                assert_eq!(ledger_state.queued_transactions[&user_pk].len(), 1);
+            }
+
+            let mut block_2 = create_block(vec![order_txns.remove(0)]);
+            ledger_state.apply_block(&mut block_2);
+
+            let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+            assert_eq!(user_account_info.expected_nonce, 3);
+
+            let prices: Vec<u64> = user_account_info
+                .open_orders
+                .values()
+                .map(|order| order.price_multiple)
+                .collect();
+            assert_eq!(prices, vec![2_000, 2_010, 2_020]);
+            assert!(ledger_state.queued_transactions.get(&user_pk).is_none());
+        }
+
+        #[test]
+        pub fn test_nonce_gap_queue_is_capped() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let cap = MAX_QUEUED_TRANSACTIONS_PER_ACCOUNT as u64;
+            let order_txns: Vec<SignedTransaction> = (1..=cap + 1)
+                .map(|nonce| {
+                    create_order_txn(
+                        &mut user_sk,
+                        0,
+                        OrderDirection::Buy,
+                        OrderType::Limit(2_000, 100),
+                        nonce,
+                    )
+                })
+                .collect();
+
+            let mut block_1 = create_block(order_txns);
+            ledger_state.apply_block(&mut block_1);
+
+            assert_eq!(
+                ledger_state.queued_transactions[&user_pk].len(),
+                cap as usize
+            );
+            assert_eq!(
+                block_1.transactions()[cap as usize].get_status(),
+                TransactionStatus::Rejected(RejectReason::OutOfOrderNonce)
+            );
+        }
+
+        fn create_order_txn_valid_until(
+            sk: &mut SigningKey,
+            nonce: Nonce,
+            valid_until: u64,
+        ) -> SignedTransaction {
+            let unsigned = UnsignedTransaction::Order(OrderTransaction {
+                from: sk.verifying_key().to_bytes(),
+                market_id: 0,
+                direction: OrderDirection::Buy,
+                order_type: OrderType::Limit(2_000, 100),
+                status: TransactionStatus::Pending,
+                nonce,
+                valid_until: Some(valid_until),
+            });
+            unsigned.sign(sk)
+        }
+
+        #[test]
+        pub fn test_transaction_executes_up_to_deadline() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let order_txn = create_order_txn_valid_until(&mut user_sk, 0, 5);
+            let mut block_1 = create_block_in_view(vec![order_txn], 5);
+            ledger_state.apply_block(&mut block_1);
+
+            assert_eq!(
+                block_1.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+            assert_eq!(user_account_info.expected_nonce, 1);
+            assert_eq!(user_account_info.open_orders.len(), 1);
+        }
+
+        #[test]
+        pub fn test_transaction_rejected_after_deadline() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let order_txn = create_order_txn_valid_until(&mut user_sk, 0, 5);
+            let mut block_1 = create_block_in_view(vec![order_txn], 6);
+            let outcomes = ledger_state.apply_block(&mut block_1);
+
+            assert_eq!(outcomes.len(), 1);
+            assert_eq!(outcomes[0].next_nonce, None);
+            assert_eq!(
+                block_1.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::Expired)
+            );
+            let user_account_info = ledger_state.get_account_info_or_default(&user_pk);
+            assert_eq!(user_account_info.expected_nonce, 0);
+            assert!(user_account_info.open_orders.is_empty());
+        }
+
+        #[test]
+        pub fn test_queued_transaction_past_deadline_reports_expired() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+
+            let late = create_order_txn_valid_until(&mut user_sk, 1, 6);
+            let gap_filler = create_order_txn_valid_until(&mut user_sk, 0, 7);
+            let late_hash = late.hash;
+            ledger_state.apply_block(&mut create_block_in_view(vec![late], 5));
+
+            let outcomes = ledger_state.apply_block(&mut create_block_in_view(vec![gap_filler], 7));
+
+            assert_eq!(outcomes.len(), 2);
+            assert_eq!(outcomes[0].status, TransactionStatus::Executed);
+            assert_eq!(outcomes[1].tx_hash, late_hash);
+            assert_eq!(
+                outcomes[1].status,
+                TransactionStatus::Rejected(RejectReason::Expired)
+            );
+            assert_eq!(outcomes[1].next_nonce, None);
+            let user_pk = user_sk.verifying_key().to_bytes();
+            assert!(ledger_state.queued_transactions.get(&user_pk).is_none());
+        }
+
+        #[test]
+        pub fn test_expiry_follows_block_views_not_wall_clock() {
+            let mut replicas = [test_setup(), test_setup()];
+            let mut user_sk = get_alice_sk();
+
+            // nonce 1 waits on the gap until after its deadline
+            let late = create_order_txn_valid_until(&mut user_sk, 1, 6);
+            let gap_filler = create_order_txn_valid_until(&mut user_sk, 0, 7);
+            let blocks = [
+                create_block_in_view(vec![late], 5),
+                create_block_in_view(vec![gap_filler], 7),
+            ];
+
+            let mut statuses = vec![];
+            for (index, ledger_state) in replicas.iter_mut().enumerate() {
+                // the replicas apply the blocks at different wall clock times
+                std::thread::sleep(std::time::Duration::from_millis(20 * index as u64));
+                let mut replica_statuses = vec![];
+                for block in blocks.iter() {
+                    let mut block = block.clone();
+                    ledger_state.apply_block(&mut block);
+                    assert_eq!(ledger_state.current_view(), block.view_number());
+                    replica_statuses.push(block.transactions()[0].get_status());
+                }
+                statuses.push(replica_statuses);
+            }
+
+            assert_eq!(statuses[0], statuses[1]);
+            assert_eq!(replicas[0].state_root(), replicas[1].state_root());
+            // nonce 1 was still queued at view 7, past its deadline, so it never executed
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let user_account_info = replicas[0].get_account_info_or_default(&user_pk);
+            assert_eq!(user_account_info.expected_nonce, 1);
+            assert_eq!(user_account_info.open_orders.len(), 1);
+
+            let restored = LedgerState::restore(replicas[0].snapshot());
+            assert_eq!(restored.current_view(), 7);
+        }
+
+        #[test]
+        pub fn test_block_of_unfunded_orders_is_filtered_before_matching() {
+            let mut ledger_state = test_setup();
+            let accounts_before = ledger_state.accounts.len();
+            let next_id_before = ledger_state.order_manager.next_id();
+            let market_hashes_before = ledger_state.spot_clearinghouse.get_market_hashes();
+
+            let transactions: Vec<SignedTransaction> = (0..200u8)
+                .map(|seed| {
+                    let mut sk = SigningKey::from_bytes(&[seed; 32]);
+                    let (direction, order_type) = if seed % 2 == 0 {
+                        (OrderDirection::Buy, OrderType::Limit(2_500, 100))
+                    } else {
+                        (OrderDirection::Sell, OrderType::Market(100))
+                    };
+                    create_order_txn(&mut sk, 0, direction, order_type, 0)
+                })
+                .collect();
+            let mut block = create_block(transactions);
+            let outcomes = ledger_state.apply_block(&mut block);
+
+            assert!(outcomes.iter().all(|outcome| outcome.next_nonce.is_none()));
+            for transaction in block.transactions() {
+                assert!(matches!(
+                    transaction.get_status(),
+                    TransactionStatus::Error(ExecError::InsufficientFunds { have: 0, .. })
+                ));
+                assert!(ledger_state.get_receipt(&transaction.hash).is_none());
+            }
+            // nothing was spent on them: no accounts, order ids or book changes
+            assert_eq!(ledger_state.accounts.len(), accounts_before);
+            assert_eq!(ledger_state.order_manager.next_id(), next_id_before);
+            assert_eq!(
+                ledger_state.spot_clearinghouse.get_market_hashes(),
+                market_hashes_before
+            );
+        }
+
+        #[test]
+        pub fn test_block_with_forged_transaction_is_rejected() {
+            let mut ledger_state = test_setup();
+            let mut bob_sk = get_bob_sk();
+            let mut carol_sk = get_carol_sk();
+            let alice_pk = get_alice_sk().verifying_key().to_bytes();
+            let state_root_before = ledger_state.state_root();
+
+            let genuine = create_transfer_txn(&mut bob_sk, alice_pk, 100, 0, 7);
+            // spends from carol's account under bob's signature, as if a leader injected it
+            let mut forged = create_transfer_txn(&mut bob_sk, alice_pk, 100, 0, 8);
+            if let UnsignedTransaction::Transfer(transfer) = &mut forged.tx {
+                transfer.from = carol_sk.verifying_key().to_bytes();
+            }
+            forged.hash = forged.tx.hash();
+            let valid = create_transfer_txn(&mut carol_sk, alice_pk, 100, 0, 5);
+
+            let mut block = create_block(vec![genuine, forged, valid]);
+            let outcomes = ledger_state.apply_block(&mut block);
+
+            assert_eq!(outcomes.len(), 3);
+            assert!(outcomes.iter().all(|outcome| outcome.next_nonce.is_none()));
+            let statuses: Vec<TransactionStatus> = block
+                .transactions()
+                .iter()
+                .map(|transaction| transaction.get_status())
+                .collect();
+            assert_eq!(
+                statuses,
+                vec![
+                    TransactionStatus::Rejected(RejectReason::InvalidBlock),
+                    TransactionStatus::Rejected(RejectReason::InvalidSignature),
+                    TransactionStatus::Rejected(RejectReason::InvalidBlock),
+                ]
+            );
+            assert_eq!(ledger_state.state_root(), state_root_before);
+        }
+
+        #[test]
+        pub fn test_empty_block_only_advances_view() {
+            let mut ledger_state = test_setup();
+            let state_root_before = ledger_state.state_root();
+
+            let mut block = create_block_in_view(vec![], 9);
+            assert!(ledger_state.apply_block(&mut block).is_empty());
+
+            assert_eq!(ledger_state.current_view(), 9);
+            assert_eq!(ledger_state.state_root(), state_root_before);
+        }
+
+        #[test]
+        pub fn test_halted_market_rejects_orders_and_cancels() {
+            let mut ledger_state = test_setup();
+            let (_faucet_pk, mut admin_sk) = config::retrieve_faucet_keys();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let order_id = *ledger_state.accounts[&mm_1_pk]
+                .open_orders
+                .keys()
+                .next()
+                .unwrap();
+
+            let mut halt = create_block(vec![create_set_market_status_txn(
+                &mut admin_sk,
+                0,
+                MarketStatus::Halted,
+                6,
+            )]);
+            ledger_state.apply_block(&mut halt);
+            assert_eq!(
+                halt.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert_eq!(
+                ledger_state.spot_clearinghouse.get_market_status(0),
+                Some(MarketStatus::Halted)
+            );
+
+            let order = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                7,
+            );
+            let mut block = create_block(vec![order]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::MarketHalted)
+            );
+
+            let cancel = create_cancel_txn(&mut mm_1_sk, 0, order_id, 7);
+            let mut block = create_block(vec![cancel]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::MarketHalted)
+            );
+            assert!(
+                ledger_state.accounts[&mm_1_pk]
+                    .open_orders
+                    .contains_key(&order_id)
+            );
+        }
+
+        #[test]
+        pub fn test_cancel_only_market_rejects_orders_but_allows_cancels() {
+            let mut ledger_state = test_setup();
+            let (_faucet_pk, mut admin_sk) = config::retrieve_faucet_keys();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let mut order_ids = ledger_state.accounts[&mm_1_pk].open_orders.keys().copied();
+            let (reduced_id, cancelled_id) = (order_ids.next().unwrap(), order_ids.next().unwrap());
+
+            let mut block = create_block(vec![create_set_market_status_txn(
+                &mut admin_sk,
+                0,
+                MarketStatus::CancelOnly,
+                6,
+            )]);
+            ledger_state.apply_block(&mut block);
+
+            let order = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                7,
+            );
+            let mut block = create_block(vec![order]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::MarketCancelOnly)
+            );
+
+            let reduce = create_reduce_txn(&mut mm_1_sk, 0, reduced_id, 100, 7);
+            let mut block = create_block(vec![reduce]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+
+            let cancel = create_cancel_txn(&mut mm_1_sk, 0, cancelled_id, 8);
+            let mut block = create_block(vec![cancel]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert!(
+                !ledger_state.accounts[&mm_1_pk]
+                    .open_orders
+                    .contains_key(&cancelled_id)
+            );
+        }
+
+        #[test]
+        pub fn test_only_admin_sets_market_status() {
+            let mut ledger_state = test_setup();
+            let (_faucet_pk, mut admin_sk) = config::retrieve_faucet_keys();
+            let mut mm_1_sk = get_bob_sk();
+
+            let unauthorized =
+                create_set_market_status_txn(&mut mm_1_sk, 0, MarketStatus::Halted, 7);
+            let mut block = create_block(vec![unauthorized]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::Unauthorized)
+            );
+            assert_eq!(
+                ledger_state.spot_clearinghouse.get_market_status(0),
+                Some(MarketStatus::Active)
+            );
+
+            // halting and reopening returns the market to normal trading
+            let halt = create_set_market_status_txn(&mut admin_sk, 0, MarketStatus::Halted, 6);
+            let reopen = create_set_market_status_txn(&mut admin_sk, 0, MarketStatus::Active, 7);
+            ledger_state.apply_block(&mut create_block(vec![halt]));
+            ledger_state.apply_block(&mut create_block(vec![reopen]));
+            assert_eq!(
+                ledger_state.spot_clearinghouse.get_market_status(0),
+                Some(MarketStatus::Active)
+            );
+
+            let order = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                7,
+            );
+            let mut block = create_block(vec![order]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+        }
+
+        #[test]
+        pub fn test_best_price_history_records_transitions_by_view() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let history = |ledger_state: &LedgerState| {
+                let ClientResponse::BestPriceHistoryQueryResponse(history) =
+                    ledger_state.handle_query(ClientQuery::BestPriceHistoryQuery(0))
+                else {
+                    panic!("Expected BestPriceHistoryQueryResponse");
+                };
+                history.unwrap()
+            };
+            let recorded_in_setup = history(&ledger_state).len();
+
+            // improves the best bid, then leaves it where it was
+            let improve = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_460, 100),
+                0,
+            );
+            let improve_hash = improve.hash;
+            ledger_state.apply_block(&mut create_block_in_view(vec![improve], 3));
+            let order_id = ledger_state.get_receipt(&improve_hash).unwrap().order_id;
+            let cancel = create_cancel_txn(&mut user_sk, 0, order_id.unwrap(), 1);
+            ledger_state.apply_block(&mut create_block_in_view(vec![cancel], 5));
+            // rests behind the best bid without moving it
+            let behind = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                2,
+            );
+            ledger_state.apply_block(&mut create_block_in_view(vec![behind], 6));
+
+            let history = history(&ledger_state);
+            assert_eq!(
+                history[recorded_in_setup..],
+                [
+                    BestPriceSnapshot {
+                        view: 3,
+                        best_bid: Some(2_460),
+                        best_ask: Some(2_500),
+                    },
+                    BestPriceSnapshot {
+                        view: 5,
+                        best_bid: Some(2_450),
+                        best_ask: Some(2_500),
+                    },
+                ]
+            );
+            assert!(ledger_state.get_best_price_history(1).is_none());
+        }
+
+        #[test]
+        pub fn test_market_buy_receipt_reports_balance_deltas() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let balances_before = ledger_state.get_balance_summary(&user_pk);
+            let (_, _, precision) = ledger_state.get_market_precision(0).unwrap();
+            // exactly the first order resting at the best ask
+            let quote_lots = base_to_quote_lots(600, 2_500, &precision);
+            let base_amount = 600 * precision.base_lot_size as u128;
+            let quote_amount = quote_lots as u128 * precision.quote_lot_size as u128;
+
+            let buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(quote_lots),
+                0,
+            );
+            let buy_hash = buy.hash;
+            ledger_state.apply_block(&mut create_block(vec![buy]));
+
+            let receipt = ledger_state.get_receipt(&buy_hash).unwrap();
+            assert_eq!(
+                receipt.balance_deltas,
+                vec![
+                    BalanceDelta {
+                        asset_id: 0,
+                        amount: base_amount,
+                        is_increase: true,
+                    },
+                    BalanceDelta {
+                        asset_id: 1,
+                        amount: quote_amount,
+                        is_increase: false,
+                    },
+                ]
+            );
+
+            let balances_after = ledger_state.get_balance_summary(&user_pk);
+            assert_eq!(
+                balances_after[0].total - balances_before[0].total,
+                base_amount
+            );
+            assert_eq!(
+                balances_before[1].total - balances_after[1].total,
+                quote_amount
+            );
+        }
+
+        #[test]
+        pub fn test_transfer_all_leaves_locked_balance_behind() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let user_pk = get_alice_sk().verifying_key().to_bytes();
+            let quote_balance = |ledger_state: &LedgerState, public_key: &PublicKeyHash| {
+                ledger_state
+                    .spot_clearinghouse
+                    .get_account_balance(public_key)
+                    .and_then(|balance| balance.find_asset_id(1))
+                    .cloned()
+                    .unwrap()
+            };
+            let mm_1_before = quote_balance(&ledger_state, &mm_1_pk);
+            let user_before = quote_balance(&ledger_state, &user_pk);
+            // mm_1's resting bids keep part of its quote locked
+            assert!(mm_1_before.locked_balance() > 0);
+
+            let transfer_all = create_transfer_all_txn(&mut mm_1_sk, user_pk, 1, 7);
+            let transfer_all_hash = transfer_all.hash;
+            let mut block = create_block(vec![transfer_all]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+
+            let user_after = quote_balance(&ledger_state, &user_pk);
+            assert_eq!(
+                user_after.total_balance - user_before.total_balance,
+                mm_1_before.available_balance
+            );
+            assert_eq!(quote_balance(&ledger_state, &mm_1_pk).available_balance, 0);
+            assert_eq!(ledger_state.accounts[&mm_1_pk].open_orders.len(), 5);
+            assert_eq!(
+                ledger_state
+                    .get_receipt(&transfer_all_hash)
+                    .unwrap()
+                    .balance_deltas,
+                vec![BalanceDelta {
+                    asset_id: 1,
+                    amount: mm_1_before.available_balance,
+                    is_increase: false,
+                }]
+            );
+        }
+
+        #[test]
+        pub fn test_transfer_all_rejects_nothing_available() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let user_pk = get_alice_sk().verifying_key().to_bytes();
+
+            let first = create_transfer_all_txn(&mut mm_1_sk, user_pk, 1, 7);
+            ledger_state.apply_block(&mut create_block(vec![first]));
+
+            let second = create_transfer_all_txn(&mut mm_1_sk, user_pk, 1, 8);
+            let never_held = create_transfer_all_txn(&mut mm_1_sk, user_pk, 3, 8);
+            for transaction in [second, never_held] {
+                let mut block = create_block(vec![transaction]);
+                let outcomes = ledger_state.apply_block(&mut block);
+                assert_eq!(
+                    block.transactions()[0].get_status(),
+                    TransactionStatus::Rejected(RejectReason::NothingToTransfer)
+                );
+                assert_eq!(outcomes[0].next_nonce, None);
+            }
+        }
+
+        #[test]
+        pub fn test_transfer_all_from_faucet_respects_cooldown() {
+            let mut ledger_state = LedgerState::new();
+            ledger_state.params.faucet_cooldown = 3;
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let user_pk = get_alice_sk().verifying_key().to_bytes();
+
+            let drip = create_faucet_txn(&mut faucet_sk, user_pk, 1, 1_000, 0);
+            ledger_state.apply_block(&mut create_block_in_view(vec![drip], 10));
+
+            let transfer_all = create_transfer_all_txn(&mut faucet_sk, user_pk, 1, 1);
+            let mut block = create_block_in_view(vec![transfer_all], 12);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::FaucetCooldown)
+            );
+
+            let transfer_all = create_transfer_all_txn(&mut faucet_sk, user_pk, 1, 1);
+            let mut block = create_block_in_view(vec![transfer_all], 13);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert_eq!(ledger_state.accounts[&user_pk].last_faucet_drip, Some(13));
+        }
+
+        #[test]
+        pub fn test_taker_fees_flow_into_collector() {
+            let mut ledger_state = test_setup();
+            let fee_collector = SigningKey::from_bytes(&[9; 32]).verifying_key().to_bytes();
+            ledger_state.params.taker_fee_bps = 10;
+            ledger_state.params.fee_collector = fee_collector;
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let supply_before = [
+                total_supply(&ledger_state, 0),
+                total_supply(&ledger_state, 1),
+            ];
+            let user_balance = |ledger_state: &LedgerState, asset_id: u32| {
+                ledger_state
+                    .spot_clearinghouse
+                    .get_account_balance(&user_pk)
+                    .and_then(|balance| balance.find_asset_id(asset_id))
+                    .unwrap()
+                    .total_balance
+            };
+            let base_before = user_balance(&ledger_state, 0);
+
+            // lifts the 2_500 ask, paying its fee in base
+            let buy = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_500, 600),
+                0,
+            );
+            // hits the 2_450 bid, paying its fee in quote
+            let sell = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Market(100),
+                1,
+            );
+            let (buy_hash, sell_hash) = (buy.hash, sell.hash);
+            ledger_state.apply_block(&mut create_block(vec![buy]));
+            let base_received = user_balance(&ledger_state, 0) - base_before;
+            ledger_state.apply_block(&mut create_block(vec![sell]));
+
+            let base_fee = ledger_state.get_receipt(&buy_hash).unwrap().fees;
+            let quote_fee = ledger_state.get_receipt(&sell_hash).unwrap().fees;
+            assert!(base_fee > 0 && quote_fee > 0);
+            assert_eq!(taker_fee(base_received + base_fee, 10), base_fee);
+
+            let ClientResponse::FeeBalanceQueryResponse(fee_balance) =
+                ledger_state.handle_query(ClientQuery::FeeBalanceQuery)
+            else {
+                panic!("Expected FeeBalanceQueryResponse");
+            };
+            let fee_totals: Vec<(u32, u128)> = fee_balance
+                .iter()
+                .map(|summary| (summary.asset_id, summary.total))
+                .collect();
+            assert_eq!(fee_totals, vec![(0, base_fee), (1, quote_fee)]);
+
+            assert_eq!(
+                [
+                    total_supply(&ledger_state, 0),
+                    total_supply(&ledger_state, 1)
+                ],
+                supply_before
+            );
+        }
+
+        #[test]
+        pub fn test_apply_block_reports_each_transaction_outcome() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mut mm_2_sk = get_carol_sk();
+            let mut unfunded_sk = SigningKey::from_bytes(&[42; 32]);
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let alice_pk = get_alice_sk().verifying_key().to_bytes();
+
+            let transfer = create_transfer_txn(&mut mm_1_sk, alice_pk, 100, 0, 7);
+            let duplicate = create_transfer_txn(&mut mm_2_sk, alice_pk, 100, 0, 5);
+            let unfunded = create_order_txn(
+                &mut unfunded_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_500, 100),
+                0,
+            );
+            let hashes = [transfer.hash, duplicate.hash, unfunded.hash];
+            let mut block = create_block(vec![transfer, duplicate, unfunded]);
+            let outcomes = ledger_state.apply_block(&mut block);
+
+            assert_eq!(outcomes.len(), 3);
+            let outcome_of = |hash: Sha256Hash| {
+                outcomes
+                    .iter()
+                    .find(|outcome| outcome.tx_hash == hash)
+                    .unwrap()
+            };
+            assert_eq!(outcome_of(hashes[0]).status, TransactionStatus::Executed);
+            assert_eq!(outcome_of(hashes[0]).next_nonce, Some((mm_1_pk, 8)));
+            assert_eq!(
+                outcome_of(hashes[1]).status,
+                TransactionStatus::Rejected(RejectReason::DuplicateNonce)
+            );
+            assert_eq!(outcome_of(hashes[1]).next_nonce, None);
+            assert!(matches!(
+                outcome_of(hashes[2]).status,
+                TransactionStatus::Error(ExecError::InsufficientFunds { have: 0, .. })
+            ));
+            assert_eq!(outcome_of(hashes[2]).next_nonce, None);
+        }
+
+        #[test]
+        pub fn test_open_order_limit() {
+            let mut ledger_state = test_setup();
+            ledger_state.params.max_open_orders = 2;
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let orders: Vec<SignedTransaction> = (0..3)
+                .map(|nonce| {
+                    create_order_txn(
+                        &mut user_sk,
+                        0,
+                        OrderDirection::Buy,
+                        OrderType::Limit(2_000, 100),
+                        nonce,
+                    )
+                })
+                .collect();
+
+            // ids 11, 12
+            let mut block_1 = create_block(orders);
+            ledger_state.apply_block(&mut block_1);
+
+            assert_eq!(
+                block_1.transactions()[2].get_status(),
+                TransactionStatus::Rejected(RejectReason::OpenOrderLimitReached)
+            );
+            assert_eq!(
+                ledger_state
+                    .accounts
+                    .get(&user_pk)
+                    .unwrap()
+                    .open_orders
+                    .len(),
+                2
+            );
+
+            // Cancelling frees up a slot
+            let cancel = create_cancel_txn(&mut user_sk, 0, 11, 2);
+            let retry = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                3,
+            );
+            let mut block_2 = create_block(vec![cancel, retry]);
+            ledger_state.apply_block(&mut block_2);
+
+            assert_eq!(
+                block_2.transactions()[1].get_status(),
+                TransactionStatus::Executed
+            );
+            let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+            assert_eq!(user_account_info.open_orders.len(), 2);
+            assert_eq!(user_account_info.expected_nonce, 4);
+        }
+
+        #[test]
+        pub fn test_batch_order_records_failed_orders_and_executes_rest() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            let batch = create_batch_order_txn(
+                &mut user_sk,
+                vec![
+                    order_spec(OrderDirection::Buy, OrderType::Limit(2_300, 300)),
+                    order_spec(OrderDirection::Buy, OrderType::Limit(0, 300)),
+                    order_spec(
+                        OrderDirection::Sell,
+                        OrderType::Limit(2_700, 1_000_000_000_000),
+                    ),
+                    order_spec(OrderDirection::Sell, OrderType::Limit(2_700, 400)),
+                ],
+                false,
+                0,
+            );
+
+            let mut block = create_block(vec![batch]);
+            ledger_state.apply_block(&mut block);
+
+            let transaction = &block.transactions()[0];
+            assert_eq!(transaction.get_status(), TransactionStatus::Executed);
+
+            let order_statuses = batch_order_statuses(transaction);
+            assert_eq!(order_statuses.len(), 4);
+            assert_eq!(order_statuses[0], TransactionStatus::Executed);
+            assert_eq!(
+                order_statuses[1],
+                TransactionStatus::Rejected(RejectReason::InvalidTick)
+            );
+            assert!(matches!(
+                order_statuses[2],
+                TransactionStatus::Error(ExecError::InsufficientFunds { .. })
+            ));
+            assert_eq!(order_statuses[3], TransactionStatus::Executed);
+
+            let receipt = ledger_state.get_receipt(&transaction.hash).unwrap();
+            assert_eq!(&receipt.order_statuses, order_statuses);
+
+            // both valid orders rest on the book under a single nonce
+            let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+            assert_eq!(user_account_info.expected_nonce, 1);
+            let open_order_ids: Vec<OrderId> = user_account_info
+                .open_orders
+                .values()
+                .map(|order| order.common.id)
+                .collect();
+            assert_eq!(open_order_ids, vec![11, 12]);
+        }
+
+        #[test]
+        pub fn test_all_or_nothing_batch_rolls_back_on_failure() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let balances_before = ledger_state
+                .get_account_info_with_balances_or_default(&user_pk)
+                .spot_balances;
+            let depth_before = ledger_state.get_order_book_depth(0, 10);
+            let mut fill_rx = ledger_state.subscribe_fills();
+
+            let batch = create_batch_order_txn(
+                &mut user_sk,
+                vec![
+                    order_spec(OrderDirection::Buy, OrderType::Limit(2_300, 300)),
+                    order_spec(OrderDirection::Buy, OrderType::Market(700)),
+                    order_spec(OrderDirection::Sell, OrderType::Limit(2_700, 0)),
+                    order_spec(OrderDirection::Sell, OrderType::Limit(2_700, 400)),
+                ],
+                true,
+                0,
+            );
+
+            let mut block = create_block(vec![batch]);
+            ledger_state.apply_block(&mut block);
+
+            let transaction = &block.transactions()[0];
+            assert_eq!(
+                transaction.get_status(),
+                TransactionStatus::Rejected(RejectReason::BatchOrderFailed(2))
+            );
+            assert_eq!(
+                batch_order_statuses(transaction),
+                &vec![
+                    TransactionStatus::Executed,
+                    TransactionStatus::Executed,
+                    TransactionStatus::Rejected(RejectReason::ZeroOrderSize),
+                ]
+            );
+
+            // nothing from the batch is left behind, including the market buy's fills
+            let user_account_info = ledger_state.get_account_info_or_default(&user_pk);
+            assert_eq!(user_account_info.expected_nonce, 0);
+            assert!(user_account_info.open_orders.is_empty());
+            assert!(user_account_info.completed_orders.is_empty());
+            assert!(ledger_state.get_receipt(&transaction.hash).is_none());
+            assert_eq!(ledger_state.get_order_book_depth(0, 10), depth_before);
+            assert!(fill_rx.try_recv().is_err());
+
+            let balances_after = ledger_state
+                .get_account_info_with_balances_or_default(&user_pk)
+                .spot_balances;
+            for (before, after) in balances_before
+                .asset_balances
+                .iter()
+                .zip(balances_after.asset_balances.iter())
+            {
+                assert_eq!(before.available_balance, after.available_balance);
+                assert_eq!(before.total_balance, after.total_balance);
+            }
+
+            // the nonce is still usable and order ids were not consumed
+            let retry = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_300, 300),
+                0,
+            );
+            let mut block_2 = create_block(vec![retry]);
+            ledger_state.apply_block(&mut block_2);
+
+            let user_account_info = ledger_state.accounts.get(&user_pk).unwrap();
+            assert_eq!(user_account_info.expected_nonce, 1);
+            assert_eq!(
+                user_account_info
+                    .open_orders
+                    .values()
+                    .next()
+                    .unwrap()
+                    .common
+                    .id,
+                11
+            );
+        }
+
+        #[test]
+        pub fn test_all_or_nothing_rollback_restores_makers_and_fee_collector() {
+            let mut ledger_state = test_setup();
+            let fee_collector = SigningKey::from_bytes(&[9; 32]).verifying_key().to_bytes();
+            ledger_state.params.taker_fee_bps = 10;
+            ledger_state.params.fee_collector = fee_collector;
+            let mut user_sk = get_alice_sk();
+            let (_, _, precision) = ledger_state.get_market_precision(0).unwrap();
+            let maker_state = |ledger_state: &LedgerState| {
+                [get_bob_sk(), get_carol_sk()].map(|maker_sk| {
+                    let maker_pk = maker_sk.verifying_key().to_bytes();
+                    let balances =
+                        ledger_state.get_account_info_with_balances_or_default(&maker_pk);
+                    let open_orders = balances.account_info.open_orders.len();
+                    let balances: Vec<(u128, u128)> = balances
+                        .spot_balances
+                        .asset_balances
+                        .iter()
+                        .map(|balance| (balance.available_balance, balance.total_balance))
+                        .collect();
+                    (open_orders, balances)
+                })
+            };
+            let makers_before = maker_state(&ledger_state);
+
+            // the market buy takes the first order resting at the best ask before the
+            // zero sized sell fails
+            let quote_lots = base_to_quote_lots(600, 2_500, &precision);
+            let batch = create_batch_order_txn(
+                &mut user_sk,
+                vec![
+                    order_spec(OrderDirection::Buy, OrderType::Market(quote_lots)),
+                    order_spec(OrderDirection::Sell, OrderType::Limit(2_700, 0)),
+                ],
+                true,
+                0,
+            );
+            let mut block = create_block(vec![batch]);
+            ledger_state.apply_block(&mut block);
+            assert_eq!(
+                block.transactions()[0].get_status(),
+                TransactionStatus::Rejected(RejectReason::BatchOrderFailed(1))
+            );
+
+            assert_eq!(maker_state(&ledger_state), makers_before);
+            assert!(
+                ledger_state
+                    .spot_clearinghouse
+                    .get_account_balance(&fee_collector)
+                    .is_none()
+            );
+        }
+
+        #[test]
+        pub fn test_order_locks_reconcile_with_locked_balance() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let mm_1_nonce = ledger_state
+                .get_account_info_or_default(&mm_1_pk)
+                .expected_nonce;
+
+            // ids 11, 12, 13
+            let user_orders = vec![
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_460, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_200, 100),
+                    1,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Sell,
+                    OrderType::Limit(2_650, 400),
+                    2,
+                ),
+            ];
+            // partially fills order 11 as the maker
+            let mm_1_sell = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::Limit(2_460, 100),
+                mm_1_nonce,
+            );
+
+            let mut block_1 = create_block(user_orders);
+            let mut block_2 = create_block(vec![mm_1_sell]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            // a base lot costs price / 10 quote lots in this market
+            let order_locks = ledger_state.get_order_locks(&user_pk);
+            let locks: Vec<(OrderId, u32, u128)> = order_locks
+                .iter()
+                .map(|lock| (lock.order_id, lock.asset_id, lock.amount))
+                .collect();
+            assert_eq!(
+                locks,
+                vec![
+                    (11, 1, 200 * 246 * 100),
+                    (12, 1, 100 * 220 * 100),
+                    (13, 0, 400 * 100),
+                ]
+            );
+
+            let balances = ledger_state
+                .get_account_info_with_balances_or_default(&user_pk)
+                .spot_balances;
+            for token_balance in balances.asset_balances.iter() {
+                let locked_by_orders: u128 = order_locks
+                    .iter()
+                    .filter(|lock| lock.asset_id == token_balance.asset_id)
+                    .map(|lock| lock.amount)
+                    .sum();
+                assert_eq!(locked_by_orders, token_balance.locked_balance());
+            }
+        }
+
+        #[test]
+        pub fn test_self_fill_and_counterparty_fill_on_same_resting_order() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+            let mm_2_pk = get_carol_sk().verifying_key().to_bytes();
+
+            let totals = |ledger_state: &LedgerState, asset_id: u32| -> u128 {
+                [user_pk, mm_1_pk, mm_2_pk]
+                    .iter()
+                    .filter_map(|pk| ledger_state.spot_clearinghouse.get_account_balance(pk))
+                    .filter_map(|balance| balance.find_asset_id(asset_id))
+                    .map(|token_balance| token_balance.total_balance)
+                    .sum()
+            };
+            let best_ask_volume = |ledger_state: &LedgerState| {
+                ledger_state.get_order_book_depth(0, 1).unwrap().asks[0].volume
+            };
+            let base_before = totals(&ledger_state, 0);
+            let quote_before = totals(&ledger_state, 1);
+            let volume_before = best_ask_volume(&ledger_state);
+            let mm_1_base_before = ledger_state
+                .spot_clearinghouse
+                .get_account_balance(&mm_1_pk)
+                .unwrap()
+                .find_asset_id(0)
+                .unwrap()
+                .total_balance;
+
+            // mm_1 self fills 100 lots of its own ask 4 at 2_500, then the user takes 200 more
+            let mm_1_self_fill = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(25_000),
+                7,
+            );
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(50_000),
+                0,
+            );
+            let mut block_1 = create_block(vec![mm_1_self_fill]);
+            let mut block_2 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            let order = ledger_state.accounts[&mm_1_pk].get_open_order(4).unwrap();
+            assert_eq!(order.self_filled, 100);
+            assert_eq!(order.filled_base_lots, 200);
+            assert_eq!(order.get_order_remaining(), order.base_lots - 300);
+            assert_eq!(best_ask_volume(&ledger_state), volume_before - 300);
+
+            // only the counterparty fill moves mm_1's base
+            let mm_1_base = ledger_state
+                .spot_clearinghouse
+                .get_account_balance(&mm_1_pk)
+                .unwrap()
+                .find_asset_id(0)
+                .unwrap()
+                .total_balance;
+            assert_eq!(mm_1_base, mm_1_base_before - 200 * 100);
+            assert_eq!(totals(&ledger_state, 0), base_before);
+            assert_eq!(totals(&ledger_state, 1), quote_before);
+
+            let mm_1_cancel = create_cancel_txn(&mut mm_1_sk, 0, 4, 8);
+            let mut block_3 = create_block(vec![mm_1_cancel]);
+            ledger_state.apply_block(&mut block_3);
+            assert_eq!(
+                block_3.transactions()[0].get_status(),
+                TransactionStatus::Executed
+            );
+            assert_eq!(best_ask_volume(&ledger_state), volume_before - 600);
+
+            // every lot still locked is backed by an open order
+            for pk in [mm_1_pk, user_pk] {
+                let order_locks = ledger_state.get_order_locks(&pk);
+                let balances = ledger_state
+                    .get_account_info_with_balances_or_default(&pk)
+                    .spot_balances;
+                for token_balance in balances.asset_balances.iter() {
+                    let locked_by_orders: u128 = order_locks
+                        .iter()
+                        .filter(|lock| lock.asset_id == token_balance.asset_id)
+                        .map(|lock| lock.amount)
+                        .sum();
+                    assert_eq!(locked_by_orders, token_balance.locked_balance());
+                }
+            }
+        }
+
+        #[test]
+        pub fn test_self_filled_order_taken_in_full_settles_counterparty_size() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let mut mm_1_sk = get_bob_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+
+            let balance = |ledger_state: &LedgerState, pk, asset_id| {
+                let token_balance = ledger_state
+                    .spot_clearinghouse
+                    .get_account_balance(pk)
+                    .unwrap()
+                    .find_asset_id(asset_id)
+                    .unwrap()
+                    .clone();
+                (token_balance.total_balance, token_balance.available_balance)
+            };
+            let (mm_1_base_before, mm_1_base_available_before) =
+                balance(&ledger_state, &mm_1_pk, 0);
+            let (user_base_before, _) = balance(&ledger_state, &user_pk, 0);
+
+            // mm_1 self fills 100 lots of its own ask 4 at 2_500, the user takes the other 500
+            let mm_1_self_fill = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(25_000),
+                7,
+            );
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Market(125_000),
+                0,
+            );
+            let mut block_1 = create_block(vec![mm_1_self_fill]);
+            let mut block_2 = create_block(vec![user_buy_1]);
+            ledger_state.apply_block(&mut block_1);
+            ledger_state.apply_block(&mut block_2);
+
+            let mm_1_account_info = &ledger_state.accounts[&mm_1_pk];
+            assert!(mm_1_account_info.get_open_order(4).is_none());
+            assert_completed_limit_order(
+                mm_1_account_info.completed_orders.last().unwrap(),
+                4,
+                500,
+                100,
+            );
+
+            let (user_base, _) = balance(&ledger_state, &user_pk, 0);
+            assert_eq!(user_base, user_base_before + 500 * 100);
+            let (mm_1_base, mm_1_base_available) = balance(&ledger_state, &mm_1_pk, 0);
+            assert_eq!(mm_1_base, mm_1_base_before - 500 * 100);
+            // the self filled lots are unlocked rather than sold
+            assert_eq!(mm_1_base_available, mm_1_base_available_before + 100 * 100);
+        }
+
+        #[test]
+        pub fn test_account_markets_from_orders_and_balances() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // ALT/USD, and BET/ALT which trades only assets the user never holds
+            for (asset_id, symbol) in [(2, "ALT"), (3, "BET")] {
+                ledger_state.asset_manager.assets.push(Asset {
+                    asset_id,
+                    asset_name: symbol.to_string(),
+                    lot_size: 100,
+                    decimals: 4,
+                    symbol: symbol.to_string(),
+                    display_decimals: 2,
+                });
+            }
+            ledger_state.asset_manager.next_asset_id = 4;
+            let alt_market_id = ledger_state
+                .spot_clearinghouse
+                .add_market(
+                    2,
+                    1,
+                    "ALT".to_string(),
+                    "USD".to_string(),
+                    10,
+                    2,
+                    LotSizes::default(),
+                )
+                .unwrap();
+            let bet_market_id = ledger_state
+                .spot_clearinghouse
+                .add_market(
+                    3,
+                    2,
+                    "BET".to_string(),
+                    "ALT".to_string(),
+                    10,
+                    2,
+                    LotSizes::default(),
+                )
+                .unwrap();
+            assert_eq!(ledger_state.get_markets().len(), 3);
+
+            let user_orders = vec![
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_300, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    alt_market_id,
+                    OrderDirection::Buy,
+                    OrderType::Limit(500, 200),
+                    1,
+                ),
+            ];
+            let mut block_1 = create_block(user_orders);
+            ledger_state.apply_block(&mut block_1);
+
+            let market_ids = ledger_state.get_account_markets(&user_pk);
+            assert_eq!(market_ids, vec![0, alt_market_id]);
+            assert!(!market_ids.contains(&bet_market_id));
+
+            let unknown_pk = [9; 32];
+            assert!(ledger_state.get_account_markets(&unknown_pk).is_empty());
+        }
+
+        #[test]
+        pub fn test_markets_on_same_base_asset_fill_with_their_own_lot_sizes() {
+            let mut ledger_state = test_setup();
+            // a maker without orders elsewhere, so its asks cannot self cross
+            let mut maker_sk = SigningKey::from_bytes(&[42; 32]);
+            let mut taker_sks = [get_alice_sk(), get_carol_sk()];
+
+            ledger_state.asset_manager.assets.push(Asset {
+                asset_id: 2,
+                asset_name: "ALT".to_string(),
+                lot_size: 100,
+                decimals: 4,
+                symbol: "ALT".to_string(),
+                display_decimals: 2,
+            });
+            ledger_state.asset_manager.next_asset_id = 3;
+            // ALT against USD with the asset's lot size, and against SUPE with 10x coarser lots
+            let market_ids = [
+                ledger_state
+                    .add_market(2, 1, 10, 2, LotSizes::default())
+                    .unwrap(),
+                ledger_state
+                    .add_market(
+                        2,
+                        0,
+                        10,
+                        2,
+                        LotSizes {
+                            base: Some(1_000),
+                            quote: None,
+                        },
+                    )
+                    .unwrap(),
+            ];
+
+            let maker_pk = maker_sk.verifying_key().to_bytes();
+            let maker_balance = ledger_state.get_account_spot_balances_mut(&maker_pk);
+            let alt_balance = SpotClearingHouse::get_account_token_balance_mut(maker_balance, 2);
+            alt_balance.total_balance += 1_000_000;
+            alt_balance.available_balance += 1_000_000;
+
+            let asks = market_ids
+                .iter()
+                .enumerate()
+                .map(|(index, &market_id)| {
+                    create_order_txn(
+                        &mut maker_sk,
+                        market_id,
+                        OrderDirection::Sell,
+                        OrderType::Limit(500, 5),
+                        index as u64,
+                    )
+                })
+                .collect();
+            let mut block_1 = create_block(asks);
+            ledger_state.apply_block(&mut block_1);
+
+            // the same 5 lot order on each market, from different takers
+            let bids = market_ids
+                .iter()
+                .zip(taker_sks.iter_mut())
+                .zip([0, 6])
+                .map(|((&market_id, sk), nonce)| {
+                    create_order_txn(
+                        sk,
+                        market_id,
+                        OrderDirection::Buy,
+                        OrderType::Limit(500, 5),
+                        nonce,
+                    )
+                })
+                .collect();
+            let mut block_2 = create_block(bids);
+            ledger_state.apply_block(&mut block_2);
+            for transaction in block_1.transactions().iter().chain(block_2.transactions()) {
+                assert_eq!(transaction.get_status(), TransactionStatus::Executed);
+            }
+
+            let alt_received: Vec<u128> = taker_sks
+                .iter()
+                .map(|sk| {
+                    let balances = ledger_state
+                        .get_account_info_with_balances_or_default(&sk.verifying_key().to_bytes());
+                    balances
+                        .spot_balances
+                        .find_asset_id(2)
+                        .unwrap()
+                        .total_balance
+                })
+                .collect();
+            assert_eq!(alt_received, vec![5 * 100, 5 * 1_000]);
+        }
+
+        #[test]
+        pub fn test_balance_summary_aggregates_across_markets() {
+            let mut ledger_state = test_setup();
+
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+
+            // second market trading a new asset against the same quote asset
+            ledger_state.asset_manager.assets.push(Asset {
+                asset_id: 2,
+                asset_name: "ALT".to_string(),
+                lot_size: 100,
+                decimals: 4,
+                symbol: "ALT".to_string(),
+                display_decimals: 2,
+            });
+            ledger_state.asset_manager.next_asset_id = 3;
+            let alt_market_id = ledger_state
+                .spot_clearinghouse
+                .add_market(
+                    2,
+                    1,
+                    "ALT".to_string(),
+                    "USD".to_string(),
+                    10,
+                    2,
+                    LotSizes::default(),
+                )
+                .unwrap();
+            assert_eq!(alt_market_id, 1);
+
+            let user_balance = ledger_state.get_account_spot_balances_mut(&user_pk);
+            let alt_balance = SpotClearingHouse::get_account_token_balance_mut(user_balance, 2);
+            alt_balance.total_balance += 1_000_000;
+            alt_balance.available_balance += 1_000_000;
+
+            let user_orders = vec![
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_300, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    alt_market_id,
+                    OrderDirection::Buy,
+                    OrderType::Limit(500, 200),
+                    1,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    alt_market_id,
+                    OrderDirection::Sell,
+                    OrderType::Limit(3_000, 100),
+                    2,
+                ),
+            ];
+            let mut block = create_block(user_orders);
+            ledger_state.apply_block(&mut block);
+            for transaction in block.transactions() {
+                assert_eq!(transaction.get_status(), TransactionStatus::Executed);
+            }
+
+            // a base lot costs price / 10 quote lots in both markets
+            let quote_locked = (300 * 230 + 200 * 50) * 100;
+            let alt_locked = 100 * 100;
+            assert_eq!(
+                ledger_state.get_balance_summary(&user_pk),
+                vec![
+                    AssetBalanceSummary {
+                        asset_id: 0,
+                        total: 1_000_000_000,
+                        available: 1_000_000_000,
+                        locked: 0,
+                    },
+                    AssetBalanceSummary {
+                        asset_id: 1,
+                        total: 1_000_000_000_000,
+                        available: 1_000_000_000_000 - quote_locked,
+                        locked: quote_locked,
+                    },
+                    AssetBalanceSummary {
+                        asset_id: 2,
+                        total: 1_000_000,
+                        available: 1_000_000 - alt_locked,
+                        locked: alt_locked,
+                    },
+                ]
+            );
+
+            let response = ledger_state.handle_query(ClientQuery::BalanceSummaryQuery(user_pk));
+            let ClientResponse::BalanceSummaryQueryResponse(balance_summary) = response else {
+                panic!("Expected balance summary");
+            };
+            assert_eq!(balance_summary, ledger_state.get_balance_summary(&user_pk));
+        }
+
+        #[test]
+        pub fn test_identical_ledgers_have_same_state_root() {
+            let mut ledger_state_1 = test_setup();
+            let mut ledger_state_2 = test_setup();
+            assert_eq!(ledger_state_1.state_root(), ledger_state_2.state_root());
+
+            let mut user_sk = get_alice_sk();
+            let user_buy_1 = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_550, 1700),
+                0,
+            );
+
+            let mut block_1 = create_block(vec![user_buy_1.clone()]);
+            ledger_state_1.apply_block(&mut block_1);
+            assert_ne!(ledger_state_1.state_root(), ledger_state_2.state_root());
+
+            let mut block_2 = create_block(vec![user_buy_1]);
+            ledger_state_2.apply_block(&mut block_2);
+            assert_eq!(ledger_state_1.state_root(), ledger_state_2.state_root());
+        }
+
+        #[test]
+        pub fn test_reapplying_block_is_a_no_op() {
+            let mut user_sk = get_alice_sk();
+            let user_pk = user_sk.verifying_key().to_bytes();
+            let (_, mut faucet_sk) = config::retrieve_faucet_keys();
+            let transactions = vec![
+                create_faucet_txn(&mut faucet_sk, user_pk, 1, 1_000_000, 6),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Market(1_000),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Sell,
+                    OrderType::Limit(2_700, 100),
+                    1,
+                ),
+            ];
+
+            let order_hashes = [transactions[1].hash, transactions[2].hash];
+
+            let mut applied_once = test_setup();
+            applied_once.apply_block(&mut create_block(transactions.clone()));
+
+            let mut applied_twice = test_setup();
+            let mut block = create_block(transactions);
+            applied_twice.apply_block(&mut block);
+            let outcomes = applied_twice.apply_block(&mut block);
+
+            assert!(outcomes.is_empty());
+            assert_eq!(applied_twice.state_root(), applied_once.state_root());
+            assert_eq!(
+                applied_twice.get_balance_summary(&user_pk),
+                applied_once.get_balance_summary(&user_pk)
+            );
+            assert_eq!(applied_twice.accounts[&user_pk].expected_nonce, 2);
+            for tx_hash in order_hashes {
+                assert_eq!(
+                    applied_twice.get_receipt(&tx_hash).unwrap().status,
+                    applied_once.get_receipt(&tx_hash).unwrap().status
+                );
+            }
+            assert_eq!(
+                applied_twice.get_order_book_depth(0, 10),
+                applied_once.get_order_book_depth(0, 10)
+            );
+        }
+
+        #[test]
+        pub fn test_shuffled_blocks_reach_same_state() {
+            use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+            let mut user_sk = get_alice_sk();
+            let mut mm_1_sk = get_bob_sk();
+            let mut mm_2_sk = get_carol_sk();
+            let mm_1_nonce = 7;
+            let mm_2_nonce = 6;
+
+            // crossing orders from several accounts, with a nonce gap filled within the block
+            let transactions = vec![
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_600, 1_500),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Sell,
+                    OrderType::Market(800),
+                    1,
+                ),
+                create_order_txn(
+                    &mut mm_1_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Market(50_000),
+                    mm_1_nonce,
+                ),
+                create_order_txn(
+                    &mut mm_2_sk,
+                    0,
+                    OrderDirection::Sell,
+                    OrderType::Limit(2_450, 300),
+                    mm_2_nonce,
+                ),
+                create_cancel_txn(&mut mm_2_sk, 0, 9, mm_2_nonce + 1),
+            ];
+
+            let mut expected = test_setup();
+            let mut block = create_block(transactions.clone());
+            expected.apply_block(&mut block);
+            // the block keeps the order it was proposed in
+            let hashes: Vec<_> = block.transactions().iter().map(|tx| tx.hash).collect();
+            let proposed: Vec<_> = transactions.iter().map(|tx| tx.hash).collect();
+            assert_eq!(hashes, proposed);
+
+            let mut rng = StdRng::seed_from_u64(2077);
+            for _ in 0..10 {
+                let mut shuffled = transactions.clone();
+                shuffled.shuffle(&mut rng);
+
+                let mut ledger_state = test_setup();
+                ledger_state.apply_block(&mut create_block(shuffled));
+                assert_eq!(ledger_state.state_root(), expected.state_root());
+                assert_eq!(
+                    ledger_state.get_order_book_depth(0, 10),
+                    expected.get_order_book_depth(0, 10)
+                );
+            }
+        }
+
+        fn snapshot_queries(public_keys: &[PublicKeyHash]) -> Vec<ClientQuery> {
+            let mut queries = vec![
+                ClientQuery::AssetQuery,
+                ClientQuery::MarketsQuery,
+                ClientQuery::MarketInfoQuery(0),
+                ClientQuery::OrderBookQuery {
+                    market_id: 0,
+                    depth: 10,
+                },
+            ];
+            for public_key in public_keys {
+                queries.extend([
+                    ClientQuery::AccountQuery(*public_key),
+                    ClientQuery::AccountMarketsQuery(*public_key),
+                    ClientQuery::BalanceSummaryQuery(*public_key),
+                    ClientQuery::OrderLocksQuery(*public_key),
+                    ClientQuery::SimulateOrder {
+                        public_key: *public_key,
+                        order: OrderSpec {
+                            market_id: 0,
+                            direction: OrderDirection::Buy,
+                            order_type: OrderType::Market(100_000),
+                        },
+                    },
+                ]);
+                queries.extend((0..14).map(|order_id| ClientQuery::OrderQuery {
+                    public_key: *public_key,
+                    order_id,
+                }));
+            }
+            queries
+        }
+
+        #[test]
+        pub fn test_snapshot_restores_identical_ledger() {
+            let mut ledger_state = test_setup();
+            let mut user_sk = get_alice_sk();
+            let mut mm_1_sk = get_bob_sk();
+            let public_keys = [
+                user_sk.verifying_key().to_bytes(),
+                mm_1_sk.verifying_key().to_bytes(),
+                get_carol_sk().verifying_key().to_bytes(),
+            ];
+
+            // a partial fill, a resting order and a transaction queued on a nonce gap
+            let transactions = vec![
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_500, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_400, 200),
+                    1,
+                ),
+                create_cancel_txn(&mut mm_1_sk, 0, 4, 8),
+            ];
+            ledger_state.apply_block(&mut create_block(transactions));
+
+            assert_eq!(ledger_state.queued_transactions.len(), 1);
+
+            let encoded = bincode::serialize(&ledger_state.snapshot()).unwrap();
+            let mut restored = LedgerState::restore(bincode::deserialize(&encoded).unwrap());
+
+            assert_eq!(restored.state_root(), ledger_state.state_root());
+            assert_eq!(
+                restored.order_manager.next_id(),
+                ledger_state.order_manager.next_id()
+            );
+            let queries = snapshot_queries(&public_keys).into_iter();
+            for (query, restored_query) in queries.zip(snapshot_queries(&public_keys)) {
+                assert_eq!(
+                    format!("{:?}", restored.handle_query(restored_query)),
+                    format!("{:?}", ledger_state.handle_query(query))
+                );
+            }
+
+            // both ledgers keep evolving the same way, the queued cancel included
+            let mm_1_cancel = create_cancel_txn(&mut mm_1_sk, 0, 7, 7);
+            ledger_state.apply_block(&mut create_block(vec![mm_1_cancel.clone()]));
+            restored.apply_block(&mut create_block(vec![mm_1_cancel]));
+            assert_eq!(restored.state_root(), ledger_state.state_root());
+            assert!(restored.queued_transactions.is_empty());
+            let queries = snapshot_queries(&public_keys).into_iter();
+            for (query, restored_query) in queries.zip(snapshot_queries(&public_keys)) {
+                assert_eq!(
+                    format!("{:?}", restored.handle_query(restored_query)),
+                    format!("{:?}", ledger_state.handle_query(query))
+                );
+            }
+        }
+
+        /// `test_setup` plus ALT/USD and BET/USD markets, and five funded traders
+        fn test_setup_with_traders() -> (LedgerState, Vec<SigningKey>) {
+            let mut ledger_state = test_setup();
+
+            for (asset_id, symbol) in [(2, "ALT"), (3, "BET")] {
+                ledger_state.asset_manager.assets.push(Asset {
+                    asset_id,
+                    asset_name: symbol.to_string(),
+                    lot_size: 100,
+                    decimals: 4,
+                    symbol: symbol.to_string(),
+                    display_decimals: 2,
+                });
+                ledger_state
+                    .spot_clearinghouse
+                    .add_market(
+                        asset_id,
+                        1,
+                        symbol.to_string(),
+                        "USD".to_string(),
+                        10,
+                        2,
+                        LotSizes::default(),
+                    )
+                    .unwrap();
+            }
+            ledger_state.asset_manager.next_asset_id = 4;
+
+            let traders: Vec<SigningKey> = (1..=5)
+                .map(|seed| SigningKey::from_bytes(&[seed; 32]))
+                .collect();
+            for trader in traders.iter() {
+                let balance =
+                    ledger_state.get_account_spot_balances_mut(&trader.verifying_key().to_bytes());
+                for asset_id in 1..4 {
+                    let token_balance =
+                        SpotClearingHouse::get_account_token_balance_mut(balance, asset_id);
+                    token_balance.total_balance += 1_000_000_000;
+                    token_balance.available_balance += 1_000_000_000;
+                }
+            }
+            (ledger_state, traders)
+        }
+
+        fn canonical_order(transactions: &[SignedTransaction]) -> Vec<SignedTransaction> {
+            let mut ordered = transactions.to_vec();
+            ordered.sort_by(|a, b| a.canonical_cmp(b));
+            ordered
+        }
+
+        #[test]
+        pub fn test_parallel_and_serial_execution_reach_same_state() {
+            let (mut serial, mut traders) = test_setup_with_traders();
+            let (mut parallel, _) = test_setup_with_traders();
+            serial.set_matching_threads(1);
+            parallel.set_matching_threads(4);
+            let mut user_sk = get_alice_sk();
+            let receiver = traders[4].verifying_key().to_bytes();
+
+            let transactions = vec![
+                // market 0, against the market makers
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Market(50_000),
+                    0,
+                ),
+                // market 1, two traders crossing
+                create_order_txn(
+                    &mut traders[0],
+                    1,
+                    OrderDirection::Sell,
+                    OrderType::Limit(500, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[0],
+                    1,
+                    OrderDirection::Sell,
+                    OrderType::Limit(520, 100),
+                    1,
+                ),
+                create_order_txn(
+                    &mut traders[1],
+                    1,
+                    OrderDirection::Buy,
+                    OrderType::Limit(510, 200),
+                    0,
+                ),
+                // market 2, the second order self crosses and leaves its order id unused
+                create_order_txn(
+                    &mut traders[2],
+                    2,
+                    OrderDirection::Buy,
+                    OrderType::Limit(300, 100),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[2],
+                    2,
+                    OrderDirection::Sell,
+                    OrderType::Limit(200, 50),
+                    1,
+                ),
+                // transfers between accounts trading nowhere
+                create_transfer_txn(&mut traders[3], receiver, 1, 1_000, 0),
+            ];
+
+            let shards = plan_shards(&serial, &canonical_order(&transactions));
+            assert_eq!(shards.len(), 4);
+
+            let mut serial_fills = serial.subscribe_fills();
+            let mut parallel_fills = parallel.subscribe_fills();
+            let mut serial_block = create_block(transactions.clone());
+            let mut parallel_block = create_block(transactions.clone());
+            let serial_nonces = serial.apply_block(&mut serial_block);
+            let parallel_nonces = parallel.apply_block(&mut parallel_block);
+
+            assert_eq!(serial_nonces, parallel_nonces);
+            assert_eq!(serial.state_root(), parallel.state_root());
+            assert_eq!(
+                serial.order_manager.next_id(),
+                parallel.order_manager.next_id()
+            );
+            for (serial_tx, parallel_tx) in serial_block
+                .transactions()
+                .iter()
+                .zip(parallel_block.transactions())
+            {
+                assert_eq!(serial_tx.get_status(), parallel_tx.get_status());
+                assert_eq!(
+                    format!("{:?}", serial.get_receipt(&serial_tx.hash)),
+                    format!("{:?}", parallel.get_receipt(&parallel_tx.hash))
+                );
+            }
+            assert_eq!(
+                serial_block.transactions()[5].get_status(),
+                TransactionStatus::Rejected(RejectReason::SelfCross)
+            );
+
+            let drain = |fills: &mut tokio::sync::mpsc::UnboundedReceiver<FillEvent>| {
+                std::iter::from_fn(|| fills.try_recv().ok()).collect::<Vec<_>>()
+            };
+            let serial_fills = drain(&mut serial_fills);
+            assert_eq!(serial_fills, drain(&mut parallel_fills));
+            let filled_markets: BTreeSet<MarketId> =
+                serial_fills.iter().map(|event| event.market_id).collect();
+            assert_eq!(filled_markets, BTreeSet::from([0, 1]));
+        }
+
+        #[test]
+        pub fn test_replicas_applying_same_block_assign_same_order_ids() {
+            let (mut first, mut traders) = test_setup_with_traders();
+            let (mut second, _) = test_setup_with_traders();
+            first.set_matching_threads(1);
+            second.set_matching_threads(4);
+
+            let transactions = vec![
+                create_order_txn(
+                    &mut traders[0],
+                    1,
+                    OrderDirection::Sell,
+                    OrderType::Limit(500, 300),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[1],
+                    2,
+                    OrderDirection::Buy,
+                    OrderType::Limit(300, 100),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[0],
+                    1,
+                    OrderDirection::Sell,
+                    OrderType::Limit(520, 100),
+                    1,
+                ),
+                create_order_txn(
+                    &mut traders[2],
+                    2,
+                    OrderDirection::Sell,
+                    OrderType::Limit(400, 100),
+                    0,
+                ),
+            ];
+
+            let mut first_block = create_block(transactions.clone());
+            let mut second_block = create_block(transactions);
+            first.apply_block(&mut first_block);
+            second.apply_block(&mut second_block);
+
+            let order_ids = |ledger_state: &LedgerState, block: &Block| {
+                block
+                    .transactions()
+                    .iter()
+                    .map(|tx| ledger_state.get_receipt(&tx.hash).unwrap().order_id)
+                    .collect::<Vec<_>>()
+            };
+            let first_ids = order_ids(&first, &first_block);
+            assert_eq!(first_ids, order_ids(&second, &second_block));
+            assert!(first_ids.iter().all(Option::is_some));
+            assert_eq!(first.state_root(), second.state_root());
+        }
+
+        #[test]
+        pub fn test_diverged_order_id_counter_changes_state_root() {
+            let mut ledger_state = test_setup();
+            let state_root = ledger_state.state_root();
+            let next_id = ledger_state.order_manager.next_id();
+
+            ledger_state.order_manager.set_next_id(next_id + 1);
+            assert_ne!(ledger_state.state_root(), state_root);
+            ledger_state.order_manager.set_next_id(next_id);
+            assert_eq!(ledger_state.state_root(), state_root);
+        }
+
+        #[test]
+        pub fn test_all_or_none_order_rests_until_filled_whole() {
+            let mut ledger_state = test_setup();
+            let mut mm_1_sk = get_bob_sk();
+            let mut mm_2_sk = get_carol_sk();
+            let mut user_sk = get_alice_sk();
+            let mm_1_pk = mm_1_sk.verifying_key().to_bytes();
+
+            let aon_ask = create_order_txn(
+                &mut mm_1_sk,
+                0,
+                OrderDirection::Sell,
+                OrderType::AllOrNone(2_480, 500),
+                7,
+            );
+            let mut block_1 = create_block(vec![aon_ask]);
+            ledger_state.apply_block(&mut block_1);
+            let aon_order_id = ledger_state
+                .get_receipt(&block_1.transactions()[0].hash)
+                .unwrap()
+                .order_id
+                .unwrap();
+
+            // too small to take it whole, so the bid rests across it
+            let small_bid = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_480, 200),
+                0,
+            );
+            let mut block_2 = create_block(vec![small_bid]);
+            ledger_state.apply_block(&mut block_2);
+            let receipt = ledger_state
+                .get_receipt(&block_2.transactions()[0].hash)
+                .unwrap();
+            assert!(receipt.fills.is_empty());
+            let aon_order = &ledger_state.accounts[&mm_1_pk].open_orders[&aon_order_id];
+            assert_eq!(aon_order.filled_base_lots, 0);
+
+            let large_bid = create_order_txn(
+                &mut mm_2_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_480, 500),
+                6,
+            );
+            let mut block_3 = create_block(vec![large_bid]);
+            ledger_state.apply_block(&mut block_3);
+            let receipt = ledger_state
+                .get_receipt(&block_3.transactions()[0].hash)
+                .unwrap();
+            assert_eq!(receipt.fills.len(), 1);
+            assert_eq!(receipt.fills[0].maker_order_id, aon_order_id);
+            assert_eq!(receipt.fills[0].base_lots, 500);
+            assert!(
+                !ledger_state.accounts[&mm_1_pk]
+                    .open_orders
+                    .contains_key(&aon_order_id)
+            );
+        }
+
+        #[test]
+        pub fn test_top_of_book_subscribers_see_best_price_changes() {
+            let mut ledger_state = test_setup();
+            let mut top_of_book = ledger_state.subscribe_top_of_book();
+            let mut user_sk = get_alice_sk();
+
+            // a bid behind the best bid of 2_450, then one improving on it
+            let behind = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_000, 100),
+                0,
+            );
+            let improving = create_order_txn(
+                &mut user_sk,
+                0,
+                OrderDirection::Buy,
+                OrderType::Limit(2_460, 100),
+                1,
+            );
+            ledger_state.apply_block(&mut create_block(vec![behind]));
+            assert!(top_of_book.try_recv().is_err());
+
+            ledger_state.apply_block(&mut create_block(vec![improving]));
+            assert_eq!(
+                top_of_book.try_recv().unwrap(),
+                TopOfBookEvent {
+                    market_id: 0,
+                    best_bid: Some(2_460),
+                    best_ask: Some(2_500),
+                }
+            );
+            assert!(top_of_book.try_recv().is_err());
+        }
+
+        #[test]
+        pub fn test_balance_change_pushes_one_account_update() {
+            let mut ledger_state = test_setup();
+            let mut bob_sk = get_bob_sk();
+            let mut carol_sk = get_carol_sk();
+            let alice_pk = get_alice_sk().verifying_key().to_bytes();
+            let bob_pk = bob_sk.verifying_key().to_bytes();
+            let available = |account: &AccountInfoWithBalances| {
+                account
+                    .spot_balances
+                    .find_asset_id(0)
+                    .map_or(0, |balance| balance.available_balance)
+            };
+            let before =
+                available(&ledger_state.get_account_info_with_balances_or_default(&alice_pk));
+            let mut updates = ledger_state.subscribe_account(alice_pk);
+
+            let to_alice = create_transfer_txn(&mut bob_sk, alice_pk, 100, 0, 7);
+            ledger_state.apply_block(&mut create_block(vec![to_alice]));
+            let update = updates.try_recv().unwrap();
+            assert_eq!(available(&update), before + 100);
+            assert!(updates.try_recv().is_err());
+
+            // blocks leaving the account untouched push nothing
+            let to_bob = create_transfer_txn(&mut carol_sk, bob_pk, 100, 0, 5);
+            ledger_state.apply_block(&mut create_block(vec![to_bob]));
+            assert!(updates.try_recv().is_err());
+        }
+
+        #[test]
+        pub fn test_lagging_account_subscriber_is_dropped() {
+            let mut ledger_state = test_setup();
+            let mut bob_sk = get_bob_sk();
+            let alice_pk = get_alice_sk().verifying_key().to_bytes();
+            let mut updates = ledger_state.subscribe_account(alice_pk);
+
+            for nonce in 0..=ACCOUNT_SUBSCRIBER_BUFFER as u64 {
+                let transfer = create_transfer_txn(&mut bob_sk, alice_pk, 1, 0, 7 + nonce);
+                ledger_state.apply_block(&mut create_block_in_view(vec![transfer], nonce));
+            }
+
+            assert!(ledger_state.account_subscribers.is_empty());
+            for _ in 0..ACCOUNT_SUBSCRIBER_BUFFER {
+                assert!(updates.try_recv().is_ok());
+            }
+            assert!(matches!(
+                updates.try_recv(),
+                Err(mpsc::error::TryRecvError::Disconnected)
+            ));
+        }
+
+        #[test]
+        pub fn test_account_resting_in_two_markets_joins_their_shards() {
+            let (mut ledger_state, mut traders) = test_setup_with_traders();
+            let mut mm_1_sk = get_bob_sk();
+            let mut user_sk = get_alice_sk();
+
+            // mm_1 rests in market 0 from the setup, and now in market 1 too
+            let balance =
+                ledger_state.get_account_spot_balances_mut(&mm_1_sk.verifying_key().to_bytes());
+            let alt_balance = SpotClearingHouse::get_account_token_balance_mut(balance, 2);
+            alt_balance.total_balance += 1_000_000;
+            alt_balance.available_balance += 1_000_000;
+            let mm_1_sell = create_order_txn(
+                &mut mm_1_sk,
+                1,
+                OrderDirection::Sell,
+                OrderType::Limit(2_600, 100),
+                7,
+            );
+            ledger_state.apply_block(&mut create_block(vec![mm_1_sell]));
+
+            let transactions = canonical_order(&[
+                create_order_txn(
+                    &mut user_sk,
+                    0,
+                    OrderDirection::Buy,
+                    OrderType::Limit(2_000, 100),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[0],
+                    1,
+                    OrderDirection::Buy,
+                    OrderType::Limit(550, 100),
+                    0,
+                ),
+                create_order_txn(
+                    &mut traders[1],
+                    2,
+                    OrderDirection::Buy,
+                    OrderType::Limit(550, 100),
+                    0,
+                ),
+            ]);
+
+            let shards = plan_shards(&ledger_state, &transactions);
+            assert_eq!(shards.len(), 2);
+            let joined = shards
+                .iter()
+                .find(|shard| shard.markets.contains(&0))
+                .unwrap();
+            assert_eq!(joined.markets, BTreeSet::from([0, 1]));
+            assert_eq!(joined.positions.len(), 2);
+            assert!(
+                joined
+                    .accounts
+                    .contains(&mm_1_sk.verifying_key().to_bytes())
+            );
+        }
+
+        #[test]
+        pub fn test_charging_markets_execute_in_separate_shards() {
+            let (mut serial, mut traders) = test_setup_with_traders();
+            let (mut parallel, _) = test_setup_with_traders();
+            let fee_collector = SigningKey::from_bytes(&[9; 32]).verifying_key().to_bytes();
+            for ledger_state in [&mut serial, &mut parallel] {
+                ledger_state.params.taker_fee_bps = 10;
+                ledger_state.params.fee_collector = fee_collector;
+            }
+            serial.set_matching_threads(1);
+            parallel.set_matching_threads(4);
+
+            let mut transactions = Vec::new();
+            for (market_id, maker, taker) in [(1, 0, 1), (2, 2, 3)] {
+                transactions.push(create_order_txn(
+                    &mut traders[maker],
+                    market_id,
+                    OrderDirection::Sell,
+                    OrderType::Limit(500, 300),
+                    0,
+                ));
+                transactions.push(create_order_txn(
+                    &mut traders[taker],
+                    market_id,
+                    OrderDirection::Buy,
+                    OrderType::Limit(510, 200),
+                    0,
+                ));
+            }
+
+            let shards = plan_shards(&parallel, &canonical_order(&transactions));
+            assert_eq!(shards.len(), 2);
+            assert!(
+                shards
+                    .iter()
+                    .all(|shard| !shard.accounts.contains(&fee_collector))
+            );
+
+            let mut serial_block = create_block(transactions.clone());
+            let mut parallel_block = create_block(transactions.clone());
+            serial.apply_block(&mut serial_block);
+            parallel.apply_block(&mut parallel_block);
+
+            let fees: Vec<u128> = transactions
+                .iter()
+                .map(|txn| parallel.get_receipt(&txn.hash).unwrap().fees)
+                .filter(|fee| *fee > 0)
+                .collect();
+            assert_eq!(fees.len(), 2);
+            let fee_balance = parallel.get_fee_balance();
+            assert_eq!(
+                fee_balance
+                    .iter()
+                    .map(|summary| summary.total)
+                    .sum::<u128>(),
+                fees.iter().sum::<u128>()
+            );
+            assert_eq!(fee_balance, serial.get_fee_balance());
+            assert_eq!(parallel.state_root(), serial.state_root());
+        }
+    }
+
+    mod test_add_market {
+        use crate::state::{
+            asset::Asset, spot_clearinghouse::MarketError, spot_market::LotSizes,
+            state::LedgerState,
+        };
+
+        #[test]
+        fn test_add_market_rejects_unknown_assets() {
+            let mut ledger_state = LedgerState::new();
+            let state_root = ledger_state.state_root();
+
+            assert_eq!(
+                ledger_state.add_market(7, 1, 10, 2, LotSizes::default()),
+                Err(MarketError::UnknownAsset(7))
+            );
+            assert_eq!(
+                ledger_state.add_market(0, 7, 10, 2, LotSizes::default()),
+                Err(MarketError::UnknownAsset(7))
+            );
+            assert_eq!(ledger_state.get_markets().len(), 1);
+            assert_eq!(ledger_state.state_root(), state_root);
+        }
+
+        #[test]
+        fn test_add_market_rejects_same_base_and_quote() {
+            let mut ledger_state = LedgerState::new();
+
+            assert_eq!(
+                ledger_state.add_market(1, 1, 10, 2, LotSizes::default()),
+                Err(MarketError::SameAsset(1))
+            );
+            assert_eq!(ledger_state.get_markets().len(), 1);
+        }
+
+        #[test]
+        fn test_add_market_rejects_zero_lot_size() {
+            let mut ledger_state = LedgerState::new();
+            ledger_state.asset_manager.assets.push(Asset {
+                asset_id: 2,
+                asset_name: "ALT".to_string(),
+                lot_size: 100,
+                decimals: 4,
+                symbol: "ALT".to_string(),
+                display_decimals: 2,
+            });
+            ledger_state.asset_manager.next_asset_id = 3;
+
+            assert_eq!(
+                ledger_state.add_market(
+                    2,
+                    1,
+                    10,
+                    2,
+                    LotSizes {
+                        base: Some(0),
+                        quote: None,
+                    }
+                ),
+                Err(MarketError::ZeroLotSize)
+            );
+            assert_eq!(
+                ledger_state.add_market(
+                    2,
+                    1,
+                    10,
+                    2,
+                    LotSizes {
+                        base: None,
+                        quote: Some(0),
+                    }
+                ),
+                Err(MarketError::ZeroLotSize)
+            );
+            assert_eq!(ledger_state.get_markets().len(), 1);
+        }
+
+        #[test]
+        fn test_add_market_names_market_after_its_assets() {
+            let mut ledger_state = LedgerState::new();
+            ledger_state.asset_manager.assets.push(Asset {
+                asset_id: 2,
+                asset_name: "ALT".to_string(),
+                lot_size: 100,
+                decimals: 4,
+                symbol: "ALT".to_string(),
+                display_decimals: 2,
+            });
+            ledger_state.asset_manager.next_asset_id = 3;
+
+            let market_id = ledger_state
+                .add_market(2, 1, 10, 2, LotSizes::default())
+                .unwrap();
+            let market = ledger_state.get_market_info(market_id).unwrap();
+            assert_eq!(market.base_asset_id, 2);
+            assert_eq!(market.quote_asset_id, 1);
+        }
+    }
+
+    mod test_self_cross_index {
+        use rand::{Rng, SeedableRng, rngs::StdRng};
+
+        use std::collections::BTreeMap;
+
+        use crate::state::{
+            order::{LimitOrder, OrderDirection, OrderId, OrderStateManager},
+            state::AccountInfo,
+        };
+
+        fn naive_self_cross(
+            open_orders: &BTreeMap<OrderId, LimitOrder>,
+            direction: &OrderDirection,
+            price: u64,
+        ) -> bool {
+            match direction {
+                OrderDirection::Buy => open_orders.values().any(|order| {
+                    order.common.direction == OrderDirection::Sell && price >= order.price_multiple
+                }),
+                OrderDirection::Sell => open_orders.values().any(|order| {
+                    order.common.direction == OrderDirection::Buy && price <= order.price_multiple
+                }),
+            }
+        }
+
+        fn assert_matches_naive(account_info: &AccountInfo) {
+            for price in (0..=1_100).step_by(25) {
+                for direction in [OrderDirection::Buy, OrderDirection::Sell] {
+                    assert_eq!(
+                        account_info.would_self_cross(&direction, price),
+                        naive_self_cross(&account_info.open_orders, &direction, price),
+                        "{:?} at {}",
+                        direction,
+                        price
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_index_matches_naive_scan_with_many_open_orders() {
+            let mut rng = StdRng::seed_from_u64(7);
+            let mut order_manager = OrderStateManager::new();
+            let mut account_info = AccountInfo::new();
+            let account = [0u8; 32];
+
+            for _ in 0..2_000 {
+                // keep the book growing so the best prices are removed often once it is large
+                if account_info.open_orders.len() > 500 || rng.gen_bool(0.3) {
+                    if !account_info.open_orders.is_empty() {
+                        let index = rng.gen_range(0..account_info.open_orders.len());
+                        let order_id = *account_info.open_orders.keys().nth(index).unwrap();
+                        account_info.remove_open_order(order_id);
+                    }
+                } else {
+                    // buys below and sells above 500, with overlap so both sides can cross
+                    let direction = if rng.gen_bool(0.5) {
+                        OrderDirection::Buy
+                    } else {
+                        OrderDirection::Sell
+                    };
+                    let price = match direction {
+                        OrderDirection::Buy => rng.gen_range(1..=600),
+                        OrderDirection::Sell => rng.gen_range(400..=1_000),
+                    };
+                    let market_id = rng.gen_range(0..3);
+                    let order =
+                        order_manager.new_limit_order(market_id, account, direction, price, 100);
+                    account_info.add_open_order(order);
+                }
+
+                assert_matches_naive(&account_info);
+            }
+        }
+
+        #[test]
+        fn test_removing_best_order_falls_back_to_next_best() {
+            let mut order_manager = OrderStateManager::new();
+            let mut account_info = AccountInfo::new();
+            let account = [0u8; 32];
+
+            for price in [400, 450, 450] {
+                let order =
+                    order_manager.new_limit_order(0, account, OrderDirection::Buy, price, 100);
+                account_info.add_open_order(order);
+            }
+
+            // one of two orders at the best bid leaves the best bid unchanged
+            account_info.remove_open_order(1);
+            assert!(account_info.would_self_cross(&OrderDirection::Sell, 450));
+
+            account_info.remove_open_order(2);
+            assert!(!account_info.would_self_cross(&OrderDirection::Sell, 450));
+            assert!(account_info.would_self_cross(&OrderDirection::Sell, 400));
+
+            account_info.remove_open_order(0);
+            assert!(!account_info.would_self_cross(&OrderDirection::Sell, 0));
+        }
+    }
+
+    mod test_open_orders {
+        use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+        use crate::state::{
+            order::{LimitOrder, OrderDirection, OrderId, OrderStateManager},
+            state::AccountInfo,
+        };
+
+        fn account_with_bids(count: u64) -> (AccountInfo, Vec<LimitOrder>) {
+            let mut order_manager = OrderStateManager::new();
+            let mut account_info = AccountInfo::new();
+            // rising prices, so removing the oldest order never rescans for the best bid
+            let orders: Vec<LimitOrder> = (1..=count)
+                .map(|price| {
+                    order_manager.new_limit_order(0, [0; 32], OrderDirection::Buy, price, 100)
+                })
+                .collect();
+            for order in &orders {
+                account_info.add_open_order(order.clone());
+            }
+            (account_info, orders)
+        }
+
+        fn ids<'a>(orders: impl Iterator<Item = &'a LimitOrder>) -> Vec<OrderId> {
+            orders.map(|order| order.common.id).collect()
+        }
+
+        #[test]
+        fn test_removals_keep_open_orders_sorted_by_id() {
+            let (mut account_info, mut remaining) = account_with_bids(5_000);
+            let mut rng = StdRng::seed_from_u64(2100);
+            let mut removal_order = ids(remaining.iter());
+            removal_order.shuffle(&mut rng);
+
+            for (step, order_id) in removal_order.into_iter().enumerate() {
+                let removed = account_info.remove_open_order(order_id).unwrap();
+                assert_eq!(removed.common.id, order_id);
+                assert!(account_info.remove_open_order(order_id).is_none());
+
+                let index = remaining
+                    .iter()
+                    .position(|order| order.common.id == order_id)
+                    .unwrap();
+                remaining.remove(index);
+
+                if step % 500 == 0 {
+                    assert_eq!(
+                        ids(account_info.open_orders.values()),
+                        ids(remaining.iter())
+                    );
+                    // still serialized as the list of orders, so state roots are unchanged
+                    assert_eq!(
+                        bincode::serialize(&account_info).unwrap(),
+                        bincode::serialize(&(
+                            account_info.expected_nonce,
+                            &remaining,
+                            &account_info.completed_orders,
+                            account_info.best_open_bid,
+                            account_info.best_open_ask,
+                            account_info.last_faucet_drip,
+                            ()
+                        ))
+                        .unwrap()
+                    );
+                }
+            }
+            assert!(account_info.open_orders.is_empty());
+        }
+
+        #[test]
+        fn test_removing_oldest_orders_keeps_the_rest() {
+            let (mut account_info, orders) = account_with_bids(2_000);
+            let top_price = orders.last().unwrap().price_multiple;
+
+            for (removed_count, order) in orders.iter().enumerate().take(orders.len() - 1) {
+                let removed = account_info.remove_open_order(order.common.id).unwrap();
+                assert_eq!(removed.common.id, order.common.id);
+                assert_eq!(
+                    account_info.open_orders.len(),
+                    orders.len() - removed_count - 1
+                );
+                assert_eq!(
+                    account_info.open_orders.values().next().unwrap().common.id,
+                    orders[removed_count + 1].common.id
+                );
+                assert!(account_info.would_self_cross(&OrderDirection::Sell, top_price));
+            }
+
+            assert_eq!(
+                ids(account_info.open_orders.values()),
+                ids(orders.last().into_iter())
+            );
+        }
     }
 }
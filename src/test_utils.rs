@@ -59,6 +59,7 @@ pub mod test_helpers {
             asset_id: 0,
             nonce: 0,
             status: TransactionStatus::Pending,
+            valid_until: None,
         });
         unsigned_txn.sign(&mut get_alice_sk())
     }
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     hotstuff::message::HotStuffMessage,
-    message_protocol::{AppMessage, ControlMessage},
+    message_protocol::{AppMessage, ControlMessage, SyncMessage},
     node::{client::handler::QueryRequest, state::PeerId},
 };
 
@@ -15,15 +15,18 @@ pub enum Message {
     Connection(ControlMessage),
     Application(AppMessage),
     HotStuff(HotStuffMessage),
+    Sync(SyncMessage),
 }
 
 pub enum ReplicaOutbound {
     Broadcast(HotStuffMessage),
     SendTo(PeerId, HotStuffMessage),
+    Sync(PeerId, SyncMessage),
 }
 
 pub enum ReplicaInBound {
     HotStuff(HotStuffMessage),
+    Sync(SyncMessage),
     Transaction(SignedTransaction),
     Query(QueryRequest),
 }
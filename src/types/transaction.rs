@@ -1,4 +1,4 @@
-use std::{fmt, ops::Deref};
+use std::{cmp::Ordering, fmt, ops::Deref};
 
 use ed25519::{Signature, signature::SignerMut};
 use ed25519_dalek::{SigningKey, VerifyingKey};
@@ -10,8 +10,9 @@ use crate::{
     hotstuff::utils,
     state::{
         asset::AssetId,
-        order::{OrderDirection, OrderId, OrderType},
+        order::{OrderDirection, OrderId, OrderPriceMultiple, OrderType},
         spot_clearinghouse::MarketId,
+        spot_market::MarketStatus,
         state::{ExecError, Nonce},
     },
 };
@@ -19,16 +20,82 @@ use crate::{
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum TransactionStatus {
     Executed,
-    Rejected(String),
+    Rejected(RejectReason),
     Error(ExecError),
     Pending,
 }
 
+/// Why a transaction was rejected before or during execution
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    DuplicateNonce,
+    OutOfOrderNonce,
+    Expired,
+    MempoolFull,
+    EvictedFromMempool,
+    SelfCross,
+    OpenOrderLimitReached,
+    NoLiquidity,
+    InvalidTick,
+    ZeroOrderSize,
+    EmptyBatch,
+    BatchTooLarge,
+    BatchOrderFailed(usize),
+    NoMatchingOrder,
+    ReductionExceedsRemaining,
+    FaucetCooldown,
+    InvalidSignature,
+    /// Another transaction in the same block failed verification
+    InvalidBlock,
+    MarketHalted,
+    MarketCancelOnly,
+    /// Only the admin account may send the transaction
+    Unauthorized,
+    /// Nothing of the asset was available to transfer
+    NothingToTransfer,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::DuplicateNonce => write!(f, "Duplicate nonce"),
+            RejectReason::OutOfOrderNonce => write!(f, "Out of order nonce"),
+            RejectReason::Expired => write!(f, "Expired"),
+            RejectReason::MempoolFull => write!(f, "Mempool full"),
+            RejectReason::EvictedFromMempool => write!(f, "Evicted from mempool"),
+            RejectReason::SelfCross => write!(f, "Self cross"),
+            RejectReason::OpenOrderLimitReached => write!(f, "Open order limit reached"),
+            RejectReason::NoLiquidity => write!(f, "No liquidity"),
+            RejectReason::InvalidTick => write!(f, "Invalid tick"),
+            RejectReason::ZeroOrderSize => write!(f, "Zero order size"),
+            RejectReason::EmptyBatch => write!(f, "Empty batch"),
+            RejectReason::BatchTooLarge => write!(f, "Batch too large"),
+            RejectReason::BatchOrderFailed(index) => write!(f, "Batch order {} failed", index),
+            RejectReason::NoMatchingOrder => write!(f, "No matching order"),
+            RejectReason::ReductionExceedsRemaining => {
+                write!(f, "Reduction exceeds remaining size")
+            }
+            RejectReason::FaucetCooldown => write!(f, "Faucet cooldown"),
+            RejectReason::InvalidSignature => write!(f, "Invalid signature"),
+            RejectReason::InvalidBlock => write!(f, "Invalid block"),
+            RejectReason::MarketHalted => write!(f, "Market halted"),
+            RejectReason::MarketCancelOnly => write!(f, "Market is cancel only"),
+            RejectReason::Unauthorized => write!(f, "Unauthorized"),
+            RejectReason::NothingToTransfer => write!(f, "Nothing to transfer"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum UnsignedTransaction {
     Transfer(TransferTransaction),
     Order(OrderTransaction),
     CancelOrder(CancelOrderTransaction),
+    CancelOrderAtPrice(CancelOrderAtPriceTransaction),
+    BatchOrder(BatchOrderTransaction),
+    ReduceOrder(ReduceOrderTransaction),
+    SetMarketStatus(SetMarketStatusTransaction),
+    TransferAll(TransferAllTransaction),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -40,6 +107,22 @@ pub struct TransferTransaction {
     pub status: TransactionStatus,
 
     pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
+}
+
+/// Transfers the sender's whole available balance of an asset, as of execution, so fills
+/// and unlocks earlier in the block are included. Balance locked in open orders stays behind
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferAllTransaction {
+    pub from: PublicKeyHash,
+    pub to: PublicKeyHash,
+    pub asset_id: AssetId,
+    pub status: TransactionStatus,
+
+    pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,6 +134,8 @@ pub struct OrderTransaction {
     pub status: TransactionStatus,
 
     pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -61,16 +146,103 @@ pub struct CancelOrderTransaction {
     pub status: TransactionStatus,
 
     pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
+}
+
+/// Cancels the sender's resting order at `price` on the `direction` side of the book,
+/// for clients that lost track of the order's id. The oldest order is cancelled if several match
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CancelOrderAtPriceTransaction {
+    pub from: PublicKeyHash,
+    pub market_id: MarketId,
+    pub direction: OrderDirection,
+    pub price: OrderPriceMultiple,
+    pub status: TransactionStatus,
+
+    pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
+}
+
+/// Shrinks the sender's resting order by `base_lots` without losing its place in the queue.
+/// Reducing by the whole unfilled remainder cancels the order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReduceOrderTransaction {
+    pub from: PublicKeyHash,
+    pub market_id: MarketId,
+    pub order_id: OrderId,
+    pub base_lots: u64,
+    pub status: TransactionStatus,
+
+    pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
+}
+
+/// Admin transaction switching which operations a market accepts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetMarketStatusTransaction {
+    pub from: PublicKeyHash,
+    pub market_id: MarketId,
+    pub market_status: MarketStatus,
+    pub status: TransactionStatus,
+
+    pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
+}
+
+/// A single order within a batch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderSpec {
+    pub market_id: MarketId,
+    pub direction: OrderDirection,
+    pub order_type: OrderType,
+}
+
+/// Places several orders under a single nonce. Orders that fail are recorded in
+/// `order_statuses` without affecting the rest, unless `all_or_nothing` is set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchOrderTransaction {
+    pub from: PublicKeyHash,
+    pub orders: Vec<OrderSpec>,
+    pub all_or_nothing: bool,
+    pub status: TransactionStatus,
+    // status of each order, in the same order as `orders`, filled in on execution
+    pub order_statuses: Vec<TransactionStatus>,
+
+    pub nonce: Nonce,
+    // last view in which the transaction may execute
+    pub valid_until: Option<u64>,
 }
 
 pub type Sha256Hash = [u8; 32];
 pub type PublicKeyHash = Sha256Hash;
 
 impl UnsignedTransaction {
+    /// Prefixed to the bytes a transaction signs, so a signature over one kind of transaction
+    /// never verifies as another kind. No tag is a prefix of another
+    fn domain_tag(&self) -> &'static [u8] {
+        match self {
+            UnsignedTransaction::Transfer(_) => b"superliquid:transfer:",
+            UnsignedTransaction::Order(_) => b"superliquid:order:",
+            UnsignedTransaction::CancelOrder(_) => b"superliquid:cancel_order:",
+            UnsignedTransaction::CancelOrderAtPrice(_) => b"superliquid:cancel_order_at_price:",
+            UnsignedTransaction::BatchOrder(_) => b"superliquid:batch_order:",
+            UnsignedTransaction::ReduceOrder(_) => b"superliquid:reduce_order:",
+            UnsignedTransaction::SetMarketStatus(_) => b"superliquid:set_market_status:",
+            UnsignedTransaction::TransferAll(_) => b"superliquid:transfer_all:",
+        }
+    }
+
     pub fn hash(&self) -> Sha256Hash {
         // probably want to implement my own encoding and hashing
         let encoded = bincode::serialize(&self).unwrap();
-        Sha256::digest(&encoded).into()
+        let mut hasher = Sha256::new();
+        hasher.update(self.domain_tag());
+        hasher.update(&encoded);
+        hasher.finalize().into()
     }
 
     pub fn sign(self, signing_key: &mut SigningKey) -> SignedTransaction {
@@ -102,32 +274,27 @@ pub struct SignedTransaction {
 }
 
 impl SignedTransaction {
-    pub fn verify_sender(&self) -> bool {
-        match &self.tx {
-            UnsignedTransaction::Transfer(transaction) => {
-                let public_key = PublicKeyString::from_bytes(transaction.from).as_public_key();
-                let tx_hash = self.hash;
-                let signature = utils::string_to_sig(&self.signature.as_str())
-                    .expect("Conversion from string to signature failed");
-                public_key.verify_strict(&tx_hash, &signature).is_ok()
-            }
-
-            UnsignedTransaction::Order(transaction) => {
-                let public_key = PublicKeyString::from_bytes(transaction.from).as_public_key();
-                let tx_hash = self.hash();
-                let signature = utils::string_to_sig(&self.signature.as_str())
-                    .expect("Conversion from string to signature failed");
-                public_key.verify_strict(&tx_hash, &signature).is_ok()
-            }
+    /// Id the transaction is tracked by, and its receipt keyed by: the SHA-256 of its
+    /// domain tagged bytes as signed. Fixed at signing, so execution updating its status
+    /// doesn't change it
+    pub fn hash(&self) -> Sha256Hash {
+        self.hash
+    }
 
-            UnsignedTransaction::CancelOrder(transaction) => {
-                let public_key = PublicKeyString::from_bytes(transaction.from).as_public_key();
-                let tx_hash = self.hash();
-                let signature = utils::string_to_sig(&self.signature.as_str())
-                    .expect("Conversion from string to signature failed");
-                public_key.verify_strict(&tx_hash, &signature).is_ok()
-            }
+    pub fn verify_sender(&self) -> bool {
+        // the stored hash is only trusted once it's known to be the hash that was signed
+        let tx_hash = self.tx.hash();
+        if tx_hash != self.hash {
+            return false;
         }
+
+        let Ok(public_key) = VerifyingKey::from_bytes(&self.get_from_account()) else {
+            return false;
+        };
+        let Ok(signature) = utils::string_to_sig(self.signature.as_str()) else {
+            return false;
+        };
+        public_key.verify_strict(&tx_hash, &signature).is_ok()
     }
 
     pub fn get_from_account(&self) -> PublicKeyHash {
@@ -135,6 +302,11 @@ impl SignedTransaction {
             UnsignedTransaction::Transfer(transaction) => transaction.from,
             UnsignedTransaction::Order(transaction) => transaction.from,
             UnsignedTransaction::CancelOrder(transaction) => transaction.from,
+            UnsignedTransaction::CancelOrderAtPrice(transaction) => transaction.from,
+            UnsignedTransaction::BatchOrder(transaction) => transaction.from,
+            UnsignedTransaction::ReduceOrder(transaction) => transaction.from,
+            UnsignedTransaction::SetMarketStatus(transaction) => transaction.from,
+            UnsignedTransaction::TransferAll(transaction) => transaction.from,
         }
     }
 
@@ -143,6 +315,11 @@ impl SignedTransaction {
             UnsignedTransaction::Transfer(transaction) => transaction.nonce,
             UnsignedTransaction::Order(transaction) => transaction.nonce,
             UnsignedTransaction::CancelOrder(transaction) => transaction.nonce,
+            UnsignedTransaction::CancelOrderAtPrice(transaction) => transaction.nonce,
+            UnsignedTransaction::BatchOrder(transaction) => transaction.nonce,
+            UnsignedTransaction::ReduceOrder(transaction) => transaction.nonce,
+            UnsignedTransaction::SetMarketStatus(transaction) => transaction.nonce,
+            UnsignedTransaction::TransferAll(transaction) => transaction.nonce,
         }
     }
 
@@ -151,8 +328,54 @@ impl SignedTransaction {
             UnsignedTransaction::Transfer(transaction) => transaction.status.clone(),
             UnsignedTransaction::Order(transaction) => transaction.status.clone(),
             UnsignedTransaction::CancelOrder(transaction) => transaction.status.clone(),
+            UnsignedTransaction::CancelOrderAtPrice(transaction) => transaction.status.clone(),
+            UnsignedTransaction::BatchOrder(transaction) => transaction.status.clone(),
+            UnsignedTransaction::ReduceOrder(transaction) => transaction.status.clone(),
+            UnsignedTransaction::SetMarketStatus(transaction) => transaction.status.clone(),
+            UnsignedTransaction::TransferAll(transaction) => transaction.status.clone(),
+        }
+    }
+
+    pub fn set_status(&mut self, status: TransactionStatus) {
+        match &mut self.tx {
+            UnsignedTransaction::Transfer(transaction) => transaction.status = status,
+            UnsignedTransaction::Order(transaction) => transaction.status = status,
+            UnsignedTransaction::CancelOrder(transaction) => transaction.status = status,
+            UnsignedTransaction::CancelOrderAtPrice(transaction) => transaction.status = status,
+            UnsignedTransaction::BatchOrder(transaction) => transaction.status = status,
+            UnsignedTransaction::ReduceOrder(transaction) => transaction.status = status,
+            UnsignedTransaction::SetMarketStatus(transaction) => transaction.status = status,
+            UnsignedTransaction::TransferAll(transaction) => transaction.status = status,
         }
     }
+
+    pub fn get_valid_until(&self) -> Option<u64> {
+        match &self.tx {
+            UnsignedTransaction::Transfer(transaction) => transaction.valid_until,
+            UnsignedTransaction::Order(transaction) => transaction.valid_until,
+            UnsignedTransaction::CancelOrder(transaction) => transaction.valid_until,
+            UnsignedTransaction::CancelOrderAtPrice(transaction) => transaction.valid_until,
+            UnsignedTransaction::BatchOrder(transaction) => transaction.valid_until,
+            UnsignedTransaction::ReduceOrder(transaction) => transaction.valid_until,
+            UnsignedTransaction::SetMarketStatus(transaction) => transaction.valid_until,
+            UnsignedTransaction::TransferAll(transaction) => transaction.valid_until,
+        }
+    }
+
+    /// Whether the transaction's deadline has passed by `view`
+    pub fn is_expired(&self, view: u64) -> bool {
+        self.get_valid_until()
+            .is_some_and(|valid_until| view > valid_until)
+    }
+
+    /// Order in which transactions of a block are executed: by sender, then nonce,
+    /// with ties broken by signature. Consensus critical, changing it forks the chain
+    pub fn canonical_cmp(&self, other: &Self) -> Ordering {
+        self.get_from_account()
+            .cmp(&other.get_from_account())
+            .then_with(|| self.get_nonce().cmp(&other.get_nonce()))
+            .then_with(|| self.signature.as_str().cmp(other.signature.as_str()))
+    }
 }
 
 impl Deref for SignedTransaction {
@@ -288,6 +511,7 @@ mod tests {
             asset_id: 0,
             nonce: 0,
             status: TransactionStatus::Pending,
+            valid_until: None,
         });
 
         let tx2 = tx1.clone();
@@ -308,12 +532,182 @@ mod tests {
             asset_id: 0,
             nonce: 0,
             status: TransactionStatus::Pending,
+            valid_until: None,
         });
 
         let signed = unsigned.sign(&mut sk);
         assert!(signed.verify_sender(), "Signature should verify");
     }
 
+    #[test]
+    fn test_signatures_do_not_verify_across_transaction_kinds() {
+        let (mut sk, vk) = generate_keypair();
+        let transfer = UnsignedTransaction::Transfer(TransferTransaction {
+            from: vk.to_bytes(),
+            to: PublicKeyString::default().to_bytes(),
+            amount: 100,
+            asset_id: 0,
+            nonce: 0,
+            status: TransactionStatus::Pending,
+            valid_until: None,
+        });
+        let order = UnsignedTransaction::Order(OrderTransaction {
+            from: vk.to_bytes(),
+            market_id: 0,
+            direction: OrderDirection::Buy,
+            order_type: OrderType::Limit(100, 1),
+            nonce: 0,
+            status: TransactionStatus::Pending,
+            valid_until: None,
+        });
+
+        let signed_transfer = transfer.clone().sign(&mut sk);
+        let signed_order = order.clone().sign(&mut sk);
+        assert!(signed_transfer.verify_sender());
+        assert!(signed_order.verify_sender());
+
+        let forged_order = SignedTransaction {
+            tx: order,
+            signature: signed_transfer.signature.clone(),
+            hash: signed_transfer.hash,
+        };
+        assert!(!forged_order.verify_sender());
+
+        let forged_transfer = SignedTransaction {
+            tx: transfer,
+            signature: signed_order.signature.clone(),
+            hash: signed_order.hash,
+        };
+        assert!(!forged_transfer.verify_sender());
+    }
+
+    #[test]
+    fn test_domain_tags_are_distinct_and_prefix_free() {
+        let (_, vk) = generate_keypair();
+        let from = vk.to_bytes();
+        let transactions = [
+            UnsignedTransaction::Transfer(TransferTransaction {
+                from,
+                to: from,
+                amount: 0,
+                asset_id: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::Order(OrderTransaction {
+                from,
+                market_id: 0,
+                direction: OrderDirection::Buy,
+                order_type: OrderType::Market(0),
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::CancelOrder(CancelOrderTransaction {
+                from,
+                market_id: 0,
+                order_id: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::CancelOrderAtPrice(CancelOrderAtPriceTransaction {
+                from,
+                market_id: 0,
+                direction: OrderDirection::Buy,
+                price: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::BatchOrder(BatchOrderTransaction {
+                from,
+                orders: vec![],
+                all_or_nothing: false,
+                order_statuses: vec![],
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::ReduceOrder(ReduceOrderTransaction {
+                from,
+                market_id: 0,
+                order_id: 0,
+                base_lots: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::SetMarketStatus(SetMarketStatusTransaction {
+                from,
+                market_id: 0,
+                market_status: MarketStatus::Halted,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+            UnsignedTransaction::TransferAll(TransferAllTransaction {
+                from,
+                to: from,
+                asset_id: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            }),
+        ];
+
+        for (i, a) in transactions.iter().enumerate() {
+            for b in &transactions[i + 1..] {
+                assert!(!a.domain_tag().starts_with(b.domain_tag()));
+                assert!(!b.domain_tag().starts_with(a.domain_tag()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_transaction_hash_tracks_the_signed_transaction() {
+        let (mut sk, vk) = generate_keypair();
+        let transfer = |amount| {
+            UnsignedTransaction::Transfer(TransferTransaction {
+                from: vk.to_bytes(),
+                to: PublicKeyString::default().to_bytes(),
+                amount,
+                asset_id: 0,
+                nonce: 0,
+                status: TransactionStatus::Pending,
+                valid_until: None,
+            })
+        };
+
+        let mut signed = transfer(100).sign(&mut sk);
+        assert_eq!(signed.hash(), transfer(100).sign(&mut sk).hash());
+        assert_ne!(signed.hash(), transfer(101).sign(&mut sk).hash());
+
+        // executing the transaction doesn't change the id its receipt is keyed by
+        let tx_hash = signed.hash();
+        signed.set_status(TransactionStatus::Executed);
+        assert_eq!(signed.hash(), tx_hash);
+    }
+
+    #[test]
+    fn test_mismatched_stored_hash_fails_verification() {
+        let (mut sk, vk) = generate_keypair();
+        let mut signed = UnsignedTransaction::Transfer(TransferTransaction {
+            from: vk.to_bytes(),
+            to: PublicKeyString::default().to_bytes(),
+            amount: 100,
+            asset_id: 0,
+            nonce: 0,
+            status: TransactionStatus::Pending,
+            valid_until: None,
+        })
+        .sign(&mut sk);
+
+        signed.hash = [7; 32];
+        assert!(!signed.verify_sender());
+    }
+
     #[test]
     fn test_signature_string_conversion() {
         let (sk, _vk) = generate_keypair();
@@ -412,6 +806,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
             let tx2 = tx1.clone();
 
@@ -428,6 +823,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             let tx2 = UnsignedTransaction::Transfer(TransferTransaction {
@@ -437,6 +833,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             assert_ne!(
@@ -461,6 +858,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             let signed = unsigned.sign(&mut sk1);
@@ -483,6 +881,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             let unsigned2 = UnsignedTransaction::Transfer(TransferTransaction {
@@ -492,6 +891,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             let signed1 = unsigned1.sign(&mut sk1);
@@ -514,6 +914,7 @@ mod tests {
                 asset_id: 0,
                 nonce: 0,
                 status: TransactionStatus::Pending,
+                valid_until: None,
             });
 
             let hash_before = unsigned.hash();